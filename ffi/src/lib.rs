@@ -0,0 +1,3 @@
+//! Shared and static libraries exporting the C bindings of blakediff, declared in `include/blakediff.h`.
+
+pub use blakediff::ffi::*;
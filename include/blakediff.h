@@ -0,0 +1,35 @@
+/* C bindings of the blakediff engine, built with `cargo build --release -p blakediff-ffi`.
+ * Regenerate with `cbindgen --config cbindgen.toml --output include/blakediff.h`. */
+
+#ifndef BLAKEDIFF_H
+#define BLAKEDIFF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* called for each hashed file with its hash, size and path, strings being only valid during the call */
+typedef void (*BlakediffEntryCallback)(const char *hash, uint64_t size, const char *path, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* hash every file under `dir`, calling `callback` for each one with `user_data`, from the calling thread.
+ * The scan stops at the first error. Returns 0 on success, the exit code of the error otherwise,
+ * 2 when `dir` or `callback` is NULL. */
+int blakediff_scan(const char *dir, BlakediffEntryCallback callback, void *user_data);
+
+/* compare two reports held in memory, storing in `json` the comparison as the JSON document of
+ * `blakediff compare --format json`, the reports being named `report_1` and `report_2`.
+ * `json` is left untouched on error, and must be released with blakediff_free_string.
+ * Returns 0 on success, the exit code of the error otherwise, 2 when an argument is NULL. */
+int blakediff_compare(const uint8_t *report_1, size_t len_1, const uint8_t *report_2, size_t len_2, char **json);
+
+/* release a string allocated by blakediff, doing nothing on NULL */
+void blakediff_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BLAKEDIFF_H */
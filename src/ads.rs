@@ -0,0 +1,90 @@
+//! Alternate data streams of NTFS files : besides its content, a file can hold named streams (`Zone.Identifier`
+//! of downloaded files, metadata of some applications, hidden data), read by opening `path:name`.
+
+use std::io;
+use std::path::Path;
+
+/// A named stream of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    /// name of the stream, without its `:$DATA` type
+    pub name: String,
+    pub size: u64,
+}
+
+/// path of a stream of `path`, which opens it
+pub fn stream_path(path: &str, name: &str) -> String { format!("{}:{}", path, name) }
+
+/// named streams of a file, its unnamed content being left out
+#[cfg(windows)]
+pub fn streams(path: &Path) -> io::Result<Vec<Stream>> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data = ffi::FindStreamData {
+        size: 0,
+        name: [0; ffi::STREAM_NAME_LENGTH],
+    };
+    let handle = unsafe { ffi::FindFirstStreamW(wide.as_ptr(), ffi::FIND_STREAM_INFO_STANDARD, &mut data, 0) };
+    if handle == ffi::INVALID_HANDLE_VALUE {
+        let e = io::Error::last_os_error();
+        // not even an unnamed stream, or a filesystem without streams
+        return match e.raw_os_error() {
+            Some(ffi::ERROR_HANDLE_EOF) | Some(ffi::ERROR_INVALID_PARAMETER) => Ok(Vec::new()),
+            _ => Err(e),
+        };
+    }
+    let mut streams = Vec::new();
+    loop {
+        let length = data.name.iter().position(|&c| c == 0).unwrap_or(data.name.len());
+        // `:name:$DATA`, `::$DATA` for the content of the file
+        let name = String::from_utf16_lossy(&data.name[..length]);
+        let name = name.strip_prefix(':').unwrap_or(&name);
+        let name = name.strip_suffix(":$DATA").unwrap_or(name);
+        if !name.is_empty() {
+            streams.push(Stream {
+                name: name.to_owned(),
+                size: data.size as u64,
+            });
+        }
+        if unsafe { ffi::FindNextStreamW(handle, &mut data) } == 0 {
+            let e = io::Error::last_os_error();
+            unsafe { ffi::FindClose(handle) };
+            return match e.raw_os_error() {
+                Some(ffi::ERROR_HANDLE_EOF) => Ok(streams),
+                _ => Err(e),
+            };
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn streams(_path: &Path) -> io::Result<Vec<Stream>> { Err(io::Error::new(io::ErrorKind::Unsupported, "alternate data streams only exist on windows")) }
+
+#[cfg(windows)]
+#[allow(non_snake_case)]
+mod ffi {
+    use std::ffi::c_void;
+
+    pub type Handle = *mut c_void;
+    pub const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+    pub const FIND_STREAM_INFO_STANDARD: i32 = 0;
+    pub const ERROR_HANDLE_EOF: i32 = 38;
+    pub const ERROR_INVALID_PARAMETER: i32 = 87;
+    /// MAX_PATH + 36
+    pub const STREAM_NAME_LENGTH: usize = 296;
+
+    /// WIN32_FIND_STREAM_DATA
+    #[repr(C)]
+    pub struct FindStreamData {
+        pub size: i64,
+        pub name: [u16; STREAM_NAME_LENGTH],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn FindFirstStreamW(file_name: *const u16, info_level: i32, data: *mut FindStreamData, flags: u32) -> Handle;
+        pub fn FindNextStreamW(find: Handle, data: *mut FindStreamData) -> i32;
+        pub fn FindClose(find: Handle) -> i32;
+    }
+}
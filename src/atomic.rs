@@ -0,0 +1,63 @@
+//! Files written through a `.tmp` sibling renamed over them once complete, so that a crash, an error or an
+//! interruption never leaves a truncated file in their place.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file being written, in place only once [`AtomicFile::commit`] is called, removed when dropped before.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: File,
+    done: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let tmp = tmp_path(&path);
+        let file = File::create(&tmp)?;
+        Ok(Self { path, tmp, file, done: false })
+    }
+
+    /// another handle on the temporary file, for writers which need to own theirs : they must be flushed before
+    /// the commit
+    pub fn handle(&self) -> io::Result<File> { self.file.try_clone() }
+
+    /// sync the content to the disk, then rename the temporary file over the destination
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.tmp, &self.path)?;
+        self.done = true;
+        Ok(())
+    }
+
+    /// leave the destination as it is and the content in the temporary file, returned
+    pub fn keep(mut self) -> PathBuf {
+        self.done = true;
+        self.tmp.clone()
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.file.write(buf) }
+
+    fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// temporary sibling written before `path` : `path` followed by `.tmp`
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
@@ -0,0 +1,413 @@
+//! Acoustic fingerprints for `generate --algo audio`, in the spirit of chromaprint : the first two minutes of a track,
+//! mixed down to mono at 5512 Hz, are cut into frames whose energy in 33 bands between 300 and 2000 Hz gives 32 bits,
+//! one per pair of neighbouring bands, set when their difference grows since the previous frame. Encodings of a same
+//! recording, FLAC or MP3 at any bit rate, keep most of these bits, the distance of two fingerprints being the share
+//! of differing bits once aligned.
+//!
+//! Audio files are decoded by ffmpeg, `BLAKEDIFF_FFMPEG` replacing the command, fed through a pipe : formats needing
+//! to seek, like M4A files with their index at the end, get no fingerprint. Without ffmpeg, only WAV files are read.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use crate::input::Digest;
+
+/// sample rate of the analysis
+const RATE: usize = 5512;
+/// only the beginning of the tracks is fingerprinted
+const SECONDS: usize = 120;
+/// samples of a frame, 0.74 s
+const FRAME: usize = 4096;
+/// samples between two frames, the frames overlapping enough for a track starting a bit later to keep its bits
+const HOP: usize = 512;
+const BANDS: usize = 33;
+const LOW: f32 = 300.0;
+const HIGH: f32 = 2000.0;
+/// WAV files read without ffmpeg are not buffered past this length
+const MAX_WAV: usize = 64 << 20;
+/// frames by which two fingerprints are shifted at most to align them, about 6 s
+const MAX_OFFSET: isize = 64;
+/// frames two aligned fingerprints share at least
+const MIN_OVERLAP: usize = 16;
+
+/// ffmpeg was not found, warned once
+static NO_FFMPEG: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Wav,
+    /// read by ffmpeg only
+    Other,
+}
+
+/// audio format of a content from its first 12 bytes
+fn format(data: &[u8]) -> Option<Format> {
+    match data {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Format::Wav),
+        [b'f', b'L', b'a', b'C', ..] | [b'I', b'D', b'3', ..] | [b'O', b'g', b'g', b'S', ..] | [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', _, ..] => Some(Format::Other),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'M', b'4', b'A' | b'B', b' ', ..] => Some(Format::Other),
+        // an MPEG audio frame header
+        [0xff, second, ..] if second & 0xe0 == 0xe0 && second & 0x06 != 0 => Some(Format::Other),
+        _ => None,
+    }
+}
+
+/// the track decoded by ffmpeg, as a WAV file
+type Decoded = JoinHandle<io::Result<Vec<u8>>>;
+
+enum State {
+    /// the first bytes, until the format is known
+    Start(Vec<u8>),
+    Ffmpeg {
+        child: Child,
+        stdin: Option<ChildStdin>,
+        output: Decoded,
+    },
+    Wav(Vec<u8>),
+    Skipped,
+}
+
+/// Acoustic fingerprint of a content fed like a digest, written as 8 hex digits per frame, every 0.09 s, or `-` for
+/// contents which are not audio files, or can't be decoded.
+pub struct Fingerprint {
+    state: State,
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self { Self::new() }
+}
+
+impl Fingerprint {
+    pub fn new() -> Self { Self { state: State::Start(Vec::new()) } }
+
+    /// decoder of a content of `format`, starting with `data`
+    fn start(format: Option<Format>, data: Vec<u8>) -> State {
+        let Some(format) = format else { return State::Skipped };
+        if !NO_FFMPEG.load(Ordering::Relaxed) {
+            match ffmpeg() {
+                Ok((child, stdin, output)) => {
+                    let mut state = State::Ffmpeg { child, stdin: Some(stdin), output };
+                    feed(&mut state, &data);
+                    return state;
+                }
+                Err(e) => {
+                    if !NO_FFMPEG.swap(true, Ordering::Relaxed) {
+                        log::warn!("ffmpeg : {}, only WAV files get an acoustic fingerprint", e);
+                    }
+                }
+            }
+        }
+        match format {
+            Format::Wav => State::Wav(data),
+            Format::Other => State::Skipped,
+        }
+    }
+}
+
+/// ffmpeg decoding its standard input into a WAV file on its standard output, read from another thread
+fn ffmpeg() -> io::Result<(Child, ChildStdin, Decoded)> {
+    let ffmpeg = std::env::var("BLAKEDIFF_FFMPEG").ok().filter(|ffmpeg| !ffmpeg.trim().is_empty()).unwrap_or_else(|| String::from("ffmpeg"));
+    let mut words = ffmpeg.split_whitespace();
+    let mut child = Command::new(words.next().unwrap_or("ffmpeg"))
+        .args(words)
+        .args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0", "-vn", "-t"])
+        .arg(SECONDS.to_string())
+        .args(["-f", "wav", "-c:a", "pcm_s16le", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let output = thread::spawn(move || {
+        let mut wav = Vec::new();
+        stdout.read_to_end(&mut wav).map(|_| wav)
+    });
+    Ok((child, stdin, output))
+}
+
+fn feed(state: &mut State, data: &[u8]) {
+    match state {
+        // ffmpeg stops reading after the first two minutes
+        State::Ffmpeg { stdin, .. } => *stdin = stdin.take().filter(|mut pipe| pipe.write_all(data).is_ok()),
+        State::Wav(wav) if wav.len() < MAX_WAV => wav.extend_from_slice(&data[..data.len().min(MAX_WAV - wav.len())]),
+        _ => {}
+    }
+}
+
+impl Digest for Fingerprint {
+    fn update(&mut self, data: &[u8]) {
+        if let State::Start(start) = &mut self.state {
+            start.extend_from_slice(data);
+            if start.len() >= 12 {
+                let start = std::mem::take(start);
+                self.state = Self::start(format(&start), start);
+            }
+            return;
+        }
+        feed(&mut self.state, data);
+    }
+
+    fn finalize_hex(self) -> String {
+        let wav = match self.state {
+            State::Start(start) => match Self::start(format(&start), start) {
+                State::Wav(wav) => Some(wav),
+                State::Ffmpeg { child, stdin, output } => decoded(child, stdin, output),
+                _ => None,
+            },
+            State::Ffmpeg { child, stdin, output } => decoded(child, stdin, output),
+            State::Wav(wav) => Some(wav),
+            State::Skipped => None,
+        };
+        let frames = wav.as_deref().and_then(samples).map(|(samples, rate)| fingerprint(&resample(&samples, rate))).unwrap_or_default();
+        match frames.is_empty() {
+            true => String::from("-"),
+            false => frames.iter().map(|frame| format!("{:08x}", frame)).collect(),
+        }
+    }
+}
+
+/// the WAV file written by ffmpeg, once it succeeded
+fn decoded(mut child: Child, stdin: Option<ChildStdin>, output: Decoded) -> Option<Vec<u8>> {
+    drop(stdin);
+    let wav = output.join().ok()?.ok()?;
+    child.wait().ok()?.success().then_some(wav)
+}
+
+/// mono samples of a WAV file, from -1 to 1, with their rate. Files streamed by ffmpeg have no length in their
+/// header, their data going to the end.
+fn samples(wav: &[u8]) -> Option<(Vec<f32>, usize)> {
+    let le16 = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as usize;
+    let le32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+    let (mut pos, mut fmt) = (12, None);
+    while pos + 8 <= wav.len() {
+        let (id, len) = (&wav[pos..pos + 4], le32(&wav[pos + 4..]));
+        let body = &wav[pos + 8..wav.len().min((pos + 8).saturating_add(len))];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                // the extensible format tells the actual one in its first 2 bytes of sub-format
+                let tag = if le16(body) == 0xfffe && body.len() >= 26 { le16(&body[24..]) } else { le16(body) };
+                fmt = Some((tag, le16(&body[2..]), le32(&body[4..]), le16(&body[14..])));
+            }
+            b"data" => {
+                let (tag, channels, rate, bits) = fmt?;
+                let width = bits / 8;
+                if channels == 0 || rate == 0 || width == 0 {
+                    return None;
+                }
+                let sample = |b: &[u8]| -> Option<f32> {
+                    Some(match (tag, bits) {
+                        (1, 8) => (b[0] as f32 - 128.0) / 128.0,
+                        (1, 16) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+                        (1, 24) => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+                        (1, 32) => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+                        (3, 32) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                        (3, 64) => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+                        _ => return None,
+                    })
+                };
+                let samples = body
+                    .chunks_exact(width * channels)
+                    .take(SECONDS * rate)
+                    .map(|frame| Some(frame.chunks_exact(width).map(sample).sum::<Option<f32>>()? / channels as f32))
+                    .collect::<Option<Vec<_>>>()?;
+                return Some((samples, rate));
+            }
+            _ => {}
+        }
+        pos = (pos + 8).saturating_add(len + len % 2);
+    }
+    None
+}
+
+/// `samples` at [`RATE`], each the mean of the samples around it
+fn resample(samples: &[f32], rate: usize) -> Vec<f32> {
+    let mut sums = Vec::with_capacity(samples.len() + 1);
+    sums.push(0f64);
+    samples.iter().for_each(|&sample| sums.push(sums[sums.len() - 1] + sample as f64));
+    let ratio = rate as f64 / RATE as f64;
+    (0..(samples.len() as f64 / ratio) as usize)
+        .map(|i| {
+            let start = ((i as f64 * ratio) as usize).min(samples.len() - 1);
+            let end = (((i + 1) as f64 * ratio) as usize).clamp(start + 1, samples.len());
+            ((sums[end] - sums[start]) / (end - start) as f64) as f32
+        })
+        .collect()
+}
+
+/// 32 bits per frame of `samples`, the first frame only serving as a reference
+fn fingerprint(samples: &[f32]) -> Vec<u32> {
+    let bin = |frequency: f32| (frequency * FRAME as f32 / RATE as f32) as usize;
+    let edges = (0..=BANDS).map(|k| bin(LOW * (HIGH / LOW).powf(k as f32 / BANDS as f32))).collect::<Vec<_>>();
+    let window = (0..FRAME).map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME as f32).cos()).collect::<Vec<_>>();
+    let mut previous: Option<Vec<f32>> = None;
+    let mut frames = Vec::new();
+    for start in (0..samples.len().saturating_sub(FRAME - 1)).step_by(HOP) {
+        let mut re = samples[start..start + FRAME].iter().zip(&window).map(|(sample, w)| sample * w).collect::<Vec<_>>();
+        let mut im = vec![0f32; FRAME];
+        fft(&mut re, &mut im);
+        let energies = edges.windows(2).map(|edge| (edge[0]..edge[1].max(edge[0] + 1)).map(|k| re[k] * re[k] + im[k] * im[k]).sum::<f32>()).collect::<Vec<_>>();
+        if let Some(previous) = &previous {
+            frames.push((0..BANDS - 1).fold(0u32, |bits, m| bits << 1 | ((energies[m] - energies[m + 1]) - (previous[m] - previous[m + 1]) > 0.0) as u32));
+        }
+        previous = Some(energies);
+    }
+    frames
+}
+
+/// in-place radix-2 FFT, the length being a power of 2
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let (t_re, t_im) = (re[b] * w_re - im[b] * w_im, re[b] * w_im + im[b] * w_re);
+                (re[b], im[b]) = (re[a] - t_re, im[a] - t_im);
+                (re[a], im[a]) = (re[a] + t_re, im[a] + t_im);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// frames of a fingerprint
+pub(crate) fn parse(fingerprint: &str) -> Option<Vec<u32>> {
+    if fingerprint.is_empty() || !fingerprint.len().is_multiple_of(8) || !fingerprint.is_ascii() {
+        return None;
+    }
+    (0..fingerprint.len()).step_by(8).map(|i| u32::from_str_radix(&fingerprint[i..i + 8], 16).ok()).collect()
+}
+
+/// percentage of bits differing between two fingerprints, at the offset aligning them best
+pub(crate) fn distance(first: &[u32], second: &[u32]) -> u32 {
+    (-MAX_OFFSET..=MAX_OFFSET)
+        .filter_map(|offset| {
+            let (a, b) = if offset < 0 { (first, second.get(offset.unsigned_abs()..)?) } else { (first.get(offset as usize..)?, second) };
+            let overlap = a.len().min(b.len());
+            (overlap >= MIN_OVERLAP.min(first.len().min(second.len()))).then(|| {
+                let errors = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum::<u32>();
+                errors * 100 / (overlap as u32 * 32)
+            })
+        })
+        .min()
+        .unwrap_or(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::Similarity;
+
+    /// 16 bits mono WAV file of `samples`, from -1 to 1
+    fn wav(samples: &[f32], rate: u32) -> Vec<u8> {
+        let data = samples.iter().flat_map(|sample| ((sample * 32767.0) as i16).to_le_bytes()).collect::<Vec<_>>();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, 1 channel, rate, byte rate, block align, bits
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    /// `seconds` of notes between 300 and 2000 Hz changing every quarter of a second, the same for a given seed
+    fn melody(seconds: usize, rate: usize, seed: u64) -> Vec<f32> {
+        let mut x = seed * 2 + 1;
+        let mut frequency = 0.0;
+        (0..seconds * rate)
+            .map(|i| {
+                if i % (rate / 4) == 0 {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    frequency = 300.0 + (x % 1700) as f32;
+                }
+                0.8 * (2.0 * std::f32::consts::PI * frequency * i as f32 / rate as f32).sin()
+            })
+            .collect()
+    }
+
+    /// fingerprint of a WAV file
+    fn frames(wav: &[u8]) -> Vec<u32> {
+        let (samples, rate) = samples(wav).unwrap();
+        fingerprint(&resample(&samples, rate))
+    }
+
+    #[test]
+    fn fft_of_a_pure_tone() {
+        let n = 64;
+        let mut re = (0..n).map(|i| (2.0 * std::f32::consts::PI * 5.0 * i as f32 / n as f32).cos()).collect::<Vec<_>>();
+        let mut im = vec![0f32; n];
+        fft(&mut re, &mut im);
+        for k in 0..n {
+            // all the energy in the bin of the tone and in its mirror
+            let expected = if k == 5 || k == n - 5 { n as f32 / 2.0 } else { 0.0 };
+            assert!((re[k] - expected).abs() < 1e-3 && im[k].abs() < 1e-3, "bin {} : {} {}", k, re[k], im[k]);
+        }
+        let mut re = (0..n).map(|i| (2.0 * std::f32::consts::PI * 3.0 * i as f32 / n as f32).sin()).collect::<Vec<_>>();
+        let mut im = vec![0f32; n];
+        fft(&mut re, &mut im);
+        assert!((im[3] + n as f32 / 2.0).abs() < 1e-3 && (im[n - 3] - n as f32 / 2.0).abs() < 1e-3, "{} {}", im[3], im[n - 3]);
+    }
+
+    #[test]
+    fn resampling_keeps_the_duration() {
+        for rate in [RATE, 8000, 11025, 44100, 48000] {
+            let resampled = resample(&vec![0.5; rate * 3], rate);
+            assert!(resampled.len().abs_diff(RATE * 3) <= 1, "{} Hz : {} samples", rate, resampled.len());
+            assert!(resampled.iter().all(|&sample| (sample - 0.5).abs() < 1e-6));
+        }
+        // each sample is the mean of those it replaces
+        let alternating = (0..RATE * 4).map(|i| (i % 2) as f32).collect::<Vec<_>>();
+        let resampled = resample(&alternating, RATE * 2);
+        assert_eq!(resampled.len(), RATE * 2);
+        assert!(resampled.iter().all(|&sample| (sample - 0.5).abs() < 1e-6));
+        assert_eq!(resample(&alternating, RATE), alternating);
+    }
+
+    #[test]
+    fn fingerprints_of_a_same_recording_are_close() {
+        let (rate, threshold) = (22050, Similarity::Audio.threshold());
+        let original = melody(20, rate, 1);
+        let first = frames(&wav(&original, rate as u32));
+        assert!(!first.is_empty());
+        assert_eq!(distance(&first, &first), 0);
+        // quieter, cut at both ends, or sampled at another rate
+        let quieter = original.iter().map(|sample| sample * 0.3).collect::<Vec<_>>();
+        let copies = [wav(&quieter, rate as u32), wav(&original[rate..rate * 15], rate as u32), wav(&melody(20, 44100, 1), 44100)];
+        for copy in copies {
+            let copy = frames(&copy);
+            assert!(distance(&first, &copy) <= threshold, "{}", distance(&first, &copy));
+        }
+        let other = frames(&wav(&melody(20, rate, 2), rate as u32));
+        assert!(distance(&first, &other) > threshold, "{}", distance(&first, &other));
+    }
+}
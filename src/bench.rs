@@ -0,0 +1,80 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use blakediff::input::Input;
+use blakediff::tempdir::TempDir;
+use blakediff::{BlakediffError, Result};
+
+/// hash `files` files of random data, `size` bytes in total, with both mmap and buffered reads
+/// and an increasing number of threads, displaying the throughput of each combination
+pub fn bench(size: u64, files: u64) -> Result<()> {
+    let files = files.max(1);
+    let dir = TempDir::new("bench")?;
+    println!("generating {} files, {} bytes in {}", files, size, dir.path().display());
+    let paths = generate_files(dir.path(), size, files)?;
+
+    let max_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = std::iter::successors(Some(1), |n| Some(n * 2)).take_while(|&n| n < max_threads).chain([max_threads]).collect::<Vec<_>>();
+
+    // a first pass loads the files in the page cache, so that every run reads from memory
+    hash_all(&paths, Input::open)?;
+    for (mode, open) in [("mmap", Input::open as fn(&Path) -> io::Result<Input>), ("buffered", Input::open_buffered)] {
+        for &n in &threads {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(n).build().map_err(|e| BlakediffError::Other(e.to_string()))?;
+            let start = Instant::now();
+            pool.install(|| hash_all(&paths, open))?;
+            let elapsed = start.elapsed().as_secs_f64();
+            println!("{:<8} {:>3} threads : {:>10.2} MiB/s ({:.3} s)", mode, n, size as f64 / elapsed / (1024.0 * 1024.0), elapsed);
+        }
+    }
+    Ok(())
+}
+
+fn hash_all(paths: &[PathBuf], open: fn(&Path) -> io::Result<Input>) -> io::Result<()> { paths.par_iter().try_for_each(|path| open(path)?.hash().map(|_| ())) }
+
+/// write `files` files of pseudo-random content in `dir`, `size` bytes in total
+fn generate_files(dir: &Path, size: u64, files: u64) -> io::Result<Vec<PathBuf>> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut buffer = vec![0u8; 65536];
+    (0..files)
+        .map(|i| {
+            let path = dir.join(format!("{:08}.bin", i));
+            // the remainder goes to the first files
+            let mut remaining = size / files + u64::from(i < size % files);
+            let mut file = io::BufWriter::new(fs::File::create(&path)?);
+            while remaining > 0 {
+                // xorshift64, random enough to defeat any compression or deduplication below us
+                for chunk in buffer.chunks_exact_mut(8) {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    chunk.copy_from_slice(&state.to_le_bytes());
+                }
+                let n = remaining.min(buffer.len() as u64) as usize;
+                file.write_all(&buffer[..n])?;
+                remaining -= n as u64;
+            }
+            file.flush()?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// parse a size like `1G`, `512M`, `64K` or `1000`, with binary multiples
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let multiple: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit '{}'", unit)),
+    };
+    number.parse::<u64>().map_err(|e| format!("invalid size '{}' : {}", s, e))?.checked_mul(multiple).ok_or_else(|| format!("size too large '{}'", s))
+}
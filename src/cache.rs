@@ -0,0 +1,252 @@
+//! Persistent hash cache : the BLAKE3 and the other digests of the files already hashed, keyed by their device,
+//! inode, size and modification time, so that a file left untouched since the last run isn't read again. A file
+//! rewritten within the same modification time, on a filesystem with a coarse clock, keeps its stale hash. The
+//! files deleted or modified since they were cached are pruned when the cache is saved.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::input::Algorithm;
+use crate::report::{self, Entry, Field};
+use crate::walker::FileEntry;
+use crate::{hash_file_digests, Result};
+
+/// name of the cache file in the cache directory
+const FILE_NAME: &str = "hashes.txt";
+
+/// cache file of the previous versions, holding BLAKE3 hashes without paths, read when there is no other one
+const LEGACY_FILE_NAME: &str = "blake3.txt";
+
+/// device, inode, size and modification time of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: Duration,
+}
+
+impl Key {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Key {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.len(),
+            mtime: report::mtime(metadata),
+        })
+    }
+
+    // Without inode numbers, nothing is cached.
+    #[cfg(not(unix))]
+    fn of(_metadata: &fs::Metadata) -> Option<Self> { None }
+
+    /// `<dev> <ino> <size> <mtime> <hash> <digests> <path>`, the digests being `-` or `<algorithm>=<digest>`
+    /// separated by `;`. The lines of the legacy cache end after the hash.
+    fn parse(line: &str) -> Option<(Self, Cached)> {
+        let mut fields = line.splitn(7, ' ');
+        let mut number = || fields.next()?.parse().ok();
+        let (dev, ino, size) = (number()?, number()?, number()?);
+        let mtime = report::parse_mtime(fields.next()?)?;
+        let hash = fields.next().filter(|hash| hash.len() == 64)?.to_owned();
+        let key = Key { dev, ino, size, mtime };
+        let Some(digests) = fields.next() else { return Some((key, Cached { hash, digests: Vec::new(), path: None, seen: false })) };
+        let digests = digests
+            .split(';')
+            .filter(|&digest| digest != "-")
+            .map(|digest| match digest.split_once('=')?.0.parse() {
+                Ok(Field::Digest(algorithm)) => Some((algorithm, digest.split_once('=')?.1.to_owned())),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let path = PathBuf::from(fields.next().filter(|path| !path.is_empty())?);
+        Some((key, Cached { hash, digests, path: Some(path), seen: false }))
+    }
+
+    fn line(&self, cached: &Cached, path: &Path) -> String {
+        let digests = match cached.digests.is_empty() {
+            true => String::from("-"),
+            false => cached.digests.iter().map(|(algorithm, digest)| format!("{}={}", algorithm.name(), digest)).collect::<Vec<_>>().join(";"),
+        };
+        format!("{} {} {} {} {} {} {}", self.dev, self.ino, self.size, report::format_mtime(self.mtime), cached.hash, digests, path.display())
+    }
+}
+
+/// What the cache holds for a file.
+struct Cached {
+    hash: String,
+    digests: Vec<(Algorithm, String)>,
+    /// unknown for the entries of the legacy cache
+    path: Option<PathBuf>,
+    /// met while walking, so still there
+    seen: bool,
+}
+
+/// Hash cache stored in a directory, shared by the hashing threads.
+pub struct Cache {
+    file: PathBuf,
+    hashes: Mutex<HashMap<Key, Cached>>,
+    /// hashes were added since the cache was opened
+    dirty: AtomicBool,
+}
+
+impl Cache {
+    /// open the cache stored in `dir`, creating the directory. Unreadable lines are ignored.
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let file = dir.join(FILE_NAME);
+        let read = |file: &Path| -> io::Result<Option<HashMap<Key, Cached>>> {
+            match File::open(file) {
+                Ok(f) => Ok(Some(BufReader::new(f).lines().map_while(io::Result::ok).filter_map(|line| Key::parse(&line)).collect())),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        };
+        let hashes = match read(&file)? {
+            Some(hashes) => hashes,
+            None => read(&dir.join(LEGACY_FILE_NAME))?.unwrap_or_default(),
+        };
+        log::debug!("{} hashes in the cache {}", hashes.len(), file.display());
+        Ok(Self {
+            file,
+            hashes: Mutex::new(hashes),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// same as [`hash_file_digests`], taking the hash and the `digests` from the cache when the file didn't change
+    /// and they are all there, and adding them otherwise
+    pub fn hash_file(&self, file: &FileEntry, with_size: bool, with_mtime: bool, digests: &[Algorithm]) -> io::Result<Entry> {
+        // only regular files are cached : the target of symbolic links is read again, special files have no content
+        let Some(key) = Key::of(&file.metadata).filter(|_| file.metadata.is_file()) else { return hash_file_digests(file, with_size, with_mtime, digests) };
+        let hit = self.hashes.lock().unwrap().get_mut(&key).and_then(|cached| {
+            cached.seen = true;
+            if cached.path.as_deref() != Some(&file.path) {
+                // renamed, or from the legacy cache
+                cached.path = Some(file.path.clone());
+                self.dirty.store(true, Ordering::Relaxed);
+            }
+            let found = digests.iter().map(|&algorithm| cached.digests.iter().find(|(cached, _)| *cached == algorithm).cloned()).collect::<Option<Vec<_>>>()?;
+            Some((cached.hash.clone(), found))
+        });
+        if let Some((hash, digests)) = hit {
+            return Ok(Entry {
+                hash,
+                size: with_size.then_some(key.size),
+                mtime: with_mtime.then_some(key.mtime),
+                xattrs: None,
+                target: None,
+                digests,
+                path: file.path.to_string_lossy().into_owned(),
+            });
+        }
+        let entry = hash_file_digests(file, with_size, with_mtime, digests)?;
+        let mut hashes = self.hashes.lock().unwrap();
+        // the digests of other algorithms cached before are still those of the file
+        let mut digests = entry.digests.clone();
+        if let Some(cached) = hashes.get(&key) {
+            digests.extend(cached.digests.iter().filter(|(algorithm, _)| entry.digest(*algorithm).is_none()).cloned());
+        }
+        let cached = Cached {
+            hash: entry.hash.clone(),
+            digests,
+            path: Some(file.path.clone()),
+            seen: true,
+        };
+        hashes.insert(key, cached);
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(entry)
+    }
+
+    /// write the cache back when it changed, through a temporary file renamed over the previous one. The files
+    /// not met by this run are looked up, and left out when they were deleted or modified since they were cached,
+    /// or when their path is unknown.
+    pub fn save(&self) -> Result<()> {
+        let mut hashes = self.hashes.lock().unwrap();
+        let before = hashes.len();
+        hashes.retain(|key, cached| cached.seen || cached.path.as_ref().is_some_and(|path| fs::metadata(path).ok().and_then(|metadata| Key::of(&metadata)) == Some(*key)));
+        if before != hashes.len() {
+            log::info!("{} files deleted or modified pruned from the cache {}", before - hashes.len(), self.file.display());
+        } else if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let tmp = self.file.with_extension(format!("{}.tmp", std::process::id()));
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for (key, cached) in hashes.iter() {
+            if let Some(path) = &cached.path {
+                writeln!(out, "{}", key.line(cached, path))?;
+            }
+        }
+        out.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&tmp, &self.file)?;
+        // its hashes moved to the new cache
+        let _ = fs::remove_file(self.file.with_file_name(LEGACY_FILE_NAME));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::tempdir::TempDir;
+    use crate::walker::Walker;
+
+    fn file_entry(path: &Path) -> FileEntry { Walker::new(path).iter().next().unwrap().unwrap() }
+
+    #[test]
+    fn lines_roundtrip() {
+        let key = Key { dev: 64769, ino: 1234, size: 42, mtime: Duration::new(1700000000, 5) };
+        let cached = Cached {
+            hash: "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262".into(),
+            digests: vec![(Algorithm::Sha256, "e3b0".into()), (Algorithm::Chunks, "ab,cd".into())],
+            path: None,
+            seen: false,
+        };
+        let line = key.line(&cached, Path::new("my music/a b.mp3"));
+        assert_eq!(line, "64769 1234 42 1700000000.000000005 af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262 sha256=e3b0;chunks=ab,cd my music/a b.mp3");
+        let (parsed, back) = Key::parse(&line).unwrap();
+        assert_eq!((parsed, back.hash, back.digests, back.path), (key, cached.hash.clone(), cached.digests, Some(PathBuf::from("my music/a b.mp3"))));
+        // legacy lines have neither digests nor path
+        let (_, legacy) = Key::parse(&format!("64769 1234 42 1700000000.000000005 {}", cached.hash)).unwrap();
+        assert_eq!((legacy.digests.len(), legacy.path), (0, None));
+        assert!(Key::parse("64769 1234 42 1700000000.000000005 short - path").is_none());
+        assert!(Key::parse(&format!("64769 1234 42 1700000000.000000005 {} unknown=1 path", cached.hash)).is_none());
+    }
+
+    #[test]
+    fn digests_are_cached_and_deleted_files_pruned() {
+        let tmp = TempDir::new("cache").unwrap();
+        let dir = tmp.path();
+        let (files, store) = (dir.join("files"), dir.join("cache"));
+        fs::create_dir_all(&files).unwrap();
+        let (kept, deleted) = (files.join("kept"), files.join("deleted"));
+        fs::write(&kept, "kept").unwrap();
+        fs::write(&deleted, "deleted").unwrap();
+
+        let cache = Cache::open(&store).unwrap();
+        let first = cache.hash_file(&file_entry(&kept), false, false, &[Algorithm::Sha256]).unwrap();
+        cache.hash_file(&file_entry(&deleted), false, false, &[]).unwrap();
+        cache.save().unwrap();
+
+        fs::remove_file(&deleted).unwrap();
+        let cache = Cache::open(&store).unwrap();
+        assert_eq!(cache.hashes.lock().unwrap().len(), 2);
+        let again = cache.hash_file(&file_entry(&kept), false, false, &[Algorithm::Sha256]).unwrap();
+        assert_eq!(again, first);
+        // taken from the cache, nothing was added
+        assert!(!cache.dirty.load(Ordering::Relaxed));
+        let more = cache.hash_file(&file_entry(&kept), false, false, &[Algorithm::Sha256, Algorithm::Xxh3]).unwrap();
+        assert_eq!((more.digests.len(), cache.hashes.lock().unwrap().values().map(|cached| cached.digests.len()).max()), (2, Some(2)));
+        assert_eq!(again.digest(Algorithm::Sha256).map(str::len), Some(64));
+        cache.save().unwrap();
+        let saved = fs::read_to_string(store.join(FILE_NAME)).unwrap();
+        assert_eq!(saved.lines().count(), 1);
+        assert!(saved.trim_end().ends_with("/kept"), "{}", saved);
+    }
+}
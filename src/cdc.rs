@@ -0,0 +1,154 @@
+//! Content-defined chunking (FastCDC) : files are cut where a rolling hash of their last bytes matches a mask, so
+//! that an insertion or an appended tail only changes the chunks around it. Two files sharing most of their chunks
+//! are partial duplicates, like a log and its rotated copy, or two versions of an archive.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::input::{Algorithm, Digest};
+use crate::report::Entry;
+
+/// no cut before this length
+const MIN: usize = 16 << 10;
+/// length around which chunks are cut
+const AVG: usize = 64 << 10;
+/// cut at this length at least
+const MAX: usize = 256 << 10;
+/// before the average length, a cut needs more bits of the hash to be zero, after it fewer : the lengths gather
+/// around the average
+const MASK_SMALL: u64 = !0 << (64 - 18);
+const MASK_LARGE: u64 = !0 << (64 - 14);
+
+/// chunks shared by more files are left out of the comparison, like the blocks of zeroes of sparse files
+const MAX_SHARING: usize = 1000;
+
+/// random values of the gear hash, one per byte value, from a fixed seed
+const GEAR: [u64; 256] = {
+    let mut gear = [0u64; 256];
+    let mut state = 0x6a09_e667_f3bc_c908u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        gear[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    gear
+};
+
+/// Chunks of a content fed like a digest, written as the comma separated first 16 hex digits of the BLAKE3 of each
+/// chunk, or `-` when empty.
+pub struct Chunker {
+    hasher: blake3::Hasher,
+    /// gear hash of the bytes of the current chunk
+    fingerprint: u64,
+    len: usize,
+    chunks: Vec<String>,
+}
+
+impl Default for Chunker {
+    fn default() -> Self { Self::new() }
+}
+
+impl Chunker {
+    pub fn new() -> Self {
+        Self {
+            hasher: blake3::Hasher::new(),
+            fingerprint: 0,
+            len: 0,
+            chunks: Vec::new(),
+        }
+    }
+
+    fn cut(&mut self) {
+        let hash = self.hasher.finalize();
+        self.chunks.push(hash.to_hex()[..16].to_owned());
+        self.hasher.reset();
+        (self.fingerprint, self.len) = (0, 0);
+    }
+}
+
+impl Digest for Chunker {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // the first bytes of a chunk are never a cut point, they are not even hashed
+            let skip = MIN.saturating_sub(self.len).min(data.len());
+            let mut end = skip;
+            let mut cut = false;
+            while end < data.len() {
+                self.fingerprint = (self.fingerprint << 1).wrapping_add(GEAR[data[end] as usize]);
+                end += 1;
+                let len = self.len + end;
+                let mask = if len < AVG { MASK_SMALL } else { MASK_LARGE };
+                if self.fingerprint & mask == 0 || len >= MAX {
+                    cut = true;
+                    break;
+                }
+            }
+            self.hasher.update(&data[..end]);
+            self.len += end;
+            data = &data[end..];
+            if cut {
+                self.cut();
+            }
+        }
+    }
+
+    fn finalize_hex(mut self) -> String {
+        if self.len > 0 {
+            self.cut();
+        }
+        match self.chunks.is_empty() {
+            true => String::from("-"),
+            false => self.chunks.join(","),
+        }
+    }
+}
+
+/// Two files sharing chunks without being identical.
+#[derive(Debug, Clone)]
+pub struct Partial<'a> {
+    pub first: &'a Entry,
+    pub second: &'a Entry,
+    /// chunks of the smaller file also in the other one, from 0 to 1
+    pub shared: f64,
+}
+
+/// pairs of `entries` whose chunks are at least `min_shared` in the other file, the most similar first. Identical
+/// files are left to the duplicates.
+pub fn partial_duplicates<'a>(entries: impl IntoIterator<Item = &'a Entry>, min_shared: f64) -> Vec<Partial<'a>> {
+    let files = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let chunks = entry.digest(Algorithm::Chunks)?.split(',').filter_map(|chunk| u64::from_str_radix(chunk, 16).ok()).collect::<HashSet<_>>();
+            (!chunks.is_empty()).then_some((entry, chunks))
+        })
+        .collect::<Vec<_>>();
+    let mut sharing: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, (_, chunks)) in files.iter().enumerate() {
+        chunks.iter().for_each(|&chunk| sharing.entry(chunk).or_default().push(i));
+    }
+    let mut common: HashMap<(usize, usize), usize> = HashMap::new();
+    for owners in sharing.values().filter(|owners| owners.len() > 1 && owners.len() <= MAX_SHARING) {
+        for (n, &i) in owners.iter().enumerate() {
+            owners[n + 1..].iter().for_each(|&j| *common.entry((i, j)).or_default() += 1);
+        }
+    }
+    let mut partial = common
+        .into_iter()
+        .filter(|&((i, j), _)| files[i].0.hash != files[j].0.hash)
+        .map(|((i, j), count)| {
+            let (first, second) = if files[i].0.path <= files[j].0.path { (files[i].0, files[j].0) } else { (files[j].0, files[i].0) };
+            Partial {
+                first,
+                second,
+                shared: count as f64 / files[i].1.len().min(files[j].1.len()) as f64,
+            }
+        })
+        .filter(|partial| partial.shared >= min_shared)
+        .collect::<Vec<_>>();
+    partial.sort_by(|a, b| b.shared.total_cmp(&a.shared).then_with(|| (&a.first.path, &a.second.path).cmp(&(&b.first.path, &b.second.path))));
+    partial
+}
@@ -0,0 +1,215 @@
+//! Files sharing their blocks on copy-on-write filesystems : reflinks on btrfs and xfs, found with their extents,
+//! clones on APFS, found with their clone id, and block clones on ReFS, found with their clusters. Such duplicates
+//! don't take more space than a single copy.
+
+use std::io;
+use std::path::Path;
+
+/// true when `a` and `b` share all their data blocks, false when they don't or it can't be told
+pub fn shared(a: &Path, b: &Path) -> bool {
+    match blocks_shared(a, b) {
+        Ok(shared) => shared,
+        Err(e) => {
+            log::debug!("sharing of {} and {} unknown : {}", a.display(), b.display(), e);
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn blocks_shared(a: &Path, b: &Path) -> io::Result<bool> {
+    let (a, b) = (fiemap::extents(a)?, fiemap::extents(b)?);
+    let comparable = |extents: &[fiemap::Extent]| !extents.is_empty() && extents.iter().all(|e| e.flags & fiemap::EXTENT_SHARED != 0 && e.flags & fiemap::EXTENT_NO_ADDRESS == 0);
+    Ok(comparable(&a) && comparable(&b) && a.iter().map(fiemap::Extent::span).eq(b.iter().map(fiemap::Extent::span)))
+}
+
+#[cfg(target_os = "macos")]
+fn blocks_shared(a: &Path, b: &Path) -> io::Result<bool> {
+    let ((a_id, a_flags), (b_id, b_flags)) = (apfs::clone(a)?, apfs::clone(b)?);
+    Ok(a_id == b_id && a_flags & apfs::EF_MAY_SHARE_BLOCKS != 0 && b_flags & apfs::EF_MAY_SHARE_BLOCKS != 0)
+}
+
+#[cfg(windows)]
+fn blocks_shared(a: &Path, b: &Path) -> io::Result<bool> {
+    let (a, b) = (refs::clusters(a)?, refs::clusters(b)?);
+    // unallocated ranges have no cluster
+    let comparable = |clusters: &[(i64, i64)]| !clusters.is_empty() && clusters.iter().all(|&(_, lcn)| lcn != -1);
+    Ok(comparable(&a) && a == b)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn blocks_shared(_a: &Path, _b: &Path) -> io::Result<bool> { Err(io::Error::new(io::ErrorKind::Unsupported, "only detected on linux, macos and windows")) }
+
+/// extents of a file through the FIEMAP ioctl
+#[cfg(target_os = "linux")]
+mod fiemap {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xC020_660B;
+    const FLAG_SYNC: u32 = 0x1;
+    const EXTENT_LAST: u32 = 0x1;
+    /// location unknown, delayed allocation, encoded or inline data : the physical address means nothing
+    pub const EXTENT_NO_ADDRESS: u32 = 0x2 | 0x4 | 0x8 | 0x200;
+    pub const EXTENT_SHARED: u32 = 0x2000;
+    /// extents asked at each call
+    const BATCH: usize = 128;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct Extent {
+        logical: u64,
+        physical: u64,
+        length: u64,
+        reserved64: [u64; 2],
+        pub flags: u32,
+        reserved: [u32; 3],
+    }
+
+    impl Extent {
+        /// logical and physical ranges of the extent
+        pub fn span(&self) -> (u64, u64, u64) { (self.logical, self.physical, self.length) }
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        start: u64,
+        length: u64,
+        flags: u32,
+        mapped_extents: u32,
+        extent_count: u32,
+        reserved: u32,
+        extents: [Extent; BATCH],
+    }
+
+    pub fn extents(path: &Path) -> io::Result<Vec<Extent>> {
+        let file = File::open(path)?;
+        let mut extents = Vec::new();
+        let mut start = 0;
+        loop {
+            let mut map = Fiemap {
+                start,
+                length: u64::MAX - start,
+                flags: FLAG_SYNC,
+                mapped_extents: 0,
+                extent_count: BATCH as u32,
+                reserved: 0,
+                extents: [Extent::default(); BATCH],
+            };
+            if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP, &mut map) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mapped = &map.extents[..map.mapped_extents as usize];
+            extents.extend_from_slice(mapped);
+            match mapped.last() {
+                Some(last) if last.flags & EXTENT_LAST == 0 => start = last.logical + last.length,
+                _ => return Ok(extents),
+            }
+        }
+    }
+}
+
+/// clone id and extended flags of a file through getattrlist
+#[cfg(target_os = "macos")]
+mod apfs {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    pub const EF_MAY_SHARE_BLOCKS: u64 = 0x1;
+
+    pub fn clone(path: &Path) -> io::Result<(u64, u64)> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut attributes: libc::attrlist = unsafe { std::mem::zeroed() };
+        attributes.bitmapcount = libc::ATTR_BIT_MAP_COUNT;
+        // with FSOPT_ATTR_CMN_EXTENDED, the fork attributes are the extended common ones
+        attributes.forkattr = libc::ATTR_CMNEXT_CLONEID | libc::ATTR_CMNEXT_EXT_FLAGS;
+        // length, then the attributes in the order of their bits
+        let mut buffer = [0u8; 4 + 8 + 8];
+        let options = libc::FSOPT_ATTR_CMN_EXTENDED | libc::FSOPT_NOFOLLOW;
+        if unsafe { libc::getattrlist(path.as_ptr(), &mut attributes as *mut _ as *mut libc::c_void, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), options) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let u64_at = |offset: usize| u64::from_ne_bytes(buffer[offset..offset + 8].try_into().unwrap());
+        Ok((u64_at(4), u64_at(12)))
+    }
+}
+
+/// clusters of a file on a ReFS volume through FSCTL_GET_RETRIEVAL_POINTERS : block clones map their ranges to the
+/// same clusters, while files of other volumes never share any
+#[cfg(windows)]
+#[allow(non_snake_case)]
+mod refs {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+
+    const FSCTL_GET_RETRIEVAL_POINTERS: u32 = 0x0009_0073;
+    const ERROR_MORE_DATA: i32 = 234;
+
+    /// RETRIEVAL_POINTERS_BUFFER, with room for 512 extents per call
+    #[repr(C)]
+    struct RetrievalPointers {
+        extent_count: u32,
+        starting_vcn: i64,
+        /// next virtual cluster and logical cluster of each extent
+        extents: [[i64; 2]; 512],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetVolumeInformationByHandleW(file: *mut c_void, volume_name: *mut u16, volume_name_size: u32, serial: *mut u32, max_component_length: *mut u32, flags: *mut u32, file_system_name: *mut u16, file_system_name_size: u32) -> i32;
+        fn DeviceIoControl(device: *mut c_void, code: u32, input: *const c_void, input_size: u32, output: *mut c_void, output_size: u32, returned: *mut u32, overlapped: *mut c_void) -> i32;
+    }
+
+    /// next virtual cluster and logical cluster of the extents of `path`, which must be on a ReFS volume
+    pub fn clusters(path: &Path) -> io::Result<Vec<(i64, i64)>> {
+        let file = File::open(path)?;
+        let handle = file.as_raw_handle();
+        let mut file_system = [0u16; 32];
+        let null = std::ptr::null_mut();
+        if unsafe { GetVolumeInformationByHandleW(handle, null as *mut u16, 0, null as *mut u32, null as *mut u32, null as *mut u32, file_system.as_mut_ptr(), file_system.len() as u32) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let length = file_system.iter().position(|&c| c == 0).unwrap_or(file_system.len());
+        let file_system = String::from_utf16_lossy(&file_system[..length]);
+        if file_system != "ReFS" {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("no block clones on {}", file_system)));
+        }
+        let mut clusters = Vec::new();
+        let mut starting_vcn = 0i64;
+        loop {
+            let mut output: Box<RetrievalPointers> = Box::new(unsafe { std::mem::zeroed() });
+            let mut returned = 0u32;
+            let done = unsafe {
+                DeviceIoControl(
+                    handle,
+                    FSCTL_GET_RETRIEVAL_POINTERS,
+                    &starting_vcn as *const i64 as *const c_void,
+                    std::mem::size_of::<i64>() as u32,
+                    &mut *output as *mut RetrievalPointers as *mut c_void,
+                    std::mem::size_of::<RetrievalPointers>() as u32,
+                    &mut returned,
+                    null,
+                )
+            } != 0;
+            if !done {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() != Some(ERROR_MORE_DATA) {
+                    return Err(e);
+                }
+            }
+            let count = (output.extent_count as usize).min(output.extents.len());
+            clusters.extend(output.extents[..count].iter().map(|&[next_vcn, lcn]| (next_vcn, lcn)));
+            match clusters.last() {
+                Some(&(next_vcn, _)) if !done && count > 0 => starting_vcn = next_vcn,
+                _ => return Ok(clusters),
+            }
+        }
+    }
+}
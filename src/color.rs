@@ -0,0 +1,73 @@
+//! Colors of the text output, one per kind of result, enabled once by [`init`] for the whole process.
+
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// when to color the output
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum When {
+    /// when the standard output is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// kind of a result, with its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// yellow
+    Duplicate,
+    /// red, for the files of the first report, or removed
+    OnlyInFirst,
+    /// green, for the files of the second report, or added
+    OnlyInSecond,
+    /// cyan
+    Moved,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Duplicate => "33",
+            Style::OnlyInFirst => "31",
+            Style::OnlyInSecond => "32",
+            Style::Moved => "36",
+        }
+    }
+}
+
+/// enable the colors or not, from `--color` and the NO_COLOR convention
+pub fn init(when: When) {
+    let enabled = match when {
+        When::Always => true,
+        When::Never => false,
+        When::Auto => std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && io::stdout().is_terminal(),
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool { ENABLED.load(Ordering::Relaxed) }
+
+/// `value` displayed in the color of `style` when colors are enabled
+pub fn paint<T: fmt::Display>(style: Style, value: T) -> Painted<T> { Painted { style, value } }
+
+/// A value displayed in a color.
+pub struct Painted<T> {
+    style: Style,
+    value: T,
+}
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match enabled() {
+            true => write!(f, "\x1b[{}m{}\x1b[0m", self.style.code(), self.value),
+            false => self.value.fmt(f),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Results as typed columns, for the columnar formats : rows are gathered into Arrow record batches of
+//! [`BATCH_ROWS`] rows, then written as Parquet row groups or as the messages of an Arrow IPC stream.
+
+use std::time::Duration;
+
+use crate::input::Algorithm;
+use crate::report::Entry;
+
+/// type of the values of a column, all of them being nullable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Unsigned,
+    Text,
+    /// instant since the unix epoch, in UTC
+    Timestamp,
+}
+
+/// value of a row in a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    Null,
+    Unsigned(u64),
+    Text(&'a str),
+    Timestamp(Duration),
+}
+
+/// A row of a columnar output.
+pub trait Columns {
+    /// name and kind of each column
+    const COLUMNS: &'static [(&'static str, Kind)];
+
+    /// values of the row, in the order of [`Columns::COLUMNS`]
+    fn values(&self) -> Vec<Value<'_>>;
+}
+
+/// a report line, unrecorded fields being null
+impl Columns for Entry {
+    const COLUMNS: &'static [(&'static str, Kind)] = &[("hash", Kind::Text), ("size", Kind::Unsigned), ("mtime", Kind::Timestamp), ("xattrs", Kind::Text), ("path", Kind::Text), ("target", Kind::Text), ("sha256", Kind::Text), ("xxh3", Kind::Text), ("phash", Kind::Text), ("audio", Kind::Text), ("chunks", Kind::Text), ("tlsh", Kind::Text), ("ssdeep", Kind::Text)];
+
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![
+            Value::Text(&self.hash),
+            self.size.map_or(Value::Null, Value::Unsigned),
+            self.mtime.map_or(Value::Null, Value::Timestamp),
+            self.xattrs.as_deref().map_or(Value::Null, Value::Text),
+            Value::Text(&self.path),
+            self.target.as_deref().map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Sha256).map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Xxh3).map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Phash).map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Audio).map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Chunks).map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Tlsh).map_or(Value::Null, Value::Text),
+            self.digest(Algorithm::Ssdeep).map_or(Value::Null, Value::Text),
+        ]
+    }
+}
+
+/// rows of a record batch, and of a Parquet row group
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+/// encoding of the record batches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// a Parquet file, readable once complete
+    Parquet,
+    /// an Arrow IPC stream, each batch being readable as soon as it is written
+    Arrow,
+}
+
+impl Encoding {
+    /// name of the format, and of the feature needed to write it
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Parquet => "parquet",
+            Encoding::Arrow => "arrow",
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+pub use self::arrow::{BatchWriter, Batches};
+
+#[cfg(feature = "arrow")]
+mod arrow {
+    use std::io::Write;
+    use std::marker::PhantomData;
+    use std::sync::Arc;
+
+    use arrow_array::builder::{ArrayBuilder, StringBuilder, TimestampNanosecondBuilder, UInt64Builder};
+    use arrow_array::{ArrayRef, RecordBatch};
+    use arrow_ipc::writer::StreamWriter;
+    use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+    #[cfg(feature = "parquet")]
+    use parquet::arrow::ArrowWriter;
+    #[cfg(feature = "parquet")]
+    use parquet::basic::Compression;
+    #[cfg(feature = "parquet")]
+    use parquet::file::properties::WriterProperties;
+
+    use super::{Columns, Encoding, Kind, Value, BATCH_ROWS};
+    use crate::{BlakediffError, Result};
+
+    /// Builder of the record batches of rows `R`.
+    pub struct Batches<R> {
+        schema: SchemaRef,
+        builders: Vec<Box<dyn ArrayBuilder>>,
+        rows: usize,
+        row: PhantomData<fn(&R)>,
+    }
+
+    impl<R: Columns> Batches<R> {
+        pub fn new() -> Self {
+            let fields = R::COLUMNS.iter().map(|&(name, kind)| Field::new(name, data_type(kind), true)).collect::<Vec<_>>();
+            Self {
+                schema: Arc::new(Schema::new(fields)),
+                builders: R::COLUMNS.iter().map(|&(_, kind)| builder(kind)).collect(),
+                rows: 0,
+                row: PhantomData,
+            }
+        }
+
+        pub fn schema(&self) -> SchemaRef { self.schema.clone() }
+
+        /// rows pushed since the last batch
+        pub fn len(&self) -> usize { self.rows }
+
+        pub fn is_empty(&self) -> bool { self.rows == 0 }
+
+        pub fn push(&mut self, row: &R) {
+            for ((builder, &(_, kind)), value) in self.builders.iter_mut().zip(R::COLUMNS).zip(row.values()) {
+                let any = builder.as_any_mut();
+                match (kind, value) {
+                    (Kind::Unsigned, Value::Unsigned(value)) => any.downcast_mut::<UInt64Builder>().unwrap().append_value(value),
+                    (Kind::Unsigned, _) => any.downcast_mut::<UInt64Builder>().unwrap().append_null(),
+                    (Kind::Text, Value::Text(value)) => any.downcast_mut::<StringBuilder>().unwrap().append_value(value),
+                    (Kind::Text, _) => any.downcast_mut::<StringBuilder>().unwrap().append_null(),
+                    (Kind::Timestamp, Value::Timestamp(value)) => any.downcast_mut::<TimestampNanosecondBuilder>().unwrap().append_value(i64::try_from(value.as_nanos()).unwrap_or(i64::MAX)),
+                    (Kind::Timestamp, _) => any.downcast_mut::<TimestampNanosecondBuilder>().unwrap().append_null(),
+                }
+            }
+            self.rows += 1;
+        }
+
+        /// record batch of the rows pushed since the last one
+        pub fn finish(&mut self) -> Result<RecordBatch> {
+            let columns = self.builders.iter_mut().map(|builder| builder.finish()).collect::<Vec<ArrayRef>>();
+            self.rows = 0;
+            RecordBatch::try_new(self.schema.clone(), columns).map_err(arrow_error)
+        }
+    }
+
+    impl<R: Columns> Default for Batches<R> {
+        fn default() -> Self { Self::new() }
+    }
+
+    /// Record batches of rows `R` written to `W`, every [`BATCH_ROWS`] rows.
+    pub struct BatchWriter<W: Write + Send, R> {
+        sink: Sink<W>,
+        batches: Batches<R>,
+    }
+
+    enum Sink<W: Write + Send> {
+        #[cfg(feature = "parquet")]
+        Parquet(ArrowWriter<W>),
+        Arrow(StreamWriter<W>),
+    }
+
+    impl<W: Write + Send, R: Columns> BatchWriter<W, R> {
+        pub fn new(encoding: Encoding, out: W) -> Result<Self> {
+            let batches = Batches::new();
+            let sink = match encoding {
+                #[cfg(feature = "parquet")]
+                Encoding::Parquet => {
+                    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+                    Sink::Parquet(ArrowWriter::try_new(out, batches.schema(), Some(properties)).map_err(parquet_error)?)
+                }
+                #[cfg(not(feature = "parquet"))]
+                Encoding::Parquet => return Err(BlakediffError::Format(String::from("the parquet format needs blakediff built with the parquet feature"))),
+                Encoding::Arrow => Sink::Arrow(StreamWriter::try_new(out, &batches.schema()).map_err(arrow_error)?),
+            };
+            Ok(Self { sink, batches })
+        }
+
+        pub fn push(&mut self, row: &R) -> Result<()> {
+            self.batches.push(row);
+            if self.batches.len() == BATCH_ROWS {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        /// write the remaining rows and the end of the file or stream
+        pub fn finish(mut self) -> Result<()> {
+            self.flush()?;
+            match self.sink {
+                #[cfg(feature = "parquet")]
+                Sink::Parquet(writer) => writer.close().map(drop).map_err(parquet_error),
+                Sink::Arrow(mut writer) => {
+                    writer.finish().map_err(arrow_error)?;
+                    Ok(writer.get_mut().flush()?)
+                }
+            }
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            if self.batches.is_empty() {
+                return Ok(());
+            }
+            let batch = self.batches.finish()?;
+            match &mut self.sink {
+                #[cfg(feature = "parquet")]
+                Sink::Parquet(writer) => writer.write(&batch).map_err(parquet_error),
+                Sink::Arrow(writer) => {
+                    writer.write(&batch).map_err(arrow_error)?;
+                    // readers get the batch right away
+                    Ok(writer.get_mut().flush()?)
+                }
+            }
+        }
+    }
+
+    fn data_type(kind: Kind) -> DataType {
+        match kind {
+            Kind::Unsigned => DataType::UInt64,
+            Kind::Text => DataType::Utf8,
+            Kind::Timestamp => DataType::Timestamp(TimeUnit::Nanosecond, Some("+00:00".into())),
+        }
+    }
+
+    fn builder(kind: Kind) -> Box<dyn ArrayBuilder> {
+        match kind {
+            Kind::Unsigned => Box::new(UInt64Builder::new()),
+            Kind::Text => Box::new(StringBuilder::new()),
+            Kind::Timestamp => Box::new(TimestampNanosecondBuilder::new().with_timezone("+00:00")),
+        }
+    }
+
+    fn arrow_error(e: ArrowError) -> BlakediffError {
+        match e {
+            ArrowError::IoError(_, e) => BlakediffError::Io(e),
+            e => BlakediffError::Other(e.to_string()),
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    fn parquet_error(e: parquet::errors::ParquetError) -> BlakediffError {
+        match e {
+            parquet::errors::ParquetError::External(e) => match e.downcast::<std::io::Error>() {
+                Ok(e) => BlakediffError::Io(*e),
+                Err(e) => BlakediffError::Other(e.to_string()),
+            },
+            e => BlakediffError::Other(e.to_string()),
+        }
+    }
+}
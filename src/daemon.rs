@@ -0,0 +1,342 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use took::{Timer, Took};
+
+use crate::metrics::{self, Metrics};
+use crate::update;
+use blakediff::{BlakediffError, Result};
+
+/// configuration file of the daemon, for instance :
+/// ```toml
+/// interval = 3600
+/// watch = true
+/// metrics = "127.0.0.1:9184"
+///
+/// [[roots]]
+/// dir = "/data/music"
+/// report = "/var/lib/blakediff/music.txt"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// seconds between two scans
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// use multi-threading for walk in directories
+    #[serde(default)]
+    parallel: bool,
+    /// also rescan a root once its files changed, through inotify on linux
+    #[serde(default)]
+    watch: bool,
+    /// leave the files which no longer exist out of the reports, logging them as removed
+    #[serde(default = "default_prune_missing")]
+    prune_missing: bool,
+    /// address serving the Prometheus metrics of the scans on `/metrics`
+    metrics: Option<String>,
+    roots: Vec<Root>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Root {
+    /// directory to scan
+    dir: PathBuf,
+    /// report kept up to date with the content of `dir`
+    report: PathBuf,
+}
+
+fn default_interval() -> u64 { 3600 }
+
+fn default_prune_missing() -> bool { true }
+
+/// quiet time after a change before a watched root is rescanned, so that a copy in progress is scanned once
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// rescan the roots of the configuration file forever, refreshing their reports and logging changes
+pub fn daemon(config_file: String) -> Result<()> {
+    let config: Config = toml::from_str(&fs::read_to_string(&config_file)?).map_err(|e| BlakediffError::Format(format!("invalid configuration {} : {}", config_file, e)))?;
+    let mut watcher = config.watch.then(|| watch(&config.roots)).flatten();
+    log::info!("watching {} roots every {} seconds{}", config.roots.len(), config.interval, if watcher.is_some() { " and on changes" } else { "" });
+    let metrics = Arc::new(Metrics::default());
+    if let Some(listen) = &config.metrics {
+        metrics::serve(metrics.clone(), listen)?;
+    }
+    let scan = |root: &Root| {
+        // a failing root must not stop the others, it will be retried on next scan
+        if let Err(e) = scan(root, config.parallel, config.prune_missing, &metrics) {
+            log::error!("scan of {} failed : {}", root.dir.display(), e);
+            metrics.failed(&root.dir.to_string_lossy());
+        }
+    };
+    loop {
+        config.roots.iter().for_each(scan);
+        // until the next full scan, the roots whose files changed are rescanned alone
+        let deadline = Instant::now() + Duration::from_secs(config.interval);
+        while let Some(changed) = changes(&mut watcher, deadline) {
+            changed.into_iter().for_each(|root| scan(&config.roots[root]));
+        }
+    }
+}
+
+/// watcher of the directories of `roots`, their reports being left out, or None when it can't be set up
+fn watch(roots: &[Root]) -> Option<inotify::Watcher> {
+    let watched = roots.iter().map(|root| (root.dir.clone(), [root.report.clone()].into_iter().collect())).collect::<Vec<_>>();
+    match inotify::Watcher::new(&watched) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            log::warn!("changes are not watched, roots are only scanned on schedule : {}", e);
+            None
+        }
+    }
+}
+
+/// roots which changed before `deadline`, None once it is reached
+fn changes(watcher: &mut Option<inotify::Watcher>, deadline: Instant) -> Option<HashSet<usize>> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return None;
+    }
+    let Some(events) = watcher else {
+        thread::sleep(remaining);
+        return None;
+    };
+    match events.wait(remaining, SETTLE) {
+        Ok(changed) if changed.is_empty() => None,
+        Ok(changed) => Some(changed),
+        Err(e) => {
+            log::error!("changes are no longer watched, roots are only scanned on schedule : {}", e);
+            *watcher = None;
+            None
+        }
+    }
+}
+
+fn scan(root: &Root, parallel: bool, prune_missing: bool, metrics: &Metrics) -> Result<()> {
+    let took = Timer::new();
+    let summary = update::refresh(&root.report, Path::new(&root.dir), parallel, prune_missing)?;
+    metrics.scanned(&root.dir.to_string_lossy(), &summary, *took.took().as_std());
+    summary.added.iter().for_each(|path| log::info!("added : {}", path));
+    summary.updated.iter().for_each(|path| log::info!("updated : {}", path));
+    summary.removed.iter().for_each(|path| log::info!("removed : {}", path));
+    log::info!(
+        "scan of {} : {} unchanged, {} updated, {} added, {} removed in {}",
+        root.dir.display(),
+        summary.unchanged,
+        summary.updated.len(),
+        summary.added.len(),
+        summary.removed.len(),
+        Took::from_std(*took.took().as_std())
+    );
+    Ok(())
+}
+
+/// changes of the files under the roots through inotify : every directory is watched, those created later too
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::{HashMap, HashSet};
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    const MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_ATTRIB | libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_ONLYDIR | libc::IN_DONT_FOLLOW;
+
+    pub struct Watcher {
+        fd: OwnedFd,
+        /// root and directory of each watch
+        watches: HashMap<i32, (usize, PathBuf)>,
+        /// files of each root whose changes are ignored, its report
+        ignored: Vec<HashSet<PathBuf>>,
+    }
+
+    impl Watcher {
+        /// watch every directory of each root, but the changes of its ignored files and of their temporary copies
+        pub fn new(roots: &[(PathBuf, HashSet<PathBuf>)]) -> io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut watcher = Watcher {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                watches: HashMap::new(),
+                ignored: Vec::new(),
+            };
+            for (root, (dir, ignored)) in roots.iter().enumerate() {
+                let dir = fs::canonicalize(dir)?;
+                watcher.ignored.push(ignored.iter().flat_map(|file| Self::resolved(file)).flat_map(|file| [blakediff::atomic::tmp_path(&file), file]).collect());
+                watcher.add(root, &dir)?;
+            }
+            Ok(watcher)
+        }
+
+        /// the paths under which the events of `file` come, like those of the canonical roots : with its parent
+        /// directories resolved, which is where a report is written even through a link, and with all its links
+        /// resolved once it exists
+        fn resolved(file: &Path) -> Vec<PathBuf> {
+            let mut paths = vec![crate::trash::absolute(file).unwrap_or_else(|_| file.to_owned())];
+            paths.extend(fs::canonicalize(file).ok().filter(|canonical| *canonical != paths[0]));
+            paths
+        }
+
+        /// watch `dir` and the directories under it, symbolic links being left out like in the reports
+        fn add(&mut self, root: usize, dir: &Path) -> io::Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+            if wd < 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() == Some(libc::ENOSPC) {
+                    return Err(io::Error::new(e.kind(), format!("{} : {}, raise fs.inotify.max_user_watches", dir.display(), e)));
+                }
+                // removed meanwhile, or unreadable
+                log::warn!("{} : not watched, {}", dir.display(), e);
+                return Ok(());
+            }
+            self.watches.insert(wd, (root, dir.to_owned()));
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    self.add(root, &entry.path())?;
+                }
+            }
+            Ok(())
+        }
+
+        /// roots with changes within `timeout`, once no other change came for `settle`, or none
+        pub fn wait(&mut self, timeout: Duration, settle: Duration) -> io::Result<HashSet<usize>> {
+            let mut changed = HashSet::new();
+            let start = Instant::now();
+            loop {
+                let wait = if changed.is_empty() { timeout.saturating_sub(start.elapsed()) } else { settle };
+                if !self.poll(wait)? {
+                    return Ok(changed);
+                }
+                self.read(&mut changed)?;
+                // a root changing all the time is still rescanned by the end of `timeout`
+                if !changed.is_empty() && start.elapsed() >= timeout {
+                    return Ok(changed);
+                }
+            }
+        }
+
+        /// events are ready within `timeout`
+        fn poll(&self, timeout: Duration) -> io::Result<bool> {
+            let mut poll = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            match unsafe { libc::poll(&mut poll, 1, timeout.as_millis().min(i32::MAX as u128) as i32) } {
+                ready if ready >= 0 => Ok(ready > 0),
+                _ => match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+                    e => Err(e),
+                },
+            }
+        }
+
+        /// read the pending events, adding their root to `changed`
+        fn read(&mut self, changed: &mut HashSet<usize>) -> io::Result<()> {
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let read = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+                if read < 0 {
+                    return match io::Error::last_os_error() {
+                        e if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+                        e => Err(e),
+                    };
+                }
+                let mut offset = 0;
+                while offset + std::mem::size_of::<libc::inotify_event>() <= read as usize {
+                    let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::inotify_event) };
+                    let name_start = offset + std::mem::size_of::<libc::inotify_event>();
+                    let name = &buffer[name_start..name_start + event.len as usize];
+                    let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                    offset = name_start + event.len as usize;
+                    self.event(event, name, changed)?;
+                }
+            }
+        }
+
+        fn event(&mut self, event: libc::inotify_event, name: &[u8], changed: &mut HashSet<usize>) -> io::Result<()> {
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                // events were lost
+                changed.extend(0..self.ignored.len());
+                return Ok(());
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                return Ok(());
+            }
+            let Some((root, dir)) = self.watches.get(&event.wd).cloned() else { return Ok(()) };
+            let path = dir.join(std::ffi::OsString::from_vec(name.to_vec()));
+            if self.ignored[root].contains(&path) {
+                return Ok(());
+            }
+            log::debug!("{} changed", path.display());
+            if event.mask & libc::IN_ISDIR != 0 && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                self.add(root, &path)?;
+            }
+            changed.insert(root);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod inotify {
+    use std::collections::HashSet;
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    pub struct Watcher;
+
+    impl Watcher {
+        pub fn new(_roots: &[(PathBuf, HashSet<PathBuf>)]) -> io::Result<Self> { Err(io::Error::new(io::ErrorKind::Unsupported, "changes are only watched on linux")) }
+
+        pub fn wait(&mut self, _timeout: Duration, _settle: Duration) -> io::Result<HashSet<usize>> { Ok(HashSet::new()) }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+
+    #[test]
+    fn changes_are_watched_but_the_report() {
+        let tmp = TempDir::new("daemon").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        let dir = fs::canonicalize(dir).unwrap();
+        std::os::unix::fs::symlink("a", dir.join("alias")).unwrap();
+        // the report given relative to the current directory, through a link to the directory holding it
+        let up = std::env::current_dir().unwrap().components().skip(1).map(|_| "..").collect::<PathBuf>();
+        let report = up.join(dir.strip_prefix("/").unwrap()).join("alias/report.txt");
+        assert!(report.is_relative());
+        let mut watcher = inotify::Watcher::new(&[(dir.clone(), [report.clone()].into_iter().collect())]).unwrap();
+        let wait = |watcher: &mut inotify::Watcher| watcher.wait(Duration::from_millis(300), Duration::from_millis(50)).unwrap();
+
+        fs::write(dir.join("a/b/file"), b"new").unwrap();
+        assert_eq!(wait(&mut watcher), [0].into_iter().collect());
+        // writing the report, through its temporary file, is not a change
+        let file = blakediff::atomic::AtomicFile::create(&report).unwrap();
+        file.commit().unwrap();
+        assert!(wait(&mut watcher).is_empty());
+        let file = blakediff::atomic::AtomicFile::create(dir.join("a/report.txt")).unwrap();
+        file.commit().unwrap();
+        assert!(wait(&mut watcher).is_empty());
+        // directories created later are watched too
+        fs::create_dir(dir.join("a/c")).unwrap();
+        assert_eq!(wait(&mut watcher), [0].into_iter().collect());
+        fs::write(dir.join("a/c/file"), b"new").unwrap();
+        assert_eq!(wait(&mut watcher), [0].into_iter().collect());
+        fs::remove_file(dir.join("a/b/file")).unwrap();
+        assert_eq!(wait(&mut watcher), [0].into_iter().collect());
+        assert!(wait(&mut watcher).is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+
+use blakediff::input::Input;
+use blakediff::output::{quoted, Size};
+use blakediff::report::{self, Entry};
+use blakediff::{clones, scan, BlakediffError, Result};
+
+use crate::trash;
+
+/// which file of a group of duplicates is kept
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// the first path in alphabetical order
+    First,
+    /// the shortest path
+    Shortest,
+    /// the file with the oldest modification time
+    Oldest,
+    /// the file with the newest modification time
+    Newest,
+}
+
+/// what is done with the other files of a group
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// only display the groups
+    Report,
+    /// remove the duplicates
+    Delete,
+    /// replace the duplicates by hard links to the kept file
+    Hardlink,
+    /// replace the duplicates by copy-on-write clones of the kept file (btrfs, xfs)
+    Reflink,
+}
+
+/// find duplicates in `dir` and apply `action` on all of them but the one selected by `keep`, deleted files going
+/// to the trash with `trash`. Nothing is modified unless `apply` is set. The files are hashed again right before, those
+/// changed since the scan being left alone, and a failed action is logged without stopping the others.
+pub fn dedupe(dir: String, keep: Keep, action: Action, trash: bool, apply: bool, parallel: bool, fast: bool) -> Result<()> {
+    if trash && action != Action::Delete {
+        return Err(BlakediffError::Format(String::from("--trash only applies to --action delete")));
+    }
+    let groups = scan::duplicates_staged(Path::new(&dir), parallel, fast)?;
+
+    // duplicates already sharing their blocks with the kept file take no extra space
+    let (mut duplicates, mut reclaimable, mut cloned, mut failed) = (0usize, 0u64, 0u64, 0usize);
+    for (stage, mut group) in groups {
+        select_keeper(&mut group, keep);
+        let (keeper, others) = group.split_first().unwrap();
+        // never destroy a duplicate when the kept file no longer holds its content
+        if apply && action != Action::Report && !unchanged(keeper) {
+            log::warn!("{} changed or is missing since the scan, its group is left untouched", keeper.path);
+            continue;
+        }
+        if fast {
+            println!("keep : {} [{}]", quoted(&keeper.path), stage.name());
+        } else {
+            println!("keep : {}", quoted(&keeper.path));
+        }
+        for dupe in others {
+            let shared = clones::shared(Path::new(&keeper.path), Path::new(&dupe.path));
+            let destroyed = apply && (action == Action::Delete || (matches!(action, Action::Hardlink | Action::Reflink) && !shared));
+            if destroyed && !unchanged(dupe) {
+                log::warn!("{} changed or is missing since the scan, it is left in place", dupe.path);
+                continue;
+            }
+            let done = match (action, apply) {
+                (Action::Report, _) if shared => Ok("duplicate, already cloned"),
+                (Action::Report, _) => Ok("duplicate"),
+                (Action::Hardlink | Action::Reflink, _) if shared => Ok("already cloned"),
+                (Action::Delete, false) if trash => Ok("would trash"),
+                (Action::Delete, false) => Ok("would delete"),
+                (Action::Hardlink, false) => Ok("would hardlink"),
+                (Action::Reflink, false) => Ok("would reflink"),
+                (Action::Delete, true) if trash => trash::trash(Path::new(&dupe.path)).map(|trashed| {
+                    log::debug!("{} moved to {}", dupe.path, trashed.display());
+                    "trashed"
+                }),
+                (Action::Delete, true) => fs::remove_file(&dupe.path).map(|()| "deleted"),
+                (Action::Hardlink, true) => replace_with(Path::new(&keeper.path), Path::new(&dupe.path), |src, dst| fs::hard_link(src, dst)).map(|()| "hardlinked"),
+                (Action::Reflink, true) => replace_with(Path::new(&keeper.path), Path::new(&dupe.path), reflink).map(|()| "reflinked"),
+            };
+            // the other duplicates are still handled
+            let label = match done {
+                Ok(label) => label,
+                Err(e) => {
+                    log::error!("{} : {}", dupe.path, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            println!("  {} : {}", label, quoted(&dupe.path));
+            duplicates += 1;
+            match shared {
+                true => cloned += dupe.size.unwrap_or(0),
+                false => reclaimable += dupe.size.unwrap_or(0),
+            }
+        }
+    }
+    let verb = if apply && action != Action::Report { "reclaimed" } else { "reclaimable" };
+    match cloned {
+        0 => summary!("dedupe : {} duplicates, {} {}", duplicates, Size(reclaimable), verb),
+        _ => summary!("dedupe : {} duplicates, {} {}, {} already deduplicated", duplicates, Size(reclaimable), verb, Size(cloned)),
+    }
+    if failed > 0 {
+        return Err(BlakediffError::Io(io::Error::other(format!("{} duplicates couldn't be processed", failed))));
+    }
+    Ok(())
+}
+
+/// `entry`, found by the scan, still has its size and mtime and holds its content
+fn unchanged(entry: &Entry) -> bool {
+    let path = Path::new(&entry.path);
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file() && Some(metadata.len()) == entry.size && Some(report::mtime(&metadata)) == entry.mtime)
+        && Input::open(path).and_then(|mut input| input.hash()).is_ok_and(|hash| entry.matches(&hash))
+}
+
+/// move the file to keep at the head of `group`
+fn select_keeper(group: &mut [Entry], keep: Keep) {
+    let index = match keep {
+        Keep::First => 0,
+        Keep::Shortest => group.iter().position_min_by_key(|c| c.path.len()).unwrap(),
+        Keep::Oldest => group.iter().position_min_by_key(|c| c.mtime).unwrap(),
+        Keep::Newest => group.iter().position_max_by_key(|c| c.mtime).unwrap(),
+    };
+    group[..=index].rotate_right(1);
+}
+
+/// replace `dupe` by the result of `link(keeper, tmp)`, renamed over `dupe` so that it is never lost on failure
+fn replace_with(keeper: &Path, dupe: &Path, link: impl Fn(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+    let mut tmp = dupe.as_os_str().to_owned();
+    tmp.push(".blakediff.tmp");
+    let tmp = PathBuf::from(tmp);
+    link(keeper, &tmp)?;
+    fs::rename(&tmp, dupe).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let src_file = fs::File::open(src)?;
+    let dst_file = fs::File::options().write(true).create_new(true).open(dst)?;
+    if unsafe { libc::ioctl(dst_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) } != 0 {
+        let err = io::Error::last_os_error();
+        drop(dst_file);
+        let _ = fs::remove_file(dst);
+        return Err(err);
+    }
+    dst_file.set_permissions(src_file.metadata()?.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> { Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are only supported on linux")) }
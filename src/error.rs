@@ -0,0 +1,88 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+/// Errors of blakediff, each kind with its own exit code so that scripts can tell them apart.
+#[derive(Error, Debug)]
+pub enum BlakediffError {
+    /// reading or writing a file failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// a line of a report can't be parsed
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    /// invalid usage : argument, configuration file...
+    #[error("{0}")]
+    Format(String),
+    /// a file or directory can't be read while walking a tree
+    #[error("{} : {source}", path.display())]
+    Walk { path: PathBuf, source: io::Error },
+    /// a file can't be read while hashing it
+    #[error("{} : {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    /// interrupted by Ctrl-C or SIGTERM
+    #[error("interrupted")]
+    Interrupted,
+    /// any other failure
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = BlakediffError> = std::result::Result<T, E>;
+
+/// what to do with a file or directory which can't be read, when the others are hashed anyway
+pub type OnError = Arc<dyn Fn(BlakediffError) + Send + Sync>;
+
+impl BlakediffError {
+    /// exit code of the process failing with this error, 1 being kept for failed checks (verify, find...)
+    /// and findings in strict mode, 2 being shared with the usage errors of clap
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BlakediffError::Format(_) => 2,
+            BlakediffError::Io(_) | BlakediffError::Walk { .. } | BlakediffError::Read { .. } => 3,
+            BlakediffError::Parse { .. } => 4,
+            BlakediffError::Other(_) => 5,
+            BlakediffError::Interrupted => 130,
+        }
+    }
+
+    /// what was being done : `walk`, `read`, `io`, `parse`, `usage`, `interrupted` or `other`
+    pub fn stage(&self) -> &'static str {
+        match self {
+            BlakediffError::Walk { .. } => "walk",
+            BlakediffError::Read { .. } => "read",
+            BlakediffError::Io(_) => "io",
+            BlakediffError::Parse { .. } => "parse",
+            BlakediffError::Format(_) => "usage",
+            BlakediffError::Interrupted => "interrupted",
+            BlakediffError::Other(_) => "other",
+        }
+    }
+
+    /// file or directory which failed
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            BlakediffError::Walk { path, .. } | BlakediffError::Read { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// the error of reading `path`, unless it already tells which file failed or isn't about a file
+    pub fn reading(self, path: impl Into<PathBuf>) -> Self {
+        match self {
+            BlakediffError::Io(source) => BlakediffError::Read { path: path.into(), source },
+            BlakediffError::Other(message) => BlakediffError::Read { path: path.into(), source: io::Error::other(message) },
+            e => e,
+        }
+    }
+
+    /// error of the operating system
+    pub fn io(&self) -> Option<&io::Error> {
+        match self {
+            BlakediffError::Io(source) | BlakediffError::Walk { source, .. } | BlakediffError::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
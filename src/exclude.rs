@@ -0,0 +1,117 @@
+//! Exclusion patterns of a walk, as in the `--exclude-from` files of rsync and tar : one glob per line, `#` starting
+//! a comment.
+//!
+//! A pattern without `/` matches the name of a file or directory at any depth, one with a `/` the path relative to
+//! the root of the walk, a leading `/` being ignored. `*` matches anything but `/`, `**` anything, `?` one character
+//! and `[a-z]` or `[!a-z]` one of a set. A trailing `/` restricts the pattern to directories, whose whole subtree is
+//! then left out.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{BlakediffError, Result};
+
+/// Directories of version control systems and caches, full of machine-generated duplicates, left out by
+/// [`Excludes::skip_vcs`].
+pub const VCS: [&str; 6] = [".git/", ".svn/", ".hg/", "node_modules/", "__pycache__/", ".cache/"];
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    /// matched against the relative path instead of the name
+    anchored: bool,
+    dir_only: bool,
+}
+
+/// A list of exclusion patterns, empty by default.
+#[derive(Debug, Clone, Default)]
+pub struct Excludes {
+    patterns: Vec<Pattern>,
+}
+
+impl Excludes {
+    /// add the patterns of `path`, one per line
+    pub fn read(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path).map_err(|e| BlakediffError::Format(format!("{} : {}", path.display(), e)))?;
+        self.extend(text.lines());
+        Ok(())
+    }
+
+    /// add `lines`, skipping the blank ones and the comments
+    pub fn extend<'a>(&mut self, lines: impl IntoIterator<Item = &'a str>) {
+        for line in lines {
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let dir_only = line.ends_with('/');
+            let glob = line.trim_end_matches('/');
+            let anchored = glob.contains('/');
+            self.patterns.push(Pattern {
+                glob: glob.trim_start_matches('/').to_owned(),
+                anchored,
+                dir_only,
+            });
+        }
+    }
+
+    /// add the directories of [`VCS`]
+    pub fn skip_vcs(&mut self) { self.extend(VCS) }
+
+    pub fn is_empty(&self) -> bool { self.patterns.is_empty() }
+
+    /// `relative`, a path under the root of the walk, is excluded
+    pub fn excludes(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        let name = path.rsplit('/').next().unwrap_or_default();
+        self.patterns
+            .iter()
+            .filter(|pattern| is_dir || !pattern.dir_only)
+            .any(|pattern| glob(pattern.glob.as_bytes(), if pattern.anchored { path.as_bytes() } else { name.as_bytes() }))
+    }
+}
+
+/// `text` matches the whole of `pattern`
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        // `**/` matches any number of directories, none included
+        [b'*', b'*', b'/', rest @ ..] => (0..=text.len()).any(|i| (i == 0 || text[i - 1] == b'/') && glob(rest, &text[i..])),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        [b'*', rest @ ..] => (0..=text.len()).take_while(|&i| i == 0 || text[i - 1] != b'/').any(|i| glob(rest, &text[i..])),
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob(rest, tail)),
+        [b'[', rest @ ..] => match (class(rest), text) {
+            (Some((set, after)), [c, tail @ ..]) => *c != b'/' && set(*c) && glob(after, tail),
+            // an unclosed `[` is a literal
+            (None, [b'[', tail @ ..]) => glob(rest, tail),
+            _ => false,
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => matches!(text, [t, tail @ ..] if t == c && glob(rest, tail)),
+    }
+}
+
+/// the set of `[...]`, after its `[`, with the pattern following its `]`
+fn class(pattern: &[u8]) -> Option<(impl Fn(u8) -> bool + '_, &[u8])> {
+    let (negated, body) = match pattern {
+        [b'!' | b'^', body @ ..] => (true, body),
+        body => (false, body),
+    };
+    // a `]` right after the `[` is part of the set
+    let end = body.iter().skip(1).position(|&c| c == b']')? + 1;
+    let set = &body[..end];
+    let member = move |c: u8| {
+        let mut i = 0;
+        let mut found = false;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == b'-' {
+                found |= (set[i]..=set[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((member, &body[end + 1..]))
+}
@@ -0,0 +1,164 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use blakediff::columnar::{Columns, Encoding, Kind, Value};
+use blakediff::output::{self, quoted};
+use blakediff::report::{self, Entry};
+use blakediff::Result;
+
+/// print groups of duplicates like fdupes : one path per line, each group followed by a blank line
+pub fn fdupes(groups: &[Vec<String>]) {
+    for group in groups {
+        group.iter().for_each(|path| println!("{}", quoted(path)));
+        println!();
+    }
+}
+
+/// header of an rmlint json dump
+#[derive(Serialize)]
+struct Header {
+    description: &'static str,
+    cwd: String,
+    args: &'static str,
+    version: &'static str,
+    rev: &'static str,
+    progress: u64,
+    checksum_type: &'static str,
+}
+
+/// file of an rmlint json dump
+#[derive(Serialize)]
+struct Lint<'a> {
+    id: usize,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    progress: usize,
+    checksum: &'a str,
+    path: String,
+    size: u64,
+    depth: usize,
+    inode: u64,
+    disk_id: u64,
+    is_original: bool,
+    mtime: f64,
+}
+
+/// footer of an rmlint json dump
+#[derive(Serialize)]
+struct Footer {
+    aborted: bool,
+    progress: u64,
+    duplicates: u64,
+    duplicate_sets: usize,
+    total_files: usize,
+    total_lint_size: u64,
+}
+
+/// print groups of duplicates as the JSON document of `rmlint -o json` : a header, one object per file
+/// with the first file of each group as original, and a footer with totals
+pub fn rmlint(groups: &[Vec<Entry>]) -> Result<()> {
+    let cwd = env::current_dir().unwrap_or_default();
+    let header = Header {
+        description: "rmlint json-dump of lint files",
+        cwd: cwd.to_string_lossy().into_owned(),
+        args: "blakediff analyze --format rmlint",
+        version: env!("CARGO_PKG_VERSION"),
+        rev: "blakediff",
+        progress: 0,
+        checksum_type: "blake3",
+    };
+    let mut out = BufWriter::new(io::stdout().lock());
+    write_object(&mut out, "[\n", &header)?;
+    let (mut duplicates, mut total_lint_size) = (0u64, 0u64);
+    let total_files = groups.iter().map(Vec::len).sum::<usize>();
+    let mut id = 0;
+    for group in groups {
+        for (i, entry) in group.iter().enumerate() {
+            let path = cwd.join(&entry.path);
+            let metadata = fs::metadata(&path).ok();
+            // the report may come from another machine, what it lacks is then unknown
+            let size = entry.size.or_else(|| metadata.as_ref().map(fs::Metadata::len)).unwrap_or(0);
+            let (disk_id, inode) = metadata.as_ref().map_or((0, 0), blakediff::scan::inode);
+            let mtime = entry.mtime.or_else(|| metadata.as_ref().map(report::mtime)).map_or(0.0, |mtime| mtime.as_secs_f64());
+            if i > 0 {
+                duplicates += 1;
+                total_lint_size += size;
+            }
+            id += 1;
+            write_object(&mut out, ",\n", &Lint {
+                id,
+                kind: "duplicate_file",
+                progress: id * 100 / total_files.max(1),
+                checksum: &entry.hash,
+                path: path.to_string_lossy().into_owned(),
+                size,
+                depth: depth(&path),
+                inode,
+                disk_id,
+                is_original: i == 0,
+                mtime,
+            })?;
+        }
+    }
+    write_object(&mut out, ",\n", &Footer {
+        aborted: false,
+        progress: 100,
+        duplicates,
+        duplicate_sets: groups.len(),
+        total_files,
+        total_lint_size,
+    })?;
+    writeln!(out, "\n]")?;
+    Ok(out.flush()?)
+}
+
+/// write `value` as a JSON object on one line after `separator`, as soon as it is known
+fn write_object(out: &mut impl Write, separator: &str, value: &impl Serialize) -> io::Result<()> {
+    out.write_all(separator.as_bytes())?;
+    Ok(serde_json::to_writer(out, value)?)
+}
+
+/// number of components of `path` below the root
+fn depth(path: &Path) -> usize { path.components().count().saturating_sub(1) }
+
+/// file of a group of duplicates, in the table, parquet and arrow outputs of `analyze`
+struct Duplicate<'a> {
+    /// number of the group, from 1
+    group: u64,
+    entry: &'a Entry,
+}
+
+impl Columns for Duplicate<'_> {
+    const COLUMNS: &'static [(&'static str, Kind)] = &[("group", Kind::Unsigned), ("hash", Kind::Text), ("size", Kind::Unsigned), ("mtime", Kind::Timestamp), ("path", Kind::Text)];
+
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![
+            Value::Unsigned(self.group),
+            Value::Text(&self.entry.hash),
+            self.entry.size.map_or(Value::Null, Value::Unsigned),
+            self.entry.mtime.map_or(Value::Null, Value::Timestamp),
+            Value::Text(&self.entry.path),
+        ]
+    }
+}
+
+/// print groups of duplicates as a table, one row per file
+pub fn table(groups: &[Vec<Entry>]) -> Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    output::write_table(&mut out, rows(groups))?;
+    Ok(out.flush()?)
+}
+
+/// print groups of duplicates as a parquet file or an arrow stream, one row per file
+pub fn columnar(encoding: Encoding, groups: &[Vec<Entry>]) -> Result<()> {
+    output::print_columnar(encoding, rows(groups))
+}
+
+/// rows of groups of duplicates, numbered from 1
+fn rows(groups: &[Vec<Entry>]) -> impl Iterator<Item = Duplicate<'_>> {
+    groups.iter().zip(1..).flat_map(|(group, number)| group.iter().map(move |entry| Duplicate { group: number, entry }))
+}
@@ -0,0 +1,146 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use blakediff::report::{self, Entry, Field};
+use blakediff::Result;
+
+use blakediff::tempdir::TempDir;
+
+type Entries = Box<dyn Iterator<Item = Result<Entry>>>;
+
+/// runs merged at once, so that the open files stay far below the usual limits
+const FAN_IN: usize = 64;
+
+/// call `group` on each group of duplicates of `report_file`, in hash order, without loading the whole report :
+/// entries are sorted by hash in runs of about `memory_limit` bytes written to temporary files, which are merged
+/// by [`FAN_IN`] into longer runs until a last streaming pass can merge them all
+pub fn duplicates(report_file: &Path, memory_limit: usize, mut group: impl FnMut(Vec<Entry>) -> Result<()>) -> Result<()> {
+    let tmp = TempDir::new("extsort")?;
+    let mut written = 0usize;
+    let mut run = || {
+        written += 1;
+        tmp.path().join(format!("run-{}", written))
+    };
+    let mut runs: Vec<PathBuf> = Vec::new();
+    let (mut buffer, mut used) = (Vec::new(), 0usize);
+    for entry in report::Reader::open(report_file)? {
+        let entry = Entry { size: None, mtime: None, ..entry? };
+        used += mem::size_of::<Entry>() + entry.hash.len() + entry.path.len();
+        buffer.push(entry);
+        if used >= memory_limit {
+            let path = run();
+            spill(&mut buffer, &path)?;
+            runs.push(path);
+            used = 0;
+        }
+    }
+    log::info!("{} runs written to {}", runs.len(), tmp.path().display());
+    while runs.len() >= FAN_IN {
+        runs = runs.chunks(FAN_IN).map(|chunk| merge_into(chunk, run())).collect::<Result<_>>()?;
+        log::info!("merged into {} runs", runs.len());
+    }
+    buffer.sort_unstable_by(|a, b| (&a.hash, &a.path).cmp(&(&b.hash, &b.path)));
+    let mut runs = runs.iter().map(|path| open(path)).collect::<Result<Vec<_>>>()?;
+    runs.push(Box::new(buffer.into_iter().map(Ok)));
+
+    // entries of a same hash come together, sorted by path
+    let mut current: Vec<Entry> = Vec::new();
+    for entry in merge(runs) {
+        let entry = entry?;
+        if current.first().is_some_and(|first| first.hash != entry.hash) {
+            let done = mem::take(&mut current);
+            if done.len() > 1 {
+                group(done)?;
+            }
+        }
+        current.push(entry);
+    }
+    if current.len() > 1 {
+        group(current)?;
+    }
+    Ok(())
+}
+
+/// sort `buffer` into the run file `path`, emptying it
+fn spill(buffer: &mut Vec<Entry>, path: &Path) -> Result<()> {
+    buffer.sort_unstable_by(|a, b| (&a.hash, &a.path).cmp(&(&b.hash, &b.path)));
+    write(buffer.drain(..).map(Ok), path)
+}
+
+/// merge the run files `runs` into the run file `path`, removing them
+fn merge_into(runs: &[PathBuf], path: PathBuf) -> Result<PathBuf> {
+    write(merge(runs.iter().map(|run| open(run)).collect::<Result<_>>()?), &path)?;
+    runs.iter().try_for_each(std::fs::remove_file)?;
+    Ok(path)
+}
+
+fn write(entries: impl Iterator<Item = Result<Entry>>, path: &Path) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for entry in entries {
+        writeln!(out, "{}", entry?.to_line(&[Field::Hash, Field::Path]))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// entries of the run file `path`
+fn open(path: &Path) -> Result<Entries> { Ok(Box::new(report::Reader::new(BufReader::new(File::open(path)?)))) }
+
+/// k-way merge of sorted runs
+fn merge(mut runs: Vec<Entries>) -> impl Iterator<Item = Result<Entry>> {
+    let mut heap = BinaryHeap::new();
+    let mut failed = None;
+    for (i, run) in runs.iter_mut().enumerate() {
+        match run.next() {
+            Some(Ok(e)) => heap.push(Reverse((e.hash, e.path, i))),
+            Some(Err(e)) => failed = Some(e),
+            None => {}
+        }
+    }
+    std::iter::from_fn(move || {
+        if let Some(e) = failed.take() {
+            return Some(Err(e));
+        }
+        let Reverse((hash, path, i)) = heap.pop()?;
+        match runs[i].next() {
+            Some(Ok(e)) => heap.push(Reverse((e.hash, e.path, i))),
+            Some(Err(e)) => failed = Some(e),
+            None => {}
+        }
+        Some(Ok(Entry { hash, size: None, mtime: None, xattrs: None, target: None, digests: Vec::new(), path }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+    use blakediff::report::Report;
+    use itertools::Itertools;
+
+    #[test]
+    fn runs_are_merged_by_passes() {
+        let tmp = TempDir::new("extsort-test").unwrap();
+        let dir = tmp.path();
+        let report_file = dir.join("report.txt");
+        let hash = |i: usize| blake3::hash(&(i % 97).to_le_bytes()).to_string();
+        let lines = (0..FAN_IN * FAN_IN + 10).map(|i| format!("{} file{}\n", hash(i * 7919), i)).collect::<String>();
+        std::fs::write(&report_file, lines).unwrap();
+
+        // a run per entry : two merge passes
+        let mut groups = Vec::new();
+        duplicates(&report_file, 1, |group| {
+            groups.push(group.into_iter().map(|entry| entry.path).collect::<Vec<_>>());
+            Ok(())
+        })
+        .unwrap();
+        let report = Report::load(&report_file).unwrap();
+        let mut expected = report.duplicates().into_iter().map(|group| group.into_iter().map(|entry| entry.path.clone()).sorted().collect::<Vec<_>>()).collect::<Vec<_>>();
+        expected.sort_by_key(|group| report.by_path(&group[0]).unwrap().hash.clone());
+        assert_eq!(groups, expected);
+    }
+}
@@ -0,0 +1,24 @@
+//! Report arguments of `analyze` and `compare` given as `http://` or `https://` URLs, only downloaded with `--fetch`.
+
+use std::path::Path;
+
+use blakediff::report::Report;
+use blakediff::{BlakediffError, Result};
+
+/// whether `report` is a URL rather than a path
+pub fn is_url(report: &str) -> bool { report.starts_with("http://") || report.starts_with("https://") }
+
+/// the report at `report`, a path or, with `fetch`, a URL downloaded whole
+pub fn load(report: &str, fetch: bool) -> Result<Report> {
+    match is_url(report) {
+        false => Report::load(Path::new(report)),
+        true if !fetch => Err(BlakediffError::Format(format!("{} : reports are only downloaded with --fetch", report))),
+        true => download(report),
+    }
+}
+
+#[cfg(feature = "http")]
+fn download(url: &str) -> Result<Report> { blakediff::http::load(url) }
+
+#[cfg(not(feature = "http"))]
+fn download(url: &str) -> Result<Report> { Err(BlakediffError::Format(format!("{} : blakediff was built without the http feature", url))) }
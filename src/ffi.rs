@@ -0,0 +1,79 @@
+//! C bindings of the engine, declared in `include/blakediff.h` and built into shared and static libraries by the
+//! `blakediff-ffi` crate of `ffi/`. Functions return 0 on success, or the exit code of the error otherwise
+//! (see [`BlakediffError::exit_code`](crate::BlakediffError::exit_code)), NULL arguments being usage errors.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::Path;
+use std::slice;
+
+use crate::report::{Reader, Report};
+use crate::walker::Walker;
+use crate::{compare_reports, hash_file, BlakediffError, Result};
+
+/// called for each hashed file with its hash, size and path, strings being only valid during the call.
+/// A NULL function pointer is `None`.
+pub type BlakediffEntryCallback = Option<extern "C" fn(hash: *const c_char, size: u64, path: *const c_char, user_data: *mut c_void)>;
+
+/// hash every file under `dir`, calling `callback` for each one with `user_data`, from the calling thread.
+/// The scan stops at the first error.
+///
+/// # Safety
+/// `dir` must be a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blakediff_scan(dir: *const c_char, callback: BlakediffEntryCallback, user_data: *mut c_void) -> c_int {
+    if dir.is_null() {
+        return invalid("dir is null");
+    }
+    let Some(callback) = callback else {
+        return invalid("callback is null");
+    };
+    let dir = CStr::from_ptr(dir).to_string_lossy();
+    status(Walker::new(Path::new(dir.as_ref())).iter().try_for_each(|file| {
+        let entry = hash_file(&file?, true, false)?;
+        // paths can't hold NUL on the supported platforms
+        let (hash, path) = (CString::new(entry.hash).unwrap_or_default(), CString::new(entry.path).unwrap_or_default());
+        callback(hash.as_ptr(), entry.size.unwrap_or(0), path.as_ptr(), user_data);
+        Ok(())
+    }))
+}
+
+/// compare two reports held in memory, storing in `json` the comparison as the JSON document of
+/// `blakediff compare --format json`, the reports being named `report_1` and `report_2`.
+/// `json` is left untouched on error, and must be released with [`blakediff_free_string`].
+///
+/// # Safety
+/// `report_1` and `report_2` must point to `len_1` and `len_2` readable bytes, `json` to a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn blakediff_compare(report_1: *const u8, len_1: usize, report_2: *const u8, len_2: usize, json: *mut *mut c_char) -> c_int {
+    if report_1.is_null() || report_2.is_null() || json.is_null() {
+        return invalid("null argument");
+    }
+    let (buffer_1, buffer_2) = (slice::from_raw_parts(report_1, len_1), slice::from_raw_parts(report_2, len_2));
+    status((|| {
+        let r1 = Report::read(Reader::new(buffer_1))?;
+        let r2 = Report::read(Reader::new(buffer_2))?;
+        let comparison = serde_json::to_string(&compare_reports(("report_1", &r1), ("report_2", &r2))).map_err(std::io::Error::from)?;
+        *json = CString::new(comparison).map_err(|e| BlakediffError::Other(e.to_string()))?.into_raw();
+        Ok(())
+    })())
+}
+
+/// release a string allocated by blakediff, doing nothing on NULL
+///
+/// # Safety
+/// `s` must come from blakediff and not be released twice.
+#[no_mangle]
+pub unsafe extern "C" fn blakediff_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => e.exit_code(),
+    }
+}
+
+fn invalid(message: &str) -> c_int { BlakediffError::Format(message.to_owned()).exit_code() }
@@ -0,0 +1,27 @@
+use std::path::Path;
+
+use blakediff::input::Input;
+use blakediff::output::quoted;
+use blakediff::report;
+use blakediff::Result;
+
+use crate::Outcome;
+
+/// hash `file` and display the entries of `reports` with the same hash, the exit code is 1 when there is none
+pub fn find(file: String, reports: Vec<String>) -> Result<Outcome> {
+    let hash = Input::open(Path::new(&file))?.hash()?;
+    let mut found = 0usize;
+    for report_file in &reports {
+        for entry in report::Reader::open(Path::new(report_file))? {
+            let entry = entry?;
+            if entry.matches(&hash) {
+                found += 1;
+                println!("{} : {}", report_file, quoted(&entry.path));
+            }
+        }
+    }
+    if found == 0 {
+        summary!("no copy of {} found", file);
+    }
+    Ok(Outcome::found(found == 0))
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use itertools::Itertools;
+
+use blakediff::report::{self, Entry};
+use blakediff::Result;
+
+use crate::Outcome;
+
+/// check `report_file` line by line : malformed lines, invalid hashes, and paths listed several times.
+/// With `fix`, a repaired copy is written there, keeping the last entry of each path.
+/// The exit code is 1 when an error is found.
+pub fn fsck(report_file: String, fix: Option<String>) -> Result<Outcome> {
+    let mut reader = report::Reader::open(Path::new(&report_file))?;
+    let mut errors = 0usize;
+    // line of each path and its entry
+    let mut entries: HashMap<String, (usize, Entry)> = HashMap::new();
+    while let Some(entry) = reader.next() {
+        let line = reader.line_number();
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors += 1;
+                println!("{}", e);
+                continue;
+            }
+        };
+        if !entry.is_special() && entry.hash != report::EMPTY && (entry.hash.len() != 64 || !entry.hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))) {
+            errors += 1;
+            println!("line {}: invalid hash '{}'", line, entry.hash);
+            continue;
+        }
+        if let Some((previous, known)) = entries.get(&entry.path) {
+            errors += 1;
+            if known.hash == entry.hash {
+                println!("line {}: duplicate entry of line {} for {}", line, previous, entry.path);
+            } else {
+                println!("line {}: conflicting entry with line {} for {}, {} instead of {}", line, previous, entry.path, entry.hash, known.hash);
+            }
+        }
+        entries.insert(entry.path.clone(), (line, entry));
+    }
+    summary!("fsck : {} lines, {} entries, {} errors", reader.line_number(), entries.len(), errors);
+    if let Some(fix) = fix {
+        let fields = reader.fields().to_vec();
+        report::save(Path::new(&fix), &fields, entries.values().sorted_by_key(|(line, _)| *line).map(|(_, entry)| entry))?;
+        summary!("repaired report written to {}", fix);
+    }
+    Ok(Outcome::found(errors > 0))
+}
@@ -0,0 +1,162 @@
+//! Git revisions as sources of `generate` : the blobs of a commit or tree are read from the object database of
+//! the repository through the `git` command, without a checkout. Their paths are the ones of a checkout in the
+//! repository, and their modification time the commit time.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::OnError;
+use crate::report::Entry;
+use crate::source::{Backend, Item};
+use crate::{interrupt, BlakediffError, Result};
+
+/// A revision of a repository : commit, branch, tag, `HEAD~2`...
+pub struct Tree {
+    repo: PathBuf,
+    rev: String,
+    /// commit time, 0 for a bare tree
+    mtime: Duration,
+}
+
+impl Tree {
+    pub fn new(repo: &Path, rev: &str) -> Result<Self> {
+        // fail early on unknown revisions
+        git(repo, &["rev-parse", "--verify", "--quiet", &format!("{}^{{tree}}", rev)]).map_err(|_| BlakediffError::Format(format!("{} : no tree '{}' in this repository", repo.display(), rev)))?;
+        let commit_time = git(repo, &["log", "-1", "--format=%ct", rev, "--"]).ok();
+        let mtime = commit_time.and_then(|time| String::from_utf8_lossy(&time).trim().parse().ok()).map(Duration::from_secs).unwrap_or_default();
+        Ok(Self {
+            repo: repo.to_owned(),
+            rev: rev.to_owned(),
+            mtime,
+        })
+    }
+}
+
+/// output of a git command run in `repo`
+fn git(repo: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git").arg("-C").arg(repo).args(args).stderr(Stdio::piped()).output().map_err(|e| BlakediffError::Other(format!("git : {}", e)))?;
+    if !output.status.success() {
+        return Err(BlakediffError::Other(format!("git {} : {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(output.stdout)
+}
+
+impl Backend for Tree {
+    /// regular and executable files, submodules and symbolic links being skipped
+    fn list(&self) -> Result<Vec<Item>> {
+        let listing = git(&self.repo, &["ls-tree", "-r", "-l", "-z", "--full-tree", &self.rev])?;
+        let mut items = Vec::new();
+        for record in listing.split(|&b| b == 0).filter(|record| !record.is_empty()) {
+            let record = String::from_utf8_lossy(record);
+            // <mode> <type> <object> <size>\t<path>
+            let Some((info, path)) = record.split_once('\t') else { continue };
+            let mut info = info.split_whitespace();
+            let (Some(mode), Some("blob"), Some(oid), Some(size)) = (info.next(), info.next(), info.next(), info.next()) else { continue };
+            if mode == "120000" {
+                log::debug!("symbolic link {} skipped", path);
+                continue;
+            }
+            items.push(Item {
+                path: self.repo.join(path).to_string_lossy().into_owned(),
+                location: oid.to_owned(),
+                size: size.parse().unwrap_or(0),
+                mtime: self.mtime,
+            });
+        }
+        Ok(items)
+    }
+
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>> { Ok(Box::new(io::Cursor::new(git(&self.repo, &["cat-file", "blob", &item.location])?))) }
+
+    /// every blob through a single `git cat-file --batch`, in the order of the tree
+    fn generate(&self, _parallel: bool, with_size: bool, with_mtime: bool, _on_error: Option<OnError>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+        let items = self.list()?;
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| BlakediffError::Other(format!("git : {}", e)))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let oids = items.iter().map(|item| item.location.clone()).collect::<Vec<_>>();
+        // fed from another thread, git blocking on its output otherwise
+        let feeder = thread::spawn(move || oids.iter().try_for_each(|oid| writeln!(stdin, "{}", oid)));
+
+        let hashed = items.iter().try_for_each(|item| {
+            interrupt::check()?;
+            let mut header = String::new();
+            stdout.read_line(&mut header)?;
+            let size = match header.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, "blob", size] => size.parse::<u64>().map_err(|e| BlakediffError::Other(format!("git cat-file : {}", e)))?,
+                _ => return Err(BlakediffError::Other(format!("git cat-file : unexpected answer '{}' for {}", header.trim(), item.path))),
+            };
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut (&mut stdout).take(size), &mut hasher)?;
+            // content is followed by a newline
+            stdout.read_exact(&mut [0])?;
+            write(Entry {
+                hash: hasher.finalize().to_string(),
+                size: with_size.then_some(size),
+                mtime: with_mtime.then_some(item.mtime),
+                xattrs: None,
+                target: None,
+                digests: Vec::new(),
+                path: item.path.clone(),
+            })
+        });
+        if hashed.is_err() {
+            let _ = child.kill();
+        }
+        drop(stdout);
+        let _ = feeder.join();
+        let status = child.wait()?;
+        hashed?;
+        if !status.success() {
+            return Err(BlakediffError::Other(format!("git cat-file --batch : {}", status)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tempdir::TempDir;
+
+    #[test]
+    fn blobs_are_hashed_through_cat_file() {
+        let tmp = TempDir::new("git").unwrap();
+        let repo = tmp.path();
+        std::fs::create_dir_all(repo.join("docs")).unwrap();
+        std::fs::write(repo.join("docs/a.txt"), b"first\n").unwrap();
+        std::fs::write(repo.join("b.txt"), b"second\n").unwrap();
+        let run = |args: &[&str]| git(repo, args).map(|_| ());
+        let committed = run(&["init", "-q"]).and_then(|_| run(&["add", "."])).and_then(|_| run(&["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "-m", "t"]));
+        if committed.is_err() {
+            // no git on this machine
+            return;
+        }
+        let tree = Tree::new(repo, "HEAD").unwrap();
+        let mut entries = Vec::new();
+        tree.generate(false, true, false, None, &mut |entry| {
+            entries.push(entry);
+            Ok(())
+        })
+        .unwrap();
+        let hashed: Vec<_> = entries.iter().map(|e| (e.path.strip_prefix(repo.to_str().unwrap()).unwrap().to_owned(), e.hash.clone(), e.size)).collect();
+        assert_eq!(
+            hashed,
+            [
+                ("/b.txt".to_owned(), blake3::hash(b"second\n").to_string(), Some(7)),
+                ("/docs/a.txt".to_owned(), blake3::hash(b"first\n").to_string(), Some(6)),
+            ]
+        );
+        assert!(Tree::new(repo, "no-such-branch").is_err());
+    }
+}
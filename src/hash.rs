@@ -0,0 +1,30 @@
+use std::path::Path;
+
+use blakediff::input::{Algorithm, Input};
+use blakediff::output;
+use blakediff::{BlakediffError, Result};
+
+use crate::Outcome;
+
+/// display the hash of each file like a report line, or check them all against `check`.
+/// The exit code is 1 when a file can't be read or doesn't match.
+pub fn hash(files: Vec<String>, check: Option<String>, algorithm: Algorithm) -> Result<Outcome> {
+    let check = check.map(|expected| expected.to_ascii_lowercase());
+    let mut failures = 0usize;
+    for file in &files {
+        let digest = algorithm.digest(None).map_err(BlakediffError::Format)?;
+        match (Input::open(Path::new(file)).and_then(|mut input| input.digest(digest)), &check) {
+            (Ok(hash), None) => println!("{} {}", hash, file),
+            (Ok(hash), Some(expected)) if hash == *expected => println!("OK : {}", file),
+            (Ok(_), Some(_)) => {
+                failures += 1;
+                println!("FAILED : {}", file);
+            }
+            (Err(e), _) => {
+                failures += 1;
+                output::print_error(&BlakediffError::Read { path: file.into(), source: e });
+            }
+        }
+    }
+    Ok(Outcome::found(failures > 0))
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use itertools::Itertools;
+
+use blakediff::color::{paint, Style};
+use blakediff::output::quoted;
+use blakediff::report;
+use crate::snapshot::{self, Snapshot};
+use blakediff::{BlakediffError, Result};
+
+/// display the timeline of the snapshots in `store` : files added, changed, moved or removed between successive snapshots
+pub fn history(store: String, name: Option<String>) -> Result<()> {
+    let snapshots = snapshot::list(Path::new(&store), name.as_deref())?;
+    let names = snapshots.iter().map(|s| s.name.as_str()).unique().collect::<Vec<_>>();
+    if names.len() > 1 {
+        return Err(BlakediffError::Format(format!("snapshots of several directories in {} ({}), choose one with --name", store, names.join(", "))));
+    }
+    if snapshots.is_empty() {
+        return Err(BlakediffError::Other(format!("no snapshot in {}", store)));
+    }
+
+    let mut previous: HashMap<String, String> = HashMap::new();
+    for snapshot in &snapshots {
+        let current = load(snapshot)?;
+        let date = snapshot.date();
+        // a path removed while another one appears with its content is a move
+        let removed = previous.iter().filter(|(path, _)| !current.contains_key(*path)).map(|(path, hash)| (hash, path)).into_group_map();
+        let mut moved = Vec::new();
+        for (path, hash) in current.iter().sorted() {
+            match previous.get(path) {
+                Some(old) if old != hash => println!("{} changed : {}", date, quoted(path)),
+                Some(_) => {}
+                None => match removed.get(hash).and_then(|sources| sources.iter().find(|source| !moved.contains(*source))) {
+                    Some(source) => {
+                        println!("{}", paint(Style::Moved, format_args!("{} moved : {} -> {}", date, quoted(source), quoted(path))));
+                        moved.push(*source);
+                    }
+                    None => println!("{}", paint(Style::OnlyInSecond, format_args!("{} added : {}", date, quoted(path)))),
+                },
+            }
+        }
+        removed.values().flatten().filter(|path| !moved.contains(*path)).sorted().for_each(|path| println!("{}", paint(Style::OnlyInFirst, format_args!("{} removed : {}", date, quoted(path)))));
+        previous = current;
+    }
+    Ok(())
+}
+
+/// hash of each path of a snapshot
+fn load(snapshot: &Snapshot) -> Result<HashMap<String, String>> {
+    let mut hashes = HashMap::new();
+    for entry in report::Reader::open(&snapshot.path)? {
+        let entry = entry?;
+        hashes.insert(entry.path, entry.hash);
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use blakediff::report::Entry;
+use blakediff::{BlakediffError, Result};
+
+/// hooks file, for instance :
+/// ```toml
+/// [[hooks]]
+/// exec = "dms-tag --duplicate-of {keeper} {dupe}"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    hooks: Vec<Hook>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Hook {
+    /// command template, see [`run`]
+    exec: String,
+}
+
+/// command templates of the hooks file `path`
+pub fn load(path: &str) -> Result<Vec<String>> {
+    let config: Config = toml::from_str(&fs::read_to_string(path)?).map_err(|e| BlakediffError::Format(format!("invalid hooks file {} : {}", path, e)))?;
+    Ok(config.hooks.into_iter().map(|hook| hook.exec).collect())
+}
+
+/// run each command template on the groups of duplicates, the first file of a group being its keeper.
+/// A template holding `{dupe}` runs once per duplicate of a group, otherwise once per group.
+/// Placeholders are `{keeper}`, `{dupe}`, `{hash}`, and `{dupes}` which expands to one argument per duplicate.
+/// Commands run without a shell, their words being split on whitespace outside of quotes.
+/// A failing command, or one which can't be started, is reported and the next ones still run.
+pub fn run(templates: &[String], groups: &[Vec<Entry>]) -> Result<()> {
+    let (runs, failures) = run_counted(templates, groups)?;
+    summary!("hooks : {} commands run, {} failed", runs, failures);
+    Ok(())
+}
+
+/// commands run and failed by [`run`]
+fn run_counted(templates: &[String], groups: &[Vec<Entry>]) -> Result<(usize, usize)> {
+    let templates = templates.iter().map(|template| split(template)).collect::<Result<Vec<_>>>()?;
+    let (mut runs, mut failures) = (0usize, 0usize);
+    for group in groups {
+        let (keeper, dupes) = group.split_first().unwrap();
+        for words in &templates {
+            let per_dupe = words.iter().any(|word| word.contains("{dupe}"));
+            let targets: Vec<Option<&Entry>> = if per_dupe { dupes.iter().map(Some).collect() } else { vec![None] };
+            for dupe in targets {
+                let args = expand(words, keeper, dupes, dupe);
+                runs += 1;
+                match Command::new(&args[0]).args(&args[1..]).status() {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => {
+                        log::error!("{} failed : {}", args.join(" "), status);
+                        failures += 1;
+                    }
+                    Err(e) => {
+                        log::error!("can't run {} : {}", args[0], e);
+                        failures += 1;
+                    }
+                }
+            }
+        }
+    }
+    Ok((runs, failures))
+}
+
+/// arguments of a command, placeholders replaced
+fn expand(words: &[String], keeper: &Entry, dupes: &[Entry], dupe: Option<&Entry>) -> Vec<String> {
+    let mut args = Vec::new();
+    for word in words {
+        if word == "{dupes}" {
+            args.extend(dupes.iter().map(|e| e.path.clone()));
+            continue;
+        }
+        let mut arg = word.replace("{keeper}", &keeper.path).replace("{hash}", &keeper.hash);
+        if let Some(dupe) = dupe {
+            arg = arg.replace("{dupe}", &dupe.path);
+        }
+        args.push(arg);
+    }
+    args
+}
+
+/// words of a command template, split on whitespace outside of single or double quotes
+fn split(template: &str) -> Result<Vec<String>> {
+    let (mut words, mut word, mut quote, mut in_word) = (Vec::new(), String::new(), None, false);
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(BlakediffError::Format(format!("unterminated quote in hook '{}'", template)));
+    }
+    if in_word {
+        words.push(word);
+    }
+    if words.is_empty() {
+        return Err(BlakediffError::Format("empty hook command".to_owned()));
+    }
+    Ok(words)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> Entry {
+        Entry {
+            hash: String::from("h"),
+            size: None,
+            mtime: None,
+            xattrs: None,
+            path: path.to_owned(),
+            target: None,
+            digests: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn templates_are_split_and_expanded() {
+        let words = split(r#"tag --note "duplicate of {keeper}" '{dupe}' {dupes}"#).unwrap();
+        assert_eq!(words, ["tag", "--note", "duplicate of {keeper}", "{dupe}", "{dupes}"]);
+        let group = [entry("k"), entry("a b"), entry("c")];
+        assert_eq!(expand(&words, &group[0], &group[1..], Some(&group[2])), ["tag", "--note", "duplicate of k", "c", "a b", "c"]);
+        assert!(split("tag 'open").is_err() && split("  ").is_err());
+    }
+
+    #[test]
+    fn commands_which_cant_start_are_counted() {
+        let templates = [String::from("blakediff-no-such-command {dupe}"), String::from("true {keeper}"), String::from("false")];
+        let groups = [vec![entry("k"), entry("a"), entry("b")]];
+        assert_eq!(run_counted(&templates, &groups).unwrap(), (4, 3));
+    }
+}
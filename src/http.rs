@@ -0,0 +1,44 @@
+//! Reports published over HTTP(S) : they are downloaded whole before being read, and checked against the
+//! BLAKE3 given in the fragment of their URL (`https://host/report.txt#blake3=<hash>`), if any. Nothing is
+//! downloaded unless asked for : [`Reader::open`](crate::report::Reader::open) only reads local files.
+
+use std::io::{self, Read};
+use std::time::Duration;
+
+use crate::report::{Reader, Report};
+use crate::Result;
+
+/// URL without its fragment, and the BLAKE3 expected from its content when the fragment is `blake3=<hash>`
+pub fn split_checksum(url: &str) -> (&str, Option<&str>) {
+    match url.split_once('#') {
+        Some((url, fragment)) => (url, fragment.strip_prefix("blake3=")),
+        None => (url, None),
+    }
+}
+
+/// download the content of `url`, failing when it doesn't match the checksum of its fragment
+pub fn fetch(url: &str) -> io::Result<Vec<u8>> {
+    let (location, checksum) = split_checksum(url);
+    let agent = ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(30)).build();
+    let response = agent.get(location).call().map_err(|e| match e {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, format!("{} : not found", location)),
+        e => io::Error::other(e.to_string()),
+    })?;
+    let mut content = Vec::new();
+    response.into_reader().read_to_end(&mut content)?;
+    log::debug!("{} bytes downloaded from {}", content.len(), location);
+    if let Some(expected) = checksum {
+        let actual = blake3::hash(&content).to_string();
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} : blake3 is {}, {} expected", location, actual, expected)));
+        }
+    }
+    Ok(content)
+}
+
+/// download the report at `url`, gzip compressed when its path ends with `.gz`
+pub fn load(url: &str) -> Result<Report> {
+    let (location, _) = split_checksum(url);
+    let path = location.split_once('?').map_or(location, |(path, _)| path);
+    Report::read(Reader::decoded(io::Cursor::new(fetch(url)?), path.ends_with(".gz")))
+}
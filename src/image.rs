@@ -0,0 +1,264 @@
+//! Perceptual hashes of images for `generate --algo phash` : the lowest frequencies of the DCT of a 32×32 grayscale
+//! thumbnail keep the shape of a picture, so that its copies at another resolution, quality or with other metadata
+//! get hashes a few bits apart. PNG and JPEG are decoded by the `image` crate, with their EXIF orientation applied :
+//! a photo rotated by its metadata matches the same photo rotated in its pixels. HEIC and other HEIF images are
+//! decoded by libheif with the `heic` feature, which applies their rotations and mirrors, and refused without it.
+
+use std::f32::consts::PI;
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+use crate::input::Digest;
+
+/// side of the thumbnail
+const SIZE: usize = 32;
+/// larger contents are not decoded
+const MAX_LEN: usize = 256 << 20;
+/// larger images are not decoded, their width and height being read from headers which could claim anything
+const MAX_SIDE: u32 = 1 << 16;
+const MAX_PIXELS: u64 = 1 << 28;
+
+/// why an image can't be hashed
+type Invalid = String;
+
+type Thumbnail = [f32; SIZE * SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Jpeg,
+    /// HEIC, HEIF and AVIF
+    Heif,
+}
+
+/// bytes needed to tell the format of a content
+const MAGIC_LEN: usize = 12;
+
+/// format of a content from its first bytes
+fn format(data: &[u8]) -> Option<Format> {
+    match data {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(Format::Png),
+        [0xff, 0xd8, 0xff, ..] => Some(Format::Jpeg),
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] if [b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1", b"avif"].iter().any(|b| brand.starts_with(*b)) => Some(Format::Heif),
+        _ => None,
+    }
+}
+
+/// whether an image of `width` × `height` pixels can be decoded
+fn check_size(width: u32, height: u32) -> Result<(), Invalid> {
+    match (width, height) {
+        (0, _) | (_, 0) => Err(String::from("image without pixels")),
+        _ if width > MAX_SIDE || height > MAX_SIDE || width as u64 * height as u64 > MAX_PIXELS => Err(String::from("image too large")),
+        _ => Ok(()),
+    }
+}
+
+/// thumbnail of an image
+fn decode(data: &[u8]) -> Result<Thumbnail, Invalid> {
+    let image = match format(data) {
+        Some(Format::Png) => raster(data, ImageFormat::Png)?,
+        Some(Format::Jpeg) => raster(data, ImageFormat::Jpeg)?,
+        Some(Format::Heif) => heif(data)?,
+        None => return Err(String::from("not a PNG, JPEG or HEIF image")),
+    };
+    Ok(thumbnail(&image))
+}
+
+/// PNG or JPEG image as displayed with its EXIF orientation
+fn raster(data: &[u8], format: ImageFormat) -> Result<DynamicImage, Invalid> {
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format).into_decoder().map_err(|e| e.to_string())?;
+    let (width, height) = decoder.dimensions();
+    check_size(width, height)?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// luma of the primary image of a HEIF container, with its transformations applied
+#[cfg(feature = "heic")]
+fn heif(data: &[u8]) -> Result<DynamicImage, Invalid> {
+    use libheif_rs::{ColorSpace, DecodingOptions, HeifContext, LibHeif};
+
+    let context = HeifContext::read_from_bytes(data).map_err(|e| e.to_string())?;
+    let handle = context.primary_image_handle().map_err(|e| e.to_string())?;
+    check_size(handle.width(), handle.height())?;
+    let mut options = DecodingOptions::new();
+    if let Some(options) = options.as_mut() {
+        options.set_convert_hdr_to_8bit(true);
+    }
+    let decoded = LibHeif::new().decode(&handle, ColorSpace::Monochrome, options).map_err(|e| e.to_string())?;
+    let luma = decoded.planes().y.ok_or_else(|| String::from("HEIF image without luma"))?;
+    let pixels = luma.data.chunks(luma.stride).flat_map(|row| &row[..luma.width as usize]).copied().collect();
+    image::GrayImage::from_raw(luma.width, luma.height, pixels).map(DynamicImage::ImageLuma8).ok_or_else(|| String::from("truncated HEIF image"))
+}
+
+#[cfg(not(feature = "heic"))]
+fn heif(_: &[u8]) -> Result<DynamicImage, Invalid> { Err(String::from("HEIC and HEIF images need blakediff built with the heic feature")) }
+
+/// 32×32 grayscale thumbnail of `image`, each pixel being the mean of the ones it covers
+fn thumbnail(image: &DynamicImage) -> Thumbnail {
+    let mut thumbnail = [0f32; SIZE * SIZE];
+    thumbnail.copy_from_slice(image.thumbnail_exact(SIZE as u32, SIZE as u32).to_luma32f().as_raw());
+    thumbnail
+}
+
+/// Perceptual hash of a content fed like a digest, written as 16 hex digits, or `-` for contents which are not PNG,
+/// JPEG or HEIF images, or can't be decoded. Images are kept in memory until finalized, other contents are dropped
+/// after their first bytes, HEIF images keeping them to be reported without the heic feature.
+#[derive(Default)]
+pub struct Phash {
+    data: Vec<u8>,
+    skipped: bool,
+}
+
+impl Phash {
+    pub fn new() -> Self { Self::default() }
+}
+
+impl Digest for Phash {
+    fn update(&mut self, data: &[u8]) {
+        if self.skipped {
+            return;
+        }
+        self.data.extend_from_slice(data);
+        if self.data.len() > MAX_LEN {
+            (self.data, self.skipped) = (Vec::new(), true);
+        } else if self.data.len() >= MAGIC_LEN {
+            match format(&self.data) {
+                Some(Format::Png | Format::Jpeg) => {}
+                Some(Format::Heif) if cfg!(feature = "heic") => {}
+                Some(Format::Heif) => (self.data, self.skipped) = (self.data[..MAGIC_LEN].to_vec(), true),
+                None => (self.data, self.skipped) = (Vec::new(), true),
+            }
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match decode(&self.data) {
+            Ok(thumbnail) => format!("{:016x}", phash(&thumbnail)),
+            Err(invalid) => {
+                match format(&self.data) {
+                    Some(Format::Heif) if !cfg!(feature = "heic") => log::warn!("phash : {}", invalid),
+                    Some(_) => log::debug!("phash : {}", invalid),
+                    None => {}
+                }
+                String::from("-")
+            }
+        }
+    }
+}
+
+/// bits set in the 8×8 lowest frequencies of the DCT of `thumbnail` above their median, row after row
+fn phash(thumbnail: &Thumbnail) -> u64 {
+    let cos = |k: usize, n: usize| ((2 * n + 1) as f32 * k as f32 * PI / (2 * SIZE) as f32).cos();
+    // DCT of the rows, then of the columns
+    let mut rows = [[0f32; 8]; SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, coefficient) in row.iter_mut().enumerate() {
+            *coefficient = (0..SIZE).map(|x| thumbnail[y * SIZE + x] * cos(u, x)).sum();
+        }
+    }
+    let mut low = [0f32; 64];
+    for (i, coefficient) in low.iter_mut().enumerate() {
+        let (v, u) = (i / 8, i % 8);
+        *coefficient = (0..SIZE).map(|y| rows[y][u] * cos(v, y)).sum();
+    }
+    let mut sorted = low;
+    sorted.sort_by(f32::total_cmp);
+    let median = (sorted[31] + sorted[32]) / 2.0;
+    low.iter().fold(0, |hash, &coefficient| hash << 1 | (coefficient > median) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::{Compression, Crc};
+    use image::GrayImage;
+
+    use super::*;
+
+    /// `image` encoded as `format`
+    fn encoded(image: &GrayImage, format: ImageFormat) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    /// `width` × `height` image dark on its left half, or on its top half when `top`
+    fn halves(width: u32, height: u32, top: bool) -> GrayImage { GrayImage::from_fn(width, height, |x, y| image::Luma([if (top && y < height / 2) || (!top && x < width / 2) { 0 } else { 255 }])) }
+
+    fn hash(data: &[u8]) -> String {
+        let mut phash = Phash::new();
+        phash.update(data);
+        phash.finalize_hex()
+    }
+
+    fn png_chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(body);
+        [&(body.len() as u32).to_be_bytes()[..], kind, body, &crc.sum().to_be_bytes()].concat()
+    }
+
+    /// header of an 8-bit grayscale PNG of `width` × `height`, followed by a scanline of its pixels
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&[0; 64]).unwrap();
+        let header = [&width.to_be_bytes()[..], &height.to_be_bytes(), &[8, 0, 0, 0, 0]].concat();
+        [&b"\x89PNG\r\n\x1a\n"[..], &png_chunk(b"IHDR", &header), &png_chunk(b"IDAT", &zlib.finish().unwrap())].concat()
+    }
+
+    #[test]
+    fn copies_hash_alike() {
+        let large = decode(&encoded(&halves(128, 128, false), ImageFormat::Png)).unwrap();
+        assert_eq!((large[0], large[SIZE - 1]), (0.0, 1.0));
+        // a smaller copy, or one compressed with losses, gets the same hash
+        let small = decode(&encoded(&halves(48, 48, false), ImageFormat::Png)).unwrap();
+        assert_eq!(phash(&large), phash(&small));
+        let jpeg = decode(&encoded(&halves(48, 40, false), ImageFormat::Jpeg)).unwrap();
+        assert!((phash(&large) ^ phash(&jpeg)).count_ones() <= 8);
+        assert_ne!(phash(&large), phash(&decode(&encoded(&halves(64, 64, true), ImageFormat::Png)).unwrap()));
+    }
+
+    #[test]
+    fn exif_orientation_is_applied() {
+        // landscape image dark on its left, displayed rotated a quarter turn clockwise : dark on its top
+        let jpeg = encoded(&halves(64, 32, false), ImageFormat::Jpeg);
+        let ifd = [&b"MM\0\x2a\0\0\0\x08\0\x01"[..], &[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0], &[0; 4]].concat();
+        let exif = [&b"Exif\0\0"[..], &ifd].concat();
+        let rotated = [&jpeg[..2], &[0xff, 0xe1], &((exif.len() + 2) as u16).to_be_bytes(), &exif, &jpeg[2..]].concat();
+        let upright = phash(&decode(&encoded(&halves(32, 64, true), ImageFormat::Png)).unwrap());
+        assert!((phash(&decode(&rotated).unwrap()) ^ upright).count_ones() <= 8);
+        assert!((phash(&decode(&jpeg).unwrap()) ^ upright).count_ones() > 8);
+    }
+
+    #[test]
+    fn truncated_images_fail_without_panic() {
+        for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+            let data = encoded(&halves(16, 16, false), format);
+            for len in 0..data.len() {
+                _ = hash(&data[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn too_large_is_refused() {
+        assert_eq!(decode(&png_header(20000, 20000)).unwrap_err(), "image too large");
+        assert!(decode(&png_header(u32::MAX >> 1, 1)).is_err());
+        assert!(decode(&png_header(0, 1)).is_err());
+    }
+
+    #[cfg(not(feature = "heic"))]
+    #[test]
+    fn heic_is_refused() {
+        let data = [&[0, 0, 0, 0x18][..], b"ftypheic", &[0; 64]].concat();
+        assert_eq!(format(&data), Some(Format::Heif));
+        assert!(decode(&data).unwrap_err().contains("heic"));
+        assert_eq!(hash(&data), "-");
+    }
+}
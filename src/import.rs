@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use blakediff::input::Input;
+use blakediff::report::{self, Entry, Field};
+use blakediff::Result;
+
+/// convert the output of fdupes or jdupes (one path per line, groups separated by a blank line) read from `file`,
+/// `-` for the standard input, into a report printed on the standard output.
+/// Only one file of each group is hashed, its hash being recorded for all the others.
+pub fn import_dupes(file: String) -> Result<()> {
+    let reader: Box<dyn BufRead> = if file == "-" { Box::new(io::stdin().lock()) } else { Box::new(io::BufReader::new(File::open(&file)?)) };
+    let mut group = Vec::new();
+    let mut size = None;
+    println!("{}", report::header(&[Field::Hash, Field::Size, Field::Path]));
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            print_group(&group, size);
+            group.clear();
+            size = None;
+        } else if let Some(bytes) = group.is_empty().then(|| parse_size_line(line)).flatten() {
+            // written before each group by `fdupes --size`
+            size = Some(bytes);
+        } else {
+            group.push(line.to_owned());
+        }
+    }
+    print_group(&group, size);
+    Ok(())
+}
+
+/// `1234 bytes each:`, as written by `fdupes --size` and `jdupes --size`
+fn parse_size_line(line: &str) -> Option<u64> { line.strip_suffix(" bytes each:").or_else(|| line.strip_suffix(" byte each:"))?.parse().ok() }
+
+fn print_group(paths: &[String], size: Option<u64>) {
+    if paths.is_empty() {
+        return;
+    }
+    // the first file which can still be read gives the hash of the group
+    let hashed = paths.iter().find_map(|path| Input::open(Path::new(path)).and_then(|mut input| Ok((input.hash()?, input.len()?))).ok());
+    let Some((hash, len)) = hashed else {
+        log::warn!("no file of the group of {} can be read, group skipped", paths[0]);
+        return;
+    };
+    for path in paths {
+        let entry = Entry {
+            hash: hash.clone(),
+            size: Some(size.unwrap_or(len)),
+            mtime: None,
+            xattrs: None,
+            target: None,
+            digests: Vec::new(),
+            path: path.clone(),
+        };
+        println!("{}", entry);
+    }
+}
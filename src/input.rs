@@ -1,9 +1,13 @@
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::Duration;
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{self, Read},
 };
 
+use clap::ValueEnum;
+
 pub enum Input {
     Mmap(io::Cursor<memmap2::Mmap>),
     File(File),
@@ -15,20 +19,76 @@ impl Input {
     // filepaths that appear in a checkfile.
     pub fn open(path: &Path) -> io::Result<Self> {
         let file = File::open(path)?;
-        if let Some(mmap) = maybe_memmap_file(&file)? {
+        let metadata = file.metadata()?;
+        Self::mapped(file, &metadata)
+    }
+
+    // Same as `open`, with `metadata` already known by the caller sparing the stat of the files not worth mapping.
+    // The file may have changed since that metadata was read, so the ones mapped are stat'ed again through the
+    // opened handle, which gives the length of the mapping.
+    pub fn open_with_metadata(path: &Path, metadata: &fs::Metadata) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if !worth_mapping(metadata) {
+            return Ok(Self::File(file));
+        }
+        let metadata = file.metadata()?;
+        Self::mapped(file, &metadata)
+    }
+
+    fn mapped(file: File, metadata: &fs::Metadata) -> io::Result<Self> {
+        if let Some(mmap) = maybe_memmap_file(&file, metadata)? {
             return Ok(Self::Mmap(io::Cursor::new(mmap)));
         }
         Ok(Self::File(file))
     }
 
-    pub fn hash(&mut self) -> io::Result<String> {
-        let mut hasher = blake3::Hasher::new();
+    // Open an input file without ever mmapping it, always taking the buffered path.
+    pub fn open_buffered(path: &Path) -> io::Result<Self> { Ok(Self::File(File::open(path)?)) }
+
+    // Size in bytes of the input, without another stat when it was mmapped.
+    pub fn len(&self) -> io::Result<u64> {
+        match self {
+            Self::Mmap(cursor) => Ok(cursor.get_ref().len() as u64),
+            Self::File(file) => Ok(file.metadata()?.len()),
+        }
+    }
+
+    pub fn is_empty(&self) -> io::Result<bool> { Ok(self.len()? == 0) }
+
+    /// name of the path taken to read the input, `mmap` or `buffered`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Mmap(_) => "mmap",
+            Self::File(_) => "buffered",
+        }
+    }
+
+    /// BLAKE3 of the input
+    pub fn hash(&mut self) -> io::Result<String> { self.digest(blake3::Hasher::new()) }
+
+    /// hex digest of the input with any algorithm, through the same mmap or buffered path
+    pub fn digest<D: Digest>(&mut self, mut digest: D) -> io::Result<String> {
+        self.feed(&mut digest)?;
+        //Ok(hasher.finalize_xof())
+        Ok(digest.finalize_hex())
+    }
+
+    /// hex digests of the input with several algorithms, reading it once : each block read, or the whole mapping,
+    /// goes through all of them
+    pub fn digests(&mut self, digests: Vec<Box<dyn DynDigest>>) -> io::Result<Vec<String>> {
+        let mut fanout = Fanout(digests);
+        self.feed(&mut fanout)?;
+        Ok(fanout.0.into_iter().map(|digest| digest.finalize_boxed()).collect())
+    }
+
+    fn feed(&mut self, digest: &mut impl Digest) -> io::Result<()> {
         match self {
             // The fast path: If we mmapped the file successfully, hash using
             // multiple threads. This doesn't work on stdin, or on some files,
             // and it can also be disabled with --no-mmap.
             Self::Mmap(cursor) => {
-                hasher.update_rayon(cursor.get_ref());
+                digest.update_mapped(cursor.get_ref());
+                advise(cursor.get_ref(), Advice::Dontneed);
             }
             // The slower paths, for stdin or files we didn't/couldn't mmap.
             // This is currently all single-threaded. Doing multi-threaded
@@ -40,11 +100,140 @@ impl Input {
             // one. We might implement that in the future, but since this is
             // the slow path anyway, it's not high priority.
             Self::File(file) => {
-                copy_wide(file, &mut hasher)?;
+                copy_wide(file, digest)?;
             }
         }
-        //Ok(hasher.finalize_xof())
-        Ok(hasher.finalize().to_string())
+        Ok(())
+    }
+}
+
+/// several digests fed with the same data, see [`Input::digests`]
+struct Fanout(Vec<Box<dyn DynDigest>>);
+
+impl Digest for Fanout {
+    fn update(&mut self, data: &[u8]) { self.0.iter_mut().for_each(|digest| Digest::update(digest, data)) }
+
+    fn update_mapped(&mut self, data: &[u8]) { self.0.iter_mut().for_each(|digest| Digest::update_mapped(digest, data)) }
+
+    /// the digests, space separated
+    fn finalize_hex(self) -> String { self.0.into_iter().map(|digest| digest.finalize_boxed()).collect::<Vec<_>>().join(" ") }
+}
+
+/// A hash algorithm fed by [`Input::digest`].
+pub trait Digest {
+    fn update(&mut self, data: &[u8]);
+
+    /// update with a whole mmapped file, the place for multi-threaded hashing
+    fn update_mapped(&mut self, data: &[u8]) { self.update(data) }
+
+    /// lowercase hexadecimal representation of the digest
+    fn finalize_hex(self) -> String;
+}
+
+/// BLAKE3, keyed or not (`blake3::Hasher::new_keyed`)
+impl Digest for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) { blake3::Hasher::update(self, data); }
+
+    fn update_mapped(&mut self, data: &[u8]) { crate::threads::hash(|| self.update_rayon(data)); }
+
+    fn finalize_hex(self) -> String { self.finalize().to_string() }
+}
+
+impl Digest for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) { sha2::Digest::update(self, data) }
+
+    fn finalize_hex(self) -> String { sha2::Digest::finalize(self).iter().map(|b| format!("{:02x}", b)).collect() }
+}
+
+/// XXH3 64 bits, seeded or not (`Xxh3::with_seed`), not cryptographic but much faster
+impl Digest for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) { xxhash_rust::xxh3::Xxh3::update(self, data) }
+
+    fn finalize_hex(self) -> String { format!("{:016x}", self.digest()) }
+}
+
+impl Digest for Box<dyn DynDigest> {
+    fn update(&mut self, data: &[u8]) { self.as_mut().update(data) }
+
+    fn update_mapped(&mut self, data: &[u8]) { self.as_mut().update_mapped(data) }
+
+    fn finalize_hex(self) -> String { self.finalize_boxed() }
+}
+
+/// Object safe version of [`Digest`], to choose the algorithm at runtime.
+pub trait DynDigest: Send {
+    fn update(&mut self, data: &[u8]);
+    fn update_mapped(&mut self, data: &[u8]);
+    fn finalize_boxed(self: Box<Self>) -> String;
+}
+
+impl<D: Digest + Send> DynDigest for D {
+    fn update(&mut self, data: &[u8]) { Digest::update(self, data) }
+
+    fn update_mapped(&mut self, data: &[u8]) { Digest::update_mapped(self, data) }
+
+    fn finalize_boxed(self: Box<Self>) -> String { (*self).finalize_hex() }
+}
+
+/// hash algorithms available through [`Input::digest`]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Algorithm {
+    #[default]
+    Blake3,
+    Sha256,
+    Xxh3,
+    /// perceptual hash of PNG and JPEG images, needs the images feature, and of HEIC images with the heic feature
+    Phash,
+    /// acoustic fingerprint of audio files, decoded by ffmpeg
+    Audio,
+    /// content-defined chunks of `generate --cdc`, see [`Chunker`](crate::cdc::Chunker)
+    #[value(skip)]
+    Chunks,
+    /// similarity digests of `generate --similarity`, see [`similarity`](crate::similarity)
+    #[value(skip)]
+    Tlsh,
+    #[value(skip)]
+    Ssdeep,
+}
+
+impl Algorithm {
+    /// name of the algorithm, and of its column in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Xxh3 => "xxh3",
+            Algorithm::Phash => "phash",
+            Algorithm::Audio => "audio",
+            Algorithm::Chunks => "chunks",
+            Algorithm::Tlsh => "tlsh",
+            Algorithm::Ssdeep => "ssdeep",
+        }
+    }
+
+    /// a new digest of this algorithm, keyed with `key` when given : BLAKE3 keyed mode,
+    /// or XXH3 seeded with the first 8 bytes of the key. SHA-256 has no keyed variant.
+    pub fn digest(self, key: Option<&[u8; 32]>) -> Result<Box<dyn DynDigest>, String> {
+        Ok(match (self, key) {
+            (Algorithm::Blake3, None) => Box::new(blake3::Hasher::new()),
+            (Algorithm::Blake3, Some(key)) => Box::new(blake3::Hasher::new_keyed(key)),
+            (Algorithm::Sha256, None) => Box::new(<sha2::Sha256 as sha2::Digest>::new()),
+            (Algorithm::Sha256, Some(_)) => return Err(String::from("sha256 has no keyed variant")),
+            (Algorithm::Xxh3, None) => Box::new(xxhash_rust::xxh3::Xxh3::new()),
+            (Algorithm::Xxh3, Some(key)) => Box::new(xxhash_rust::xxh3::Xxh3::with_seed(u64::from_le_bytes(key[..8].try_into().unwrap()))),
+            #[cfg(feature = "images")]
+            (Algorithm::Phash, None) => Box::new(crate::image::Phash::new()),
+            #[cfg(not(feature = "images"))]
+            (Algorithm::Phash, None) => return Err(String::from("phash : blakediff was built without the images feature")),
+            (Algorithm::Phash, Some(_)) => return Err(String::from("phash has no keyed variant")),
+            (Algorithm::Audio, None) => Box::new(crate::audio::Fingerprint::new()),
+            (Algorithm::Audio, Some(_)) => return Err(String::from("audio fingerprints have no keyed variant")),
+            (Algorithm::Chunks, None) => Box::new(crate::cdc::Chunker::new()),
+            (Algorithm::Chunks, Some(_)) => return Err(String::from("chunks have no keyed variant")),
+            (Algorithm::Tlsh, None) => Box::new(crate::similarity::Tlsh::new()),
+            (Algorithm::Ssdeep, None) => Box::new(crate::similarity::Ssdeep::new()),
+            (Algorithm::Tlsh | Algorithm::Ssdeep, Some(_)) => return Err(String::from("similarity digests have no keyed variant")),
+        })
     }
 }
 
@@ -61,8 +250,13 @@ impl Read for Input {
 // that we support, but `std::io::copy` currently uses 8 KiB. Most platforms
 // can support at least 64 KiB, and there's some performance benefit to using
 // bigger reads, so that's what we use here.
-fn copy_wide(mut reader: impl Read, hasher: &mut blake3::Hasher) -> io::Result<u64> {
-    let mut buffer = [0; 65536];
+static READ_BUFFER: AtomicUsize = AtomicUsize::new(65536);
+
+/// size of the reads of files which are not mapped, for the whole process
+pub fn set_read_buffer(size: usize) { READ_BUFFER.store(size.max(1), Ordering::Relaxed) }
+
+fn copy_wide(mut reader: impl Read, hasher: &mut impl Digest) -> io::Result<u64> {
+    let mut buffer = vec![0; READ_BUFFER.load(Ordering::Relaxed)];
     let mut total = 0;
     loop {
         match reader.read(&mut buffer) {
@@ -77,25 +271,177 @@ fn copy_wide(mut reader: impl Read, hasher: &mut blake3::Hasher) -> io::Result<u
     }
 }
 
-// Mmap a file, if it looks like a good idea. Return None in cases where we
-// know mmap will fail, or if the file is short enough that mmapping isn't
-// worth it. However, if we do try to mmap and it fails, return the error.
-fn maybe_memmap_file(file: &File) -> io::Result<Option<memmap2::Mmap>> {
-    let metadata = file.metadata()?;
+static RETRIES: AtomicUsize = AtomicUsize::new(0);
+/// in milliseconds
+static RETRY_DELAY: AtomicU64 = AtomicU64::new(1000);
+
+/// read files up to `retries` more times after a transient error, waiting `delay` in between, for the whole process
+pub fn set_retries(retries: usize, delay: Duration) {
+    RETRIES.store(retries, Ordering::Relaxed);
+    RETRY_DELAY.store(delay.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// call `read` on the file at `path` again after the transient errors of network filesystems and failing disks,
+/// as many times as [`set_retries`] allows
+pub fn retry<T>(path: &Path, mut read: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let retries = RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        match read() {
+            Err(e) if attempt < retries && transient(&e) => {
+                attempt += 1;
+                let delay = Duration::from_millis(RETRY_DELAY.load(Ordering::Relaxed));
+                log::warn!("{} : {}, retrying in {:?} ({}/{})", path.display(), e, delay, attempt, retries);
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// an error which may not happen again : EIO, EAGAIN, a timeout or a dropped connection
+fn transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    #[cfg(unix)]
+    let os = [libc::EIO];
+    // ERROR_UNEXP_NET_ERR, ERROR_NETNAME_DELETED, ERROR_SEM_TIMEOUT
+    #[cfg(windows)]
+    let os = [59, 64, 121];
+    #[cfg(not(any(unix, windows)))]
+    let os: [i32; 0] = [];
+    matches!(e.kind(), Interrupted | WouldBlock | TimedOut | ConnectionReset | ConnectionAborted) || e.raw_os_error().is_some_and(|code| os.contains(&code))
+}
+
+static MMAP: AtomicBool = AtomicBool::new(true);
+
+/// memory advice given to the kernel about mapped files, see madvise(2)
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// files are read sequentially, pages can be read ahead aggressively and dropped soon after
+    Sequential,
+    /// back the mappings with huge pages, reducing page faults (linux)
+    Hugepage,
+    /// release the pages of a file once hashed
+    Dontneed,
+}
+
+/// advice enabled by `set_advice`, one bit per variant
+static ADVICE: AtomicU8 = AtomicU8::new(0);
+
+/// give `advice` to the kernel about the files mapped by `Input::open`, for the whole process
+pub fn set_advice(advice: &[Advice]) { ADVICE.store(advice.iter().fold(0, |bits, &a| bits | 1 << a as u8), Ordering::Relaxed) }
+
+/// give `advice` about `map` when it is enabled, failures being harmless
+fn advise(map: &memmap2::Mmap, advice: Advice) {
+    if ADVICE.load(Ordering::Relaxed) & 1 << advice as u8 == 0 {
+        return;
+    }
+    #[cfg(unix)]
+    let result = match advice {
+        Advice::Sequential => map.advise(memmap2::Advice::Sequential),
+        #[cfg(target_os = "linux")]
+        Advice::Hugepage => map.advise(memmap2::Advice::HugePage),
+        #[cfg(not(target_os = "linux"))]
+        Advice::Hugepage => Ok(()),
+        Advice::Dontneed => map.advise(memmap2::Advice::DontNeed),
+    };
+    #[cfg(not(unix))]
+    let result: io::Result<()> = Ok(());
+    if let Err(e) = result {
+        log::debug!("madvise {:?} failed : {}", advice, e);
+    }
+}
+
+/// allow or forbid `Input::open` to map files in memory, for the whole process
+pub fn set_mmap(enabled: bool) { MMAP.store(enabled, Ordering::Relaxed) }
+
+// Whether a file of `metadata` should be mapped : false when we know mmap will
+// fail, or if the file is short enough that mmapping isn't worth it.
+fn worth_mapping(metadata: &fs::Metadata) -> bool {
+    let file_size = metadata.len();
+    MMAP.load(Ordering::Relaxed) &&
+        metadata.is_file() && // Not a real file.
+        file_size <= isize::MAX as u64 && // Too long to safely map. https://github.com/danburkert/memmap-rs/issues/69
+        file_size != 0 && // Mapping an empty file currently fails. https://github.com/danburkert/memmap-rs/issues/72
+        file_size >= 16 * 1024 // Mapping small files is not worth it.
+}
+
+// Mmap a file, if it looks like a good idea, `metadata` being the one of the
+// opened `file`. If we do try to mmap and it fails, return the error.
+fn maybe_memmap_file(file: &File, metadata: &fs::Metadata) -> io::Result<Option<memmap2::Mmap>> {
     let file_size = metadata.len();
     Ok(
-        if !metadata.is_file() ||// Not a real file.
-            file_size > isize::max_value() as u64 ||// Too long to safely map. https://github.com/danburkert/memmap-rs/issues/69
-            file_size == 0 || // Mapping an empty file currently fails. https://github.com/danburkert/memmap-rs/issues/72       
-            file_size < 16 * 1024 // Mapping small files is not worth it.
-        {
-            
+        if !worth_mapping(metadata) {
             None
         } else {
-            // Explicitly set the length of the memory map, so that filesystem
-            // changes can't race to violate the invariants we just checked.
+            // Map the length the opened file had when stat'ed, which the checks above
+            // were made on. A file truncated while mapped still faults on the pages
+            // past its new end : reports of files modified while hashed are unreliable
+            // either way, and the buffered path is there for such files.
             let map = unsafe { memmap2::MmapOptions::new().len(file_size as usize).map(file)? };
+            advise(&map, Advice::Sequential);
+            advise(&map, Advice::Hugepage);
             Some(map)
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tempdir::TempDir;
+
+    fn file(dir: &TempDir, name: &str, content: &[u8]) -> std::path::PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn digests(path: &Path, algorithms: &[Algorithm]) -> Vec<String> { Input::open(path).unwrap().digests(algorithms.iter().map(|a| a.digest(None).unwrap()).collect()).unwrap() }
+
+    #[test]
+    fn known_digests() {
+        let dir = TempDir::new("input-digests").unwrap();
+        let abc = file(&dir, "abc", b"abc");
+        let empty = file(&dir, "empty", b"");
+        let algorithms = [Algorithm::Blake3, Algorithm::Sha256, Algorithm::Xxh3];
+        assert_eq!(
+            digests(&abc, &algorithms)[..2],
+            ["6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"]
+        );
+        assert_eq!(
+            digests(&empty, &algorithms),
+            [crate::report::EMPTY_HASH, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "2d06800538d394c2"]
+        );
+        assert_eq!(Input::open(&empty).unwrap().hash().unwrap(), crate::report::EMPTY_HASH);
+        assert!(Algorithm::Sha256.digest(Some(&[0; 32])).is_err());
+    }
+
+    #[test]
+    fn mapped_and_buffered_reads_agree() {
+        let content = (0..1_000_003u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<_>>();
+        let dir = TempDir::new("input-mapped").unwrap();
+        let path = file(&dir, "large", &content);
+        let mut mapped = Input::open(&path).unwrap();
+        let mut buffered = Input::open_buffered(&path).unwrap();
+        assert_eq!((mapped.kind(), buffered.kind()), ("mmap", "buffered"));
+        assert_eq!((mapped.len().unwrap(), buffered.len().unwrap()), (content.len() as u64, content.len() as u64));
+        let expected = blake3::hash(&content).to_string();
+        assert_eq!(mapped.hash().unwrap(), expected);
+        assert_eq!(buffered.hash().unwrap(), expected);
+
+        // several algorithms in one read give the digests of separate reads
+        let algorithms = [Algorithm::Blake3, Algorithm::Sha256, Algorithm::Xxh3];
+        let separate = algorithms.iter().map(|a| Input::open_buffered(&path).unwrap().digest(a.digest(None).unwrap()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(digests(&path, &algorithms), separate);
+        let key = [7u8; 32];
+        assert_eq!(Input::open(&path).unwrap().digest(blake3::Hasher::new_keyed(&key)).unwrap(), blake3::keyed_hash(&key, &content).to_string());
+
+        // the file truncated since the walker read its metadata is mapped with its length when opened
+        let stale = fs::metadata(&path).unwrap();
+        fs::write(&path, &content[..20_000]).unwrap();
+        let mut truncated = Input::open_with_metadata(&path, &stale).unwrap();
+        assert_eq!((truncated.kind(), truncated.len().unwrap()), ("mmap", 20_000));
+        assert_eq!(truncated.hash().unwrap(), blake3::hash(&content[..20_000]).to_string());
+    }
+}
@@ -0,0 +1,36 @@
+//! Ctrl-C handling : long running commands check [`check`] between files, so that they stop cleanly
+//! instead of dying in the middle of a line.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{BlakediffError, Result};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// catch SIGINT and SIGTERM, a second signal killing the process right away
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handler(signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+    let handler = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// true once a signal was caught
+pub fn interrupted() -> bool { INTERRUPTED.load(Ordering::SeqCst) }
+
+/// fail with [`BlakediffError::Interrupted`] once a signal was caught
+pub fn check() -> Result<()> {
+    if interrupted() {
+        return Err(BlakediffError::Interrupted);
+    }
+    Ok(())
+}
@@ -0,0 +1,477 @@
+//! blakediff engine : hash the files of a directory into a report, find the duplicates of a report
+//! and compare two reports, returning structured results instead of printing them.
+
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::path::Path;
+use std::time::Instant;
+
+use itertools::Itertools;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+
+use crate::color::{paint, Style};
+use crate::columnar::{Columns, Kind, Value};
+use crate::input::{Algorithm, Input};
+use crate::output::{quoted, Render, Seq, Size};
+use crate::report::{Entry, Field, Report};
+use crate::similarity::Similarity;
+use crate::walker::FileEntry;
+
+pub mod ads;
+pub mod atomic;
+pub mod audio;
+pub mod cache;
+pub mod cdc;
+pub mod clones;
+pub mod color;
+pub mod columnar;
+pub mod error;
+pub mod exclude;
+#[cfg(feature = "blakediff-ffi")]
+pub mod ffi;
+pub mod git;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "images")]
+pub mod image;
+pub mod input;
+pub mod interrupt;
+#[cfg(feature = "oci")]
+pub mod oci;
+pub mod output;
+pub mod pipeline;
+#[cfg(feature = "python")]
+mod python;
+pub mod report;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod scan;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+pub mod similarity;
+pub mod source;
+#[cfg(feature = "tar")]
+pub mod tarball;
+pub mod tempdir;
+pub mod threads;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod unicode;
+pub mod walker;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+pub mod xattrs;
+
+pub use crate::error::{BlakediffError, Result};
+
+/// hash every file under `dir` into a report entry, with the requested metadata, in walk order
+pub fn generate(dir: &Path, parallel: bool, with_size: bool, with_mtime: bool) -> Result<Vec<Entry>> { pipeline::hash_tree(dir, parallel, with_size, with_mtime) }
+
+/// groups of paths sharing the same hash in a report, sorted
+pub fn find_duplicates_in_report(report_file: &Path) -> Result<Vec<Vec<String>>> {
+    Ok(analyze(report_file)?.into_iter().map(|group| group.into_iter().map(|e| e.path).collect()).collect())
+}
+
+/// groups of entries sharing the same hash in a report, sorted by path
+pub fn analyze(report_file: &Path) -> Result<Vec<Vec<Entry>>> {
+    Ok(Report::load(report_file)?.duplicates().into_iter().map(|group| group.into_iter().cloned().collect()).collect())
+}
+
+/// for each hash of `from` missing in `to`, its last entry, sorted by path. When both reports record the extended
+/// attributes, a hash found in `to` only with other attributes is missing too.
+fn missing_entries(from: &Report, to: &Report) -> Vec<Entry> {
+    let with_xattrs = from.fields().contains(&Field::Xattrs) && to.fields().contains(&Field::Xattrs);
+    from.hashes()
+        .filter_map(|hash| match to.contains_hash(hash) {
+            false => from.by_hash(hash).last(),
+            true if with_xattrs => from.by_hash(hash).filter(|e| !to.by_hash(hash).any(|other| other.xattrs == e.xattrs)).last(),
+            true => None,
+        })
+        .sorted_by(|a, b| a.path.cmp(&b.path))
+        .cloned()
+        .collect()
+}
+
+/// entries of `from` whose content is in no other file of `from` nor in `other`, sorted by path : the files only this
+/// report holds a copy of
+pub fn unique_in(from: &Report, other: &Report) -> Vec<Entry> { from.unique().into_iter().filter(|e| !other.contains_hash(&e.hash)).cloned().collect() }
+
+/// total size of `entries`, None if no entry carries its size
+pub fn total_size(entries: &[Entry]) -> Option<u64> { entries.iter().filter_map(|e| e.size).fold(None, |total, size| Some(total.unwrap_or(0) + size)) }
+
+/// result of the comparison of two reports
+pub struct Comparison {
+    /// for each report, its entries whose hash is missing in the other one
+    pub only_in: [(String, Vec<Entry>); 2],
+    /// for each report, its groups of duplicates
+    pub duplicates: [(String, Vec<Group>); 2],
+    /// files only in one report each whose similarity digests are close, with `compare --fuzzy`
+    pub similar: Vec<Similar>,
+}
+
+/// Paths of a report sharing a hash.
+#[derive(Debug, Clone, Serialize)]
+pub struct Group {
+    pub hash: String,
+    pub paths: Vec<String>,
+}
+
+/// A file of the first report and a file of the second one, each missing in the other report, whose similarity digests
+/// are close.
+pub struct Similar {
+    pub similarity: Similarity,
+    pub first: Entry,
+    pub second: Entry,
+    /// distance between their digests, see [`Similarity::distance`]
+    pub distance: u32,
+}
+
+impl Comparison {
+    /// true when a report holds files missing in the other one
+    pub fn has_differences(&self) -> bool { self.only_in.iter().any(|(_, entries)| !entries.is_empty()) || !self.similar.is_empty() }
+
+    /// move the files only in one report close to a file only in the other one, within `threshold` or the default
+    /// threshold of each digest, from `only_in` to `similar`. Each digest recorded in both reports pairs the files
+    /// left by the previous ones.
+    pub fn pair_similar(&mut self, r1: &Report, r2: &Report, threshold: Option<u32>) -> Result<()> {
+        let recorded = |report: &Report, similarity: Similarity| report.iter().any(|entry| entry.digest(similarity.algorithm()).is_some());
+        let similarities = [Similarity::Tlsh, Similarity::Ssdeep, Similarity::Phash, Similarity::Audio].into_iter().filter(|&similarity| recorded(r1, similarity) && recorded(r2, similarity)).collect::<Vec<_>>();
+        if similarities.is_empty() {
+            return Err(BlakediffError::Format(String::from("--fuzzy needs two reports recording the same digests of `generate --similarity` or `--algo phash,audio`")));
+        }
+        for similarity in similarities {
+            let [(_, first), (_, second)] = &self.only_in;
+            let pairs = similarity::similar_pairs(first, second, similarity, threshold.unwrap_or(similarity.threshold())).into_iter().map(|(first, second, distance)| (first.path.clone(), second.path.clone(), distance)).collect::<Vec<_>>();
+            for (first, second, distance) in pairs {
+                let [(_, only_first), (_, only_second)] = &mut self.only_in;
+                let take = |entries: &mut Vec<Entry>, path: &str| entries.remove(entries.iter().position(|e| e.path == path).expect("paired from these entries"));
+                self.similar.push(Similar {
+                    similarity,
+                    first: take(only_first, &first),
+                    second: take(only_second, &second),
+                    distance,
+                });
+            }
+        }
+        self.similar.sort_by(|a, b| a.first.path.cmp(&b.first.path));
+        Ok(())
+    }
+}
+
+/// compare two reports : the files of each one missing in the other, and their duplicates
+pub fn compare(report_1: &str, report_2: &str) -> Result<Comparison> {
+    let r1 = Report::load(Path::new(report_1))?;
+    let r2 = Report::load(Path::new(report_2))?;
+    Ok(compare_reports((report_1, &r1), (report_2, &r2)))
+}
+
+/// compare two reports already in memory, each given with the name to display
+pub fn compare_reports((name_1, r1): (&str, &Report), (name_2, r2): (&str, &Report)) -> Comparison {
+    let groups = |report: &Report| {
+        report
+            .duplicates()
+            .into_iter()
+            .map(|group| Group {
+                hash: group[0].hash.clone(),
+                paths: group.into_iter().map(|e| e.path.clone()).collect(),
+            })
+            .collect()
+    };
+    Comparison {
+        only_in: [(name_1.to_owned(), missing_entries(r1, r2)), (name_2.to_owned(), missing_entries(r2, r1))],
+        duplicates: [(name_1.to_owned(), groups(r1)), (name_2.to_owned(), groups(r2))],
+        similar: Vec::new(),
+    }
+}
+
+/// same as [`compare`], also pairing the files only in one report with a file only in the other one whose similarity
+/// digests are close, see [`Comparison::pair_similar`]
+pub fn compare_fuzzy(report_1: &str, report_2: &str, threshold: Option<u32>) -> Result<Comparison> {
+    let r1 = Report::load(Path::new(report_1))?;
+    let r2 = Report::load(Path::new(report_2))?;
+    let mut comparison = compare_reports((report_1, &r1), (report_2, &r2));
+    comparison.pair_similar(&r1, &r2, threshold)?;
+    Ok(comparison)
+}
+
+impl Serialize for Comparison {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct File<'a> {
+            hash: &'a str,
+            path: &'a str,
+            size: Option<u64>,
+        }
+        #[derive(Serialize)]
+        struct OnlyIn<'a, S> {
+            report: &'a str,
+            files: S,
+            total_size: Option<u64>,
+        }
+        #[derive(Serialize)]
+        struct Duplicates<'a> {
+            report: &'a str,
+            groups: &'a [Group],
+        }
+        #[derive(Serialize)]
+        struct Similar<'a> {
+            digest: &'static str,
+            distance: u32,
+            first: File<'a>,
+            second: File<'a>,
+        }
+        let mut state = serializer.serialize_struct("Comparison", 3)?;
+        let only_in = || {
+            self.only_in.iter().map(|(report, entries)| OnlyIn {
+                report,
+                files: Seq(move || entries.iter().map(|e| File { hash: &e.hash, path: &e.path, size: e.size })),
+                total_size: total_size(entries),
+            })
+        };
+        state.serialize_field("only_in", &Seq(only_in))?;
+        let duplicates = || self.duplicates.iter().map(|(report, groups)| Duplicates { report, groups });
+        state.serialize_field("duplicates", &Seq(duplicates))?;
+        let similar = || {
+            self.similar.iter().map(|pair| Similar {
+                digest: pair.similarity.algorithm().name(),
+                distance: pair.distance,
+                first: File { hash: &pair.first.hash, path: &pair.first.path, size: pair.first.size },
+                second: File { hash: &pair.second.hash, path: &pair.second.path, size: pair.second.size },
+            })
+        };
+        state.serialize_field("similar", &Seq(similar))?;
+        state.end()
+    }
+}
+
+/// CSV line of a [`Comparison`]
+#[derive(Serialize)]
+pub struct ComparisonRow<'a> {
+    /// `only_in`, `similar`, `duplicate` or `unique`
+    status: &'static str,
+    report: &'a str,
+    /// number of the group of duplicates or of the pair of similar files, from 1
+    group: Option<usize>,
+    /// distance between the similarity digests of a pair of similar files
+    distance: Option<u32>,
+    hash: &'a str,
+    path: &'a str,
+    size: Option<u64>,
+}
+
+impl Columns for ComparisonRow<'_> {
+    const COLUMNS: &'static [(&'static str, Kind)] = &[("status", Kind::Text), ("report", Kind::Text), ("group", Kind::Unsigned), ("distance", Kind::Unsigned), ("hash", Kind::Text), ("path", Kind::Text), ("size", Kind::Unsigned)];
+
+    fn values(&self) -> Vec<Value<'_>> {
+        let unsigned = |value: Option<u64>| value.map_or(Value::Null, Value::Unsigned);
+        vec![Value::Text(self.status), Value::Text(self.report), unsigned(self.group.map(|group| group as u64)), unsigned(self.distance.map(u64::from)), Value::Text(self.hash), Value::Text(self.path), unsigned(self.size)]
+    }
+}
+
+impl Render for Comparison {
+    type Row<'a> = ComparisonRow<'a>;
+
+    fn write_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        for ((report, entries), style) in self.only_in.iter().zip([Style::OnlyInFirst, Style::OnlyInSecond]) {
+            for e in entries {
+                match e.size {
+                    Some(size) => writeln!(out, "{}", paint(style, format_args!("only in {} : {} ({})", report, quoted(&e.path), Size(size))))?,
+                    None => writeln!(out, "{}", paint(style, format_args!("only in {} : {}", report, quoted(&e.path))))?,
+                }
+            }
+            if let Some(total) = total_size(entries).filter(|_| !output::quiet()) {
+                writeln!(out, "{}", paint(style, format_args!("total only in {} : {} files, {}", report, entries.len(), Size(total))))?;
+            }
+        }
+        for pair in &self.similar {
+            writeln!(out, "{}", paint(Style::Duplicate, format_args!("similar [{}, distance {}] : {} 🟰 {}", pair.similarity.algorithm().name(), pair.distance, quoted(&pair.first.path), quoted(&pair.second.path))))?;
+        }
+        for (_, groups) in &self.duplicates {
+            for group in groups {
+                writeln!(out, "{}", paint(Style::Duplicate, format_args!("{} duplicates : {}", group.hash, group.paths.iter().map(|path| quoted(path)).join(" 🟰 "))))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rows(&self) -> Box<dyn Iterator<Item = ComparisonRow<'_>> + '_> {
+        let only_in = self.only_in.iter().flat_map(|(report, entries)| {
+            entries.iter().map(move |e| ComparisonRow {
+                status: "only_in",
+                report,
+                group: None,
+                distance: None,
+                hash: &e.hash,
+                path: &e.path,
+                size: e.size,
+            })
+        });
+        let duplicates = self.duplicates.iter().flat_map(|(report, groups)| {
+            groups.iter().enumerate().flat_map(move |(number, group)| {
+                group.paths.iter().map(move |path| ComparisonRow {
+                    status: "duplicate",
+                    report,
+                    group: Some(number + 1),
+                    distance: None,
+                    hash: &group.hash,
+                    path,
+                    size: None,
+                })
+            })
+        });
+        let [(name_1, _), (name_2, _)] = &self.only_in;
+        let similar = self.similar.iter().enumerate().flat_map(move |(pair, similar)| {
+            [(name_1, &similar.first), (name_2, &similar.second)].into_iter().map(move |(report, e)| ComparisonRow {
+                status: "similar",
+                report,
+                group: Some(pair + 1),
+                distance: Some(similar.distance),
+                hash: &e.hash,
+                path: &e.path,
+                size: e.size,
+            })
+        });
+        Box::new(only_in.chain(similar).chain(duplicates))
+    }
+}
+
+/// files of a report held by no other file, see [`unique_in`]
+pub struct Unique {
+    pub report: String,
+    pub entries: Vec<Entry>,
+}
+
+impl Serialize for Unique {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct File<'a> {
+            hash: &'a str,
+            path: &'a str,
+            size: Option<u64>,
+        }
+        let mut state = serializer.serialize_struct("Unique", 3)?;
+        state.serialize_field("report", &self.report)?;
+        state.serialize_field("files", &Seq(|| self.entries.iter().map(|e| File { hash: &e.hash, path: &e.path, size: e.size })))?;
+        state.serialize_field("total_size", &total_size(&self.entries))?;
+        state.end()
+    }
+}
+
+impl Render for Unique {
+    type Row<'a> = ComparisonRow<'a>;
+
+    fn write_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        for e in &self.entries {
+            match e.size {
+                Some(size) => writeln!(out, "unique in {} : {} ({})", self.report, quoted(&e.path), Size(size))?,
+                None => writeln!(out, "unique in {} : {}", self.report, quoted(&e.path))?,
+            }
+        }
+        if let Some(total) = total_size(&self.entries).filter(|_| !output::quiet()) {
+            writeln!(out, "total unique in {} : {} files, {}", self.report, self.entries.len(), Size(total))?;
+        }
+        Ok(())
+    }
+
+    fn rows(&self) -> Box<dyn Iterator<Item = ComparisonRow<'_>> + '_> {
+        Box::new(self.entries.iter().map(|e| ComparisonRow {
+            status: "unique",
+            report: &self.report,
+            group: None,
+            distance: None,
+            hash: &e.hash,
+            path: &e.path,
+            size: e.size,
+        }))
+    }
+}
+
+/// hash the file at `path` into a report entry, with the requested metadata
+pub fn hash_entry(path: &Path, with_size: bool, with_mtime: bool) -> io::Result<Entry> { hash_with_metadata(path, &path.metadata()?, with_size, with_mtime, &[]) }
+
+/// same as [`hash_entry`] for a file found by a [`Walker`](walker::Walker), reusing its metadata instead of another stat.
+/// A symbolic link the walker didn't follow is recorded with its target, see [`hash_link`], and a special file with its
+/// type, without being opened.
+pub fn hash_file(file: &FileEntry, with_size: bool, with_mtime: bool) -> io::Result<Entry> { hash_file_digests(file, with_size, with_mtime, &[]) }
+
+/// same as [`hash_file`], also digesting the content of regular files with `digests` in the same read
+pub fn hash_file_digests(file: &FileEntry, with_size: bool, with_mtime: bool, digests: &[Algorithm]) -> io::Result<Entry> {
+    if let Some(kind) = walker::special(&file.metadata) {
+        return Ok(Entry {
+            hash: kind.to_owned(),
+            size: if with_size { Some(0) } else { None },
+            mtime: if with_mtime { Some(report::mtime(&file.metadata)) } else { None },
+            xattrs: None,
+            target: None,
+            digests: Vec::new(),
+            path: file.path.to_string_lossy().into_owned(),
+        });
+    }
+    match file.metadata.is_symlink() {
+        true => hash_link(&file.path, &file.metadata, with_size, with_mtime),
+        false => hash_with_metadata(&file.path, &file.metadata, with_size, with_mtime, digests),
+    }
+}
+
+/// context of the hashes of link targets, which never match the hash of a file whose content is the same path
+const LINK_CONTEXT: &str = "blakediff 2024 symbolic link target";
+
+/// entry of the symbolic link at `path` itself : the BLAKE3 of the path it points to, which is its target
+pub fn hash_link(path: &Path, metadata: &fs::Metadata, with_size: bool, with_mtime: bool) -> io::Result<Entry> {
+    let target = fs::read_link(path)?;
+    let mut hasher = blake3::Hasher::new_derive_key(LINK_CONTEXT);
+    hasher.update(target.as_os_str().as_encoded_bytes());
+    Ok(Entry {
+        hash: hasher.finalize().to_hex().to_string(),
+        size: if with_size { Some(metadata.len()) } else { None },
+        mtime: if with_mtime { Some(report::mtime(metadata)) } else { None },
+        xattrs: None,
+        target: Some(target.to_string_lossy().into_owned()),
+        digests: Vec::new(),
+        path: path.to_string_lossy().into_owned(),
+    })
+}
+
+fn hash_with_metadata(path: &Path, metadata: &fs::Metadata, with_size: bool, with_mtime: bool, digests: &[Algorithm]) -> io::Result<Entry> {
+    let start = Instant::now();
+    let (hash, digests, kind) = input::retry(path, || {
+        let mut input = Input::open_with_metadata(path, metadata)?;
+        if digests.is_empty() {
+            return Ok((input.hash()?, Vec::new(), input.kind()));
+        }
+        let algorithms = iter::once(Algorithm::Blake3).chain(digests.iter().copied());
+        let mut hashes = input.digests(algorithms.clone().map(|algorithm| algorithm.digest(None).map_err(io::Error::other)).collect::<io::Result<_>>()?)?.into_iter();
+        let hash = hashes.next().unwrap_or_default();
+        Ok((hash, algorithms.skip(1).zip(hashes).collect(), input.kind()))
+    })?;
+    // at -vv, to spot the files slowing a scan down, like the ones of a failing disk
+    log::info!("{} : {} bytes hashed in {:.3?} ({})", path.display(), metadata.len(), start.elapsed(), kind);
+    Ok(Entry {
+        hash,
+        size: if with_size { Some(metadata.len()) } else { None },
+        mtime: if with_mtime { Some(report::mtime(metadata)) } else { None },
+        xattrs: None,
+        target: None,
+        digests,
+        path: path.to_string_lossy().into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Reader;
+
+    #[test]
+    fn duplicates_are_written_with_their_hash() {
+        let (a, b) = (blake3::hash(b"a").to_string(), blake3::hash(b"b").to_string());
+        let report = |lines: String| Report::read(Reader::new(lines.as_bytes())).unwrap();
+        let r1 = report(format!("{a} x\n{a} y\n{b} z\n"));
+        let r2 = report(format!("{b} z\n{b} w\n"));
+        let mut text = Vec::new();
+        compare_reports(("r1", &r1), ("r2", &r2)).write_text(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), format!("only in r1 : y\n{a} duplicates : x 🟰 y\n{b} duplicates : w 🟰 z\n"));
+    }
+}
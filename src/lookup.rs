@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use blakediff::columnar::{Columns, Kind, Value};
+use blakediff::output::{quoted, Format, Render};
+use blakediff::report;
+use blakediff::{BlakediffError, Result};
+
+use crate::Outcome;
+
+/// display the paths recorded in `report_file` for the hash starting with `prefix`,
+/// which must designate a single hash, like abbreviated git commits
+pub fn lookup(prefix: String, report_file: String, format: Format, delimiter: Option<u8>) -> Result<Outcome> {
+    let prefix = prefix.to_ascii_lowercase();
+    if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(BlakediffError::Format(format!("'{}' is not an hexadecimal hash prefix", prefix)));
+    }
+    let mut matches: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in report::Reader::open(Path::new(&report_file))? {
+        let entry = entry?;
+        if entry.hash.starts_with(&prefix) {
+            matches.entry(entry.hash).or_default().push(entry.path);
+        }
+    }
+    if matches.len() > 1 {
+        return Err(BlakediffError::Format(format!("ambiguous prefix '{}', candidates are : {}", prefix, matches.keys().join(", "))));
+    }
+    let Some((hash, paths)) = matches.into_iter().next() else {
+        summary!("no hash starting with {} in {}", prefix, report_file);
+        return Ok(Outcome::Found);
+    };
+
+    format.print_delimited(&Lookup { hash, paths }, delimiter)?;
+    Ok(Outcome::Clean)
+}
+
+/// paths recorded with a hash
+#[derive(Serialize)]
+struct Lookup {
+    hash: String,
+    paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct LookupRow<'a> {
+    hash: &'a str,
+    path: &'a str,
+}
+
+impl Columns for LookupRow<'_> {
+    const COLUMNS: &'static [(&'static str, Kind)] = &[("hash", Kind::Text), ("path", Kind::Text)];
+
+    fn values(&self) -> Vec<Value<'_>> { vec![Value::Text(self.hash), Value::Text(self.path)] }
+}
+
+impl Render for Lookup {
+    type Row<'a> = LookupRow<'a>;
+
+    fn write_text(&self, out: &mut dyn Write) -> io::Result<()> { self.paths.iter().try_for_each(|path| writeln!(out, "{} {}", self.hash, quoted(path))) }
+
+    fn rows(&self) -> Box<dyn Iterator<Item = LookupRow<'_>> + '_> { Box::new(self.paths.iter().map(|path| LookupRow { hash: &self.hash, path })) }
+}
@@ -1,20 +1,80 @@
-use clap::{command, Parser, Subcommand};
+use clap::builder::FalseyValueParser;
+use clap::{CommandFactory, Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, DirEntry, File};
 use itertools::{self, Itertools};
 
-use std::io::BufRead;
 use std::{
-    error::Error,
-    io::{self},
-    path::Path,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+    time::Duration,
 };
 use took::{Timer, Took};
 
-use crate::input::Input;
-use rayon::prelude::*;
-mod input;
+use blakediff::ads;
+use blakediff::cache::Cache;
+use blakediff::cdc;
+use blakediff::color::{self, paint, Style};
+use blakediff::input::Algorithm;
+use blakediff::columnar::Encoding;
+use blakediff::atomic::AtomicFile;
+use blakediff::output::{self, quoted, ColumnarOutput, DuplicatesFormat, Format, ReportFormat, Size};
+use blakediff::report::{self, Empty, Entry, Field, Report};
+use blakediff::scan;
+use blakediff::similarity::{self, Similarity};
+use blakediff::source;
+use blakediff::error::OnError;
+use blakediff::exclude::Excludes;
+use blakediff::unicode::Normalization;
+use blakediff::walker::{Special, Symlinks};
+use blakediff::xattrs;
+use blakediff::{BlakediffError, Result};
+
+use crate::progress::Progress;
+
+/// print a summary or another line which is not a result, unless `--quiet`
+macro_rules! summary {
+    ($($arg:tt)*) => {
+        if !blakediff::output::quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod bench;
+mod daemon;
+mod dedupe;
+mod export;
+mod extsort;
+mod fetch;
+mod find;
+mod fsck;
+mod hash;
+mod history;
+mod hooks;
+mod import;
+mod lookup;
+mod metrics;
+#[cfg(feature = "fuse")]
+mod mount;
+mod pager;
+mod progress;
+mod prune;
+mod quarantine;
+mod rsync;
+mod selftest;
+mod serve;
+mod snapshot;
+mod sync;
+mod syslog;
+mod trash;
+mod tree;
+mod update;
+mod verify;
+mod vss;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug, Clone)]
@@ -26,192 +86,1133 @@ struct Args {
 
     #[clap(flatten)]
     verbose: Verbosity,
+
+    /// when to color the results : duplicates in yellow, files only in the first report in red, only in the second
+    /// one in green, moved in cyan
+    #[arg(long, global = true, value_enum, default_value_t = color::When::Auto, env = "BLAKEDIFF_COLOR")]
+    color: color::When,
+
+    /// never send long text outputs of a terminal through `$BLAKEDIFF_PAGER`, `$PAGER` or `less`
+    #[arg(long, global = true, env = "BLAKEDIFF_NO_PAGER", value_parser = FalseyValueParser::new())]
+    no_pager: bool,
+
+    /// how paths are printed in the text outputs : `shell` quotes them for copy-paste into a shell
+    #[arg(long, global = true, value_enum, default_value_t = output::Quote::Literal, env = "BLAKEDIFF_QUOTE")]
+    quote: output::Quote,
+
+    /// how errors are printed : `json` for one object per line with the stage, path, errno and message
+    #[arg(long, global = true, value_enum, default_value_t = output::Errors::Text, env = "BLAKEDIFF_ERRORS")]
+    errors: output::Errors,
+
+    /// where logs go : `syslog` sends them to the system journal, at least at the info level
+    #[arg(long, global = true, value_enum, default_value_t = syslog::Target::Stderr, env = "BLAKEDIFF_LOG")]
+    log: syslog::Target,
+
+    /// number of threads hashing in parallel, one per core by default
+    #[arg(long, global = true, env = "BLAKEDIFF_THREADS")]
+    threads: Option<usize>,
+
+    /// memory advice about mapped files, comma separated
+    #[arg(long, global = true, value_enum, value_delimiter = ',', env = "BLAKEDIFF_MADVISE")]
+    madvise: Vec<blakediff::input::Advice>,
+
+    /// number of threads walking directories and reading files, `--threads` by default
+    #[arg(long, global = true, env = "BLAKEDIFF_IO_THREADS")]
+    io_threads: Option<usize>,
+
+    /// number of threads hashing each large file, `--threads` by default
+    #[arg(long, global = true, env = "BLAKEDIFF_HASH_THREADS")]
+    hash_threads: Option<usize>,
+
+    /// spinning disk mode : a single thread reads files, in the order of their inodes, with large reads
+    #[arg(long, global = true, env = "BLAKEDIFF_HDD", value_parser = FalseyValueParser::new())]
+    hdd: bool,
+
+    /// never map files in memory, always reading them through a buffer
+    #[arg(long, global = true, env = "BLAKEDIFF_NO_MMAP", value_parser = FalseyValueParser::new())]
+    no_mmap: bool,
+
+    /// read a file up to this many more times after a transient error (EIO, EAGAIN, timeout), as on flaky network
+    /// mounts, before it fails
+    #[arg(long, global = true, default_value_t = 0, env = "BLAKEDIFF_RETRIES")]
+    retries: usize,
+
+    /// milliseconds to wait before each retry
+    #[arg(long, global = true, default_value_t = 1000, value_name = "MS", env = "BLAKEDIFF_RETRY_DELAY")]
+    retry_delay: u64,
+
+    /// display sizes with binary units, like 1.4 GiB, in text outputs
+    #[arg(long, global = true, env = "BLAKEDIFF_HUMAN_READABLE", value_parser = FalseyValueParser::new())]
+    human_readable: bool,
+
+    /// exit with code 1 when `compare` finds differences or `analyze` finds duplicates
+    #[arg(long, global = true, env = "BLAKEDIFF_STRICT", value_parser = FalseyValueParser::new())]
+    strict: bool,
 }
 
 #[derive(Subcommand, Debug, Clone)]
 enum Commands {
     /// read all files in a directory and output hashes for each files with there paths
-    Generate {
-        /// directory to analyze
+    Generate(GenerateOptions),
+    /// read a report file and display all duplicates hash with paths
+    Analyze(AnalyzeOptions),
+    /// compare two report files with hashes and display files present in report_1 and missing in report_2
+    Compare(CompareOptions),
+    /// rehash all files listed in a report file and check them against their recorded hashes
+    Verify(VerifyOptions),
+    /// refresh a report file with the files of a directory, rehashing only new or modified files
+    Update {
+        /// report file to refresh, rewritten in place
+        report_file: String,
+        /// directory the report was generated from
         dir: String,
-        /// path where store the report_blakediff.txt
-        #[arg(short, long, default_value = ".")]
-        report_path: Option<String>,
 
         /// use multi-threading for walk in directories
-        #[arg(short, long, default_value = "false")]
+        #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
         parallel: bool,
+
+        /// drop the entries of the files which no longer exist, counted as removed in the summary
+        #[arg(long)]
+        prune_missing: bool,
     },
-    /// read a report file and display all duplicates hash with paths
-    Analyze {
-        /// report file to analyze, searching for duplicates
+    /// find duplicates in a directory and act on them, keeping one file per group
+    Dedupe {
+        /// directory to deduplicate
+        dir: String,
+
+        /// which file of each group is kept
+        #[arg(short, long, value_enum, default_value_t = dedupe::Keep::First)]
+        keep: dedupe::Keep,
+
+        /// what is done with the other files of each group
+        #[arg(short, long, value_enum, default_value_t = dedupe::Action::Report)]
+        action: dedupe::Action,
+
+        /// with `--action delete`, move the duplicates to the trash of the desktop instead of deleting them
+        #[arg(long, default_value = "false")]
+        trash: bool,
+
+        /// really modify files, by default only displays what would be done
+        #[arg(long, default_value = "false")]
+        apply: bool,
+
+        /// use multi-threading for walk in directories
+        #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
+        parallel: bool,
+
+        /// hash the first 64 KiB of the files first, fully hashing them only when these collide
+        #[arg(long, default_value = "false")]
+        fast: bool,
+    },
+    /// copy into a destination directory the contents of a source directory missing there, renaming moved files
+    Sync {
+        /// source directory
+        src: String,
+        /// destination directory
+        dst: String,
+
+        /// only display what would be done
+        #[arg(short = 'n', long, default_value = "false")]
+        dry_run: bool,
+
+        /// use multi-threading for walk in directories
+        #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
+        parallel: bool,
+    },
+    /// display the tree of a directory with the hash of each file and a rollup hash for each directory
+    Tree {
+        /// directory to display
+        dir: String,
+
+        /// maximum depth displayed, hashes still cover the whole tree
+        #[arg(short, long)]
+        depth: Option<usize>,
+    },
+    /// serve a JSON API to query a report file : `/hash/<hash>`, `/duplicates` and `/compare?with=<report_file>`
+    Serve {
+        /// report file to serve
+        #[arg(short, long)]
+        report: String,
+
+        /// address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// directory of the report files which /compare may read, comparisons being refused without it
+        #[arg(long)]
+        compare_dir: Option<String>,
+    },
+    /// run in the background, periodically refreshing the reports of the directories listed in a configuration file
+    Daemon {
+        /// configuration file (toml)
+        #[arg(short, long)]
+        config: String,
+    },
+    /// mount a read-only view of a report file with one directory per unique hash, listing all its paths
+    #[cfg(feature = "fuse")]
+    Mount {
+        /// report file to expose
         report_file: String,
+        /// directory where the view is mounted
+        mountpoint: String,
     },
-    /// compare two report files with hashes and display files present in report_1 and missing in report_2
-    Compare {
-        /// first report to analyze with
-        report_1: String,
-        /// second report file
-        report_2: String,
+    /// measure the hashing throughput on generated files, with mmap or buffered reads and several thread counts
+    Bench {
+        /// total size of the generated files (K, M, G, T suffixes)
+        #[arg(short, long, default_value = "1G", value_parser = bench::parse_size)]
+        size: u64,
+
+        /// number of generated files
+        #[arg(short, long, default_value_t = 10000)]
+        files: u64,
+    },
+    /// check blakediff on this platform : blake3 test vectors, report formats and directory walk
+    Selftest,
+    /// output the completion script of a shell
+    Completions {
+        /// shell to complete
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// hash a file and display the entries of report files with the same hash
+    Find {
+        /// file to search for
+        file: String,
+        /// report files to search in
+        #[arg(required = true)]
+        reports: Vec<String>,
+    },
+    /// display the paths recorded in a report file for a hash, given by a unique prefix
+    Lookup {
+        /// beginning of the hash, long enough to designate a single hash of the report
+        hash_prefix: String,
+        /// report file to search in
+        report_file: String,
+
+        /// output format
+        #[arg(short, long, value_enum, default_value_t = Format::Text, env = "BLAKEDIFF_FORMAT")]
+        format: Format,
+
+        /// separator of the values of the csv and tsv formats, `\t` or `tab` for a tabulation
+        #[arg(long, value_parser = output::parse_delimiter)]
+        delimiter: Option<u8>,
+    },
+    /// remove from a report file the entries whose files don't exist anymore
+    Prune {
+        /// report file to prune, rewritten in place
+        report_file: String,
+
+        /// only prune entries under this directory
+        #[arg(short, long)]
+        only_under: Option<String>,
+    },
+    /// write a timestamped and compressed report of a directory in a store, rotating old snapshots
+    Snapshot {
+        /// directory to snapshot
+        dir: String,
+
+        /// directory where snapshots are stored, ~/.blakediff/snapshots by default
+        #[arg(long)]
+        store: Option<String>,
+
+        /// name of the snapshots, the name of the directory by default
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// number of snapshots of this name to keep, all by default
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        keep: Option<u64>,
+
+        /// use multi-threading for walk in directories
+        #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
+        parallel: bool,
+    },
+    /// display when files were added, changed, moved or removed across the snapshots of a store
+    History {
+        /// directory holding the snapshots, as written by `snapshot`
+        store: String,
+
+        /// name of the snapshots, needed when the store holds snapshots of several directories
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// convert the output of fdupes or jdupes into a report, hashing one file per group
+    ImportDupes {
+        /// output of fdupes or jdupes, `-` for the standard input
+        file: String,
+    },
+    /// display the hash of files, or check them against an expected hash
+    Hash {
+        /// files to hash
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// expected hash, the exit code is 1 when a file doesn't match it
+        #[arg(short, long)]
+        check: Option<String>,
+
+        /// hash algorithm
+        #[arg(short, long, value_enum, default_value_t = Algorithm::Blake3)]
+        algorithm: Algorithm,
+    },
+    /// check the syntax of a report file, listing malformed lines and paths listed several times
+    Fsck {
+        /// report file to check
+        report_file: String,
+
+        /// write a repaired copy of the report there, keeping the last entry of each path
+        #[arg(long)]
+        fix: Option<String>,
     },
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
-    env_logger::Builder::new()
-        .filter_level(args.verbose.log_level_filter())
-        .init();
-    if let Err(e) = match args.command {
-        Commands::Generate { dir, report_path, parallel } => generate(dir, report_path, parallel),
-        Commands::Compare { report_1, report_2 } => compare(report_1, report_2),
-        Commands::Analyze { report_file } => analyze(report_file),
-    } {
-        panic!("Error {}", e);
-    }
+/// options of `generate`
+#[derive(clap::Args, Debug, Clone)]
+struct GenerateOptions {
+    /// directory to analyze
+    dir: String,
+    /// file where the report is written once complete, `report_blakediff.txt` in a directory, instead of the
+    /// standard output
+    #[arg(short, long)]
+    report_path: Option<PathBuf>,
 
-    Ok(())
+    /// format of the report, parquet or arrow for analytics tools, on a standard output redirected to a file or a pipe
+    #[arg(short, long, value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+
+    /// use multi-threading for walk in directories
+    #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
+    parallel: bool,
+
+    /// record the size of each file in the report
+    #[arg(short = 's', long, default_value = "false")]
+    with_size: bool,
+
+    /// record the modification time of each file in the report
+    #[arg(short = 'm', long, default_value = "false")]
+    with_mtime: bool,
+
+    /// record a hash of the extended attributes and ACLs of each file in the report, on linux and macos
+    #[arg(long, default_value = "false", conflicts_with_all = ["git", "tar"])]
+    with_xattrs: bool,
+
+    /// directory of a hash cache, skipping the files unchanged since they were hashed
+    #[arg(long, env = "BLAKEDIFF_CACHE")]
+    cache: Option<PathBuf>,
+
+    /// for s3:// sources, take the hash of an object from its `blake3` metadata when set, instead of downloading it
+    #[arg(long, default_value = "false")]
+    trust_metadata: bool,
+
+    /// hash the files of this commit, branch or tag of the git repository `dir`, read from its object database
+    #[arg(long, value_name = "REV")]
+    git: Option<String>,
+
+    /// hash the members of the tar archive `dir`, `-` reading it from the standard input
+    #[arg(long, default_value = "false", conflicts_with = "git")]
+    tar: bool,
+
+    /// on windows, snapshot the volume with a shadow copy and hash the files from it, locked files included
+    #[arg(long, default_value = "false", conflicts_with_all = ["git", "tar"])]
+    vss: bool,
+
+    /// on windows, also hash the alternate data streams of each file, reported as `path:stream`
+    #[arg(long, default_value = "false", conflicts_with_all = ["git", "tar"])]
+    ads: bool,
+
+    /// first count the files and their bytes, so that the progress shows the percent done and the time left
+    #[arg(long, default_value = "false")]
+    pre_scan: bool,
+
+    /// leave out the files and directories which can't be read, each one printed on the standard error, instead
+    /// of stopping at the first one. Archives, git, oci and sftp sources still stop.
+    #[arg(short = 'k', long, default_value = "false")]
+    keep_going: bool,
+
+    /// symbolic links of a local directory : followed, skipped, or recorded as `<hash of target> <path> -> <target>`
+    #[arg(long, value_enum, default_value_t = Symlinks::Follow, conflicts_with_all = ["git", "tar"])]
+    symlinks: Symlinks,
+
+    /// FIFOs, sockets and devices of a local directory, never opened : skipped, recorded with their type instead
+    /// of a hash, or failing the run
+    #[arg(long, value_enum, default_value_t = Special::Skip, conflicts_with_all = ["git", "tar"])]
+    special: Special,
+
+    /// record the zero-length files with `empty` instead of their hash, which the other commands still take as
+    /// the BLAKE3 of no byte
+    #[arg(long, default_value = "false")]
+    tag_empty: bool,
+
+    /// leave out the files and directories matching the globs of this file, one per line, `#` starting a comment,
+    /// like rsync and tar. Can be repeated.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["git", "tar"])]
+    exclude_from: Vec<PathBuf>,
+
+    /// leave out the `.git`, `.svn`, `.hg`, `node_modules`, `__pycache__` and `.cache` directories
+    #[arg(long, default_value = "false", conflicts_with_all = ["git", "tar"])]
+    skip_vcs: bool,
+
+    /// write the paths in this unicode normalization form, so that the reports of a tree synced between macOS
+    /// (NFD) and linux (NFC) compare equal. Needs the normalize feature.
+    #[arg(long, value_enum)]
+    normalize: Option<Normalization>,
+
+    /// hash algorithms, comma separated, computed in a single read of each file : BLAKE3 is always the hash
+    /// column, and each other one gets its own column, like `--algo blake3,sha256`
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "blake3", conflicts_with_all = ["git", "tar"])]
+    algo: Vec<Algorithm>,
+
+    /// also cut each file into content-defined chunks (FastCDC, 64 KiB on average) and record their hashes in a
+    /// `chunks` column, for `analyze --partial`
+    #[arg(long, default_value = "false", conflicts_with_all = ["git", "tar"])]
+    cdc: bool,
+
+    /// also record similarity digests, comma separated, close for slightly edited files, in a column of their
+    /// name, for `analyze --near-dupes`
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with_all = ["git", "tar"])]
+    similarity: Vec<Similarity>,
 }
 
+/// options of `compare`
+#[derive(clap::Args, Debug, Clone)]
+struct CompareOptions {
+    /// first report to analyze with
+    report_1: String,
+    /// second report file
+    report_2: String,
+
+    /// output format
+    #[arg(short, long, value_enum, default_value_t = Format::Text, env = "BLAKEDIFF_FORMAT")]
+    format: Format,
+
+    /// separator of the values of the csv and tsv formats, `\t` or `tab` for a tabulation
+    #[arg(long, value_parser = output::parse_delimiter)]
+    delimiter: Option<u8>,
+
+    /// print a file list instead, report_1 being the source and report_2 the destination, with paths relative to
+    /// the deepest directory holding all the files of each report
+    #[arg(long, value_enum)]
+    emit: Option<rsync::Emit>,
+
+    /// with `--emit rsync-files`, also write into this file the destination paths missing from the source,
+    /// for `rsync --files-from <FILE> --delete-missing-args`
+    #[arg(long, value_name = "FILE", requires = "emit")]
+    delete_list: Option<PathBuf>,
+
+    /// print paths separated by NUL characters instead of the format, for `xargs -0` : the files only in report_1,
+    /// only in report_2, then each group of duplicates, every group being ended by one more NUL
+    #[arg(short = 'z', long = "null", conflicts_with_all = ["delimiter", "emit"])]
+    null: bool,
+
+    /// pair the files only in report_1 with the closest file only in report_2 whose similarity digests, of
+    /// `generate --similarity` or `--algo phash,audio`, are close, and report them as similar with their distance
+    #[arg(long, conflicts_with_all = ["emit", "null"])]
+    fuzzy: bool,
+
+    /// distance up to which two files are similar for `--fuzzy`, the default one of each digest being the one of
+    /// `analyze --near-dupes`
+    #[arg(long, requires = "fuzzy")]
+    threshold: Option<u32>,
+
+    /// print the files of report_1, or of report_2, whose content is in no other file of both reports instead,
+    /// the ones only this drive holds
+    #[arg(long, value_name = "1|2", value_parser = clap::value_parser!(u8).range(1..=2), conflicts_with_all = ["emit", "fuzzy"])]
+    unique_in: Option<u8>,
+
+    /// download the reports which are http(s) URLs, needs the http feature
+    #[arg(long)]
+    fetch: bool,
+}
+
+/// options of `verify`
+#[derive(clap::Args, Debug, Clone)]
+struct VerifyOptions {
+    /// report file listing the files to check
+    report_file: String,
+    /// directory from which relative paths of the report are resolved
+    #[arg(short, long)]
+    root: Option<String>,
+
+    /// compare the size and modification time of the files with the recorded ones instead of reading them, from a
+    /// report of `generate --with-size` or `--with-mtime`
+    #[arg(long)]
+    quick: bool,
+
+    /// with `--quick`, still rehash the files whose metadata didn't change
+    #[arg(long, requires = "quick")]
+    rehash: bool,
+
+    /// check several files at once, one per core
+    #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
+    parallel: bool,
+
+    /// leave out the `OK` lines, only printing the files which are not and the summary
+    #[arg(long)]
+    quiet_ok: bool,
+}
+
+/// options of `analyze`
+#[derive(clap::Args, Debug, Clone)]
+struct AnalyzeOptions {
+    /// report file to analyze, searching for duplicates, or directory to scan directly
+    #[arg(required_unless_present = "restore")]
+    report_file: Option<String>,
+
+    /// output format, fdupes and rmlint ones can be consumed by their follow-up scripts
+    #[arg(short, long, value_enum, default_value_t = DuplicatesFormat::Text, env = "BLAKEDIFF_FORMAT")]
+    format: DuplicatesFormat,
+
+    /// move all the duplicates but the first of each group into this directory, under their original path
+    #[arg(long, conflicts_with = "restore")]
+    move_to: Option<String>,
+
+    /// move back to their original path the files moved into this directory by `--move-to`
+    #[arg(long)]
+    restore: Option<String>,
+
+    /// run this command for each group of duplicates, or each duplicate when it holds `{dupe}`,
+    /// with the placeholders `{keeper}`, `{dupe}`, `{dupes}` and `{hash}`
+    #[arg(long, conflicts_with_all = ["restore", "move_to"])]
+    exec: Vec<String>,
+
+    /// run the commands listed in this hooks file (toml) like `--exec`
+    #[arg(long, conflicts_with_all = ["restore", "move_to"])]
+    hooks: Option<String>,
+
+    /// use multi-threading for walk in directories, when scanning a directory
+    #[arg(short, long, default_value = "false", env = "BLAKEDIFF_PARALLEL", value_parser = FalseyValueParser::new())]
+    parallel: bool,
+
+    /// when scanning a directory, hash the first 64 KiB of the files first, fully hashing them only when these collide
+    #[arg(long, default_value = "false")]
+    fast: bool,
+
+    /// sort the report by hash through temporary files instead of loading it, groups being displayed in hash order
+    #[arg(long, default_value = "false", conflicts_with_all = ["move_to", "exec", "hooks", "fast"])]
+    low_memory: bool,
 
-fn analyze(report_file: String) -> Result<(), Box<dyn Error>> {
-    let input = Input::open(Path::new(&report_file))?;
-    let mut buf = io::BufReader::new(input);
-    let mut line: String = String::new();
-    let mut hmap:HashMap<String, String> = HashMap::new();
-    let mut duplicates: HashMap<String, HashSet<String>> = HashMap::new();
-    while buf.read_line(&mut line)? != 0 {
-        let split = line.split_once(' ').map(|(h,p)| (h.trim(),p.trim()));
-        match split {
-            Some((hash, path)) => {
-                // on est déjà tombé sur ce hash
-                if let Some(premier_hash) = hmap.get(hash) {
-                    //on a déjà enregistré 2 fichiers ayant ce même hash, on tombe sur un n-ième
-                    if let Some(d) = duplicates.get_mut(hash) {
-                        d.insert(path.to_owned());
-                    }
-                    //sinon c'est la première fois qu'on tombe sur un duplica, il faut créer le hashSet
-                    else {
-                        let mut hs : HashSet<String> = HashSet::new();
-                        hs.insert(path.to_owned());
-                        hs.insert(premier_hash.to_owned());
-                        duplicates.insert(hash.to_owned(), hs);
-                        
-                    }
-                }
-                else { // première fois qu'on tombe sur ce hash
-                    hmap.insert(hash.to_owned(), path.to_owned());
-                    
-                }
+    /// memory used by `--low-memory` for the entries of the report, in MiB
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..), requires = "low_memory")]
+    memory_limit: u64,
+
+    /// print the paths of each group separated by NUL characters and end groups by one more, for `xargs -0`
+    #[arg(short = 'z', long = "null", conflicts_with_all = ["format", "restore", "move_to", "exec", "hooks"])]
+    null: bool,
+
+    /// zero-length files, which all share a hash : left out, in a group of their own after the duplicates, or
+    /// listed one per line after them
+    #[arg(long, value_enum, default_value_t = Empty::Group, conflicts_with = "restore")]
+    empty: Empty,
+
+    /// start the line of each group of the text format with its hash, abbreviated to this number of hex digits,
+    /// 12 by default, to find it back in the report or with `lookup`. The empty files show the BLAKE3 of no byte.
+    #[arg(long, value_name = "DIGITS", num_args = 0..=1, require_equals = true, default_missing_value = "12", value_parser = clap::value_parser!(u8).range(4..=64), conflicts_with_all = ["restore", "null"])]
+    show_hash: Option<u8>,
+
+    /// print the files whose content no other file of the report holds instead of the duplicates
+    #[arg(long, conflicts_with_all = ["restore", "move_to", "exec", "hooks", "low_memory", "fast", "partial", "near_dupes"])]
+    unique: bool,
+
+    /// also print the pairs of files sharing chunks without being identical, from a report of `generate --cdc`
+    #[arg(long, conflicts_with_all = ["restore", "move_to", "exec", "hooks", "low_memory", "null"])]
+    partial: bool,
+
+    /// fraction of the chunks of the smaller file of a pair found in the other one for `--partial`, from 0 to 1
+    #[arg(long, default_value_t = 0.5, requires = "partial")]
+    min_shared: f64,
+
+    /// also print the groups of files whose similarity digests are close without being identical, from a report
+    /// of `generate --similarity` or `--algo phash,audio`
+    #[arg(long, conflicts_with_all = ["restore", "move_to", "exec", "hooks", "low_memory", "null"])]
+    near_dupes: bool,
+
+    /// distance up to which two files are near duplicates for `--near-dupes` : 40 by default for tlsh, 50 for
+    /// ssdeep, where it is 100 minus the match score, 8 differing bits for phash and 25% for audio
+    #[arg(long, requires = "near_dupes")]
+    threshold: Option<u32>,
+
+    /// download the report when it is an http(s) URL, needs the http feature
+    #[arg(long, conflicts_with_all = ["restore", "low_memory"])]
+    fetch: bool,
+}
+
+/// commands listing results in a text format, paged on a terminal
+fn paged(command: &Commands) -> bool {
+    match command {
+        Commands::Compare(CompareOptions { format, emit, null, .. }) => emit.is_none() && !null && !format.is_binary(),
+        Commands::Analyze(AnalyzeOptions { format, restore, null, .. }) => restore.is_none() && !null && !format.is_binary(),
+        Commands::Lookup { format, .. } => !format.is_binary(),
+        Commands::Verify(_) | Commands::Tree { .. } | Commands::Find { .. } | Commands::History { .. } | Commands::Fsck { .. } => true,
+        _ => false,
+    }
+}
+
+/// exit code of a run, the temporary files being removed before : 0 on success, the one of the error otherwise
+fn main() -> ExitCode {
+    let args = Args::parse();
+    color::init(args.color);
+    if !args.no_pager && paged(&args.command) {
+        pager::start();
+    }
+    output::set_quiet(args.verbose.is_silent());
+    output::set_human_readable(args.human_readable);
+    output::set_quote(args.quote);
+    output::set_errors(args.errors);
+    match args.log {
+        syslog::Target::Stderr => env_logger::Builder::new().filter_level(args.verbose.log_level_filter()).init(),
+        syslog::Target::Syslog => {
+            if let Err(e) = syslog::init(args.verbose.log_level_filter().max(log::LevelFilter::Info)) {
+                eprintln!("Error syslog : {}", e);
+                return exit_code(&BlakediffError::Io(e));
+            }
+        }
+    }
+    let log_target = args.log;
+    blakediff::input::set_mmap(!args.no_mmap);
+    blakediff::input::set_advice(&args.madvise);
+    blakediff::input::set_retries(args.retries, Duration::from_millis(args.retry_delay));
+    if args.hdd {
+        blakediff::walker::set_hdd(true);
+        blakediff::input::set_mmap(false);
+        blakediff::input::set_read_buffer(HDD_READ_BUFFER);
+    }
+    let threads = match args.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().map_err(|e| BlakediffError::Other(e.to_string())),
+        None => Ok(()),
+    }
+    .and_then(|()| blakediff::threads::configure(args.io_threads, args.hash_threads));
+    let strict = args.strict;
+    let outcome = threads.and_then(|()| {
+        Ok(match args.command {
+            Commands::Generate(options) => generate(options)?.into(),
+            Commands::Compare(options) => match options.unique_in {
+                Some(_) => compare_unique(options, strict)?,
+                None => compare(options, strict)?,
             },
-            None => panic!("issue while compare operation"),
-        };
-        line.clear();
-    }
-    //tri d'abord entre les duplicas d'un même fichier (une ligne),
-    duplicates.iter()
-    .map(|(_hash,set)|set.iter().sorted().collect::<Vec<&String>>())
-    .sorted_by_cached_key(|v| v[0]) // puis tri sur les lignes/fichiers (sur le nom du 1er duplica v[0])
-    .for_each(|f| {
-        print!("duplicates : {}",f.iter().join(" 🟰 "));
-        println!();
+            Commands::Analyze(options) => match (&options.report_file, &options.restore) {
+                (_, Some(restore)) => quarantine::restore(Path::new(restore))?.into(),
+                (Some(_), None) if options.unique => analyze_unique(options, strict)?,
+                (Some(_), None) if options.low_memory => analyze_low_memory(options, strict)?,
+                (Some(_), None) => analyze(options, strict)?,
+                (None, None) => unreachable!("report_file is required without --restore"),
+            },
+            Commands::Verify(options) => verify::verify(options)?,
+            Commands::Update {
+                report_file,
+                dir,
+                parallel,
+                prune_missing,
+            } => update::update(report_file, dir, parallel, prune_missing)?.into(),
+            Commands::Dedupe {
+                dir,
+                keep,
+                action,
+                trash,
+                apply,
+                parallel,
+                fast,
+            } => dedupe::dedupe(dir, keep, action, trash, apply, parallel, fast)?.into(),
+            Commands::Sync { src, dst, dry_run, parallel } => sync::sync(src, dst, dry_run, parallel)?.into(),
+            Commands::Tree { dir, depth } => tree::tree(dir, depth)?.into(),
+            Commands::Serve { report, listen, compare_dir } => serve::serve(report, listen, compare_dir)?.into(),
+            Commands::Daemon { config } => daemon::daemon(config)?.into(),
+            #[cfg(feature = "fuse")]
+            Commands::Mount { report_file, mountpoint } => mount::mount(report_file, mountpoint)?.into(),
+            Commands::Bench { size, files } => bench::bench(size, files)?.into(),
+            Commands::Selftest => Outcome::found(!selftest::selftest()?),
+            Commands::Find { file, reports } => find::find(file, reports)?,
+            Commands::Lookup { hash_prefix, report_file, format, delimiter } => lookup::lookup(hash_prefix, report_file, format, delimiter)?,
+            Commands::Prune { report_file, only_under } => prune::prune(report_file, only_under)?.into(),
+            Commands::Snapshot {
+                dir,
+                store,
+                name,
+                keep,
+                parallel,
+            } => snapshot::snapshot(dir, store, name, keep, parallel)?.into(),
+            Commands::History { store, name } => history::history(store, name)?.into(),
+            Commands::ImportDupes { file } => import::import_dupes(file)?.into(),
+            Commands::Hash { files, check, algorithm } => hash::hash(files, check, algorithm)?,
+            Commands::Fsck { report_file, fix } => fsck::fsck(report_file, fix)?,
+            Commands::Completions { shell } => {
+                clap_complete::generate(shell, &mut Args::command(), "blakediff", &mut io::stdout());
+                Outcome::Clean
+            }
+        })
     });
-    Ok(())
+    match outcome {
+        Ok(Outcome::Clean) => ExitCode::SUCCESS,
+        Ok(Outcome::Found) => ExitCode::FAILURE,
+        Err(e) => {
+            if log_target == syslog::Target::Syslog {
+                log::error!("{}", e);
+            }
+            output::print_error(&e);
+            exit_code(&e)
+        }
+    }
+}
+
+/// What a command found, turned into the exit code by `main` once every file is written and flushed : 1 for
+/// differences, duplicates, failed checks or anything else its exit code reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Clean,
+    Found,
+}
+
+impl Outcome {
+    pub fn found(found: bool) -> Self {
+        match found {
+            true => Outcome::Found,
+            false => Outcome::Clean,
+        }
+    }
+}
+
+/// commands which only fail through their errors
+impl From<()> for Outcome {
+    fn from((): ()) -> Self { Outcome::Clean }
 }
 
+fn exit_code(e: &BlakediffError) -> ExitCode { ExitCode::from(e.exit_code() as u8) }
 
-fn compare(report_1: String, report_2: String) -> Result<(), Box<dyn Error>> {
-    let path1 = Path::new(&report_1);
-    let path2 = Path::new(&report_2);
-    if path1.is_dir() || path2.is_dir() {
-        log::error!("Comparison should be avoid on directories, try on report files");
-        std::process::exit(1);
 
+fn analyze(options: AnalyzeOptions, strict: bool) -> Result<Outcome> {
+    let AnalyzeOptions { report_file, format, move_to, mut exec, hooks, parallel, fast, null, empty, show_hash, partial, min_shared, near_dupes, threshold, fetch, .. } = options;
+    let report_file = report_file.expect("report_file is required without --restore");
+    let (partial, near_dupes) = (partial.then_some(min_shared), near_dupes.then_some(threshold));
+    let path = Path::new(&report_file);
+    if partial.is_some() && (path.is_dir() || format != DuplicatesFormat::Text) {
+        return Err(BlakediffError::Format(String::from("--partial needs a report of `generate --cdc` and the text format")));
+    }
+    if near_dupes.is_some() && (path.is_dir() || format != DuplicatesFormat::Text) {
+        return Err(BlakediffError::Format(String::from("--near-dupes needs a report of `generate --similarity` and the text format")));
+    }
+    if empty == Empty::List && (null || format != DuplicatesFormat::Text) {
+        return Err(BlakediffError::Format(String::from("--empty list only applies to the text format")));
+    }
+    if show_hash.is_some() && format != DuplicatesFormat::Text {
+        return Err(BlakediffError::Format(String::from("--show-hash only applies to the text format")));
+    }
+    // a directory is scanned directly, only hashing the files sharing their size with another one
+    let mut report = Report::default();
+    let (mut stages, mut groups): (Vec<_>, Vec<_>) = if path.is_dir() {
+        scan::duplicates_staged(path, parallel, fast)?.into_iter().map(|(stage, group)| (fast.then_some(stage), group)).unzip()
+    } else if fast {
+        return Err(BlakediffError::Format(String::from("--fast only applies when scanning a directory")));
+    } else {
+        report = fetch::load(&report_file, fetch)?;
+        report.duplicates().into_iter().map(|group| (None, group.into_iter().cloned().collect())).unzip()
+    };
+    let empty_files = match groups.iter().position(|group| group[0].is_empty_file()) {
+        Some(i) => {
+            stages.remove(i);
+            groups.remove(i)
+        }
+        None => Vec::new(),
+    };
+    let listed = match empty {
+        Empty::Group if !empty_files.is_empty() => {
+            stages.push(None);
+            groups.push(empty_files);
+            Vec::new()
+        }
+        Empty::List => empty_files,
+        Empty::Group | Empty::Ignore => Vec::new(),
+    };
+    if let Some(dir) = move_to {
+        return quarantine::quarantine(&groups, Path::new(&dir)).map(Outcome::from);
+    }
+    if let Some(hooks) = hooks {
+        exec.extend(hooks::load(&hooks)?);
+    }
+    if !exec.is_empty() {
+        return hooks::run(&exec, &groups).map(Outcome::from);
+    }
+    let paths = || groups.iter().map(|group| group.iter().map(|e| e.path.clone()).collect()).collect::<Vec<Vec<String>>>();
+    match format {
+        _ if null => output::print_nul_groups(groups.iter().map(|group| group.iter().map(|e| e.path.as_str())))?,
+        DuplicatesFormat::Text => paths().iter().zip(stages).zip(&groups).for_each(|((f, stage), group)| {
+            let hash = abbreviated(&group[0].hash, show_hash);
+            match stage {
+                _ if group[0].is_empty_file() => print!("{}", paint(Style::Duplicate, format_args!("{}empty files : {}", hash, f.iter().map(|path| quoted(path)).join(" 🟰 ")))),
+                Some(stage) => print!("{}", paint(Style::Duplicate, format_args!("{}duplicates [{}] : {}", hash, stage.name(), f.iter().map(|path| quoted(path)).join(" 🟰 ")))),
+                None => print!("{}", paint(Style::Duplicate, format_args!("{}duplicates : {}", hash, f.iter().map(|path| quoted(path)).join(" 🟰 ")))),
+            }
+            println!();
+        }),
+        DuplicatesFormat::Fdupes => export::fdupes(&paths()),
+        DuplicatesFormat::Rmlint => export::rmlint(&groups)?,
+        DuplicatesFormat::Table => export::table(&groups)?,
+        DuplicatesFormat::Parquet => export::columnar(Encoding::Parquet, &groups)?,
+        DuplicatesFormat::Arrow => export::columnar(Encoding::Arrow, &groups)?,
+    }
+    for entry in &listed {
+        println!("empty : {}", quoted(&entry.path));
     }
-    let input1 = File::open(path1)?;
-    let input2 = File::open(path2)?;
-    let buf1 = io::BufReader::new(input1);
-    let buf2 = io::BufReader::new(input2);
+    let mut found = !groups.is_empty();
+    if let Some(min_shared) = partial {
+        for pair in cdc::partial_duplicates(&report, min_shared) {
+            found = true;
+            println!("{}", paint(Style::Duplicate, format_args!("partial {:.0}% : {} 🟰 {}", pair.shared * 100.0, quoted(&pair.first.path), quoted(&pair.second.path))));
+        }
+    }
+    if let Some(threshold) = near_dupes {
+        // each similarity digest recorded in the report
+        let recorded = [Similarity::Tlsh, Similarity::Ssdeep, Similarity::Phash, Similarity::Audio].into_iter().filter(|similarity| report.iter().any(|entry| entry.digest(similarity.algorithm()).is_some())).collect::<Vec<_>>();
+        if recorded.is_empty() {
+            return Err(BlakediffError::Format(String::from("--near-dupes needs a report of `generate --similarity` or `--algo phash,audio`")));
+        }
+        for similarity in recorded {
+            for group in similarity::near_duplicates(&report, similarity, threshold.unwrap_or(similarity.threshold())) {
+                found = true;
+                println!("{}", paint(Style::Duplicate, format_args!("near duplicates [{}] : {}", similarity.algorithm().name(), group.iter().map(|entry| quoted(&entry.path)).join(" 🟰 "))));
+            }
+        }
+    }
+    Ok(Outcome::found(strict && found))
+}
 
-    let h1 = buf1.lines().map(|l| l.unwrap()).fold(HashMap::new(), |mut h, line| {
-        let split = line.split_once(' ').map(|(a, b)| (String::from(a), String::from(b)));
-        match split {
-            Some((hash, path)) => h.insert(hash, path),
-            None => panic!("issue while compare operation"),
-        };
-        h
-    });
+/// start of the text line of a group with `--show-hash` : its first `digits` hex digits and a space, nothing without
+fn abbreviated(hash: &str, digits: Option<u8>) -> String {
+    // the tag of `generate --tag-empty` stands for this hash
+    let hash = if hash == report::EMPTY { report::EMPTY_HASH } else { hash };
+    digits.map(|digits| format!("{} ", hash.chars().take(digits as usize).collect::<String>())).unwrap_or_default()
+}
 
-    let h2 = buf2.lines().map(|l| l.unwrap()).fold(HashMap::new(), |mut h, line| {
-        let split = line.split_once(' ').map(|(a, b)| (String::from(a), String::from(b)));
-        match split {
-            Some((hash, path)) => h.insert(hash, path),
-            None => panic!("error occur while comparison"),
-        };
-        h
-    });
+/// files of a report whose content no other file holds
+fn analyze_unique(options: AnalyzeOptions, strict: bool) -> Result<Outcome> {
+    let AnalyzeOptions { report_file, null, format, fetch, .. } = options;
+    let report_file = report_file.expect("report_file is required without --restore");
+    let path = Path::new(&report_file);
+    if path.is_dir() {
+        return Err(BlakediffError::Format(String::from("--unique needs a report, a scanned directory only hashes the files sharing their size")));
+    }
+    if format != DuplicatesFormat::Text {
+        return Err(BlakediffError::Format(String::from("--unique only applies to the text format")));
+    }
+    let report = fetch::load(&report_file, fetch)?;
+    let unique = report.unique();
+    match null {
+        true => output::print_nul_groups([unique.iter().map(|e| e.path.as_str())])?,
+        false => unique.iter().for_each(|e| println!("unique : {}", quoted(&e.path))),
+    }
+    Ok(Outcome::found(strict && !unique.is_empty()))
+}
 
-    h1.iter().for_each(|(hash, path)| {
-        if !h2.contains_key(hash) {
-            println!("only in {} : {}", report_1, path);
+fn analyze_low_memory(options: AnalyzeOptions, strict: bool) -> Result<Outcome> {
+    let AnalyzeOptions { report_file, format, null, empty, show_hash, memory_limit, .. } = options;
+    let report_file = report_file.expect("report_file is required without --restore");
+    if let DuplicatesFormat::Rmlint | DuplicatesFormat::Table | DuplicatesFormat::Parquet | DuplicatesFormat::Arrow = format {
+        return Err(BlakediffError::Format(String::from("the rmlint, table, parquet and arrow formats need the whole report, they can't be used with --low-memory")));
+    }
+    if empty == Empty::List && (null || format != DuplicatesFormat::Text) {
+        return Err(BlakediffError::Format(String::from("--empty list only applies to the text format")));
+    }
+    if show_hash.is_some() && format != DuplicatesFormat::Text {
+        return Err(BlakediffError::Format(String::from("--show-hash only applies to the text format")));
+    }
+    if fetch::is_url(&report_file) {
+        return Err(BlakediffError::Format(String::from("--low-memory needs a local report")));
+    }
+    let mut found = false;
+    extsort::duplicates(Path::new(&report_file), (memory_limit as usize).saturating_mul(1024 * 1024), |group| {
+        // groups come in hash order, the one of the empty files among the others
+        let is_empty = group[0].is_empty_file();
+        match empty {
+            Empty::Ignore if is_empty => return Ok(()),
+            Empty::List if is_empty => {
+                group.iter().for_each(|entry| println!("empty : {}", quoted(&entry.path)));
+                return Ok(());
+            }
+            _ => found = true,
         }
-    });
-    h2.iter().for_each(|(hash, path)| {
-        if !h1.contains_key(hash) {
-            println!("only in {} : {}", report_2, path);
+        let hash = abbreviated(&group[0].hash, show_hash);
+        let paths = group.into_iter().map(|e| e.path).collect::<Vec<_>>();
+        let label = if is_empty { "empty files" } else { "duplicates" };
+        match format {
+            _ if null => output::print_nul_groups([paths.iter().map(String::as_str)])?,
+            DuplicatesFormat::Text => println!("{}", paint(Style::Duplicate, format_args!("{}{} : {}", hash, label, paths.iter().map(|path| quoted(path)).join(" 🟰 ")))),
+            DuplicatesFormat::Fdupes => export::fdupes(&[paths]),
+            DuplicatesFormat::Rmlint | DuplicatesFormat::Table | DuplicatesFormat::Parquet | DuplicatesFormat::Arrow => unreachable!("rmlint, table, parquet and arrow are rejected with --low-memory"),
         }
-    });
+        Ok(())
+    })?;
+    Ok(Outcome::found(strict && found))
+}
 
-    analyze(report_1)?;
-    analyze(report_2)?;
+fn compare(options: CompareOptions, strict: bool) -> Result<Outcome> {
+    let CompareOptions { report_1, report_2, format, delimiter, emit, delete_list, null, fuzzy, threshold, fetch, .. } = options;
+    if Path::new(&report_1).is_dir() || Path::new(&report_2).is_dir() {
+        return Err(BlakediffError::Format(String::from("comparison should be avoided on directories, try on report files")));
+    }
+    let (r1, r2) = (fetch::load(&report_1, fetch)?, fetch::load(&report_2, fetch)?);
+    if let Some(rsync::Emit::RsyncFiles) = emit {
+        let (source, destination) = (r1, r2);
+        let (source_root, destination_root) = (rsync::root(&source), rsync::root(&destination));
+        log::info!("paths relative to {} and {}", source_root, destination_root);
+        let lists = rsync::Lists::new((&source, &source_root), (&destination, &destination_root));
+        rsync::emit(&lists, delete_list.as_deref())?;
+        return Ok(Outcome::found(strict && !lists.is_empty()));
+    }
+    let mut comparison = blakediff::compare_reports((&report_1, &r1), (&report_2, &r2));
+    if fuzzy {
+        comparison.pair_similar(&r1, &r2, threshold)?;
+    }
+    match null {
+        true => {
+            let only_in = comparison.only_in.iter().map(|(_, entries)| entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>());
+            let duplicates = comparison.duplicates.iter().flat_map(|(_, groups)| groups.iter().map(|group| group.paths.iter().map(String::as_str).collect()));
+            output::print_nul_groups(only_in.chain(duplicates))?;
+        }
+        false => format.print_delimited(&comparison, delimiter)?,
+    }
+    Ok(Outcome::found(strict && comparison.has_differences()))
+}
 
-    Ok(())
+/// files of one of two reports whose content is in no other file of both
+fn compare_unique(options: CompareOptions, strict: bool) -> Result<Outcome> {
+    let CompareOptions { report_1, report_2, format, delimiter, null, unique_in, fetch, .. } = options;
+    let (r1, r2) = (fetch::load(&report_1, fetch)?, fetch::load(&report_2, fetch)?);
+    let unique = match unique_in.expect("compare_unique needs --unique-in") {
+        1 => blakediff::Unique { entries: blakediff::unique_in(&r1, &r2), report: report_1 },
+        _ => blakediff::Unique { entries: blakediff::unique_in(&r2, &r1), report: report_2 },
+    };
+    match null {
+        true => output::print_nul_groups([unique.entries.iter().map(|e| e.path.as_str())])?,
+        false => format.print_delimited(&unique, delimiter)?,
+    }
+    Ok(Outcome::found(strict && !unique.entries.is_empty()))
 }
 
-fn generate(dir: String, _report_path: Option<String>, parallel: bool) -> Result<(), Box<dyn Error>> {
+/// size of the reads of `--hdd`, long enough to amortize seeks
+const HDD_READ_BUFFER: usize = 4 << 20;
+
+/// size of the buffer of `generate` in front of stdout
+const OUTPUT_BUFFER: usize = 1 << 20;
+
+fn generate(options: GenerateOptions) -> Result<()> {
+    let GenerateOptions {
+        dir,
+        report_path,
+        format,
+        parallel,
+        with_size,
+        with_mtime,
+        with_xattrs,
+        cache,
+        trust_metadata,
+        git,
+        tar,
+        vss,
+        ads,
+        pre_scan,
+        keep_going,
+        symlinks,
+        special,
+        tag_empty,
+        exclude_from,
+        skip_vcs,
+        normalize,
+        algo,
+        cdc,
+        similarity,
+    } = options;
+    if ads && !cfg!(windows) {
+        return Err(BlakediffError::Format(String::from("--ads is only supported on windows")));
+    }
+    if with_xattrs && !cfg!(any(target_os = "linux", target_os = "macos")) {
+        return Err(BlakediffError::Format(String::from("--with-xattrs is only supported on linux and macos")));
+    }
+    // git and tar conflict with it already
+    if with_xattrs && dir.contains("://") {
+        return Err(BlakediffError::Format(format!("{} : --with-xattrs only applies to local directories", dir)));
+    }
+    if normalize.is_some() && !cfg!(feature = "normalize") {
+        return Err(BlakediffError::Format(String::from("--normalize : blakediff was built without the normalize feature")));
+    }
+    if algo.contains(&Algorithm::Phash) && !cfg!(feature = "images") {
+        return Err(BlakediffError::Format(String::from("--algo phash : blakediff was built without the images feature")));
+    }
+    if pre_scan && tar && dir == "-" {
+        return Err(BlakediffError::Format(String::from("--pre-scan can't read an archive from the standard input twice")));
+    }
+    let mut excludes = Excludes::default();
+    if skip_vcs {
+        excludes.skip_vcs();
+    }
+    for file in &exclude_from {
+        excludes.read(file)?;
+    }
     let took = Timer::new();
     //just display files
-    //visit_dirs(Path::new(&args.dir), display_files)?;
+    //visit_dirs(Path::new(&args.dir), &display_files)?;
 
-    //blake3 on files
-    visit_dirs(Path::new(&dir), blake3_mmap, parallel)?;
-    
-    log::info!("elapsed time : {}", Took::from_std(*took.took().as_std()));
-    
-    Ok(())
-}
-
-fn visit_dirs(dir: &Path, cb: fn(&Path) -> io::Result<()>, parallel: bool) -> Result<(), Box<dyn Error>> {
-    if dir.is_dir() {
-        let it = fs::read_dir(dir).unwrap();
-        let parcours = |entry: Result<DirEntry, io::Error>| -> Result<(), Box<dyn Error>> {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if path.is_dir() {
-                visit_dirs(&path, cb, parallel)?
-            } else {
-                cb(&path)?
-            }
+    // written next to its destination, which only gets complete reports
+    let staged = report_path.map(|path| report_file(path, format)).transpose()?.map(AtomicFile::create).transpose()?;
+    let sink = || -> io::Result<Box<dyn Write + Send>> {
+        match &staged {
+            Some(staged) => Ok(Box::new(staged.handle()?)),
+            None => Ok(Box::new(io::stdout())),
+        }
+    };
+    // the entries all come to this thread, which writes them in large blocks instead of a flush per line
+    let mut out = BufWriter::with_capacity(OUTPUT_BUFFER, sink()?);
+    // the columns of a parquet or arrow report are all there, null when not recorded
+    let mut columnar = match (format, &staged) {
+        (ReportFormat::Text, _) => None,
+        (ReportFormat::Parquet, Some(_)) => Some(ColumnarOutput::to(Encoding::Parquet, Box::new(BufWriter::new(sink()?)))?),
+        (ReportFormat::Arrow, Some(_)) => Some(ColumnarOutput::to(Encoding::Arrow, Box::new(BufWriter::new(sink()?)))?),
+        (ReportFormat::Parquet, None) => Some(ColumnarOutput::open(Encoding::Parquet)?),
+        (ReportFormat::Arrow, None) => Some(ColumnarOutput::open(Encoding::Arrow)?),
+    };
+    let with_targets = symlinks == Symlinks::HashTarget;
+    let digests = algo.into_iter().filter(|&algorithm| algorithm != Algorithm::Blake3).chain(cdc.then_some(Algorithm::Chunks)).chain(similarity.into_iter().map(Similarity::algorithm)).unique().collect::<Vec<_>>();
+    if columnar.is_none() && (with_size || with_mtime || with_xattrs || with_targets || !digests.is_empty()) {
+        let metadata = [with_size.then_some(Field::Size), with_mtime.then_some(Field::Mtime), with_xattrs.then_some(Field::Xattrs), Some(Field::Path), with_targets.then_some(Field::Target)];
+        let fields = std::iter::once(Field::Hash).chain(digests.iter().map(|&algorithm| Field::Digest(algorithm))).chain(metadata.into_iter().flatten()).collect::<Vec<_>>();
+        writeln!(out, "{}", report::header(&fields))?;
+    }
+    blakediff::interrupt::install();
+    let mut hashed = 0usize;
+    let cache = cache.map(|dir| Cache::open(&dir)).transpose()?.map(Arc::new);
+    // deleted once the report is written
+    let shadow = vss.then(|| vss::Shadow::create(&dir)).transpose()?;
+    let location = shadow.as_ref().map_or(dir.as_str(), |shadow| shadow.root());
+    let source = source::open(location, &source::Options { cache: cache.clone(), trust_metadata, git, tar, symlinks, special, excludes, digests })?;
+    let mut progress = match pre_scan {
+        true => {
+            let items = source.list()?;
+            let total = items.iter().map(|item| item.size).sum();
+            log::info!("{} files to hash, {} bytes", items.len(), total);
+            Some(Progress::new(items.len(), total))
+        }
+        false => None,
+    };
+    let mut bytes = 0u64;
+    let skipped = Arc::new(AtomicUsize::new(0));
+    let on_error = keep_going.then(|| {
+        let skipped = Arc::clone(&skipped);
+        Arc::new(move |e: BlakediffError| {
+            output::print_skipped(&e);
+            skipped.fetch_add(1, Ordering::Relaxed);
+        }) as OnError
+    });
+    // the attributes and streams of a file which can't be read leave it out too
+    let skip = |e: BlakediffError, path: &str| match &on_error {
+        Some(on_error) => {
+            on_error(e.reading(path));
             Ok(())
+        }
+        None => Err(e),
+    };
+    // sizes come with the metadata the sources read anyway, they are left out of the report without --with-size
+    let walk = source.generate(parallel, true, with_mtime, on_error.clone(), &mut |mut entry| {
+        if with_xattrs {
+            match xattrs::digest(Path::new(&entry.path)) {
+                Ok(digest) => entry.xattrs = Some(digest),
+                Err(e) => return skip(e.into(), &entry.path),
+            }
+        }
+        let streams = match ads.then(|| stream_entries(&entry)).transpose() {
+            Ok(streams) => streams.unwrap_or_default(),
+            Err(e) => return skip(e, &entry.path),
         };
-
-        if parallel {
-            it.par_bridge().for_each(|entry| {
-                if let Err(e) = parcours(entry) {
-                    panic!("Error {}", e)
-                }
-            });
-        } else {
-            it.for_each(|entry| {
-                if let Err(e) = parcours(entry) {
-                    panic!("Error {}", e)
-                }
-            });
+        if let Some(progress) = &mut progress {
+            progress.add(entry.size.unwrap_or(0));
+        }
+        for mut entry in std::iter::once(entry).chain(streams) {
+            if let Some(shadow) = &shadow {
+                entry.path = shadow.original(&entry.path);
+            }
+            if let Some(form) = normalize {
+                entry.path = form.normalize(&entry.path).into_owned();
+            }
+            bytes += entry.size.unwrap_or(0);
+            if !with_size {
+                entry.size = None;
+            }
+            if tag_empty && entry.is_empty_file() {
+                entry.hash = String::from(report::EMPTY);
+            }
+            match &mut columnar {
+                Some(columnar) => columnar.push(&entry)?,
+                None => writeln!(out, "{}", entry)?,
+            }
+            hashed += 1;
+        }
+        Ok(())
+    });
+    match columnar {
+        // the rows written so far are complete, there is no room for a checkpoint
+        Some(columnar) => columnar.finish()?,
+        None if matches!(walk, Err(BlakediffError::Interrupted)) => {
+            // whole lines only were written, the checkpoint tells readers the report is partial
+            writeln!(out, "# blakediff-checkpoint: interrupted after {} files", hashed)?;
         }
+        None => {}
     }
-    if dir.is_file() {
-        cb(dir)?;
+    out.flush()?;
+    // even after an interruption, the files hashed so far are not hashed again
+    if let Some(cache) = cache {
+        cache.save()?;
+    }
+    match (staged, &walk) {
+        (Some(staged), Ok(())) => staged.commit()?,
+        (Some(staged), Err(BlakediffError::Interrupted)) => {
+            let partial = staged.keep();
+            eprintln!("partial report left in {}", partial.display());
+        }
+        // removed, the destination is left as it was
+        _ => {}
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    let skipped = skipped.load(Ordering::Relaxed);
+    if !output::quiet() {
+        let elapsed = *took.took().as_std();
+        let throughput = bytes as f64 / 1e6 / elapsed.as_secs_f64().max(1e-3);
+        eprintln!("{} files hashed, {} skipped, {} in {} : {:.1} MB/s", hashed, skipped, Size(bytes), Took::from_std(elapsed), throughput);
+    }
+    walk?;
+    match skipped {
+        0 => Ok(()),
+        // the report is written, the exit code still tells it is incomplete
+        skipped => Err(BlakediffError::Io(io::Error::other(format!("{} files or directories couldn't be read, they are not in the report", skipped)))),
     }
-    Ok(())
+}
+
+/// file of the report at `path`, in `report_blakediff.txt` when it is a directory
+fn report_file(path: PathBuf, format: ReportFormat) -> Result<PathBuf> {
+    let path = match path.is_dir() {
+        true => path.join("report_blakediff.txt"),
+        false => path,
+    };
+    match path.extension().is_some_and(|ext| ext == "gz") && format == ReportFormat::Text {
+        true => Err(BlakediffError::Format(String::from("generate writes uncompressed reports, gzip them once written"))),
+        false => Ok(path),
+    }
+}
+
+/// entries of the alternate data streams of the file of `entry`, with its modification time
+fn stream_entries(entry: &Entry) -> Result<Vec<Entry>> {
+    ads::streams(Path::new(&entry.path))?
+        .into_iter()
+        .map(|stream| {
+            let path = ads::stream_path(&entry.path, &stream.name);
+            Ok(Entry {
+                hash: source::blake3(fs::File::open(&path)?)?,
+                size: entry.size.map(|_| stream.size),
+                mtime: entry.mtime,
+                xattrs: None,
+                target: None,
+                digests: Vec::new(),
+                path,
+            })
+        })
+        .collect()
 }
 
 #[allow(unused)]
@@ -219,10 +1220,3 @@ fn display_files(path: &Path) -> io::Result<()> {
     println!("Name: {}", path.to_string_lossy());
     Ok(())
 }
-
-fn blake3_mmap(path: &Path) -> io::Result<()> {
-    let mut input = Input::open(path)?;
-    let output = input.hash()?;
-    println!("{} {}", output, path.to_string_lossy());
-    Ok(())
-}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::update::Summary;
+use blakediff::{BlakediffError, Result};
+
+/// content type of the Prometheus text format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// counters of the scans of the daemon, by root
+#[derive(Default)]
+pub struct Metrics {
+    roots: Mutex<BTreeMap<String, Root>>,
+}
+
+#[derive(Default)]
+struct Root {
+    scans: u64,
+    errors: u64,
+    files_hashed: u64,
+    bytes_hashed: u64,
+    seconds: f64,
+    last_seconds: f64,
+    last_success: u64,
+    files: usize,
+    duplicate_groups: usize,
+    duplicate_files: usize,
+}
+
+impl Metrics {
+    pub fn scanned(&self, root: &str, summary: &Summary, took: Duration) {
+        let mut roots = self.roots.lock().unwrap();
+        let root = roots.entry(root.to_owned()).or_default();
+        root.scans += 1;
+        root.files_hashed += (summary.updated.len() + summary.added.len()) as u64;
+        root.bytes_hashed += summary.bytes_hashed;
+        root.seconds += took.as_secs_f64();
+        root.last_seconds = took.as_secs_f64();
+        root.last_success = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        (root.files, root.duplicate_groups, root.duplicate_files) = (summary.files, summary.duplicate_groups, summary.duplicate_files);
+    }
+
+    pub fn failed(&self, root: &str) {
+        let mut roots = self.roots.lock().unwrap();
+        let root = roots.entry(root.to_owned()).or_default();
+        root.scans += 1;
+        root.errors += 1;
+    }
+
+    /// all the metrics, in the Prometheus text format
+    pub fn render(&self) -> String {
+        let roots = self.roots.lock().unwrap();
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&Root) -> String| {
+            write_family(&mut out, name, kind, help, roots.iter().map(|(dir, root)| (format!("root=\"{}\"", escape(dir)), value(root))));
+        };
+        family("blakediff_scans_total", "counter", "scans of the root, failed ones included", &|r| r.scans.to_string());
+        family("blakediff_scan_errors_total", "counter", "failed scans of the root", &|r| r.errors.to_string());
+        family("blakediff_files_hashed_total", "counter", "files hashed because they were added or modified", &|r| r.files_hashed.to_string());
+        family("blakediff_bytes_hashed_total", "counter", "bytes read to hash added or modified files", &|r| r.bytes_hashed.to_string());
+        family("blakediff_scan_duration_seconds_total", "counter", "time spent scanning the root", &|r| r.seconds.to_string());
+        family("blakediff_last_scan_duration_seconds", "gauge", "duration of the last successful scan", &|r| r.last_seconds.to_string());
+        family("blakediff_last_success_timestamp_seconds", "gauge", "end of the last successful scan, 0 before", &|r| r.last_success.to_string());
+        family("blakediff_files", "gauge", "files in the report of the root", &|r| r.files.to_string());
+        family("blakediff_duplicate_groups", "gauge", "groups of files sharing their content in the report", &|r| r.duplicate_groups.to_string());
+        family("blakediff_duplicate_files", "gauge", "extra copies in the groups of duplicates of the report", &|r| r.duplicate_files.to_string());
+        out
+    }
+}
+
+/// write a metric family : its help and type lines, then one sample per set of labels
+pub fn write_family(out: &mut String, name: &str, kind: &str, help: &str, samples: impl Iterator<Item = (String, String)>) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+    for (labels, value) in samples {
+        let _ = match labels.is_empty() {
+            true => writeln!(out, "{} {}", name, value),
+            false => writeln!(out, "{}{{{}}} {}", name, labels, value),
+        };
+    }
+}
+
+/// label value with its backslashes, quotes and newlines escaped
+pub fn escape(value: &str) -> String { value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n") }
+
+/// serve `GET /metrics` on `listen` from a background thread
+pub fn serve(metrics: Arc<Metrics>, listen: &str) -> Result<()> {
+    let server = Server::http(listen).map_err(|e| BlakediffError::Other(format!("cannot listen on {} : {}", listen, e)))?;
+    log::info!("metrics on http://{}/metrics", listen);
+    let content_type = Header::from_bytes("Content-Type", CONTENT_TYPE).unwrap();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match (request.method(), request.url()) {
+                (Method::Get, "/metrics") => Response::from_string(metrics.render()).with_header(content_type.clone()),
+                _ => Response::from_string("not found\n").with_status_code(404),
+            };
+            if let Err(e) = request.respond(response) {
+                log::warn!("error while responding : {}", e);
+            }
+        }
+    });
+    Ok(())
+}
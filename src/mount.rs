@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request};
+use itertools::Itertools;
+
+use blakediff::report;
+use blakediff::Result;
+
+const TTL: Duration = Duration::from_secs(60);
+const ROOT: u64 = 1;
+
+/// one unique content of the report
+struct Group {
+    hash: String,
+    /// absolute paths recorded with this hash
+    paths: Vec<String>,
+    /// content of the `paths` file
+    listing: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    /// `/<hash>`
+    Group(usize),
+    /// `/<hash>/content`, symlink to the first path
+    Content(usize),
+    /// `/<hash>/paths`, all the paths of the group, one per line
+    Paths(usize),
+}
+
+impl Node {
+    fn from_ino(ino: u64) -> Option<Self> {
+        match ino {
+            ROOT => Some(Node::Root),
+            0 => None,
+            _ => {
+                let i = ((ino - 2) / 3) as usize;
+                Some(match (ino - 2) % 3 {
+                    0 => Node::Group(i),
+                    1 => Node::Content(i),
+                    _ => Node::Paths(i),
+                })
+            }
+        }
+    }
+
+    fn ino(&self) -> u64 {
+        match *self {
+            Node::Root => ROOT,
+            Node::Group(i) => 2 + 3 * i as u64,
+            Node::Content(i) => 3 + 3 * i as u64,
+            Node::Paths(i) => 4 + 3 * i as u64,
+        }
+    }
+}
+
+/// read-only filesystem with one directory per unique hash of a report
+struct DedupFs {
+    groups: Vec<Group>,
+    by_hash: HashMap<String, usize>,
+    mounted_at: SystemTime,
+    uid: u32,
+    gid: u32,
+}
+
+/// mount a read-only view of `report_file` on `mountpoint`, with a directory per unique hash
+/// holding a `content` symlink to the first recorded path and a `paths` file listing all of them.
+/// Blocks until the filesystem is unmounted.
+pub fn mount(report_file: String, mountpoint: String) -> Result<()> {
+    let cwd = env::current_dir()?;
+    let mut paths_by_hash: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in report::Reader::open(Path::new(&report_file))? {
+        let entry = entry?;
+        paths_by_hash.entry(entry.hash).or_default().push(cwd.join(entry.path).to_string_lossy().into_owned());
+    }
+    let groups = paths_by_hash
+        .into_iter()
+        .sorted()
+        .map(|(hash, paths)| Group {
+            listing: paths.iter().map(|p| format!("{}\n", p)).collect::<String>().into_bytes(),
+            hash,
+            paths,
+        })
+        .collect::<Vec<_>>();
+    let fs = DedupFs {
+        by_hash: groups.iter().enumerate().map(|(i, g)| (g.hash.clone(), i)).collect(),
+        groups,
+        mounted_at: SystemTime::now(),
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    };
+    log::info!("mounting {} unique hashes of {} on {}", fs.groups.len(), report_file, mountpoint);
+    fuser::mount2(fs, &mountpoint, &[MountOption::RO, MountOption::FSName(String::from("blakediff"))])?;
+    Ok(())
+}
+
+impl DedupFs {
+    fn exists(&self, node: Node) -> bool {
+        match node {
+            Node::Root => true,
+            Node::Group(i) | Node::Content(i) | Node::Paths(i) => i < self.groups.len(),
+        }
+    }
+
+    fn attr(&self, node: Node) -> FileAttr {
+        let (kind, perm, size) = match node {
+            Node::Root | Node::Group(_) => (FileType::Directory, 0o555, 0),
+            Node::Content(i) => (FileType::Symlink, 0o777, self.groups[i].paths[0].len() as u64),
+            Node::Paths(i) => (FileType::RegularFile, 0o444, self.groups[i].listing.len() as u64),
+        };
+        FileAttr {
+            ino: node.ino(),
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mounted_at,
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            crtime: self.mounted_at,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    fn children(&self, node: Node) -> Vec<(Node, FileType, String)> {
+        match node {
+            Node::Root => self.groups.iter().enumerate().map(|(i, g)| (Node::Group(i), FileType::Directory, g.hash.clone())).collect(),
+            Node::Group(i) => vec![
+                (Node::Content(i), FileType::Symlink, String::from("content")),
+                (Node::Paths(i), FileType::RegularFile, String::from("paths")),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+impl Filesystem for DedupFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name = name.to_string_lossy();
+        let node = match Node::from_ino(parent) {
+            Some(Node::Root) => self.by_hash.get(name.as_ref()).map(|&i| Node::Group(i)),
+            Some(node @ Node::Group(i)) if self.exists(node) => match name.as_ref() {
+                "content" => Some(Node::Content(i)),
+                "paths" => Some(Node::Paths(i)),
+                _ => None,
+            },
+            _ => None,
+        };
+        match node {
+            Some(node) => reply.entry(&TTL, &self.attr(node), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match Node::from_ino(ino).filter(|node| self.exists(*node)) {
+            Some(node) => reply.attr(&TTL, &self.attr(node)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match Node::from_ino(ino).filter(|node| self.exists(*node)) {
+            Some(Node::Content(i)) => reply.data(self.groups[i].paths[0].as_bytes()),
+            _ => reply.error(libc::EINVAL),
+        }
+    }
+
+    fn read(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, size: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+        match Node::from_ino(ino).filter(|node| self.exists(*node)) {
+            Some(Node::Paths(i)) => {
+                let listing = &self.groups[i].listing;
+                let start = (offset.max(0) as usize).min(listing.len());
+                let end = (start + size as usize).min(listing.len());
+                reply.data(&listing[start..end]);
+            }
+            _ => reply.error(libc::EISDIR),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let node = match Node::from_ino(ino).filter(|node| self.exists(*node)) {
+            Some(node @ (Node::Root | Node::Group(_))) => node,
+            _ => return reply.error(libc::ENOTDIR),
+        };
+        // the parent of every directory is the root
+        let entries = [(node, FileType::Directory, String::from(".")), (Node::Root, FileType::Directory, String::from(".."))]
+            .into_iter()
+            .chain(self.children(node));
+        for (i, (child, kind, name)) in entries.enumerate().skip(offset as usize) {
+            // the buffer is full, the kernel will call again with the next offset
+            if reply.add(child.ino(), (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
@@ -0,0 +1,431 @@
+//! Container images : `oci://<file>` reads an archive written by `docker save` (or an OCI layout archive),
+//! `oci://<image>:<tag>` saves the image first through `docker save`, `BLAKEDIFF_DOCKER` replacing the command
+//! (`podman`). The layers are applied in order, with their whiteouts, and the files of the merged filesystem
+//! are reported as `oci://<image>/<path>`. Multi-platform images are read for the platform of this machine,
+//! or the `os/arch[/variant]` of `BLAKEDIFF_OCI_PLATFORM`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::error::OnError;
+use crate::report::Entry;
+use crate::source::{Backend, Item};
+use crate::{interrupt, BlakediffError, Result};
+
+/// An image, and the archive holding it.
+pub struct Image {
+    /// image reference or path of the archive, as given after `oci://`
+    name: String,
+    archive: PathBuf,
+    /// private directory of the archive saved from the container engine, removed with the image
+    _saved: Option<Private>,
+    /// offset and size of the members of the archive
+    members: HashMap<String, (u64, u64)>,
+    /// members of the archive holding the layers, lowest first
+    layers: Vec<String>,
+}
+
+/// `manifest.json` of `docker save`
+#[derive(Deserialize)]
+struct DockerManifest {
+    #[serde(rename = "Layers")]
+    layers: Vec<String>,
+}
+
+/// `index.json` of an OCI layout, an image index of its blobs listing the manifests of each platform, or an
+/// image manifest listing the layers
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize, PartialEq, Eq)]
+struct Platform {
+    os: String,
+    architecture: String,
+    variant: Option<String>,
+}
+
+impl Platform {
+    /// platform of `BLAKEDIFF_OCI_PLATFORM`, or of this machine
+    fn selected() -> Result<Self> {
+        let Some(platform) = std::env::var("BLAKEDIFF_OCI_PLATFORM").ok().filter(|v| !v.is_empty()) else {
+            let architecture = match std::env::consts::ARCH {
+                "x86_64" => "amd64",
+                "aarch64" => "arm64",
+                "x86" => "386",
+                "powerpc64" => "ppc64le",
+                arch => arch,
+            };
+            let os = if cfg!(windows) { "windows" } else { "linux" };
+            return Ok(Platform { os: os.to_owned(), architecture: architecture.to_owned(), variant: None });
+        };
+        match platform.split('/').collect::<Vec<_>>()[..] {
+            [os, architecture] => Ok(Platform { os: os.to_owned(), architecture: architecture.to_owned(), variant: None }),
+            [os, architecture, variant] => Ok(Platform { os: os.to_owned(), architecture: architecture.to_owned(), variant: Some(variant.to_owned()) }),
+            _ => Err(BlakediffError::Format(format!("BLAKEDIFF_OCI_PLATFORM : '{}' is not os/arch[/variant]", platform))),
+        }
+    }
+
+    /// `other` is this platform, any variant matching when this one has none
+    fn matches(&self, other: &Platform) -> bool { self.os == other.os && self.architecture == other.architecture && (self.variant.is_none() || self.variant == other.variant) }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        self.variant.iter().try_for_each(|variant| write!(f, "/{}", variant))
+    }
+}
+
+impl Image {
+    /// image of an `oci://<archive>` or `oci://<image>:<tag>` URL
+    pub fn open(url: &str) -> Result<Self> {
+        let name = url.strip_prefix("oci://").filter(|name| !name.is_empty()).ok_or_else(|| BlakediffError::Format(format!("'{}' is not an oci://<image> URL", url)))?;
+        let (archive, saved) = match fs::metadata(name) {
+            Ok(metadata) if metadata.is_file() => (PathBuf::from(name), None),
+            _ => {
+                let dir = Private::create()?;
+                (save(name, &dir)?, Some(dir))
+            }
+        };
+        let mut image = Image {
+            name: name.trim_end_matches('/').to_owned(),
+            archive,
+            _saved: saved,
+            members: HashMap::new(),
+            layers: Vec::new(),
+        };
+        image.index()?;
+        Ok(image)
+    }
+
+    /// find the members and the layers of the archive
+    fn index(&mut self) -> Result<()> {
+        let mut archive = tar::Archive::new(File::open(&self.archive)?);
+        let mut links = Vec::new();
+        let not_a_tar = |e: io::Error| BlakediffError::Format(format!("{} : not an image archive, {}", self.archive.display(), e));
+        for member in archive.entries().map_err(not_a_tar)? {
+            let member = member.map_err(not_a_tar)?;
+            let name = normalize(&member.path()?.to_string_lossy());
+            if member.header().entry_type().is_symlink() {
+                // layers shared by several images of `docker save`
+                if let Some(target) = member.link_name()? {
+                    let parent = name.rsplit_once('/').map_or("", |(parent, _)| parent);
+                    let target = normalize(&format!("{}/{}", parent, target.to_string_lossy()));
+                    links.push((name, target));
+                }
+                continue;
+            }
+            self.members.insert(name, (member.raw_file_position(), member.header().size()?));
+        }
+        for (name, target) in links {
+            if let Some(&member) = self.members.get(&target) {
+                self.members.insert(name, member);
+            }
+        }
+        let json = |image: &Image, name: &str| -> Result<Option<Vec<u8>>> {
+            match image.members.contains_key(name) {
+                true => Ok(Some(image.read(name)?)),
+                false => Ok(None),
+            }
+        };
+        let invalid = |e: serde_json::Error| BlakediffError::Format(format!("{} : {}", self.archive.display(), e));
+        self.layers = if let Some(manifest) = json(self, "manifest.json")? {
+            let manifests: Vec<DockerManifest> = serde_json::from_slice(&manifest).map_err(invalid)?;
+            manifests.into_iter().next().map(|manifest| manifest.layers.iter().map(|layer| normalize(layer)).collect()).unwrap_or_default()
+        } else if let Some(index) = json(self, "index.json")? {
+            let mut manifest: Manifest = serde_json::from_slice(&index).map_err(invalid)?;
+            let platform = Platform::selected()?;
+            // nested indexes, down to the manifest of a platform
+            for _ in 0..8 {
+                if manifest.manifests.is_empty() {
+                    break;
+                }
+                let descriptor = self.select(&manifest.manifests, &platform)?;
+                manifest = serde_json::from_slice(&self.read(&blob(&descriptor.digest))?).map_err(invalid)?;
+            }
+            if manifest.layers.is_empty() && !manifest.manifests.is_empty() {
+                return Err(BlakediffError::Format(format!("{} : image indexes nested too deep", self.archive.display())));
+            }
+            manifest.layers.iter().map(|layer| blob(&layer.digest)).collect()
+        } else {
+            return Err(self.not_an_image());
+        };
+        log::info!("{} : {} layers", self.name, self.layers.len());
+        Ok(())
+    }
+
+    /// manifest of `platform` among those of an index, the first one when none tells its platform
+    fn select<'a>(&self, manifests: &'a [Descriptor], platform: &Platform) -> Result<&'a Descriptor> {
+        if manifests.iter().all(|descriptor| descriptor.platform.is_none()) {
+            return Ok(&manifests[0]);
+        }
+        manifests.iter().find(|descriptor| descriptor.platform.as_ref().is_some_and(|other| platform.matches(other))).ok_or_else(|| {
+            // attestations are listed as unknown/unknown
+            let available = manifests.iter().filter_map(|descriptor| descriptor.platform.as_ref()).filter(|other| other.os != "unknown").map(|other| other.to_string()).collect::<Vec<_>>();
+            BlakediffError::Format(format!("{} : no image for {}, set BLAKEDIFF_OCI_PLATFORM to one of {}", self.archive.display(), platform, available.join(", ")))
+        })
+    }
+
+    fn not_an_image(&self) -> BlakediffError { BlakediffError::Format(format!("{} : not an image archive, neither manifest.json nor index.json found", self.archive.display())) }
+
+    /// content of a member of the archive, without decompression
+    fn member(&self, name: &str) -> Result<io::Take<File>> {
+        let &(offset, size) = self.members.get(name).ok_or_else(|| BlakediffError::Format(format!("{} : {} is missing", self.archive.display(), name)))?;
+        let mut file = File::open(&self.archive)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file.take(size))
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.member(name)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// tar archive of the layer `index`, gzip layers being decompressed
+    fn layer(&self, index: usize) -> Result<tar::Archive<Box<dyn Read + Send + '_>>> {
+        let name = &self.layers[index];
+        let layer = crate::tarball::decompress(self.member(name)?).map_err(|e| BlakediffError::Format(format!("{} : {} : {}", self.name, name, e)))?;
+        Ok(tar::Archive::new(layer))
+    }
+
+    /// files of the merged filesystem by path, with their hash when `hash` is set
+    fn merge(&self, hash: bool) -> Result<BTreeMap<String, (Item, Option<String>)>> {
+        let mut merged = BTreeMap::new();
+        for index in 0..self.layers.len() {
+            let mut layer = self.layer(index)?;
+            // subtrees of the lower layers whited out or replaced, directories being merged instead
+            let (mut files, mut removed, mut directories, mut opaque) = (BTreeMap::new(), Vec::new(), Vec::new(), Vec::new());
+            for entry in layer.entries()? {
+                interrupt::check()?;
+                let mut entry = entry?;
+                let raw = entry.path()?.to_string_lossy().into_owned();
+                let path = format!("/{}", normalize(&raw));
+                let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+                if name == ".wh..wh..opq" {
+                    opaque.push(parent.to_owned());
+                    continue;
+                }
+                if let Some(name) = name.strip_prefix(".wh.") {
+                    removed.push(format!("{}/{}", parent, name));
+                    continue;
+                }
+                let header = entry.header();
+                let entry_type = header.entry_type();
+                match entry_type.is_dir() {
+                    true => directories.push(path.clone()),
+                    false => removed.push(path.clone()),
+                }
+                if entry_type.is_hard_link() {
+                    let Some(target) = entry.link_name()? else { continue };
+                    let target = format!("/{}", normalize(&target.to_string_lossy()));
+                    if let Some((item, hash)) = files.get(&target).or_else(|| merged.get(&target)).cloned() {
+                        let item = Item { path: self.url(&path), ..item };
+                        files.insert(path, (item, hash));
+                    }
+                    continue;
+                }
+                if !entry_type.is_file() {
+                    continue;
+                }
+                let item = Item {
+                    path: self.url(&path),
+                    location: format!("{}:{}", index, raw),
+                    size: header.size()?,
+                    mtime: Duration::from_secs(header.mtime()?),
+                };
+                let hash = match hash {
+                    true => Some(crate::source::blake3(&mut entry)?),
+                    false => None,
+                };
+                files.insert(path, (item, hash));
+            }
+            for path in removed {
+                let under = format!("{}/", path);
+                merged.retain(|file: &String, _| file != &path && !file.starts_with(&under));
+            }
+            for directory in directories {
+                merged.remove(&directory);
+            }
+            for directory in opaque {
+                let under = format!("{}/", directory);
+                merged.retain(|file: &String, _| !file.starts_with(&under));
+            }
+            merged.append(&mut files);
+        }
+        Ok(merged)
+    }
+
+    /// URL of the file `path` of the merged filesystem in the report
+    pub fn url(&self, path: &str) -> String { format!("oci://{}{}", self.name, path) }
+}
+
+/// Temporary directory only the current user can enter, under a name no other one took, so that nobody can plant a
+/// file or a link where an archive is saved. It is removed with everything in it when dropped, even on error.
+struct Private(PathBuf);
+
+impl Private {
+    fn create() -> io::Result<Self> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+            let path = std::env::temp_dir().join(format!("blakediff-oci-{}-{}-{}", std::process::id(), nanos, CREATED.fetch_add(1, Ordering::Relaxed)));
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            match builder.create(&path) {
+                Ok(()) => return Ok(Self(path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Private {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            log::warn!("cannot remove {} : {}", self.0.display(), e);
+        }
+    }
+}
+
+impl Backend for Image {
+    fn list(&self) -> Result<Vec<Item>> { Ok(self.merge(false)?.into_values().map(|(item, _)| item).collect()) }
+
+    /// the file is found again by reading its layer up to it
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>> {
+        let (index, raw) = item.location.split_once(':').and_then(|(index, raw)| Some((index.parse::<usize>().ok()?, raw))).ok_or_else(|| BlakediffError::Other(format!("{} : invalid location {}", self.name, item.location)))?;
+        let mut layer = self.layer(index)?;
+        for entry in layer.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type().is_file() && entry.path()?.to_string_lossy() == raw {
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                return Ok(Box::new(io::Cursor::new(content)));
+            }
+        }
+        Err(BlakediffError::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} : not found", item.path))))
+    }
+
+    /// files hashed while their layers are read, lowest first, as hidden files can't be told apart before
+    fn generate(&self, _parallel: bool, with_size: bool, with_mtime: bool, _on_error: Option<OnError>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+        self.merge(true)?.into_values().try_for_each(|(item, hash)| {
+            write(Entry {
+                hash: hash.unwrap_or_default(),
+                size: with_size.then_some(item.size),
+                mtime: with_mtime.then_some(item.mtime),
+                xattrs: None,
+                target: None,
+                digests: Vec::new(),
+                path: item.path,
+            })
+        })
+    }
+}
+
+/// save the image `reference` of the container engine into an archive of the private directory `dir`
+fn save(reference: &str, dir: &Private) -> Result<PathBuf> {
+    let docker = std::env::var("BLAKEDIFF_DOCKER").ok().filter(|docker| !docker.trim().is_empty()).unwrap_or_else(|| String::from("docker"));
+    let archive = dir.0.join("image.tar");
+    let mut words = docker.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or("docker"));
+    log::info!("saving {} with {}", reference, docker);
+    let output = command.args(words).arg("save").arg("-o").arg(&archive).arg(reference).stdout(Stdio::null()).stderr(Stdio::piped()).output().map_err(|e| BlakediffError::Other(format!("{} : {}", docker, e)))?;
+    if !output.status.success() {
+        return Err(BlakediffError::Other(format!("{} save {} : {}", docker, reference, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(archive)
+}
+
+/// member of an OCI layout holding the blob `digest`
+fn blob(digest: &str) -> String { format!("blobs/{}", digest.replacen(':', "/", 1)) }
+
+/// path without `./`, `..`, empty components nor leading and trailing slashes
+fn normalize(path: &str) -> String {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// tar archive of `members`
+    fn archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn multi_platform_indexes_are_resolved() {
+        let amd64 = archive(&[("arch", b"amd64")]);
+        let arm64 = archive(&[("arch", b"arm64")]);
+        let manifest = |layer: &str| format!(r#"{{"layers":[{{"digest":"sha256:{}"}}]}}"#, layer);
+        let platform = |os: &str, architecture: &str| format!(r#"{{"os":"{}","architecture":"{}"}}"#, os, architecture);
+        let index = format!(
+            r#"{{"manifests":[{{"digest":"sha256:m1","platform":{}}},{{"digest":"sha256:m2","platform":{}}},{{"digest":"sha256:m3","platform":{}}}]}}"#,
+            platform("linux", "amd64"),
+            platform("linux", "arm64"),
+            platform("unknown", "unknown")
+        );
+        let layout = archive(&[
+            ("index.json", br#"{"manifests":[{"digest":"sha256:index"}]}"#),
+            ("blobs/sha256/index", index.as_bytes()),
+            ("blobs/sha256/m1", manifest("l1").as_bytes()),
+            ("blobs/sha256/m2", manifest("l2").as_bytes()),
+            ("blobs/sha256/l1", &amd64),
+            ("blobs/sha256/l2", &arm64),
+        ]);
+        let dir = Private::create().unwrap();
+        let path = dir.0.join("layout.tar");
+        fs::write(&path, layout).unwrap();
+        let url = format!("oci://{}", path.display());
+
+        std::env::set_var("BLAKEDIFF_OCI_PLATFORM", "linux/arm64");
+        let image = Image::open(&url).unwrap();
+        assert_eq!(image.layers, ["blobs/sha256/l2"]);
+        let mut content = String::new();
+        image.open(&image.list().unwrap()[0]).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "arm64");
+
+        std::env::set_var("BLAKEDIFF_OCI_PLATFORM", "linux/s390x");
+        let error = Image::open(&url).err().unwrap().to_string();
+        assert!(error.ends_with("no image for linux/s390x, set BLAKEDIFF_OCI_PLATFORM to one of linux/amd64, linux/arm64"), "{}", error);
+        std::env::remove_var("BLAKEDIFF_OCI_PLATFORM");
+    }
+}
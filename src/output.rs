@@ -0,0 +1,475 @@
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::columnar::{Columns, Encoding, Kind, Value};
+use crate::error::{BlakediffError, Result};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// leave out the summaries, totals and other lines which are not results
+pub fn set_quiet(quiet: bool) { QUIET.store(quiet, Ordering::Relaxed) }
+
+pub fn quiet() -> bool { QUIET.load(Ordering::Relaxed) }
+
+static HUMAN_READABLE: AtomicBool = AtomicBool::new(false);
+
+/// display the sizes of the text outputs with binary units, the other formats keeping bytes
+pub fn set_human_readable(human_readable: bool) { HUMAN_READABLE.store(human_readable, Ordering::Relaxed) }
+
+/// A size in bytes of the text outputs : `1536 bytes`, or `1.5 KiB` with `--human-readable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size(pub u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match HUMAN_READABLE.load(Ordering::Relaxed) {
+            true => f.write_str(&human(self.0)),
+            false => write!(f, "{} bytes", self.0),
+        }
+    }
+}
+
+/// size with the largest binary unit keeping it above 1, with one decimal : `512 B`, `1.4 GiB`
+pub fn human(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+static ERRORS_JSON: AtomicBool = AtomicBool::new(false);
+
+/// how errors are printed on the standard error
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Errors {
+    /// `Error <message>`
+    #[default]
+    Text,
+    /// one JSON object per line : stage, path, errno and message
+    Json,
+}
+
+pub fn set_errors(errors: Errors) { ERRORS_JSON.store(errors == Errors::Json, Ordering::Relaxed) }
+
+/// JSON line of an error with `--errors json`
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    stage: &'static str,
+    path: Option<std::borrow::Cow<'a, str>>,
+    errno: Option<i32>,
+    message: String,
+    /// the file was left out and the run went on
+    skipped: bool,
+}
+
+impl<'a> ErrorRecord<'a> {
+    fn new(e: &'a BlakediffError, skipped: bool) -> Self {
+        ErrorRecord {
+            stage: e.stage(),
+            path: e.path().map(|path| path.to_string_lossy()),
+            errno: e.io().and_then(io::Error::raw_os_error),
+            message: e.io().map_or_else(|| e.to_string(), io::Error::to_string),
+            skipped,
+        }
+    }
+
+    fn print(e: &'a BlakediffError, skipped: bool) { eprintln!("{}", serde_json::to_string(&Self::new(e, skipped)).expect("an error record is always serializable")) }
+}
+
+/// print `e` on the standard error, as text or as a JSON line
+pub fn print_error(e: &BlakediffError) {
+    match ERRORS_JSON.load(Ordering::Relaxed) {
+        true => ErrorRecord::print(e, false),
+        false => eprintln!("Error {}", e),
+    }
+}
+
+/// print a file or directory left out of the results after `e`, on the standard error, as text or as a JSON line
+pub fn print_skipped(e: &BlakediffError) {
+    match ERRORS_JSON.load(Ordering::Relaxed) {
+        true => ErrorRecord::print(e, true),
+        false => eprintln!("Skipped {}", e),
+    }
+}
+
+static SHELL_QUOTE: AtomicBool = AtomicBool::new(false);
+
+/// how paths are printed in the text outputs
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quote {
+    /// as they are
+    #[default]
+    Literal,
+    /// between single quotes when they hold other characters than letters, digits and `_-./:@%+,=`, ready to paste
+    /// into a shell
+    Shell,
+}
+
+pub fn set_quote(quote: Quote) { SHELL_QUOTE.store(quote == Quote::Shell, Ordering::Relaxed) }
+
+/// `path` displayed as chosen by `--quote`
+pub fn quoted(path: &str) -> Quoted<'_> { Quoted(path) }
+
+/// A path of the text outputs : `my file (1).txt`, or `'my file (1).txt'` with `--quote shell`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quoted<'a>(pub &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+,=".contains(c);
+        match SHELL_QUOTE.load(Ordering::Relaxed) && (self.0.is_empty() || !self.0.chars().all(safe)) {
+            // a quote closes the quoted string, is escaped, then opens another one
+            true => write!(f, "'{}'", self.0.replace('\'', r"'\''")),
+            false => f.write_str(self.0),
+        }
+    }
+}
+
+/// output format of the results
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// human readable lines
+    #[default]
+    Text,
+    /// a single JSON document
+    Json,
+    /// columns padded to their widest value, with a header line, the path last
+    Table,
+    /// comma separated values, with a header line
+    Csv,
+    /// tab separated values, with a header line
+    Tsv,
+    /// Parquet file, for DuckDB, Spark or pandas, on a standard output redirected to a file
+    Parquet,
+    /// Arrow IPC stream of record batches, for polars or pandas
+    Arrow,
+    /// the JSON document as MessagePack
+    Msgpack,
+    /// the JSON document as CBOR
+    Cbor,
+}
+
+/// output format of the report of `generate`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// report lines, readable by the other commands
+    #[default]
+    Text,
+    /// Parquet file, on a standard output redirected to a file
+    Parquet,
+    /// Arrow IPC stream of record batches, written as files are hashed
+    Arrow,
+}
+
+/// output format of groups of duplicates
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatesFormat {
+    /// human readable lines
+    #[default]
+    Text,
+    /// one path per line, groups separated by a blank line, like fdupes
+    Fdupes,
+    /// JSON document of rmlint (`rmlint -o json`)
+    Rmlint,
+    /// columns padded to their widest value : group, hash, size and path
+    Table,
+    /// Parquet file, one row per file with the number of its group, on a standard output redirected to a file
+    Parquet,
+    /// Arrow IPC stream of the same rows as parquet
+    Arrow,
+}
+
+impl DuplicatesFormat {
+    /// formats refusing a terminal, never paged
+    pub fn is_binary(self) -> bool { matches!(self, DuplicatesFormat::Parquet | DuplicatesFormat::Arrow) }
+}
+
+/// A result printable in every [`Format`].
+pub trait Render: Serialize {
+    /// one line of the CSV output, its fields naming the columns, and one row of the columnar formats
+    type Row<'a>: Serialize + Columns
+    where
+        Self: 'a;
+
+    /// human readable output
+    fn write_text(&self, out: &mut dyn Write) -> io::Result<()>;
+
+    /// lines of the CSV output, produced as they are written
+    fn rows(&self) -> Box<dyn Iterator<Item = Self::Row<'_>> + '_>;
+}
+
+/// Sequence serialized from the iterator returned by its closure, without collecting it first.
+pub struct Seq<F>(pub F);
+
+impl<F, I> Serialize for Seq<F>
+where
+    F: Fn() -> I,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> { serializer.collect_seq((self.0)()) }
+}
+
+/// Destination of results, rendering them in its format.
+pub trait Writer {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()>;
+}
+
+/// human readable lines
+pub struct TextWriter<W>(pub W);
+
+/// a single JSON document per result
+pub struct JsonWriter<W>(pub W);
+
+/// the JSON document as MessagePack, structs being maps
+#[cfg(feature = "msgpack")]
+pub struct MsgpackWriter<W>(pub W);
+
+/// the JSON document as CBOR
+#[cfg(feature = "cbor")]
+pub struct CborWriter<W>(pub W);
+
+/// columns padded to their widest value, with a header line, the path last
+pub struct TableWriter<W>(pub W);
+
+/// comma separated values, with a header line, or values separated by another delimiter
+pub struct CsvWriter<W> {
+    pub out: W,
+    pub delimiter: u8,
+}
+
+impl<W> CsvWriter<W> {
+    pub fn new(out: W) -> Self { Self { out, delimiter: b',' } }
+}
+
+impl<W: Write> Writer for TextWriter<W> {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()> { Ok(value.write_text(&mut self.0)?) }
+}
+
+impl<W: Write> Writer for JsonWriter<W> {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()> {
+        serde_json::to_writer(&mut self.0, value).map_err(io::Error::from)?;
+        Ok(writeln!(self.0)?)
+    }
+}
+
+impl<W: Write> Writer for TableWriter<W> {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()> { Ok(write_table(&mut self.0, value.rows())?) }
+}
+
+#[cfg(feature = "msgpack")]
+impl<W: Write> Writer for MsgpackWriter<W> {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()> { rmp_serde::encode::write_named(&mut self.0, value).map_err(|e| BlakediffError::Other(e.to_string())) }
+}
+
+#[cfg(feature = "cbor")]
+impl<W: Write> Writer for CborWriter<W> {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()> {
+        ciborium::into_writer(value, &mut self.0).map_err(|e| match e {
+            ciborium::ser::Error::Io(e) => BlakediffError::Io(e),
+            e => BlakediffError::Other(e.to_string()),
+        })
+    }
+}
+
+impl<W: Write> Writer for CsvWriter<W> {
+    fn write<T: Render>(&mut self, value: &T) -> Result<()> {
+        let mut writer = csv::WriterBuilder::new().delimiter(self.delimiter).from_writer(&mut self.out);
+        for row in value.rows() {
+            writer.serialize(row).map_err(|e| BlakediffError::Other(e.to_string()))?;
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+impl Format {
+    /// formats refusing a terminal, never paged
+    pub fn is_binary(self) -> bool { matches!(self, Format::Parquet | Format::Arrow | Format::Msgpack | Format::Cbor) }
+
+    /// render `value` on the standard output
+    pub fn print<T: Render>(self, value: &T) -> Result<()> { self.print_delimited(value, None) }
+
+    /// render `value` on the standard output, the values of the csv and tsv formats being separated by `delimiter`
+    /// instead of their own
+    pub fn print_delimited<T: Render>(self, value: &T, delimiter: Option<u8>) -> Result<()> {
+        let mut out = io::BufWriter::new(io::stdout().lock());
+        match (self, delimiter) {
+            (Format::Text | Format::Json | Format::Table | Format::Parquet | Format::Arrow | Format::Msgpack | Format::Cbor, Some(_)) => return Err(BlakediffError::Format(String::from("--delimiter only applies to the csv and tsv formats"))),
+            (Format::Text, None) => TextWriter(&mut out).write(value),
+            (Format::Json, None) => JsonWriter(&mut out).write(value),
+            (Format::Table, None) => TableWriter(&mut out).write(value),
+            (Format::Csv, delimiter) => CsvWriter { out: &mut out, delimiter: delimiter.unwrap_or(b',') }.write(value),
+            (Format::Tsv, delimiter) => CsvWriter { out: &mut out, delimiter: delimiter.unwrap_or(b'\t') }.write(value),
+            (Format::Parquet, None) => print_columnar(Encoding::Parquet, value.rows()),
+            (Format::Arrow, None) => print_columnar(Encoding::Arrow, value.rows()),
+            #[cfg(feature = "msgpack")]
+            (Format::Msgpack, None) => refuse_terminal("msgpack").and_then(|()| MsgpackWriter(&mut out).write(value)),
+            #[cfg(feature = "cbor")]
+            (Format::Cbor, None) => refuse_terminal("cbor").and_then(|()| CborWriter(&mut out).write(value)),
+            #[cfg(not(feature = "msgpack"))]
+            (Format::Msgpack, None) => Err(BlakediffError::Format(String::from("the msgpack format needs blakediff built with the msgpack feature"))),
+            #[cfg(not(feature = "cbor"))]
+            (Format::Cbor, None) => Err(BlakediffError::Format(String::from("the cbor format needs blakediff built with the cbor feature"))),
+        }?;
+        Ok(out.flush()?)
+    }
+}
+
+/// write `rows` as a table : columns without any value are left out, the others are padded to their widest value,
+/// numbers on the right, except the path which comes last so that its spaces don't shift anything
+pub fn write_table<R: Columns>(out: &mut dyn Write, rows: impl IntoIterator<Item = R>) -> io::Result<()> {
+    let cells = rows.into_iter().map(|row| row.values().iter().zip(R::COLUMNS).map(|(&value, &(name, _))| cell(name, value)).collect::<Vec<_>>()).collect::<Vec<_>>();
+    let path = R::COLUMNS.iter().position(|&(name, _)| name == "path");
+    let columns = (0..R::COLUMNS.len())
+        .filter(|&column| Some(column) != path)
+        .chain(path)
+        .filter(|&column| cells.iter().any(|row| row[column].is_some()))
+        .collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .map(|&column| cells.iter().filter_map(|row| row[column].as_ref()).map(|cell| cell.chars().count()).chain([R::COLUMNS[column].0.len()]).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let line = |out: &mut dyn Write, values: &mut dyn Iterator<Item = &str>| -> io::Result<()> {
+        let mut line = String::new();
+        for ((value, &column), &width) in values.zip(&columns).zip(&widths) {
+            if !line.is_empty() {
+                line.push_str("  ");
+            }
+            match (R::COLUMNS[column].1, Some(column) == path) {
+                (_, true) => line.push_str(value),
+                (Kind::Unsigned, false) => line.push_str(&format!("{:>width$}", value)),
+                (_, false) => line.push_str(&format!("{:<width$}", value)),
+            }
+        }
+        writeln!(out, "{}", line.trim_end())
+    };
+    line(out, &mut columns.iter().map(|&column| R::COLUMNS[column].0))?;
+    for row in &cells {
+        line(out, &mut columns.iter().map(|&column| row[column].as_deref().unwrap_or("-")))?;
+    }
+    Ok(())
+}
+
+/// text of a value of the column `name` in a table, None for a null
+fn cell(name: &str, value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Unsigned(value) if name == "size" && HUMAN_READABLE.load(Ordering::Relaxed) => Some(human(value)),
+        Value::Unsigned(value) => Some(value.to_string()),
+        Value::Text(value) if name == "path" => Some(quoted(value).to_string()),
+        Value::Text(value) => Some(value.to_owned()),
+        Value::Timestamp(value) => Some(crate::report::format_mtime(value)),
+    }
+}
+
+/// write `groups` of paths on the standard output for `xargs -0` : each path followed by a NUL, and each group by
+/// one more
+pub fn print_nul_groups<'a, G: IntoIterator<Item = &'a str>>(groups: impl IntoIterator<Item = G>) -> Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for group in groups {
+        for path in group {
+            out.write_all(path.as_bytes())?;
+            out.write_all(b"\0")?;
+        }
+        out.write_all(b"\0")?;
+    }
+    Ok(out.flush()?)
+}
+
+/// write `rows` on the standard output as a Parquet file or an Arrow IPC stream
+pub fn print_columnar<R: Columns>(encoding: Encoding, rows: impl IntoIterator<Item = R>) -> Result<()> {
+    let mut out = ColumnarOutput::open(encoding)?;
+    for row in rows {
+        out.push(&row)?;
+    }
+    out.finish()
+}
+
+/// Record batches written row by row on the standard output or into a file, when blakediff is built with the arrow
+/// feature.
+pub struct ColumnarOutput<R> {
+    #[cfg(feature = "arrow")]
+    writer: crate::columnar::BatchWriter<Box<dyn Write + Send>, R>,
+    #[cfg(not(feature = "arrow"))]
+    row: std::marker::PhantomData<R>,
+}
+
+impl<R: Columns> ColumnarOutput<R> {
+    /// on the standard output, which must not be a terminal
+    pub fn open(encoding: Encoding) -> Result<Self> {
+        let out = binary_stdout(encoding.name())?;
+        Self::to(encoding, Box::new(out))
+    }
+
+    #[cfg(feature = "arrow")]
+    pub fn to(encoding: Encoding, out: Box<dyn Write + Send>) -> Result<Self> { Ok(Self { writer: crate::columnar::BatchWriter::new(encoding, out)? }) }
+
+    #[cfg(not(feature = "arrow"))]
+    pub fn to(encoding: Encoding, _out: Box<dyn Write + Send>) -> Result<Self> { Err(BlakediffError::Format(format!("the {} format needs blakediff built with the {} feature", encoding.name(), encoding.name()))) }
+
+    #[cfg(feature = "arrow")]
+    pub fn push(&mut self, row: &R) -> Result<()> { self.writer.push(row) }
+
+    #[cfg(not(feature = "arrow"))]
+    pub fn push(&mut self, _row: &R) -> Result<()> { unreachable!("never opened without the arrow feature") }
+
+    /// write the remaining rows and the end of the file or stream
+    #[cfg(feature = "arrow")]
+    pub fn finish(self) -> Result<()> { self.writer.finish() }
+
+    #[cfg(not(feature = "arrow"))]
+    pub fn finish(self) -> Result<()> { unreachable!("never opened without the arrow feature") }
+}
+
+/// standard output for a binary format, which is not written to a terminal
+pub fn binary_stdout(format: &str) -> Result<io::BufWriter<io::Stdout>> {
+    refuse_terminal(format)?;
+    Ok(io::BufWriter::new(io::stdout()))
+}
+
+/// error when the standard output, where a binary format would be written, is a terminal
+fn refuse_terminal(format: &str) -> Result<()> {
+    match io::stdout().is_terminal() {
+        true => Err(BlakediffError::Format(format!("the {} format is binary, redirect the standard output to a file or a pipe", format))),
+        false => Ok(()),
+    }
+}
+
+/// delimiter given on the command line : a single ASCII character, `\t` or `tab` for a tabulation
+pub fn parse_delimiter(s: &str) -> std::result::Result<u8, String> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ => match s.as_bytes() {
+            &[c] if c.is_ascii() && c != b'\n' && c != b'"' => Ok(c),
+            _ => Err(format!("'{}' is not a single ascii character", s)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_records() {
+        let skipped = BlakediffError::Read { path: "photos/IMG_0001.jpg".into(), source: io::Error::from_raw_os_error(5) };
+        let record = serde_json::to_value(ErrorRecord::new(&skipped, true)).unwrap();
+        assert_eq!(record["stage"], "read");
+        assert_eq!(record["path"], "photos/IMG_0001.jpg");
+        assert_eq!(record["errno"], 5);
+        assert_eq!(record["skipped"], true);
+        let failed = serde_json::to_value(ErrorRecord::new(&BlakediffError::Format(String::from("bad")), false)).unwrap();
+        assert_eq!(failed, serde_json::json!({"stage": "usage", "path": null, "errno": null, "message": "bad", "skipped": false}));
+    }
+}
@@ -0,0 +1,69 @@
+//! Pager of the text outputs, started before anything is printed.
+
+/// pager of the output when none is configured
+#[cfg(unix)]
+const DEFAULT: &str = "less";
+
+/// send the standard output through `$BLAKEDIFF_PAGER`, `$PAGER` or `less` when it is a terminal, like git : `less`
+/// quits right away when the output fits on the screen. The pager is waited for at exit.
+#[cfg(unix)]
+pub fn start() {
+    use std::io::IsTerminal;
+    use std::os::unix::io::AsRawFd;
+    use std::process::{Command, Stdio};
+
+    if !std::io::stdout().is_terminal() {
+        return;
+    }
+    let pager = std::env::var("BLAKEDIFF_PAGER").or_else(|_| std::env::var("PAGER")).unwrap_or_else(|_| String::from(DEFAULT));
+    if pager.trim().is_empty() || pager.trim() == "cat" {
+        return;
+    }
+    let mut command = Command::new("sh");
+    command.args(["-c", &pager]).stdin(Stdio::piped());
+    // quit when a screen is enough, keep the colors, don't clear the screen at exit
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::warn!("cannot start the pager '{}' : {}", pager, e);
+            return;
+        }
+    };
+    let stdin = child.stdin.take().expect("piped stdin");
+    unsafe {
+        libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO);
+        // quitting the pager early ends blakediff quietly, instead of failing on the next write
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    drop(stdin);
+    PAGER.store(child.id() as i32, std::sync::atomic::Ordering::SeqCst);
+    unsafe { libc::atexit(wait) };
+}
+
+#[cfg(not(unix))]
+pub fn start() {}
+
+/// the standard output goes through a pager
+#[cfg(unix)]
+pub fn active() -> bool { PAGER.load(std::sync::atomic::Ordering::SeqCst) != 0 }
+
+#[cfg(not(unix))]
+pub fn active() -> bool { false }
+
+/// process id of the pager, 0 without one
+#[cfg(unix)]
+static PAGER: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+/// at exit, once the standard output is flushed : close it so that the pager reads its end, then wait for the user
+/// to quit it
+#[cfg(unix)]
+extern "C" fn wait() {
+    let pid = PAGER.load(std::sync::atomic::Ordering::SeqCst);
+    unsafe {
+        libc::close(libc::STDOUT_FILENO);
+        libc::waitpid(pid, std::ptr::null_mut(), 0);
+    }
+}
@@ -0,0 +1,234 @@
+//! Hashing pipeline of `generate` : a walker thread produces the files, a pool of workers hashes them,
+//! and the calling thread receives the entries in walk order, through [`ordered`], which `verify` also uses. Bounded channels keep each stage
+//! from running too far ahead, so that slow directories and slow files overlap.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::cache::Cache;
+use crate::report::Entry;
+use crate::walker::{self, FileEntry, Walker};
+use crate::input::Algorithm;
+use crate::error::OnError;
+use crate::{hash_file_digests, interrupt, threads, BlakediffError, Result};
+
+/// files hashed ahead of the writer at most, waiting for a slow file to be written first
+const WINDOW: usize = 4096;
+
+/// Builder of a hashing pipeline over a directory tree.
+pub struct Pipeline {
+    walker: Walker,
+    workers: usize,
+    with_size: bool,
+    with_mtime: bool,
+    cache: Option<Arc<Cache>>,
+    digests: Vec<Algorithm>,
+    on_error: Option<OnError>,
+}
+
+impl Pipeline {
+    pub fn new(walker: Walker) -> Self {
+        Self {
+            walker,
+            workers: 1,
+            with_size: false,
+            with_mtime: false,
+            cache: None,
+            digests: Vec::new(),
+            on_error: None,
+        }
+    }
+
+    /// number of threads hashing files, at least 1, and 1 in [`hdd`](crate::walker::hdd) mode
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = if walker::hdd() { 1 } else { workers.max(1) };
+        self
+    }
+
+    /// record the size and modification time of the files
+    pub fn metadata(mut self, with_size: bool, with_mtime: bool) -> Self {
+        (self.with_size, self.with_mtime) = (with_size, with_mtime);
+        self
+    }
+
+    /// take the hashes of unchanged files from `cache`, and add the others to it. The caller saves it.
+    pub fn cache(mut self, cache: Option<Arc<Cache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// also digest the files with `digests`, in the same read as their BLAKE3 hash
+    pub fn digests(mut self, digests: Vec<Algorithm>) -> Self {
+        self.digests = digests;
+        self
+    }
+
+    /// hand the files and directories which can't be walked or read to `on_error` and go on with the others
+    pub fn skip_errors(mut self, on_error: Option<OnError>) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// call `write` on the entry of every file, in walk order, stopping at the first error.
+    /// Hashing stops when a signal was caught, see [`interrupt`].
+    pub fn run(self, write: impl FnMut(Entry) -> Result<()>) -> Result<()> {
+        let files = self.walker.skip_errors(self.on_error.clone()).channel();
+        let (with_size, with_mtime, cache, digests, on_error) = (self.with_size, self.with_mtime, &self.cache, &self.digests, &self.on_error);
+        ordered(files.into_iter(), self.workers, |file: FileEntry| {
+            let entry = interrupt::check().and_then(|()| {
+                match cache {
+                    Some(cache) => cache.hash_file(&file, with_size, with_mtime, digests),
+                    None => hash_file_digests(&file, with_size, with_mtime, digests),
+                }
+                .map_err(|source| BlakediffError::Read { path: file.path.clone(), source })
+            });
+            // a skipped file still takes its turn, so that the ones after it are written
+            match (entry, on_error) {
+                (Err(e @ BlakediffError::Read { .. }), Some(on_error)) => {
+                    on_error(e);
+                    Ok(None)
+                }
+                (entry, _) => entry.map(Some),
+            }
+        }, write)
+    }
+}
+
+/// Call `work` on every item of `items` across `workers` threads, and `write` on the results in the order of
+/// `items`, the ones `work` skips with `None` taking their turn. Stops at the first error, of `items`, `work` or
+/// `write`. Items are read on a thread of their own, at most [`WINDOW`] of them ahead of the last one written.
+pub fn ordered<T: Send, R: Send>(items: impl Iterator<Item = Result<T>> + Send, workers: usize, work: impl Fn(T) -> Result<Option<R>> + Sync, mut write: impl FnMut(R) -> Result<()>) -> Result<()> {
+    // a slot is taken for each item sent to the workers and given back once it is written
+    let (slot_tx, slot_rx) = mpsc::sync_channel(WINDOW);
+    (0..WINDOW).for_each(|_| slot_tx.send(()).unwrap());
+    let workers = workers.max(1);
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, T)>(workers * 4);
+    let (done_tx, done_rx) = mpsc::sync_channel(workers * 4);
+    // shared by the workers only, so that the dispatcher stops once they all left
+    let job_rx = Arc::new(Mutex::new(job_rx));
+
+    thread::scope(|scope| {
+        let dispatcher = scope.spawn(move || dispatch(items, slot_rx, job_tx));
+        for _ in 0..workers {
+            let (job_rx, done_tx, work) = (Arc::clone(&job_rx), done_tx.clone(), &work);
+            scope.spawn(move || loop {
+                let Ok((seq, item)) = job_rx.lock().unwrap().recv() else { return };
+                if done_tx.send((seq, work(item))).is_err() {
+                    return;
+                }
+            });
+        }
+        drop((job_rx, done_tx));
+
+        // results of the items done before the ones preceding them wait here
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let written = (|| {
+            for (seq, result) in done_rx.iter() {
+                pending.insert(seq, result);
+                while let Some(result) = pending.remove(&next) {
+                    if let Some(result) = result? {
+                        write(result)?;
+                    }
+                    next += 1;
+                    let _ = slot_tx.send(());
+                }
+            }
+            Ok(())
+        })();
+        // stop the other threads : their sends now fail
+        drop(done_rx);
+        drop(slot_tx);
+        let read = dispatcher.join().unwrap();
+        written.and(read)
+    })
+}
+
+/// number the items and hand them to the workers, as long as a slot is free
+fn dispatch<T>(items: impl Iterator<Item = Result<T>>, slots: Receiver<()>, jobs: mpsc::SyncSender<(usize, T)>) -> Result<()> {
+    for (seq, item) in items.enumerate() {
+        let item = item?;
+        if slots.recv().is_err() || jobs.send((seq, item)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// entries of every file under `dir`, in walk order, see [`Pipeline`]
+pub fn hash_tree(dir: &Path, parallel: bool, with_size: bool, with_mtime: bool) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let workers = if parallel { threads::io_threads() } else { 1 };
+    Pipeline::new(Walker::new(dir).parallel(parallel)).workers(workers).metadata(with_size, with_mtime).run(|entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::tempdir::TempDir;
+
+    #[test]
+    fn results_are_written_in_order() {
+        // the first items take the longest, so that the later ones are done before them
+        let work = |i: usize| {
+            thread::sleep(std::time::Duration::from_micros(((100 - i % 100) * 10) as u64));
+            Ok((!i.is_multiple_of(3)).then_some(i))
+        };
+        let mut written = Vec::new();
+        ordered((0..1000).map(Ok), 8, work, |i| {
+            written.push(i);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(written, (0..1000).filter(|i: &usize| !i.is_multiple_of(3)).collect::<Vec<_>>());
+
+        let failed = ordered((0..1000).map(|i| if i == 500 { Err(BlakediffError::Other(String::from("item 500"))) } else { Ok(i) }), 4, |i| Ok(Some(i)), |i| {
+            assert!(i < 500);
+            Ok(())
+        });
+        assert!(failed.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_files_are_skipped() {
+        let tmp = TempDir::new("pipeline").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(dir.join("sub/b"), "b").unwrap();
+        // a broken link fails the walk, and /proc/self/mem the read even as root, or the walk without /proc
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("broken")).unwrap();
+        std::os::unix::fs::symlink("/proc/self/mem", dir.join("mem")).unwrap();
+
+        let failed = Pipeline::new(Walker::new(dir)).run(|_| Ok(()));
+        assert!(failed.is_err());
+        let skipped = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&skipped);
+        let mut paths = Vec::new();
+        let walked = Pipeline::new(Walker::new(dir).parallel(true))
+            .workers(4)
+            .skip_errors(Some(Arc::new(move |e: BlakediffError| {
+                assert!(e.path().is_some(), "{}", e);
+                counter.fetch_add(1, Ordering::Relaxed);
+            })))
+            .run(|entry| {
+                paths.push(Path::new(&entry.path).strip_prefix(dir).unwrap().to_owned());
+                Ok(())
+            });
+        walked.unwrap();
+        paths.sort();
+        assert_eq!(paths, [Path::new("a"), Path::new("sub/b")]);
+        assert_eq!(skipped.load(Ordering::Relaxed), 2);
+    }
+}
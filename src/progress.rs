@@ -0,0 +1,76 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use blakediff::output;
+
+/// time between two redraws of the progress line
+const REDRAW: Duration = Duration::from_millis(200);
+
+/// Progress of `generate` against the totals of a pre-scan, or of `verify` against the report, drawn on the standard error when it is a terminal :
+/// percent of the bytes hashed, files, and the time left at the throughput so far.
+pub struct Progress {
+    total_files: usize,
+    total_bytes: u64,
+    files: usize,
+    bytes: u64,
+    start: Instant,
+    drawn: Option<Instant>,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(total_files: usize, total_bytes: u64) -> Self {
+        Self {
+            total_files,
+            total_bytes,
+            files: 0,
+            bytes: 0,
+            start: Instant::now(),
+            drawn: None,
+            enabled: io::stderr().is_terminal() && !output::quiet(),
+        }
+    }
+
+    /// a file of `size` bytes was hashed
+    pub fn add(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes += size;
+        if self.enabled && self.drawn.is_none_or(|drawn| drawn.elapsed() >= REDRAW) {
+            self.draw();
+        }
+    }
+
+    /// erase the progress line, before the summary
+    pub fn finish(&self) {
+        if self.enabled && self.drawn.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+
+    fn draw(&mut self) {
+        // the bytes tell the remaining work better than the files, unless all of them are empty
+        let (done, total) = match self.total_bytes {
+            0 => (self.files as f64, self.total_files as f64),
+            _ => (self.bytes as f64, self.total_bytes as f64),
+        };
+        let ratio = (done / total.max(1.0)).min(1.0);
+        let eta = match ratio > 0.0 {
+            true => format!("{} left", remaining(self.start.elapsed().mul_f64((1.0 - ratio) / ratio))),
+            false => String::from("estimating"),
+        };
+        let mut err = io::stderr().lock();
+        let _ = write!(err, "\r\x1b[K{:3.0}% {}/{} files, {}/{}, {}", ratio * 100.0, self.files, self.total_files, output::human(self.bytes), output::human(self.total_bytes), eta);
+        let _ = err.flush();
+        self.drawn = Some(Instant::now());
+    }
+}
+
+/// `1h02m`, `3m05s` or `12s`
+fn remaining(time: Duration) -> String {
+    let secs = time.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use blakediff::output::quoted;
+use blakediff::report;
+use blakediff::Result;
+
+/// remove from `report_file` the entries whose files don't exist anymore, only looking at those under `only_under` if given
+pub fn prune(report_file: String, only_under: Option<String>) -> Result<()> {
+    let report_path = Path::new(&report_file);
+    let mut reader = report::Reader::open(report_path)?;
+    let mut kept = Vec::new();
+    let mut pruned = 0usize;
+    for entry in reader.by_ref() {
+        let entry = entry?;
+        let path = Path::new(&entry.path);
+        let candidate = only_under.as_ref().is_none_or(|root| path.starts_with(root));
+        // only a file known to be missing is pruned, not one we failed to stat for another reason, nor a dangling link
+        // which is still there
+        if candidate && fs::symlink_metadata(path).is_err_and(|e| e.kind() == io::ErrorKind::NotFound) {
+            pruned += 1;
+            println!("pruned : {}", quoted(&entry.path));
+        } else {
+            kept.push(entry);
+        }
+    }
+    let fields = reader.fields().to_vec();
+    if pruned > 0 {
+        report::save(report_path, &fields, &kept)?;
+    }
+    summary!("prune : {} entries removed, {} kept", pruned, kept.len());
+    Ok(())
+}
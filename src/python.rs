@@ -0,0 +1,94 @@
+//! Python bindings of the engine, built as the `blakediff` extension module with maturin.
+//! Results are plain lists and dicts.
+
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::report::Entry;
+use crate::{total_size, BlakediffError};
+
+impl From<BlakediffError> for PyErr {
+    fn from(e: BlakediffError) -> Self {
+        match e {
+            BlakediffError::Io(e) => e.into(),
+            BlakediffError::Walk { .. } => PyOSError::new_err(e.to_string()),
+            _ => PyValueError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// dict of an entry, with None for what the report doesn't hold
+fn entry<'py>(py: Python<'py>, entry: &Entry) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("hash", &entry.hash)?;
+    dict.set_item("size", entry.size)?;
+    dict.set_item("mtime", entry.mtime.map(|mtime| mtime.as_secs_f64()))?;
+    dict.set_item("path", &entry.path)?;
+    Ok(dict)
+}
+
+/// hash every file under `dir`, one dict per file
+#[pyfunction]
+#[pyo3(signature = (dir, parallel = true, with_size = true, with_mtime = false))]
+fn generate<'py>(py: Python<'py>, dir: PathBuf, parallel: bool, with_size: bool, with_mtime: bool) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let entries = py.detach(|| crate::generate(&dir, parallel, with_size, with_mtime))?;
+    entries.iter().map(|e| entry(py, e)).collect()
+}
+
+/// groups of entries sharing the same hash in a report, sorted by path
+#[pyfunction]
+fn analyze<'py>(py: Python<'py>, report_file: PathBuf) -> PyResult<Vec<Vec<Bound<'py, PyDict>>>> {
+    let groups = py.detach(|| crate::analyze(&report_file))?;
+    groups.iter().map(|group| group.iter().map(|e| entry(py, e)).collect()).collect()
+}
+
+/// compare two reports, shaped like the JSON document of `blakediff compare --format json` with full entries as files
+#[pyfunction]
+fn compare<'py>(py: Python<'py>, report_1: &str, report_2: &str) -> PyResult<Bound<'py, PyDict>> {
+    let comparison = py.detach(|| crate::compare(report_1, report_2))?;
+    let only_in = comparison
+        .only_in
+        .iter()
+        .map(|(report, entries)| {
+            let dict = PyDict::new(py);
+            dict.set_item("report", report)?;
+            dict.set_item("files", entries.iter().map(|e| entry(py, e)).collect::<PyResult<Vec<_>>>()?)?;
+            dict.set_item("total_size", total_size(entries))?;
+            Ok(dict)
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let duplicates = comparison
+        .duplicates
+        .iter()
+        .map(|(report, groups)| {
+            let dict = PyDict::new(py);
+            dict.set_item("report", report)?;
+            let groups = groups
+                .iter()
+                .map(|group| {
+                    let dict = PyDict::new(py);
+                    dict.set_item("hash", &group.hash)?;
+                    dict.set_item("paths", &group.paths)?;
+                    Ok(dict)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            dict.set_item("groups", groups)?;
+            Ok(dict)
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let dict = PyDict::new(py);
+    dict.set_item("only_in", only_in)?;
+    dict.set_item("duplicates", duplicates)?;
+    Ok(dict)
+}
+
+#[pymodule]
+fn blakediff(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(generate, m)?)?;
+    m.add_function(wrap_pyfunction!(analyze, m)?)?;
+    m.add_function(wrap_pyfunction!(compare, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use blakediff::input::Input;
+use blakediff::report::{self, Entry, Field};
+use blakediff::{BlakediffError, Result};
+
+use crate::trash;
+
+/// file of a quarantine directory listing the moved files with their original path, in the report format
+const MANIFEST: &str = "blakediff-manifest.txt";
+
+/// move all the files of each group but the first one into `dir`, under their original absolute path,
+/// recording them in the manifest of `dir` so that they can be restored
+pub fn quarantine(groups: &[Vec<Entry>], dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut manifest = OpenOptions::new().create(true).append(true).open(dir.join(MANIFEST))?;
+    let mut moved = 0usize;
+    for group in groups {
+        let (keeper, others) = group.split_first().unwrap();
+        // never move the last copy away : the kept file must still hold the content
+        if !unchanged(keeper, Path::new(&keeper.path)) {
+            log::warn!("{} changed or is missing since the report, its group is left untouched", keeper.path);
+            continue;
+        }
+        for dupe in others {
+            // a symbolic link is moved itself, not the file it points to
+            let original = match trash::absolute(Path::new(&dupe.path)) {
+                Ok(original) => original,
+                Err(e) => {
+                    log::warn!("{} skipped : {}", dupe.path, e);
+                    continue;
+                }
+            };
+            if !unchanged(dupe, &original) {
+                log::warn!("{} changed or is missing since the report, it is left in place", dupe.path);
+                continue;
+            }
+            let target = location(dir, &original);
+            move_file(&original, &target)?;
+            let entry = Entry {
+                path: original.to_string_lossy().into_owned(),
+                ..dupe.clone()
+            };
+            // recorded right away, so that an interrupted run can still be restored
+            writeln!(manifest, "{}", entry.to_line(&[Field::Hash, Field::Path]))?;
+            manifest.flush()?;
+            println!("moved : {} -> {}", dupe.path, target.display());
+            moved += 1;
+        }
+    }
+    summary!("quarantine : {} files moved to {}", moved, dir.display());
+    Ok(())
+}
+
+/// move back the files of the quarantine `dir` to their original path, unless another file took their place. The
+/// manifest keeps the files that couldn't be restored.
+pub fn restore(dir: &Path) -> Result<()> {
+    let manifest = dir.join(MANIFEST);
+    let mut remaining = Vec::new();
+    let (mut restored, mut failed) = (0usize, 0usize);
+    for entry in report::Reader::open(&manifest)? {
+        let entry = entry?;
+        let original = Path::new(&entry.path);
+        let target = location(dir, original);
+        if original.exists() {
+            log::warn!("{} exists, {} is left in quarantine", original.display(), target.display());
+            remaining.push(entry);
+            continue;
+        }
+        if let Err(e) = move_file(&target, original) {
+            log::error!("{} : {}, left in quarantine", target.display(), e);
+            remaining.push(entry);
+            failed += 1;
+            continue;
+        }
+        println!("restored : {}", entry.path);
+        restored += 1;
+    }
+    if remaining.is_empty() {
+        fs::remove_file(&manifest)?;
+    } else {
+        report::save(&manifest, &[Field::Hash, Field::Path], &remaining)?;
+    }
+    summary!("restore : {} files restored, {} left in quarantine", restored, remaining.len());
+    if failed > 0 {
+        return Err(BlakediffError::Io(io::Error::other(format!("{} files couldn't be restored", failed))));
+    }
+    Ok(())
+}
+
+/// `path` still holds the content of `entry`
+fn unchanged(entry: &Entry, path: &Path) -> bool { Input::open(path).and_then(|mut input| input.hash()).is_ok_and(|hash| entry.matches(&hash)) }
+
+/// path of the quarantined copy of the absolute path `original`
+fn location(dir: &Path, original: &Path) -> PathBuf {
+    dir.join(original.components().filter_map(|c| if let Component::Normal(c) = c { Some(c) } else { None }).collect::<PathBuf>())
+}
+
+/// rename `src` to `dst`, creating its parent, with a copy when they are on different filesystems
+fn move_file(src: &Path, dst: &Path) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(src, dst).is_ok() {
+        return Ok(());
+    }
+    fs::copy(src, dst)?;
+    fs::remove_file(src)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+
+    fn entry(path: &Path, content: &[u8]) -> Entry {
+        Entry {
+            hash: blake3::hash(content).to_string(),
+            size: None,
+            mtime: None,
+            xattrs: None,
+            path: path.to_string_lossy().into_owned(),
+            target: None,
+            digests: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn changed_files_stay_and_links_move_themselves() {
+        let tmp = TempDir::new("quarantine").unwrap();
+        let dir = tmp.path();
+        let (files, quarantined) = (dir.join("files"), dir.join("quarantine"));
+        fs::create_dir_all(&files).unwrap();
+        let files = fs::canonicalize(&files).unwrap();
+        for name in ["keep.txt", "dupe.txt"] {
+            fs::write(files.join(name), b"same").unwrap();
+        }
+        fs::write(files.join("changed.txt"), b"edited since").unwrap();
+        std::os::unix::fs::symlink("keep.txt", files.join("link.txt")).unwrap();
+        let group = ["keep.txt", "dupe.txt", "changed.txt", "link.txt"].map(|name| entry(&files.join(name), b"same")).to_vec();
+
+        quarantine(&[group], &quarantined).unwrap();
+        assert!(files.join("keep.txt").exists() && files.join("changed.txt").exists());
+        assert!(fs::symlink_metadata(files.join("dupe.txt")).is_err() && fs::symlink_metadata(files.join("link.txt")).is_err());
+        assert!(fs::symlink_metadata(location(&quarantined, &files.join("link.txt"))).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(location(&quarantined, &files.join("dupe.txt"))).unwrap(), b"same");
+
+        // a file took the place of dupe.txt : only the link comes back, the manifest keeps dupe.txt
+        fs::write(files.join("dupe.txt"), b"new").unwrap();
+        restore(&quarantined).unwrap();
+        assert_eq!(fs::read_link(files.join("link.txt")).unwrap(), Path::new("keep.txt"));
+        let left = report::Reader::open(&quarantined.join(MANIFEST)).unwrap().map(|entry| entry.unwrap().path).collect::<Vec<_>>();
+        assert_eq!(left, [files.join("dupe.txt").to_string_lossy()]);
+    }
+}
@@ -0,0 +1,682 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::slice;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use itertools::Itertools;
+
+use crate::atomic::AtomicFile;
+use crate::error::{BlakediffError, Result};
+use crate::input::{Algorithm, Input};
+
+/// Prefix of the header line declaring the columns of a report.
+/// Reports without header are read as the historical `<hash> <path>` format.
+pub const HEADER_PREFIX: &str = "# blakediff:";
+
+/// Types written instead of a hash for the special files recorded by `generate --special record`, which have no
+/// content to hash.
+pub const SPECIAL: [&str; 4] = ["fifo", "socket", "char-device", "block-device"];
+
+/// Written instead of the hash of zero-length files by `generate --tag-empty`, the same content as [`EMPTY_HASH`] for
+/// the other commands.
+pub const EMPTY: &str = "empty";
+
+/// BLAKE3 of no byte, shared by all the zero-length files
+pub const EMPTY_HASH: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+/// what `analyze` does with the zero-length files, which all share a hash without being copies of each other
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Empty {
+    /// leave them out
+    Ignore,
+    /// in a group of their own, after the duplicates
+    #[default]
+    Group,
+    /// one per line after the duplicates, without making a group
+    List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Hash,
+    /// digest of the content with another algorithm than BLAKE3, in the same read, see [`Entry::digests`]
+    Digest(Algorithm),
+    Size,
+    Mtime,
+    Xattrs,
+    Path,
+    /// target of symbolic links, after their path and ` -> `
+    Target,
+}
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Hash => "hash",
+            Field::Digest(algorithm) => algorithm.name(),
+            Field::Size => "size",
+            Field::Mtime => "mtime",
+            Field::Xattrs => "xattrs",
+            Field::Path => "path",
+            Field::Target => "target",
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(Field::Hash),
+            "sha256" => Ok(Field::Digest(Algorithm::Sha256)),
+            "xxh3" => Ok(Field::Digest(Algorithm::Xxh3)),
+            "phash" => Ok(Field::Digest(Algorithm::Phash)),
+            "audio" => Ok(Field::Digest(Algorithm::Audio)),
+            "chunks" => Ok(Field::Digest(Algorithm::Chunks)),
+            "tlsh" => Ok(Field::Digest(Algorithm::Tlsh)),
+            "ssdeep" => Ok(Field::Digest(Algorithm::Ssdeep)),
+            "size" => Ok(Field::Size),
+            "mtime" => Ok(Field::Mtime),
+            "xattrs" => Ok(Field::Xattrs),
+            "path" => Ok(Field::Path),
+            "target" => Ok(Field::Target),
+            _ => Err(format!("unknown report field '{}'", s)),
+        }
+    }
+}
+
+/// Header line to write at the top of a report made of `fields`.
+pub fn header(fields: &[Field]) -> String { format!("{} {}", HEADER_PREFIX, fields.iter().map(Field::name).collect::<Vec<_>>().join(" ")) }
+
+/// One line of a report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub hash: String,
+    pub size: Option<u64>,
+    /// modification time, since the unix epoch
+    pub mtime: Option<Duration>,
+    /// BLAKE3 of the extended attributes and ACLs, see [`xattrs`](crate::xattrs)
+    pub xattrs: Option<String>,
+    pub path: String,
+    /// target of a symbolic link recorded as such, its hash being the one of this path
+    pub target: Option<String>,
+    /// digests of the content with other algorithms, next to its BLAKE3 hash
+    pub digests: Vec<(Algorithm, String)>,
+}
+
+impl Entry {
+    /// a special file, whose hash is its type, see [`SPECIAL`]
+    pub fn is_special(&self) -> bool { SPECIAL.contains(&self.hash.as_str()) }
+
+    /// a zero-length file, tagged by `generate --tag-empty` or not
+    pub fn is_empty_file(&self) -> bool { self.target.is_none() && (self.hash == EMPTY || self.hash == EMPTY_HASH) }
+
+    /// `hash`, just computed, is the recorded one
+    pub fn matches(&self, hash: &str) -> bool { hash == self.hash || (self.hash == EMPTY && hash == EMPTY_HASH) }
+
+    /// digest of the content with `algorithm`, when recorded
+    pub fn digest(&self, algorithm: Algorithm) -> Option<&str> { self.digests.iter().find(|(a, _)| *a == algorithm).map(|(_, digest)| digest.as_str()) }
+
+    /// fields written by `Display` for this entry
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![Field::Hash];
+        fields.extend(self.digests.iter().map(|(algorithm, _)| Field::Digest(*algorithm)));
+        if self.size.is_some() {
+            fields.push(Field::Size);
+        }
+        if self.mtime.is_some() {
+            fields.push(Field::Mtime);
+        }
+        if self.xattrs.is_some() {
+            fields.push(Field::Xattrs);
+        }
+        fields.push(Field::Path);
+        if self.target.is_some() {
+            fields.push(Field::Target);
+        }
+        fields
+    }
+
+    /// report line of this entry made of `fields`, unknown values being written as `-`
+    pub fn to_line(&self, fields: &[Field]) -> String {
+        fields
+            .iter()
+            .filter_map(|field| match field {
+                Field::Hash => Some(self.hash.clone()),
+                Field::Digest(algorithm) => Some(self.digest(*algorithm).unwrap_or("-").to_owned()),
+                Field::Size => Some(self.size.map_or_else(|| String::from("-"), |size| size.to_string())),
+                Field::Mtime => Some(self.mtime.map_or_else(|| String::from("-"), format_mtime)),
+                Field::Xattrs => Some(self.xattrs.clone().unwrap_or_else(|| String::from("-"))),
+                // the target of links is written after a bare ` -> `
+                Field::Path if fields.last() == Some(&Field::Target) => Some(escape_arrows(&self.path)),
+                Field::Path => Some(self.path.clone()),
+                // the files other than symbolic links have none
+                Field::Target => self.target.as_ref().map(|target| format!("-> {}", target)),
+            })
+            .join(" ")
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.to_line(&self.fields())) }
+}
+
+/// modification time of a file as recorded in reports
+pub fn mtime(metadata: &fs::Metadata) -> Duration { metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default() }
+
+/// `<seconds>.<nanoseconds>` representation of a modification time
+pub fn format_mtime(mtime: Duration) -> String { format!("{}.{:09}", mtime.as_secs(), mtime.subsec_nanos()) }
+
+/// `path` written before the target of links : each arrow in it, a `->` after a space and before another one or the
+/// end, gets one more backslash before it, so that the only bare ` -> ` of a line is the one of the target
+fn escape_arrows(path: &str) -> String { rewrite_arrows(path, |backslashes| backslashes + 1) }
+
+/// path of a report line read before the target of links, see [`escape_arrows`]
+fn unescape_arrows(path: &str) -> String { rewrite_arrows(path, |backslashes| backslashes.saturating_sub(1)) }
+
+/// `path` with the backslashes between the space and the `->` of each arrow counted again by `count`
+fn rewrite_arrows(path: &str, count: impl Fn(usize) -> usize) -> String {
+    let mut rewritten = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(space) = rest.find(' ') {
+        rewritten.push_str(&rest[..=space]);
+        rest = &rest[space + 1..];
+        let backslashes = rest.len() - rest.trim_start_matches('\\').len();
+        if rest[backslashes..] == *"->" || rest[backslashes..].starts_with("-> ") {
+            rewritten.push_str(&"\\".repeat(count(backslashes)));
+            rest = &rest[backslashes..];
+        }
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+pub fn parse_mtime(s: &str) -> Option<Duration> {
+    let (secs, nanos) = s.split_once('.').unwrap_or((s, "0"));
+    if nanos.len() > 9 {
+        return None;
+    }
+    let nanos: u32 = format!("{:0<9}", nanos).parse().ok()?;
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// reports whose name ends with `.gz` are gzip compressed
+fn is_compressed(path: &Path) -> bool { path.extension().is_some_and(|ext| ext == "gz") }
+
+/// Write `entries` into `path`, through a temporary sibling file renamed at the end
+/// so that an interrupted write never leaves a truncated report.
+pub fn save<'a>(path: &Path, fields: &[Field], entries: impl IntoIterator<Item = &'a Entry>) -> io::Result<()> {
+    let file = AtomicFile::create(path)?;
+    let file = if is_compressed(path) {
+        let mut encoder = GzEncoder::new(io::BufWriter::new(file), Compression::default());
+        write(&mut encoder, fields, entries)?;
+        encoder.finish()?.into_inner().map_err(|e| e.into_error())?
+    } else {
+        let mut writer = io::BufWriter::new(file);
+        write(&mut writer, fields, entries)?;
+        writer.into_inner().map_err(|e| e.into_error())?
+    };
+    file.commit()
+}
+
+fn write<'a>(writer: &mut impl Write, fields: &[Field], entries: impl IntoIterator<Item = &'a Entry>) -> io::Result<()> {
+    if fields != [Field::Hash, Field::Path] {
+        writeln!(writer, "{}", header(fields))?;
+    }
+    for entry in entries {
+        writeln!(writer, "{}", entry.to_line(fields))?;
+    }
+    Ok(())
+}
+
+/// Hash of an entry as a fixed size map key : the digest itself for 64 hex digits hashes (BLAKE3, SHA-256),
+/// the BLAKE3 of the text otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct HashKey([u8; 32]);
+
+impl HashKey {
+    fn new(hash: &str) -> Self {
+        let hash = if hash == EMPTY { EMPTY_HASH } else { hash };
+        let nibble = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+        let mut key = [0u8; 32];
+        let decoded = hash.len() == 64 && key.iter_mut().zip(hash.as_bytes().chunks(2)).all(|(byte, hex)| nibble(hex[0]).zip(nibble(hex[1])).map(|(high, low)| *byte = high << 4 | low).is_some());
+        if decoded {
+            HashKey(key)
+        } else {
+            HashKey(*blake3::hash(hash.as_bytes()).as_bytes())
+        }
+    }
+}
+
+/// no next entry in a chain
+const END: u32 = u32::MAX;
+
+/// number of the entry following `len` others, [`END`] being kept for the end of the chains
+fn next_index(len: usize) -> Result<u32> {
+    u32::try_from(len).ok().filter(|&i| i != END).ok_or_else(|| BlakediffError::Other(format!("a report holds less than {} entries", END)))
+}
+
+/// A whole report in memory, indexed by hash and by path.
+///
+/// Strings are only held by the entries : the indexes chain entry numbers, keyed by [`HashKey`] for hashes
+/// and by a fingerprint of the path for paths, keeping large reports compact. A report holds less than 2^32 entries.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    fields: Vec<Field>,
+    entries: Vec<Entry>,
+    /// first and last entries of each hash
+    by_hash: HashMap<HashKey, (u32, u32)>,
+    /// next entry with the same hash, in the order of the report
+    next_hash: Vec<u32>,
+    /// last entry of each path fingerprint
+    by_path: HashMap<u64, u32>,
+    /// previous entry with the same path fingerprint
+    previous_path: Vec<u32>,
+    path_state: RandomState,
+}
+
+impl Report {
+    /// empty report whose entries are written with `fields`
+    pub fn new(fields: Vec<Field>) -> Self {
+        Self {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    /// read the report at `path`, compressed or not
+    pub fn load(path: &Path) -> Result<Self> { Self::read(Reader::open(path)?) }
+
+    /// read a whole report from `reader`
+    pub fn read<R: BufRead>(mut reader: Reader<R>) -> Result<Self> {
+        let mut report = Self::default();
+        for entry in reader.by_ref() {
+            report.push(entry?)?;
+        }
+        report.fields = reader.fields().to_vec();
+        Ok(report)
+    }
+
+    /// write the report at `path`, see [`save`]
+    pub fn save(&self, path: &Path) -> io::Result<()> { save(path, &self.fields, &self.entries) }
+
+    /// add `entry` after the others, failing once the report holds 2^32 - 1 entries
+    pub fn push(&mut self, entry: Entry) -> Result<()> {
+        let index = next_index(self.entries.len())?;
+        self.next_hash.push(END);
+        match self.by_hash.entry(HashKey::new(&entry.hash)) {
+            std::collections::hash_map::Entry::Occupied(mut chain) => {
+                let (_, last) = chain.get_mut();
+                self.next_hash[*last as usize] = index;
+                *last = index;
+            }
+            std::collections::hash_map::Entry::Vacant(chain) => {
+                chain.insert((index, index));
+            }
+        }
+        let fingerprint = self.path_state.hash_one(&entry.path);
+        self.previous_path.push(self.by_path.insert(fingerprint, index).unwrap_or(END));
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// entries chained from `first` by `next`
+    fn chain<'a>(&'a self, first: u32, next: &'a [u32]) -> impl Iterator<Item = &'a Entry> {
+        std::iter::successors(Some(first), move |&i| Some(next[i as usize]).filter(|&n| n != END)).map(|i| &self.entries[i as usize])
+    }
+
+    pub fn fields(&self) -> &[Field] { &self.fields }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// entries in the order of the report
+    pub fn iter(&self) -> slice::Iter<'_, Entry> { self.entries.iter() }
+
+    /// distinct hashes of the report, in no particular order
+    pub fn hashes(&self) -> impl Iterator<Item = &str> { self.by_hash.values().map(|&(first, _)| self.entries[first as usize].hash.as_str()) }
+
+    /// entries recorded with `hash`, in the order of the report
+    pub fn by_hash(&self, hash: &str) -> impl Iterator<Item = &Entry> { self.by_hash.get(&HashKey::new(hash)).into_iter().flat_map(|&(first, _)| self.chain(first, &self.next_hash)) }
+
+    pub fn contains_hash(&self, hash: &str) -> bool { self.by_hash.contains_key(&HashKey::new(hash)) }
+
+    /// last entry recorded for `path`
+    pub fn by_path(&self, path: &str) -> Option<&Entry> {
+        let last = *self.by_path.get(&self.path_state.hash_one(path))?;
+        self.chain(last, &self.previous_path).find(|e| e.path == path)
+    }
+
+    /// groups of entries sharing the same hash with different paths, each sorted by path
+    pub fn duplicates(&self) -> Vec<Vec<&Entry>> {
+        self.by_hash
+            .values()
+            //tri d'abord entre les duplicas d'un même fichier (une ligne),
+            .map(|&(first, _)| self.chain(first, &self.next_hash).sorted_by(|a, b| a.path.cmp(&b.path)).dedup_by(|a, b| a.path == b.path).collect::<Vec<_>>())
+            // special files of a type share their "hash" without being copies of each other
+            .filter(|group| group.len() > 1 && !group[0].is_special())
+            .sorted_by_cached_key(|group| group[0].path.clone()) // puis tri sur les lignes/fichiers (sur le nom du 1er duplica v[0])
+            .collect()
+    }
+
+    /// last entry of each hash recorded for a single path, whose content is nowhere else in the report, sorted by path
+    pub fn unique(&self) -> Vec<&Entry> {
+        self.by_hash
+            .values()
+            .filter_map(|&(first, last)| {
+                let last = &self.entries[last as usize];
+                self.chain(first, &self.next_hash).all(|e| e.path == last.path).then_some(last)
+            })
+            .filter(|e| !e.is_special())
+            .sorted_by(|a, b| a.path.cmp(&b.path))
+            .collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a Report {
+    type Item = &'a Entry;
+    type IntoIter = slice::Iter<'a, Entry>;
+
+    fn into_iter(self) -> Self::IntoIter { self.iter() }
+}
+
+impl IntoIterator for Report {
+    type Item = Entry;
+    type IntoIter = std::vec::IntoIter<Entry>;
+
+    fn into_iter(self) -> Self::IntoIter { self.entries.into_iter() }
+}
+
+/// Iterate over the entries of a report, following its header if any.
+/// Blank lines and `#` comments are skipped.
+pub struct Reader<R> {
+    inner: R,
+    fields: Vec<Field>,
+    line: String,
+    line_number: usize,
+}
+
+impl Reader<Box<dyn BufRead + Send>> {
+    /// open the report at `path`, decompressing it when its name ends with `.gz`
+    pub fn open(path: &Path) -> io::Result<Self> { Ok(Self::decoded(Input::open(path)?, is_compressed(path))) }
+
+    /// read the report of `input`, gzip compressed or not
+    pub fn decoded(input: impl io::Read + Send + 'static, compressed: bool) -> Self {
+        Self::new(if compressed {
+            Box::new(io::BufReader::new(MultiGzDecoder::new(input)))
+        } else {
+            Box::new(io::BufReader::new(input))
+        })
+    }
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            fields: vec![Field::Hash, Field::Path],
+            line: String::new(),
+            line_number: 0,
+        }
+    }
+
+    /// fields of the entries read so far, from the header of the report
+    pub fn fields(&self) -> &[Field] { &self.fields }
+
+    /// number of the last line read, starting at 1
+    pub fn line_number(&self) -> usize { self.line_number }
+
+    fn error(&self, message: String) -> BlakediffError {
+        BlakediffError::Parse {
+            line: self.line_number,
+            message,
+        }
+    }
+
+    fn parse_header(&self, header: &str) -> Result<Vec<Field>> {
+        let fields = header.split_whitespace().map(Field::from_str).collect::<Result<Vec<_>, _>>().map_err(|e| self.error(e))?;
+        let path_last = matches!(fields.as_slice(), [.., Field::Path, Field::Target] | [.., Field::Path]);
+        if !path_last || !fields.contains(&Field::Hash) {
+            return Err(self.error(format!("invalid header '{}', expecting hash and path as last field, or followed by target", header.trim())));
+        }
+        Ok(fields)
+    }
+
+    fn parse_entry(&self, line: &str) -> Result<Entry> {
+        let mut entry = Entry {
+            hash: String::new(),
+            size: None,
+            mtime: None,
+            xattrs: None,
+            target: None,
+            digests: Vec::new(),
+            path: String::new(),
+        };
+        // the path is always the last field, but for the target of symbolic links, and may contain spaces
+        let mut rest = line;
+        for (i, field) in self.fields.iter().enumerate() {
+            let value = if *field == Field::Path && self.fields.get(i + 1) == Some(&Field::Target) {
+                let path = match rest.split_once(" -> ") {
+                    Some((path, target)) => {
+                        entry.target = Some(target.to_owned());
+                        path
+                    }
+                    None => rest,
+                };
+                entry.path = unescape_arrows(path);
+                continue;
+            } else if i + 1 == self.fields.len() {
+                rest
+            } else {
+                let (value, tail) = rest.split_once(' ').ok_or_else(|| self.error(format!("missing field {}", field.name())))?;
+                rest = tail;
+                value
+            };
+            match field {
+                Field::Hash => entry.hash = value.to_owned(),
+                Field::Digest(_) | Field::Size | Field::Mtime | Field::Xattrs if value == "-" => {}
+                Field::Digest(algorithm) => entry.digests.push((*algorithm, value.to_owned())),
+                Field::Size => entry.size = Some(value.parse().map_err(|_| self.error(format!("invalid size '{}'", value)))?),
+                Field::Mtime => entry.mtime = Some(parse_mtime(value).ok_or_else(|| self.error(format!("invalid mtime '{}'", value)))?),
+                Field::Xattrs => entry.xattrs = Some(value.to_owned()),
+                Field::Path => entry.path = value.to_owned(),
+                // read with the path
+                Field::Target => {}
+            }
+        }
+        if entry.hash.is_empty() || entry.path.is_empty() {
+            return Err(self.error(String::from("expecting '<hash> <path>'")));
+        }
+        Ok(entry)
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.inner.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(e) => return Some(Err(e.into())),
+            }
+            let line = self.line.trim_end_matches(['\n', '\r']);
+            if let Some(header) = line.strip_prefix(HEADER_PREFIX) {
+                match self.parse_header(header) {
+                    Ok(fields) => self.fields = fields,
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            return Some(self.parse_entry(line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tempdir::TempDir;
+
+    const A: &str = "81c4b7f7e0549f1514e9cae97cf40cf133920418d3dc71bedbf60ec9bd6148cb";
+    const B: &str = "9d902f9864f3043dca97e40698eee07a2fe6771591c687ed129cde8f6fcc4a79";
+
+    fn report(lines: &str) -> Report { Report::read(Reader::new(lines.as_bytes())).unwrap() }
+
+    fn paths<'a>(entries: impl IntoIterator<Item = &'a Entry>) -> Vec<&'a str> { entries.into_iter().map(|e| e.path.as_str()).collect() }
+
+    #[test]
+    fn hash_chains_keep_report_order() {
+        let report = report(&format!("{A} z\n{B} b\n{A} a\n{A} m\n"));
+        assert_eq!(paths(report.by_hash(A)), ["z", "a", "m"]);
+        assert_eq!(paths(report.by_hash(&A.to_uppercase())), ["z", "a", "m"]);
+        assert_eq!(paths(report.by_hash(B)), ["b"]);
+        assert_eq!(report.by_hash(EMPTY_HASH).count(), 0);
+        assert!(report.contains_hash(B) && !report.contains_hash(EMPTY_HASH));
+        assert_eq!(report.hashes().sorted().collect::<Vec<_>>(), [A, B]);
+    }
+
+    #[test]
+    fn path_chains_find_the_last_entry() {
+        let lines = (0..1000).map(|i| format!("{} file{}\n", if i % 2 == 0 { A } else { B }, i % 300)).collect::<String>();
+        let report = report(&lines);
+        assert_eq!(report.len(), 1000);
+        // file1 is last recorded by the entry 901, with B
+        assert_eq!(report.by_path("file1").map(|e| e.hash.as_str()), Some(B));
+        assert_eq!(report.by_path("file0").map(|e| e.hash.as_str()), Some(A));
+        assert!(report.by_path("file300").is_none());
+    }
+
+    #[test]
+    fn duplicates_and_unique() {
+        let report = report(&format!("{A} z\n{B} b\n{A} a\n{A} a\nmd5:0123 x\n{EMPTY_HASH} e1\n{EMPTY} e2\nfifo p1\nfifo p2\n"));
+        let duplicates = report.duplicates().into_iter().map(paths).collect::<Vec<_>>();
+        assert_eq!(duplicates, [vec!["a", "z"], vec!["e1", "e2"]]);
+        assert_eq!(paths(report.unique()), ["b", "x"]);
+        // the tag of the empty files is the BLAKE3 of no byte
+        assert_eq!(paths(report.by_hash(EMPTY)), ["e1", "e2"]);
+    }
+
+    #[test]
+    fn hash_keys() {
+        assert_eq!(HashKey::new(A), HashKey::new(&A.to_uppercase()));
+        assert_eq!(HashKey::new(EMPTY), HashKey::new(EMPTY_HASH));
+        assert_ne!(HashKey::new("md5:0123"), HashKey::new("md5:0124"));
+        // not hex : digested instead of decoded
+        assert_ne!(HashKey::new(&A.replace('8', "g")), HashKey::new(A));
+    }
+
+    #[test]
+    fn entry_numbers_are_bounded() {
+        assert_eq!(next_index(0).unwrap(), 0);
+        assert_eq!(next_index(END as usize - 1).unwrap(), END - 1);
+        assert!(next_index(END as usize).is_err());
+        assert!(next_index(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn legacy_lines_are_hash_and_path() {
+        let entries = Reader::new(format!("# a comment\n\n{A} dir/with spaces.txt\r\n{B} b\n").as_bytes()).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(paths(&entries), ["dir/with spaces.txt", "b"]);
+        assert_eq!(entries[0].hash, A);
+        assert_eq!(entries[0].fields(), [Field::Hash, Field::Path]);
+        assert_eq!(entries[0].to_string(), format!("{A} dir/with spaces.txt"));
+    }
+
+    #[test]
+    fn headers_declare_the_columns() {
+        let lines = format!("{HEADER_PREFIX} hash sha256 size mtime path target\n{A} - 12 1700000000.5 my link -> the target\n{B} abc - - plain file\n");
+        let mut reader = Reader::new(lines.as_bytes());
+        let link = reader.next().unwrap().unwrap();
+        assert_eq!(reader.fields(), [Field::Hash, Field::Digest(Algorithm::Sha256), Field::Size, Field::Mtime, Field::Path, Field::Target]);
+        assert_eq!((link.size, link.mtime, link.path.as_str(), link.target.as_deref()), (Some(12), Some(Duration::new(1700000000, 500_000_000)), "my link", Some("the target")));
+        assert_eq!(link.digest(Algorithm::Sha256), None);
+        let file = reader.next().unwrap().unwrap();
+        assert_eq!((file.size, file.mtime, file.path.as_str(), file.target.as_deref()), (None, None, "plain file", None));
+        assert_eq!(file.digest(Algorithm::Sha256), Some("abc"));
+        assert_eq!(reader.line_number(), 3);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn arrows_in_paths_roundtrip() {
+        let fields = [Field::Hash, Field::Path, Field::Target];
+        let mut entries = ["a -> b", "c \\-> d", "e ->", "f -> g -> h", "-> i", "j->k", "l \\\\->"].map(|path| Entry {
+            hash: A.to_owned(),
+            size: None,
+            mtime: None,
+            xattrs: None,
+            path: path.to_owned(),
+            target: None,
+            digests: Vec::new(),
+        });
+        entries[0].target = Some(String::from("x -> y"));
+        entries[2].target = Some(String::from("z"));
+        let mut lines = Vec::new();
+        write(&mut lines, &fields, &entries).unwrap();
+        let read = Reader::new(lines.as_slice()).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(read, entries);
+        assert!(String::from_utf8(lines).unwrap().contains(&format!("{A} e \\-> -> z\n")));
+    }
+
+    #[test]
+    fn invalid_lines_tell_their_number() {
+        let parse_error = |lines: &str| match Reader::new(lines.as_bytes()).collect::<Result<Vec<_>>>() {
+            Err(BlakediffError::Parse { line, message }) => (line, message),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(parse_error(&format!("{A} a\n{A}\n")).0, 2);
+        assert_eq!(parse_error(&format!("{HEADER_PREFIX} hash size path\n{A} big a\n")), (2, String::from("invalid size 'big'")));
+        assert_eq!(parse_error(&format!("{HEADER_PREFIX} hash mtime path\n{A} 1.1234567891 a\n")), (2, String::from("invalid mtime '1.1234567891'")));
+        assert_eq!(parse_error(&format!("{HEADER_PREFIX} hash color path\n")), (1, String::from("unknown report field 'color'")));
+        assert_eq!(parse_error(&format!("{HEADER_PREFIX} path hash\n")).0, 1);
+    }
+
+    #[test]
+    fn mtimes_roundtrip() {
+        for mtime in [Duration::ZERO, Duration::new(1700000000, 1), Duration::new(42, 999_999_999)] {
+            assert_eq!(parse_mtime(&format_mtime(mtime)), Some(mtime));
+        }
+        assert_eq!(parse_mtime("12"), Some(Duration::from_secs(12)));
+        assert_eq!(parse_mtime("12.25"), Some(Duration::from_millis(12250)));
+        assert_eq!(parse_mtime("x.1"), None);
+    }
+
+    #[test]
+    fn saved_reports_load_again() {
+        let tmp = TempDir::new("report").unwrap();
+        let dir = tmp.path();
+        let mut entries = report(&format!("{A} a\n{B} b c\n")).iter().cloned().collect::<Vec<_>>();
+        entries[0].size = Some(3);
+        entries[1].target = Some(String::from("a"));
+        let fields = [Field::Hash, Field::Size, Field::Path, Field::Target];
+        for name in ["report.txt", "report.txt.gz"] {
+            save(&dir.join(name), &fields, &entries).unwrap();
+            let loaded = Report::load(&dir.join(name)).unwrap();
+            assert_eq!(loaded.fields(), fields);
+            assert_eq!(loaded.iter().cloned().collect::<Vec<_>>(), entries);
+        }
+        // reports of hashes and paths keep the historical format, without header
+        save(&dir.join("legacy.txt"), &[Field::Hash, Field::Path], &entries).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("legacy.txt")).unwrap(), format!("{A} a\n{B} b c\n"));
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+
+use blakediff::atomic::AtomicFile;
+use blakediff::report::Report;
+use blakediff::Result;
+
+/// file lists `compare` can emit instead of its results
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// paths of the source missing or different at the destination, for `rsync --files-from`
+    RsyncFiles,
+}
+
+/// lists of paths relative to the roots of the reports, to hand a transfer over to rsync
+pub struct Lists {
+    /// files of the source missing at the destination, or with another content there
+    pub transfer: Vec<String>,
+    /// files of the destination whose path is not in the source
+    pub delete: Vec<String>,
+}
+
+impl Lists {
+    /// compare files path by path, relative to the roots of their reports
+    pub fn new((source, source_root): (&Report, &str), (destination, destination_root): (&Report, &str)) -> Self {
+        let by_path = |report: &Report, root: &str| report.iter().map(|e| (relative(&e.path, root), e.hash.clone())).collect::<HashMap<_, _>>();
+        let (source, destination) = (by_path(source, source_root), by_path(destination, destination_root));
+        Lists {
+            transfer: source.iter().filter(|(path, hash)| destination.get(*path) != Some(hash)).map(|(path, _)| path.clone()).sorted().collect(),
+            delete: destination.keys().filter(|path| !source.contains_key(*path)).cloned().sorted().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.transfer.is_empty() && self.delete.is_empty() }
+}
+
+/// deepest directory holding every file of the report, ending with a `/`
+pub fn root(report: &Report) -> String {
+    let mut paths = report.iter().map(|e| e.path.as_str());
+    let Some(first) = paths.next() else { return String::new() };
+    let mut root = &first[..first.rfind('/').map_or(0, |slash| slash + 1)];
+    for path in paths {
+        while !path.starts_with(root) {
+            root = &root[..root[..root.len() - 1].rfind('/').map_or(0, |slash| slash + 1)];
+        }
+    }
+    root.to_owned()
+}
+
+fn relative(path: &str, root: &str) -> String { path.strip_prefix(root).unwrap_or(path).to_owned() }
+
+/// write the transfer list on stdout and, with `delete_list`, the paths to delete into it
+pub fn emit(lists: &Lists, delete_list: Option<&Path>) -> Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    lists.transfer.iter().try_for_each(|path| writeln!(out, "{}", path))?;
+    out.flush()?;
+    if let Some(delete_list) = delete_list {
+        let mut file = BufWriter::new(AtomicFile::create(delete_list)?);
+        lists.delete.iter().try_for_each(|path| writeln!(file, "{}", path))?;
+        file.into_inner().map_err(io::IntoInnerError::into_error)?.commit()?;
+    }
+    Ok(())
+}
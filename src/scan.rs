@@ -0,0 +1,157 @@
+//! Find duplicates directly in a directory, without a report : files are grouped by size first,
+//! only those sharing their size with another one being hashed.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::input::Input;
+use crate::report::{self, Entry};
+use crate::walker::{self, Walker};
+use crate::{threads, Result};
+
+/// number of leading bytes hashed by the partial stage of a fast scan
+pub const PARTIAL_SIZE: u64 = 64 * 1024;
+
+/// hash which confirmed a group of duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// the first [`PARTIAL_SIZE`] bytes, holding the whole files
+    Partial,
+    /// the whole files
+    Full,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Partial => "partial",
+            Stage::Full => "full",
+        }
+    }
+}
+
+/// groups of identical files under `dir`, with their size and mtime, sorted by path.
+/// Empty files and additional hard links of an already seen file are left aside.
+pub fn duplicates(dir: &Path, parallel: bool) -> Result<Vec<Vec<Entry>>> { Ok(duplicates_staged(dir, parallel, false)?.into_iter().map(|(_, group)| group).collect()) }
+
+/// like [`duplicates`], with the stage which confirmed each group. When `fast` is set, the first
+/// [`PARTIAL_SIZE`] bytes of the candidates are hashed first, files being fully hashed only when
+/// their partial hashes collide.
+pub fn duplicates_staged(dir: &Path, parallel: bool, fast: bool) -> Result<Vec<(Stage, Vec<Entry>)>> {
+    let same_size = candidates(dir, parallel)?;
+    let mut staged = Vec::new();
+    let to_hash = if fast {
+        let partial = hash(same_size, partial_hash)?;
+        let mut collisions = Vec::new();
+        for group in groups(partial) {
+            if group[0].size.unwrap_or(0) <= PARTIAL_SIZE {
+                staged.push((Stage::Partial, group));
+            } else {
+                collisions.extend(group);
+            }
+        }
+        log::info!("{} files to hash fully after partial hashing", collisions.len());
+        collisions
+    } else {
+        same_size
+    };
+    staged.extend(groups(hash(to_hash, |path| Input::open(path)?.hash())?).into_iter().map(|group| (Stage::Full, group)));
+    staged.sort_by(|a, b| a.1[0].path.cmp(&b.1[0].path));
+    Ok(staged)
+}
+
+/// files under `dir` sharing their size with another one
+fn candidates(dir: &Path, parallel: bool) -> Result<Vec<Entry>> {
+    let candidates = Mutex::new(Vec::new());
+    let inodes = Mutex::new(HashSet::new());
+    Walker::new(dir).parallel(parallel).for_each(|file| {
+        let metadata = &file.metadata;
+        if file.symlink || !metadata.is_file() || metadata.len() == 0 || !inodes.lock().unwrap().insert(inode(metadata)) {
+            return Ok(());
+        }
+        candidates.lock().unwrap().push(Entry {
+            hash: String::new(),
+            size: Some(metadata.len()),
+            mtime: Some(report::mtime(metadata)),
+            xattrs: None,
+            target: None,
+            digests: Vec::new(),
+            path: file.path.to_string_lossy().into_owned(),
+        });
+        Ok(())
+    })?;
+
+    let candidates = candidates.into_inner().unwrap();
+    let (files, bytes) = (candidates.len(), candidates.iter().filter_map(|c| c.size).sum::<u64>());
+    let same_size = candidates.into_iter().into_group_map_by(|c| c.size).into_values().filter(|g| g.len() > 1).flatten().collect::<Vec<_>>();
+    log::info!(
+        "{} of {} files to hash after size prefiltering, {} of {} bytes",
+        same_size.len(),
+        files,
+        same_size.iter().filter_map(|c| c.size).sum::<u64>(),
+        bytes
+    );
+    Ok(same_size)
+}
+
+/// `entries` with their hash computed by `hash`
+fn hash(entries: Vec<Entry>, hash: impl Fn(&Path) -> io::Result<String> + Sync) -> Result<Vec<Entry>> {
+    if walker::hdd() {
+        // one file at a time, in walk order
+        return entries
+            .into_iter()
+            .map(|mut e| {
+                e.hash = hash(Path::new(&e.path))?;
+                Ok(e)
+            })
+            .collect();
+    }
+    threads::io(|| {
+        entries
+            .into_par_iter()
+            .map(|mut e| {
+                e.hash = hash(Path::new(&e.path))?;
+                Ok(e)
+            })
+            .collect()
+    })
+}
+
+/// groups of entries sharing their size and hash, sorted by path
+fn groups(entries: Vec<Entry>) -> Vec<Vec<Entry>> {
+    entries
+        .into_iter()
+        .into_group_map_by(|e| (e.size, e.hash.clone()))
+        .into_values()
+        .filter(|g| g.len() > 1)
+        .map(|g| g.into_iter().sorted_by(|a, b| a.path.cmp(&b.path)).collect())
+        .collect()
+}
+
+/// BLAKE3 of the first [`PARTIAL_SIZE`] bytes of a file, its whole hash when it is not bigger
+fn partial_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?.take(PARTIAL_SIZE), &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// device and inode of a file, identifying its hard links
+#[cfg(unix)]
+pub fn inode(metadata: &std::fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+// Without inode numbers, every file is considered distinct.
+#[cfg(not(unix))]
+pub fn inode(_metadata: &std::fs::Metadata) -> (u64, u64) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    (0, NEXT.fetch_add(1, Ordering::Relaxed))
+}
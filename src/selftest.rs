@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use blakediff::input::{Algorithm, Input};
+use blakediff::report::{self, Entry, Field};
+use blakediff::walker::Walker;
+use blakediff::tempdir::TempDir;
+use blakediff::{BlakediffError, Result};
+
+/// `hash` of the official BLAKE3 test vectors, for an input of `len` bytes `i % 251`
+/// (https://github.com/BLAKE3-team/BLAKE3/blob/master/test_vectors/test_vectors.json)
+const TEST_VECTORS: &[(usize, &str)] = &[
+    (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+    (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+    (1023, "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11"),
+    (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+    (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+    (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+    (2049, "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030"),
+    (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+    (3073, "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3"),
+    (4096, "015094013f57a5277b59d8475c0501042c0b642e531b0a1c8f58d2163229e969"),
+    (4097, "9b4052b38f1c5fc8b1f9ff7ac7b27cd242487b3d890d15c96a1c25b8aa0fb995"),
+    (5120, "9cadc15fed8b5d854562b26a9536d9707cadeda9b143978f319ab34230535833"),
+    (5121, "628bd2cb2004694adaab7bbd778a25df25c47b9d4155a55f8fbd79f2fe154cff"),
+    (6144, "3e2e5b74e048f3add6d21faab3f83aa44d3b2278afb83b80b3c35164ebeca205"),
+    (6145, "f1323a8631446cc50536a9f705ee5cb619424d46887f3c376c695b70e0f0507f"),
+    (7168, "61da957ec2499a95d6b8023e2b0e604ec7f6b50e80a9678b89d2628e99ada77a"),
+    (7169, "a003fc7a51754a9b3c7fae0367ab3d782dccf28855a03d435f8cfe74605e7817"),
+    (8192, "aae792484c8efe4f19e2ca7d371d8c467ffb10748d8a5a1ae579948f718a2a63"),
+    (8193, "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b"),
+    (16384, "f875d6646de28985646f34ee13be9a576fd515f76b5b0a26bb324735041ddde4"),
+    (31744, "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47"),
+    (102400, "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085"),
+];
+
+type Check = fn(&Path) -> Result<()>;
+
+/// run every check in a temporary directory, false when one of them fails, for an exit code of 1
+pub fn selftest() -> Result<bool> {
+    let dir = TempDir::new("selftest")?;
+    let checks: [(&str, Check); 3] = [("blake3 test vectors", test_vectors), ("report formats", report_formats), ("directory walk", walk)];
+    let mut failures = 0;
+    for (name, check) in checks {
+        match check(dir.path()) {
+            Ok(()) => println!("ok : {}", name),
+            Err(e) => {
+                failures += 1;
+                println!("FAILED : {} ({})", name, e);
+            }
+        }
+    }
+    println!("selftest : {} passed, {} failed", checks.len() - failures, failures);
+    Ok(failures == 0)
+}
+
+/// hash the vectors in memory, then from files through both the mmap and the buffered paths
+fn test_vectors(dir: &Path) -> Result<()> {
+    for &(len, expected) in TEST_VECTORS {
+        let input = (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let path = dir.join(format!("vector_{}", len));
+        fs::write(&path, &input)?;
+        let hashes = [
+            ("memory", blake3::hash(&input).to_string()),
+            ("mmap", Input::open(&path)?.hash()?),
+            ("buffered", Input::open_buffered(&path)?.hash()?),
+        ];
+        if let Some((mode, hash)) = hashes.iter().find(|(_, hash)| hash != expected) {
+            return Err(BlakediffError::Other(format!("input of {} bytes, {} : {} instead of {}", len, mode, hash, expected)));
+        }
+    }
+    Ok(())
+}
+
+/// save and read back reports with every combination of optional fields
+fn report_formats(dir: &Path) -> Result<()> {
+    let entries = [
+        Entry {
+            hash: TEST_VECTORS[0].1.to_owned(),
+            size: Some(0),
+            mtime: Some(Duration::new(1_600_000_000, 123_456_789)),
+            xattrs: Some(TEST_VECTORS[2].1.to_owned()),
+            target: Some(String::from("../target dir/with spaces.txt")),
+            digests: vec![(Algorithm::Sha256, String::from("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"))],
+            path: String::from("/some dir/with spaces.txt"),
+        },
+        Entry {
+            hash: TEST_VECTORS[1].1.to_owned(),
+            size: Some(1),
+            mtime: Some(Duration::new(0, 0)),
+            xattrs: None,
+            target: None,
+            digests: Vec::new(),
+            path: String::from("relative/ünïcödé"),
+        },
+    ];
+    for fields in [
+        vec![Field::Hash, Field::Path],
+        vec![Field::Hash, Field::Size, Field::Path],
+        vec![Field::Hash, Field::Mtime, Field::Path],
+        vec![Field::Hash, Field::Size, Field::Mtime, Field::Path],
+        vec![Field::Hash, Field::Size, Field::Mtime, Field::Xattrs, Field::Path],
+        vec![Field::Hash, Field::Size, Field::Path, Field::Target],
+        vec![Field::Hash, Field::Digest(Algorithm::Sha256), Field::Digest(Algorithm::Xxh3), Field::Size, Field::Path],
+    ] {
+        let path = dir.join("report.txt");
+        report::save(&path, &fields, &entries)?;
+        let read = report::Reader::open(&path)?.collect::<Result<Vec<_>, _>>()?;
+        let expected = entries
+            .iter()
+            .map(|e| Entry {
+                size: e.size.filter(|_| fields.contains(&Field::Size)),
+                mtime: e.mtime.filter(|_| fields.contains(&Field::Mtime)),
+                xattrs: e.xattrs.clone().filter(|_| fields.contains(&Field::Xattrs)),
+                target: e.target.clone().filter(|_| fields.contains(&Field::Target)),
+                digests: e.digests.iter().filter(|(algorithm, _)| fields.contains(&Field::Digest(*algorithm))).cloned().collect(),
+                ..e.clone()
+            })
+            .collect::<Vec<_>>();
+        if read != expected {
+            return Err(BlakediffError::Other(format!("fields {:?} : read {:?} instead of {:?}", fields, read, expected)));
+        }
+    }
+    Ok(())
+}
+
+/// walk a fixture tree through a callback, sequentially then in parallel, an iterator and a channel
+fn walk(dir: &Path) -> Result<()> {
+    let root = dir.join("tree");
+    let mut expected = ["a/b/c/deep", "a/file", "d/file", "top"].iter().map(|p| root.join(p)).collect::<Vec<_>>();
+    for path in &expected {
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, path.to_string_lossy().as_bytes())?;
+    }
+    fs::create_dir_all(root.join("empty"))?;
+    expected.sort();
+
+    for (mode, parallel) in [("callback", false), ("parallel callback", true), ("iterator", false), ("channel", true)] {
+        let walker = Walker::new(&root).parallel(parallel);
+        let mut found = match mode {
+            "iterator" => walker.iter().map(|file| file.map(|file| file.path)).collect::<Result<Vec<_>>>()?,
+            "channel" => walker.channel().into_iter().map(|file| file.map(|file| file.path)).collect::<Result<Vec<_>>>()?,
+            _ => {
+                let found = Mutex::new(Vec::<PathBuf>::new());
+                walker.for_each(|file| {
+                    found.lock().unwrap().push(file.path);
+                    Ok(())
+                })?;
+                found.into_inner().unwrap()
+            }
+        };
+        found.sort();
+        if found != expected {
+            return Err(BlakediffError::Other(format!("{} : found {:?} instead of {:?}", mode, found, expected)));
+        }
+    }
+    Ok(())
+}
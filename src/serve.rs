@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::json;
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::metrics;
+use blakediff::report::Report;
+use blakediff::{BlakediffError, Result};
+
+/// content of the served report, loaded once at startup
+struct Index {
+    report_file: String,
+    report: Report,
+    duplicates: Vec<Vec<String>>,
+    /// directory of the reports which can be compared with the served one, comparisons being refused without it
+    compare_dir: Option<PathBuf>,
+}
+
+/// serve a JSON API over a report file :
+/// - `GET /hash/<hash>` lists the paths recorded with this hash
+/// - `GET /duplicates` lists the groups of duplicates
+/// - `GET /compare?with=<report_file>` compares the served report with a report file of `compare_dir`
+/// - `GET /metrics` gives the size of the report and the requests served, in the Prometheus text format
+pub fn serve(report_file: String, listen: String, compare_dir: Option<String>) -> Result<()> {
+    if is_sqlite(Path::new(&report_file))? {
+        return Err(BlakediffError::Format(format!("{} : SQLite databases aren't supported, serve a report written by generate", report_file)));
+    }
+    let report = Report::load(Path::new(&report_file))?;
+    let compare_dir = match compare_dir {
+        Some(dir) => Some(fs::canonicalize(&dir).map_err(|e| BlakediffError::Format(format!("{} : {}", dir, e)))?),
+        None => None,
+    };
+    let index = Index {
+        duplicates: report.duplicates().into_iter().map(|group| group.into_iter().map(|e| e.path.clone()).collect()).collect(),
+        report_file,
+        report,
+        compare_dir,
+    };
+
+    let server = Server::http(&listen).map_err(|e| BlakediffError::Other(format!("cannot listen on {} : {}", listen, e)))?;
+    log::info!("serving {} on http://{}", index.report_file, listen);
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let metrics_type = Header::from_bytes("Content-Type", metrics::CONTENT_TYPE).unwrap();
+    // requests served by status
+    let mut requests = BTreeMap::new();
+    for request in server.incoming_requests() {
+        let (status, body, content_type) = match (request.method(), request.url()) {
+            (Method::Get, "/metrics") => (200, render(&index, &requests), metrics_type.clone()),
+            (Method::Get, url) => {
+                let (status, body) = route(&index, url);
+                (status, body, content_type.clone())
+            }
+            _ => (405, error("method not allowed"), content_type.clone()),
+        };
+        *requests.entry(status).or_insert(0u64) += 1;
+        log::debug!("{} {} {}", request.method(), request.url(), status);
+        let response = Response::from_string(body).with_status_code(status).with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            log::warn!("error while responding : {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn route(index: &Index, url: &str) -> (u16, String) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    if let Some(hash) = path.strip_prefix("/hash/") {
+        return if index.report.contains_hash(hash) {
+            (200, json!({"hash": hash, "paths": index.report.by_hash(hash).map(|e| &e.path).collect::<Vec<_>>()}).to_string())
+        } else {
+            (404, error("unknown hash"))
+        };
+    }
+    match path {
+        "/duplicates" => (200, json!({ "groups": index.duplicates }).to_string()),
+        "/compare" => match (&index.compare_dir, query.split('&').find_map(|param| param.strip_prefix("with=")).map(percent_decode)) {
+            (None, _) => (403, error("comparisons are disabled, serve needs --compare-dir")),
+            (Some(_), None) => (400, error("missing parameter 'with'")),
+            (Some(dir), Some(other)) => match resolve(dir, &other) {
+                // only the other report is read, the served one being compared from memory
+                Ok(other) => match Report::load(&other) {
+                    Ok(report) => (200, json!(blakediff::compare_reports((&index.report_file, &index.report), (&other.to_string_lossy(), &report))).to_string()),
+                    Err(e) => (400, error(&e.to_string())),
+                },
+                Err(status) => status,
+            },
+        },
+        _ => (404, error("not found")),
+    }
+}
+
+/// metrics of the served report, in the Prometheus text format
+fn render(index: &Index, requests: &BTreeMap<u16, u64>) -> String {
+    let mut out = String::new();
+    let gauge = |value: usize| std::iter::once((String::new(), value.to_string()));
+    metrics::write_family(&mut out, "blakediff_files", "gauge", "files in the served report", gauge(index.report.len()));
+    metrics::write_family(&mut out, "blakediff_duplicate_groups", "gauge", "groups of files sharing their content in the report", gauge(index.duplicates.len()));
+    let copies = index.duplicates.iter().map(|group| group.len() - 1).sum();
+    metrics::write_family(&mut out, "blakediff_duplicate_files", "gauge", "extra copies in the groups of duplicates of the report", gauge(copies));
+    let requests = requests.iter().map(|(status, count)| (format!("status=\"{}\"", status), count.to_string()));
+    metrics::write_family(&mut out, "blakediff_requests_total", "counter", "requests served, by status", requests);
+    out
+}
+
+fn error(msg: &str) -> String { json!({ "error": msg }).to_string() }
+
+/// path of the report `name` of the comparison directory `dir`, refusing URLs, absolute paths, `..`
+/// and links leading out of `dir`
+fn resolve(dir: &Path, name: &str) -> Result<PathBuf, (u16, String)> {
+    let relative = Path::new(name);
+    if name.contains("://") || name.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err((400, error("'with' must be the name of a report of the comparison directory")));
+    }
+    match fs::canonicalize(dir.join(relative)) {
+        Ok(path) if path.starts_with(dir) && path.is_file() => Ok(path),
+        Ok(_) => Err((400, error("'with' must be the name of a report of the comparison directory"))),
+        Err(_) => Err((404, error("unknown report"))),
+    }
+}
+
+/// whether `path` is a SQLite database rather than a report
+fn is_sqlite(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 16];
+    let read = fs::File::open(path).and_then(|file| file.take(16).read(&mut magic)).or_else(|e| match e.kind() {
+        io::ErrorKind::NotFound => Ok(0),
+        _ => Err(e),
+    })?;
+    Ok(read == 16 && &magic == b"SQLite format 3\0")
+}
+
+/// decode `%XX` sequences and `+` of a query parameter
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,186 @@
+//! Remote trees over SSH : `sftp://user@host:port/path` runs `blakediff generate` on the remote host when it is
+//! installed there, and otherwise streams the tree as a tar archive to hash it locally. Only the `ssh` command
+//! is needed, `BLAKEDIFF_SSH` replacing it with options (`ssh -i ~/.ssh/nas`).
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Duration;
+
+use crate::error::OnError;
+use crate::report::{Entry, Reader};
+use crate::source::{Backend, Item};
+use crate::{interrupt, BlakediffError, Result};
+
+/// A remote directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    /// `user@host` or `host`
+    pub destination: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+impl Remote {
+    /// remote directory of an `sftp://[user@]host[:port]/path` or `ssh://` URL, the path being absolute
+    /// unless it starts with `/~/`, for the home directory
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("sftp://").or_else(|| url.strip_prefix("ssh://"))?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |slash| rest.split_at(slash));
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => (destination, Some(port.parse().ok()?)),
+            None => (authority, None),
+        };
+        let path = match path {
+            "/~" | "/~/" => ".",
+            "/" => "/",
+            path => path.strip_prefix("/~/").unwrap_or(path).trim_end_matches('/'),
+        };
+        (!destination.is_empty()).then(|| Remote {
+            destination: destination.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    /// URL of the remote `path` in the report
+    pub fn url(&self, path: &str) -> String {
+        let port = self.port.map(|port| format!(":{}", port)).unwrap_or_default();
+        match path.strip_prefix('/') {
+            Some(absolute) => format!("sftp://{}{}/{}", self.destination, port, absolute),
+            None => format!("sftp://{}{}/~/{}", self.destination, port, path.trim_start_matches("./")),
+        }
+    }
+
+    /// run the shell `script` on the remote host, its standard output being piped
+    fn run(&self, script: &str) -> Result<Child> {
+        let ssh = std::env::var("BLAKEDIFF_SSH").ok().filter(|ssh| !ssh.trim().is_empty()).unwrap_or_else(|| String::from("ssh"));
+        let mut words = ssh.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or("ssh"));
+        command.args(words);
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg("--").arg(&self.destination).arg(script).stdin(Stdio::null()).stdout(Stdio::piped());
+        log::debug!("{:?}", command);
+        command.spawn().map_err(|e| BlakediffError::Other(format!("{} : {}", ssh, e)))
+    }
+}
+
+/// single quoted word of a POSIX shell
+fn quote(s: &str) -> String { format!("'{}'", s.replace('\'', r"'\''")) }
+
+/// path of a file listed under the remote directory, relative to it
+fn join(root: &str, relative: &str) -> String {
+    let relative = relative.trim_start_matches("./");
+    if root == "/" {
+        format!("/{}", relative)
+    } else {
+        format!("{}/{}", root, relative)
+    }
+}
+
+impl Backend for Remote {
+    /// files found by `find`, with their size and modification time given by GNU, busybox or BSD `stat`
+    fn list(&self) -> Result<Vec<Item>> {
+        let script = format!(
+            r#"cd {} && find -L . -type f -exec sh -c 'for f; do printf "%s %s\n" "$(stat -L -c "%s %Y" -- "$f" 2>/dev/null || stat -L -f "%z %m" -- "$f")" "$f"; done' sh {{}} +"#,
+            quote(&self.path)
+        );
+        let mut child = self.run(&script)?;
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut items = Vec::new();
+        let listed = stdout.lines().try_for_each(|line| -> Result<()> {
+            let line = line?;
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next().and_then(|s| s.parse().ok()), fields.next().and_then(|s| s.parse().ok()), fields.next()) {
+                (Some(size), Some(mtime), Some(relative)) => {
+                    let location = join(&self.path, relative);
+                    items.push(Item {
+                        path: self.url(&location),
+                        location,
+                        size,
+                        mtime: Duration::from_secs(mtime),
+                    })
+                }
+                _ => log::warn!("{} : unexpected listing line '{}'", self.url(&self.path), line),
+            }
+            Ok(())
+        });
+        finish(child, &self.url(&self.path), listed)?;
+        items.sort_by(|a, b| a.location.cmp(&b.location));
+        Ok(items)
+    }
+
+    /// content streamed by a remote `cat`, one ssh connection per file
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>> { Ok(Box::new(Output(self.run(&format!("cat -- {}", quote(&item.location)))?))) }
+
+    /// the whole tree through a single connection : the remote `blakediff` keeps its own walk order, tar
+    /// archives come in the order of the remote filesystem
+    fn generate(&self, _parallel: bool, with_size: bool, with_mtime: bool, _on_error: Option<OnError>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+        let flags = [with_size.then_some("-s"), with_mtime.then_some("-m")].into_iter().flatten().collect::<Vec<_>>().join(" ");
+        // the first line tells what follows : a report, or a tar archive following symbolic links like the local walk
+        let script = format!(
+            "if command -v blakediff >/dev/null 2>&1; then echo blakediff; exec blakediff generate {flags} -- {path}; else echo tar; exec tar -chf - -C {path} .; fi",
+            flags = flags,
+            path = quote(&self.path)
+        );
+        let url = self.url(&self.path);
+        let mut child = self.run(&script)?;
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut mode = String::new();
+        stdout.read_line(&mut mode)?;
+        let walked = match mode.trim_end() {
+            "blakediff" => from_report(self, stdout, write),
+            "tar" => crate::tarball::hash_members(stdout, |path| self.url(&join(&self.path, path)), with_size, with_mtime, write),
+            _ => Err(BlakediffError::Other(format!("{} : no answer from the remote host", url))),
+        };
+        finish(child, &url, walked)
+    }
+}
+
+/// standard output of a remote command, failing at its end when the command failed
+struct Output(Child);
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.stdout.as_mut().expect("stdout is piped").read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.0.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("the remote command failed, {}", status)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// entries of the report written by the remote `blakediff`, with URLs as paths
+fn from_report(remote: &Remote, stdout: BufReader<ChildStdout>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+    for entry in Reader::new(stdout) {
+        interrupt::check()?;
+        let mut entry = entry?;
+        entry.path = remote.url(&entry.path);
+        write(entry)?;
+    }
+    Ok(())
+}
+
+/// wait for the remote command, its failure coming after the one of the local side
+fn finish(mut child: Child, url: &str, walked: Result<()>) -> Result<()> {
+    if walked.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    walked?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(BlakediffError::Other(format!("{} : the remote command failed, {}", url, status))),
+    }
+}
@@ -0,0 +1,762 @@
+//! Similarity digests : where a single changed byte gives a whole other hash, these stay close for files that only
+//! differ by a few edits, and the distance between two of them tells how much. Two algorithms are available, TLSH
+//! (128 buckets, 1 byte checksum) and ssdeep (context triggered piecewise hashes), recorded by
+//! `generate --similarity` and clustered by `analyze --near-dupes`, like the perceptual hashes of images and the
+//! acoustic fingerprints of `generate --algo phash,audio`.
+
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+
+use crate::input::{Algorithm, Digest};
+use crate::report::Entry;
+
+/// similarity digests of `generate --similarity`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Similarity {
+    Tlsh,
+    Ssdeep,
+    /// perceptual hashes, recorded by `--algo phash`
+    #[value(skip)]
+    Phash,
+    /// acoustic fingerprints, recorded by `--algo audio`
+    #[value(skip)]
+    Audio,
+}
+
+impl Similarity {
+    /// the pseudo algorithm of the digest, naming its column
+    pub fn algorithm(self) -> Algorithm {
+        match self {
+            Similarity::Tlsh => Algorithm::Tlsh,
+            Similarity::Ssdeep => Algorithm::Ssdeep,
+            Similarity::Phash => Algorithm::Phash,
+            Similarity::Audio => Algorithm::Audio,
+        }
+    }
+
+    /// distance up to which two files are near duplicates when no threshold is given
+    pub fn threshold(self) -> u32 {
+        match self {
+            Similarity::Tlsh => 40,
+            Similarity::Ssdeep => 50,
+            Similarity::Phash => 8,
+            Similarity::Audio => 25,
+        }
+    }
+
+    /// distance between two digests of this algorithm, 0 for the same content : the TLSH distance, unbounded, 100
+    /// minus the ssdeep match score, the bits differing between perceptual hashes, or the percentage of bits differing
+    /// between aligned acoustic fingerprints. None when one of them is not a digest of this algorithm.
+    pub fn distance(self, first: &str, second: &str) -> Option<u32> { Some(self.parse(first)?.distance(&self.parse(second)?)) }
+
+    fn parse(self, digest: &str) -> Option<Parsed> {
+        match self {
+            Similarity::Tlsh => TlshDigest::parse(digest).map(Parsed::Tlsh),
+            Similarity::Ssdeep => SsdeepDigest::parse(digest).map(Parsed::Ssdeep),
+            Similarity::Phash => u64::from_str_radix(digest, 16).ok().map(Parsed::Phash),
+            Similarity::Audio => crate::audio::parse(digest).map(Parsed::Audio),
+        }
+    }
+}
+
+enum Parsed {
+    Tlsh(TlshDigest),
+    Ssdeep(SsdeepDigest),
+    Phash(u64),
+    Audio(Vec<u32>),
+}
+
+impl Parsed {
+    /// digests are compared in the order of this key, only with the next ones close enough
+    fn key(&self) -> u64 {
+        match self {
+            Parsed::Tlsh(digest) => digest.lvalue as u64,
+            Parsed::Ssdeep(digest) => digest.block as u64,
+            Parsed::Phash(hash) => hash.count_ones() as u64,
+            Parsed::Audio(frames) => frames.len() as u64,
+        }
+    }
+
+    /// a digest of `key` can be within `threshold` of this one, whose key is not greater
+    fn reaches(&self, key: u64, threshold: u32) -> bool {
+        match self {
+            // each step of length over the first costs 12
+            Parsed::Tlsh(digest) => key - digest.lvalue as u64 <= 1 || (key - digest.lvalue as u64) * 12 <= threshold as u64,
+            // only digests of the same block size or of the double one compare
+            Parsed::Ssdeep(digest) => key <= 2 * digest.block as u64,
+            // compared through the bits or the frames they share instead
+            Parsed::Phash(_) | Parsed::Audio(_) => true,
+        }
+    }
+
+    fn distance(&self, other: &Parsed) -> u32 {
+        match (self, other) {
+            (Parsed::Tlsh(first), Parsed::Tlsh(second)) => first.distance(second),
+            (Parsed::Ssdeep(first), Parsed::Ssdeep(second)) => 100 - first.score(second),
+            (Parsed::Phash(first), Parsed::Phash(second)) => (first ^ second).count_ones(),
+            (Parsed::Audio(first), Parsed::Audio(second)) => crate::audio::distance(first, second),
+            _ => u32::MAX,
+        }
+    }
+}
+
+/// groups of `entries` linked by pairs at most `threshold` apart in their `similarity` digests, each with at least
+/// two different contents, their paths sorted. Identical files are part of the groups but left to the duplicates
+/// when alone.
+pub fn near_duplicates<'a>(entries: impl IntoIterator<Item = &'a Entry>, similarity: Similarity, threshold: u32) -> Vec<Vec<&'a Entry>> {
+    let mut files = entries
+        .into_iter()
+        .filter_map(|entry| Some((entry, similarity.parse(entry.digest(similarity.algorithm())?)?)))
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(entry, digest)| (digest.key(), &entry.path));
+    // union-find of the files, by index
+    let mut parent = (0..files.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, j) in candidates(&files, similarity, threshold) {
+        if files[i].1.distance(&files[j].1) <= threshold {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a] = b;
+        }
+    }
+    let mut groups: HashMap<usize, Vec<&Entry>> = HashMap::new();
+    for (i, (entry, _)) in files.iter().enumerate() {
+        let group = root(&mut parent, i);
+        groups.entry(group).or_default().push(*entry);
+    }
+    let mut groups = groups
+        .into_values()
+        .filter(|group| group.iter().any(|entry| entry.hash != group[0].hash))
+        .map(|mut group| {
+            group.sort_by(|a, b| a.path.cmp(&b.path));
+            group
+        })
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    groups
+}
+
+/// pairs of an entry of `first` and an entry of `second` at most `threshold` apart in their `similarity` digests,
+/// with their distance : the closest pairs first, each entry in a single pair, sorted by the path of `first`
+pub fn similar_pairs<'a>(first: impl IntoIterator<Item = &'a Entry>, second: impl IntoIterator<Item = &'a Entry>, similarity: Similarity, threshold: u32) -> Vec<(&'a Entry, &'a Entry, u32)> {
+    let parse = |entry: &'a Entry| Some((entry, similarity.parse(entry.digest(similarity.algorithm())?)?));
+    let mut files = first.into_iter().filter_map(parse).map(|file| (false, file)).chain(second.into_iter().filter_map(parse).map(|file| (true, file))).collect::<Vec<_>>();
+    files.sort_by(|(_, (a, digest_a)), (_, (b, digest_b))| (digest_a.key(), &a.path).cmp(&(digest_b.key(), &b.path)));
+    let (sides, files): (Vec<_>, Vec<_>) = files.into_iter().unzip();
+    let mut pairs = candidates(&files, similarity, threshold)
+        .into_iter()
+        .filter(|&(i, j)| sides[i] != sides[j])
+        .map(|(i, j)| if sides[i] { (j, i) } else { (i, j) })
+        .map(|(i, j)| (i, j, files[i].1.distance(&files[j].1)))
+        .filter(|&(_, _, distance)| distance <= threshold)
+        .collect::<Vec<_>>();
+    pairs.sort_by(|a, b| (a.2, &files[a.0].0.path, &files[a.1].0.path).cmp(&(b.2, &files[b.0].0.path, &files[b.1].0.path)));
+    let mut paired = HashSet::new();
+    let mut matched = Vec::new();
+    for (i, j, distance) in pairs {
+        if !paired.contains(&i) && !paired.contains(&j) {
+            paired.extend([i, j]);
+            matched.push((files[i].0, files[j].0, distance));
+        }
+    }
+    matched.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+    matched
+}
+
+/// frames shared by more acoustic fingerprints are left out of the candidates, like silence
+const MAX_SHARING: usize = 1000;
+
+/// pairs of `files`, sorted by key, which can be at most `threshold` apart : each file with the next ones while they
+/// can be, for perceptual hashes the ones sharing a block of bits, or for acoustic fingerprints the files sharing a
+/// frame, encodings of a same track keeping some of them intact
+fn candidates(files: &[(&Entry, Parsed)], similarity: Similarity, threshold: u32) -> Vec<(usize, usize)> {
+    match similarity {
+        Similarity::Phash => return phash_candidates(files, threshold),
+        Similarity::Audio => {}
+        Similarity::Tlsh | Similarity::Ssdeep => {
+            let mut pairs = (0..files.len()).flat_map(|i| (i + 1..files.len()).take_while(move |&j| files[i].1.reaches(files[j].1.key(), threshold)).map(move |j| (i, j))).collect::<Vec<_>>();
+            if similarity == Similarity::Tlsh {
+                // L-values wrap around, the largest ones being as close to the smallest ones as to each other
+                pairs.extend((0..files.len()).rev().flat_map(|i| (0..i).take_while(move |&j| files[i].1.reaches(files[j].1.key() + 256, threshold)).map(move |j| (j, i))));
+                pairs.sort_unstable();
+                pairs.dedup();
+            }
+            return pairs;
+        }
+    }
+    let mut sharing: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, (_, digest)) in files.iter().enumerate() {
+        if let Parsed::Audio(frames) = digest {
+            frames.iter().filter(|&&frame| frame != 0 && frame != !0).collect::<HashSet<_>>().into_iter().for_each(|&frame| sharing.entry(frame).or_default().push(i));
+        }
+    }
+    let mut pairs = HashSet::new();
+    for owners in sharing.values().filter(|owners| owners.len() > 1 && owners.len() <= MAX_SHARING) {
+        for (n, &i) in owners.iter().enumerate() {
+            pairs.extend(owners[n + 1..].iter().map(|&j| (i, j)));
+        }
+    }
+    pairs.into_iter().collect()
+}
+
+/// pairs of perceptual hashes which can be at most `threshold` bits apart : cut into `threshold + 1` blocks, two
+/// such hashes have at least one block in common, so only the hashes sharing a block are compared
+fn phash_candidates(files: &[(&Entry, Parsed)], threshold: u32) -> Vec<(usize, usize)> {
+    let hashes = files.iter().map(|(_, digest)| if let Parsed::Phash(hash) = digest { *hash } else { 0 }).collect::<Vec<_>>();
+    if threshold >= 64 {
+        return (0..hashes.len()).flat_map(|i| (i + 1..hashes.len()).map(move |j| (i, j))).collect();
+    }
+    let blocks = threshold as usize + 1;
+    let block = |hash: u64, n: usize| {
+        let (start, end) = (64 * n / blocks, 64 * (n + 1) / blocks);
+        (hash >> start) & (u64::MAX >> (64 - (end - start)))
+    };
+    let mut pairs = Vec::new();
+    for n in 0..blocks {
+        let mut sharing: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, &hash) in hashes.iter().enumerate() {
+            sharing.entry(block(hash, n)).or_default().push(i);
+        }
+        for owners in sharing.values() {
+            for (m, &i) in owners.iter().enumerate() {
+                // each pair once, from the first block both hashes share
+                pairs.extend(owners[m + 1..].iter().filter(|&&j| (0..n).all(|earlier| block(hashes[i], earlier) != block(hashes[j], earlier))).map(|&j| (i, j)));
+            }
+        }
+    }
+    pairs
+}
+
+/// permutation of the Pearson hash of TLSH
+const PEARSON: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163, 14, 197, 213, 181, 161, 85, 218, 80, 64, 239, 24, 226, 236, 142, 38, 200, 110, 177, 104, 103, 141, 253, 255, 50, 77, 101, 81, 18, 45, 96, 31, 222, 25, 107, 190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227, 149, 235, 97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127, 199, 111, 62, 135, 248, 174, 169, 211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243, 132, 56, 148, 75, 128, 133, 158, 100, 130, 126, 91, 13, 153, 246, 216, 219, 119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92, 32, 136, 114, 52, 10, 138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152, 170, 7, 115, 167, 241, 206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131, 125, 173, 15, 238, 79, 95, 89, 16, 105, 137, 225, 224, 217, 160, 37, 123, 118, 73, 2, 157, 46, 116, 9, 145, 134, 228, 207, 212, 202, 215, 69, 229, 27, 188, 67, 124, 168, 252, 42, 4, 29, 108, 21, 247, 19, 205, 39, 203, 233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76, 140, 36, 210, 172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120, 51, 65, 28, 144, 254, 221, 93, 189, 194, 139, 112, 43, 71, 109, 184, 209,
+];
+
+fn pearson(salt: u8, a: u8, b: u8, c: u8) -> u8 {
+    let h = PEARSON[salt as usize];
+    let h = PEARSON[(h ^ a) as usize];
+    let h = PEARSON[(h ^ b) as usize];
+    PEARSON[(h ^ c) as usize]
+}
+
+/// buckets making the body of a TLSH digest, 2 bits each
+const BUCKETS: usize = 128;
+/// no TLSH digest under this length
+const TLSH_MIN_LEN: u64 = 50;
+
+/// TLSH digest of a content fed like a digest : counts of the trigrams of a 5 bytes sliding window in 128 buckets,
+/// written as `T1` and 70 hex digits, or `-` for contents too short or too uniform.
+pub struct Tlsh {
+    window: [u8; 5],
+    len: u64,
+    checksum: u8,
+    buckets: [u32; 256],
+}
+
+impl Default for Tlsh {
+    fn default() -> Self { Self::new() }
+}
+
+impl Tlsh {
+    pub fn new() -> Self {
+        Self {
+            window: [0; 5],
+            len: 0,
+            checksum: 0,
+            buckets: [0; 256],
+        }
+    }
+}
+
+impl Digest for Tlsh {
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let j = (self.len % 5) as usize;
+            self.window[j] = byte;
+            if self.len >= 4 {
+                let w = |back: usize| self.window[(j + 5 - back) % 5];
+                let (c0, c1, c2, c3, c4) = (w(0), w(1), w(2), w(3), w(4));
+                self.checksum = pearson(0, c0, c1, self.checksum);
+                for bucket in [pearson(2, c0, c1, c2), pearson(3, c0, c1, c3), pearson(5, c0, c2, c3), pearson(7, c0, c2, c4), pearson(11, c0, c1, c4), pearson(13, c0, c3, c4)] {
+                    self.buckets[bucket as usize] += 1;
+                }
+            }
+            self.len += 1;
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        if self.len < TLSH_MIN_LEN || self.buckets[..BUCKETS].iter().filter(|&&count| count > 0).count() <= BUCKETS / 2 {
+            return String::from("-");
+        }
+        let mut sorted = self.buckets[..BUCKETS].to_vec();
+        sorted.sort_unstable();
+        let (q1, q2, q3) = (sorted[BUCKETS / 4 - 1], sorted[BUCKETS / 2 - 1], sorted[BUCKETS * 3 / 4 - 1]);
+        let mut code = [0u8; BUCKETS / 4];
+        for (i, quad) in self.buckets[..BUCKETS].chunks(4).enumerate() {
+            code[BUCKETS / 4 - 1 - i] = quad.iter().enumerate().fold(0, |h, (j, &count)| {
+                let quartile = match count {
+                    count if count > q3 => 3,
+                    count if count > q2 => 2,
+                    count if count > q1 => 1,
+                    _ => 0,
+                };
+                h | quartile << (2 * j)
+            });
+        }
+        let digest = TlshDigest {
+            checksum: self.checksum,
+            lvalue: lvalue(self.len),
+            q1ratio: ((q1.wrapping_mul(100) as f32 / q3 as f32) as u32 % 16) as u8,
+            q2ratio: ((q2.wrapping_mul(100) as f32 / q3 as f32) as u32 % 16) as u8,
+            code,
+        };
+        digest.to_string()
+    }
+}
+
+/// logarithm of the length, coarser as it grows
+fn lvalue(len: u64) -> u8 {
+    let log = (len as f64).ln();
+    let l = match len {
+        0..=656 => log / 0.405_465_1,
+        657..=3199 => log / 0.262_364_26 - 8.727_77,
+        _ => log / 0.095_310_18 - 62.547_2,
+    };
+    (l.floor() as i64 & 0xff) as u8
+}
+
+struct TlshDigest {
+    checksum: u8,
+    lvalue: u8,
+    q1ratio: u8,
+    q2ratio: u8,
+    code: [u8; BUCKETS / 4],
+}
+
+impl TlshDigest {
+    fn parse(digest: &str) -> Option<Self> {
+        let hex = digest.strip_prefix("T1")?;
+        if hex.len() != 70 || !hex.is_ascii() {
+            return None;
+        }
+        let bytes = (0..35).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()).collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            checksum: bytes[0].rotate_left(4),
+            lvalue: bytes[1].rotate_left(4),
+            q1ratio: bytes[2] >> 4,
+            q2ratio: bytes[2] & 0xf,
+            code: bytes[3..].try_into().ok()?,
+        })
+    }
+
+    fn distance(&self, other: &Self) -> u32 {
+        fn circular(x: u8, y: u8, range: u32) -> u32 {
+            let d = (x as u32).abs_diff(y as u32);
+            d.min(range - d)
+        }
+        let mut distance = match circular(self.lvalue, other.lvalue, 256) {
+            d @ (0 | 1) => d,
+            d => d * 12,
+        };
+        for (x, y) in [(self.q1ratio, other.q1ratio), (self.q2ratio, other.q2ratio)] {
+            distance += match circular(x, y, 16) {
+                d @ (0 | 1) => d,
+                d => (d - 1) * 12,
+            };
+        }
+        distance += (self.checksum != other.checksum) as u32;
+        for (x, y) in self.code.iter().zip(&other.code) {
+            for shift in [0, 2, 4, 6] {
+                distance += match ((x >> shift) & 3).abs_diff((y >> shift) & 3) {
+                    3 => 6,
+                    d => d as u32,
+                };
+            }
+        }
+        distance
+    }
+}
+
+impl std::fmt::Display for TlshDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "T1{:02X}{:02X}{:02X}", self.checksum.rotate_left(4), self.lvalue.rotate_left(4), self.q1ratio << 4 | self.q2ratio)?;
+        self.code.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// characters of the first piecewise hash of a ssdeep digest, the second one having half
+const SPAMSUM_LEN: usize = 64;
+const MIN_BLOCK: u32 = 3;
+/// block sizes followed at once, from 3 to 3 × 2³⁰
+const BLOCK_SIZES: usize = 31;
+const ROLLING_WINDOW: usize = 7;
+const FNV_INIT: u32 = 0x2802_1967;
+
+fn fnv(h: u32, byte: u8) -> u32 { h.wrapping_mul(0x0100_0193) ^ byte as u32 }
+
+/// hash of the last 7 bytes, triggering the end of a piece when it hits the block size
+#[derive(Default)]
+struct Rolling {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl Rolling {
+    fn roll(&mut self, byte: u8) -> u32 {
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * byte as u32);
+        self.h1 = self.h1.wrapping_add(byte as u32).wrapping_sub(self.window[self.n % ROLLING_WINDOW] as u32);
+        self.window[self.n % ROLLING_WINDOW] = byte;
+        self.n += 1;
+        self.h3 = (self.h3 << 5) ^ byte as u32;
+        self.sum()
+    }
+
+    fn sum(&self) -> u32 { self.h1.wrapping_add(self.h2).wrapping_add(self.h3) }
+}
+
+/// piecewise hash of one block size
+#[derive(Clone)]
+struct Pieces {
+    h: u32,
+    /// hash of the pieces past the half of the digest, to truncate it for the double block size
+    half_h: u32,
+    digest: [u8; SPAMSUM_LEN],
+    half_digest: u8,
+    len: usize,
+}
+
+impl Pieces {
+    fn new(h: u32, half_h: u32) -> Self {
+        Self {
+            h,
+            half_h,
+            digest: [0; SPAMSUM_LEN],
+            half_digest: 0,
+            len: 0,
+        }
+    }
+}
+
+/// ssdeep digest of a content fed like a digest : a character per piece of the content, pieces ending where a
+/// rolling hash hits the block size, written as `blocksize:pieces:pieces of the double block size`. Every block size
+/// is followed in the same read, the digest keeping the one giving between 32 and 64 pieces.
+pub struct Ssdeep {
+    rolling: Rolling,
+    /// the block sizes from `start`, small ones being dropped once too small for the length
+    blocks: Vec<Pieces>,
+    start: usize,
+    len: u64,
+}
+
+impl Default for Ssdeep {
+    fn default() -> Self { Self::new() }
+}
+
+impl Ssdeep {
+    pub fn new() -> Self {
+        Self {
+            rolling: Rolling::default(),
+            blocks: vec![Pieces::new(FNV_INIT, FNV_INIT)],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn block_size(i: usize) -> u32 { MIN_BLOCK << i }
+
+    fn step(&mut self, byte: u8) {
+        let h = self.rolling.roll(byte);
+        for pieces in &mut self.blocks[self.start..] {
+            pieces.h = fnv(pieces.h, byte);
+            pieces.half_h = fnv(pieces.half_h, byte);
+        }
+        let mut i = self.start;
+        while i < self.blocks.len() {
+            let size = Self::block_size(i);
+            if h % size != size - 1 {
+                break;
+            }
+            if self.blocks[i].len == 0 && self.blocks.len() < BLOCK_SIZES {
+                // the next block size starts with the pieces of the last one
+                let last = &self.blocks[self.blocks.len() - 1];
+                self.blocks.push(Pieces::new(last.h, last.half_h));
+            }
+            let pieces = &mut self.blocks[i];
+            pieces.digest[pieces.len] = BASE64[pieces.h as usize % 64];
+            pieces.half_digest = BASE64[pieces.half_h as usize % 64];
+            if pieces.len < SPAMSUM_LEN - 1 {
+                pieces.len += 1;
+                pieces.digest[pieces.len] = 0;
+                pieces.h = FNV_INIT;
+                if pieces.len < SPAMSUM_LEN / 2 {
+                    pieces.half_h = FNV_INIT;
+                    pieces.half_digest = 0;
+                }
+            } else if self.blocks.len() - self.start >= 2 && (Self::block_size(self.start) as u64) * (SPAMSUM_LEN as u64) < self.len && self.blocks[self.start + 1].len >= SPAMSUM_LEN / 2 {
+                self.start += 1;
+            }
+            i += 1;
+        }
+    }
+}
+
+impl Digest for Ssdeep {
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.len += 1;
+            self.step(byte);
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        let rolling = self.rolling.sum();
+        let mut i = self.start;
+        while (Self::block_size(i) as u64) * (SPAMSUM_LEN as u64) < self.len {
+            i += 1;
+            if i >= BLOCK_SIZES {
+                return String::from("-");
+            }
+        }
+        i = i.min(self.blocks.len() - 1);
+        while i > self.start && self.blocks[i].len < SPAMSUM_LEN / 2 {
+            i -= 1;
+        }
+        let mut digest = format!("{}:", Self::block_size(i)).into_bytes();
+        let pieces = &self.blocks[i];
+        digest.extend_from_slice(&pieces.digest[..pieces.len]);
+        if rolling != 0 {
+            digest.push(BASE64[pieces.h as usize % 64]);
+        } else if pieces.digest[pieces.len] != 0 {
+            digest.push(pieces.digest[pieces.len]);
+        }
+        digest.push(b':');
+        if let Some(double) = self.blocks.get(i + 1) {
+            digest.extend_from_slice(&double.digest[..double.len.min(SPAMSUM_LEN / 2 - 1)]);
+            if rolling != 0 {
+                digest.push(BASE64[double.half_h as usize % 64]);
+            } else if double.half_digest != 0 {
+                digest.push(double.half_digest);
+            }
+        } else if rolling != 0 {
+            digest.push(BASE64[pieces.h as usize % 64]);
+        }
+        String::from_utf8(digest).unwrap_or_default()
+    }
+}
+
+struct SsdeepDigest {
+    block: u32,
+    first: Vec<u8>,
+    second: Vec<u8>,
+}
+
+impl SsdeepDigest {
+    fn parse(digest: &str) -> Option<Self> {
+        let mut parts = digest.splitn(3, ':');
+        let block = parts.next()?.parse().ok()?;
+        // runs of more than 3 times the same character say little about the content
+        let squeeze = |pieces: &str| pieces.bytes().enumerate().filter(|&(i, c)| i < 3 || pieces.as_bytes()[i - 3..i].iter().any(|&p| p != c)).map(|(_, c)| c).collect();
+        Some(Self {
+            block,
+            first: squeeze(parts.next()?),
+            second: squeeze(parts.next()?),
+        })
+    }
+
+    /// match score, from 0 for unrelated contents to 100
+    fn score(&self, other: &Self) -> u32 {
+        if self.block == other.block && self.first == other.first && self.second == other.second {
+            100
+        } else if self.block == other.block {
+            score(&self.first, &other.first, self.block).max(score(&self.second, &other.second, self.block * 2))
+        } else if self.block == other.block * 2 {
+            score(&self.first, &other.second, self.block)
+        } else if other.block == self.block * 2 {
+            score(&self.second, &other.first, other.block)
+        } else {
+            0
+        }
+    }
+}
+
+/// score of two piecewise hashes of `block` size, from their edit distance
+fn score(first: &[u8], second: &[u8], block: u32) -> u32 {
+    if first.len() > SPAMSUM_LEN || second.len() > SPAMSUM_LEN || !first.windows(ROLLING_WINDOW).any(|w| second.windows(ROLLING_WINDOW).any(|v| v == w)) {
+        return 0;
+    }
+    let (m, n) = (first.len() as u32, second.len() as u32);
+    let scaled = edit_distance(first, second) * SPAMSUM_LEN as u32 / (m + n) * 100 / SPAMSUM_LEN as u32;
+    if scaled >= 100 {
+        return 0;
+    }
+    // small block sizes can't score high on few pieces
+    match block >= (99 + ROLLING_WINDOW as u32) / ROLLING_WINDOW as u32 * MIN_BLOCK {
+        true => 100 - scaled,
+        false => (100 - scaled).min(block / MIN_BLOCK * m.min(n)),
+    }
+}
+
+/// insertions and deletions cost 1, substitutions 2
+fn edit_distance(first: &[u8], second: &[u8]) -> u32 {
+    let mut row = (0..=second.len() as u32).collect::<Vec<_>>();
+    for (i, &a) in first.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i as u32 + 1;
+        for (j, &b) in second.iter().enumerate() {
+            let substitution = diagonal + if a == b { 0 } else { 2 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[second.len()]
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(mut digest: impl Digest, data: &[u8]) -> String {
+        digest.update(data);
+        digest.finalize_hex()
+    }
+
+    /// pseudo-random content, the same on every run
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed * 2 + 1;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn entry(path: &str, algorithm: Algorithm, digest: String) -> Entry {
+        Entry {
+            hash: path.to_owned(),
+            size: None,
+            mtime: None,
+            xattrs: None,
+            path: path.to_owned(),
+            target: None,
+            digests: vec![(algorithm, digest)],
+        }
+    }
+
+    #[test]
+    fn ssdeep_matches_reference() {
+        // digests and score of the documentation of python-ssdeep, from the ssdeep library
+        let first = digest(Ssdeep::new(), b"Also called fuzzy hashes, Ctph can match inputs that have homologies.");
+        let second = digest(Ssdeep::new(), b"Also called fuzzy hashes, CTPH can match inputs that have homologies.");
+        assert_eq!(first, "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C");
+        assert_eq!(second, "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C");
+        assert_eq!(Similarity::Ssdeep.distance(&first, &second), Some(100 - 22));
+        assert_eq!(digest(Ssdeep::new(), b""), "3::");
+    }
+
+    #[test]
+    fn ssdeep_block_size_follows_length() {
+        for len in [1 << 10, 100_000, 1 << 20] {
+            let ssdeep = digest(Ssdeep::new(), &noise(len, 1));
+            let parsed = SsdeepDigest::parse(&ssdeep).unwrap();
+            assert!(parsed.first.len() <= SPAMSUM_LEN && parsed.second.len() <= SPAMSUM_LEN / 2, "{}", ssdeep);
+            assert!(parsed.block == MIN_BLOCK || (parsed.block as usize) * SPAMSUM_LEN / 2 < len, "{}", ssdeep);
+        }
+        let mut edited = noise(100_000, 1);
+        edited[50_000] ^= 0xff;
+        let distance = Similarity::Ssdeep.distance(&digest(Ssdeep::new(), &noise(100_000, 1)), &digest(Ssdeep::new(), &edited)).unwrap();
+        assert!(distance <= Similarity::Ssdeep.threshold(), "{}", distance);
+    }
+
+    #[test]
+    fn tlsh_lvalue_matches_reference() {
+        // largest length of each of the first L-values, from the table of the reference implementation
+        let top = [1, 2, 3, 5, 7, 11, 17, 25, 38, 57, 86, 129, 194, 291, 437, 656, 854, 1110, 1443, 1876, 2439, 3171, 3475, 3823, 4205, 4626, 5088, 5597, 6157, 6772, 7450, 8195];
+        for (l, &len) in top.iter().enumerate() {
+            assert_eq!((lvalue(len), lvalue(len + 1)), (l as u8, l as u8 + 1), "{}", len);
+        }
+    }
+
+    #[test]
+    fn tlsh_matches_reference() {
+        // digests of `seq 100`, `seq 1000` and `seq 5000`, one in each range of L-values, computed by a line by line
+        // port of tlsh_impl.cpp, from the reference implementation
+        let seq = |n: usize| (1..=n).map(|i| format!("{}\n", i)).collect::<String>();
+        for (n, expected) in [
+            (100, "T181E0E2489F01D1A6C0054B0B2474F7C51D5483F6F5578031C53214560F3F0274B7B894"),
+            (1000, "T18E81000656B697D08B108427E19BB2BC16261EADDFC734F19BE623C1092FC0A87FD587"),
+            (5000, "T134B2850ABDE914D19F21E55BE35F672AC22F1511EAD720333A1B36014B2713B1FF9899"),
+        ] {
+            assert_eq!(digest(Tlsh::new(), seq(n).as_bytes()), expected, "seq {}", n);
+        }
+    }
+
+    #[test]
+    fn tlsh_needs_length_and_variety() {
+        assert_eq!(digest(Tlsh::new(), &noise(TLSH_MIN_LEN as usize - 1, 1)), "-");
+        assert_eq!(digest(Tlsh::new(), &[0; 4096]), "-");
+        let tlsh = digest(Tlsh::new(), &noise(4096, 1));
+        assert_eq!((tlsh.len(), &tlsh[..2]), (72, "T1"));
+        assert_eq!(TlshDigest::parse(&tlsh).unwrap().to_string(), tlsh);
+    }
+
+    #[test]
+    fn tlsh_distance() {
+        let original = noise(10_000, 2);
+        let mut edited = original.clone();
+        edited[5_000..5_010].copy_from_slice(b"0123456789");
+        let (first, second, other) = (digest(Tlsh::new(), &original), digest(Tlsh::new(), &edited), digest(Tlsh::new(), &noise(10_000, 3)));
+        assert_eq!(Similarity::Tlsh.distance(&first, &first), Some(0));
+        assert!(Similarity::Tlsh.distance(&first, &second).unwrap() <= Similarity::Tlsh.threshold());
+        assert!(Similarity::Tlsh.distance(&first, &other).unwrap() > Similarity::Tlsh.threshold());
+    }
+
+    #[test]
+    fn tlsh_lvalues_wrap_around() {
+        // the same body with L-values 255 and 0, one step apart
+        let body = digest(Tlsh::new(), &noise(4096, 1));
+        let with_lvalue = |l: u8| format!("{}{:02X}{}", &body[..4], l.rotate_left(4), &body[6..]);
+        let (high, low, middle) = (with_lvalue(255), with_lvalue(0), with_lvalue(128));
+        assert_eq!(Similarity::Tlsh.distance(&high, &low), Some(1));
+        let entries = [entry("a", Algorithm::Tlsh, low), entry("b", Algorithm::Tlsh, middle), entry("c", Algorithm::Tlsh, high)];
+        let groups = near_duplicates(&entries, Similarity::Tlsh, 40);
+        assert_eq!(groups.iter().map(|group| group.iter().map(|e| e.path.as_str()).collect::<Vec<_>>()).collect::<Vec<_>>(), [["a", "c"]]);
+    }
+
+    #[test]
+    fn phash_candidates_share_a_block() {
+        let hashes = [0u64, 0xff, 0x1ff, 0xffff_0000_0000_0000, 0xffff_0000_0000_0001, 0x5555_5555_5555_5555];
+        let entries = hashes.iter().enumerate().map(|(i, hash)| entry(&i.to_string(), Algorithm::Phash, format!("{:016x}", hash))).collect::<Vec<_>>();
+        for threshold in [0, 1, 8, 9, 20, 64] {
+            // every pair within the threshold, as found by comparing all of them
+            let mut expected = Vec::new();
+            for (i, a) in hashes.iter().enumerate() {
+                expected.extend(hashes[i + 1..].iter().enumerate().filter(|(_, b)| (*a ^ **b).count_ones() <= threshold).map(|(j, _)| (i, i + 1 + j)));
+            }
+            let files = entries.iter().map(|e| (e, Similarity::Phash.parse(e.digest(Algorithm::Phash).unwrap()).unwrap())).collect::<Vec<_>>();
+            let mut found = phash_candidates(&files, threshold).into_iter().filter(|&(i, j)| files[i].1.distance(&files[j].1) <= threshold).collect::<Vec<_>>();
+            found.sort_unstable();
+            assert_eq!(found, expected, "threshold {}", threshold);
+            let mut all = phash_candidates(&files, threshold);
+            let len = all.len();
+            all.sort_unstable();
+            all.dedup();
+            assert_eq!(all.len(), len, "each pair once");
+        }
+    }
+}
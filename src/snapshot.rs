@@ -0,0 +1,136 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use itertools::Itertools;
+
+use blakediff::report::{self, Field};
+use blakediff::{BlakediffError, Result};
+
+/// extension of snapshot files, gzip compressed reports
+const EXTENSION: &str = ".txt.gz";
+
+/// store used when none is given
+pub fn default_store() -> Result<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).ok_or_else(|| BlakediffError::Other(String::from("no home directory, use --store")))?;
+    Ok(Path::new(&home).join(".blakediff").join("snapshots"))
+}
+
+/// name of the snapshots of `dir` when none is given : the last component of the directory
+pub fn default_name(dir: &Path) -> String {
+    fs::canonicalize(dir)
+        .ok()
+        .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| String::from("root"))
+}
+
+/// write a timestamped and compressed report of `dir` in `store`, then remove the oldest snapshots
+/// of the same name to keep only the `keep` most recent ones
+pub fn snapshot(dir: String, store: Option<String>, name: Option<String>, keep: Option<u64>, parallel: bool) -> Result<()> {
+    let dir = Path::new(&dir);
+    let store = store.map_or_else(default_store, |store| Ok(PathBuf::from(store)))?;
+    let name = name.unwrap_or_else(|| default_name(dir));
+    fs::create_dir_all(&store)?;
+
+    let entries = blakediff::generate(dir, parallel, true, true)?.into_iter().sorted_by(|a, b| a.path.cmp(&b.path)).collect::<Vec<_>>();
+    let path = reserve(&store, &name)?;
+    report::save(&path, &[Field::Hash, Field::Size, Field::Mtime, Field::Path], &entries).inspect_err(|_| {
+        let _ = fs::remove_file(&path);
+    })?;
+    summary!("snapshot : {} ({} files)", path.display(), entries.len());
+
+    if let Some(keep) = keep {
+        let snapshots = list(&store, Some(&name))?;
+        for old in &snapshots[..snapshots.len().saturating_sub(keep as usize)] {
+            fs::remove_file(&old.path)?;
+            println!("removed : {}", old.path.display());
+        }
+    }
+    Ok(())
+}
+
+/// create the empty file of a new snapshot named `name` in `store`, under a timestamp no other snapshot took
+fn reserve(store: &Path, name: &str) -> io::Result<PathBuf> {
+    loop {
+        // 2022-12-01T18:30:00.123456789Z becomes 20221201T183000.123456789Z, sortable and valid in file names everywhere
+        let timestamp = humantime::format_rfc3339_nanos(SystemTime::now()).to_string().replace(['-', ':'], "");
+        let path = store.join(format!("{}-{}{}", name, timestamp, EXTENSION));
+        match fs::File::options().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// a snapshot file of a store
+pub struct Snapshot {
+    pub name: String,
+    /// like `20221201T183000.123456789Z`, or `20221201T183000Z` for the snapshots of older versions
+    pub timestamp: String,
+    pub path: PathBuf,
+}
+
+impl Snapshot {
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let stem = path.file_name()?.to_str()?.strip_suffix(EXTENSION)?;
+        let (name, timestamp) = stem.rsplit_once('-').filter(|(_, timestamp)| is_timestamp(timestamp))?;
+        let (name, timestamp) = (name.to_owned(), timestamp.to_owned());
+        Some(Self { name, timestamp, path })
+    }
+
+    /// timestamp in the RFC 3339 format to the second, like `2022-12-01T18:30:00Z`
+    pub fn date(&self) -> String {
+        let t = &self.timestamp;
+        format!("{}-{}-{}T{}:{}:{}Z", &t[0..4], &t[4..6], &t[6..8], &t[9..11], &t[11..13], &t[13..15])
+    }
+}
+
+/// snapshots in `store`, only those named `name` if given, oldest first
+pub fn list(store: &Path, name: Option<&str>) -> io::Result<Vec<Snapshot>> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(store)? {
+        if let Some(snapshot) = Snapshot::from_path(entry?.path()).filter(|s| name.is_none_or(|name| s.name == name)) {
+            snapshots.push(snapshot);
+        }
+    }
+    snapshots.sort_by(|a, b| (&a.name, &a.timestamp).cmp(&(&b.name, &b.timestamp)));
+    Ok(snapshots)
+}
+
+/// timestamp of a snapshot file name, like `20221201T183000.123456789Z` or `20221201T183000Z`
+fn is_timestamp(s: &str) -> bool {
+    let Some(s) = s.strip_suffix('Z') else { return false };
+    let (seconds, nanos) = s.split_once('.').unwrap_or((s, "000000000"));
+    seconds.len() == 15 && nanos.len() == 9 && seconds.chars().enumerate().all(|(i, c)| if i == 8 { c == 'T' } else { c.is_ascii_digit() }) && nanos.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+
+    #[test]
+    fn snapshots_of_a_same_second_are_kept_apart() {
+        let tmp = TempDir::new("snapshot").unwrap();
+        let dir = tmp.path();
+        let (files, store) = (dir.join("files"), dir.join("store"));
+        fs::create_dir_all(&files).unwrap();
+        fs::write(files.join("a.txt"), b"a").unwrap();
+        for _ in 0..3 {
+            snapshot(files.to_string_lossy().into_owned(), Some(store.to_string_lossy().into_owned()), Some(String::from("my-files")), Some(2), false).unwrap();
+        }
+        let snapshots = list(&store, Some("my-files")).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots[0].timestamp < snapshots[1].timestamp);
+        assert_eq!(snapshots[1].date().len(), "2022-12-01T18:30:00Z".len());
+        assert_eq!(fs::read_dir(&store).unwrap().count(), 2);
+
+        assert!(is_timestamp("20221201T183000Z") && is_timestamp("20221201T183000.123456789Z"));
+        assert!(!is_timestamp("20221201T183000") && !is_timestamp("20221201T183000.123Z") && !is_timestamp("20221201-183000Z"));
+        let old = Snapshot::from_path(PathBuf::from("my-files-20221201T183000Z.txt.gz")).unwrap();
+        assert_eq!((old.name.as_str(), old.date()), ("my-files", String::from("2022-12-01T18:30:00Z")));
+    }
+}
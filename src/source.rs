@@ -0,0 +1,245 @@
+//! Sources of `generate` : a local directory tree or a remote one, each behind a [`Backend`] listing its
+//! files and reading them, so that every source gets the same hashing and the same reports.
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rayon::prelude::*;
+
+use crate::cache::Cache;
+use crate::exclude::Excludes;
+use crate::input::{self, Algorithm, Input};
+use crate::pipeline::Pipeline;
+use crate::report::{self, Entry};
+use crate::walker::{Special, Symlinks, Walker};
+use crate::error::OnError;
+use crate::{interrupt, threads, BlakediffError, Result};
+
+/// files hashed together before their entries are written, in parallel mode
+const CHUNK: usize = 64;
+
+/// A file listed by a [`Backend`].
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// path of the file in the report : a local path or an URL
+    pub path: String,
+    /// what the backend needs to read the file : local path, object key, href...
+    pub location: String,
+    pub size: u64,
+    pub mtime: Duration,
+}
+
+/// A tree of files which can be hashed into a report.
+pub trait Backend: Send + Sync {
+    /// files of the tree, in the order of the report
+    fn list(&self) -> Result<Vec<Item>>;
+
+    /// content of a listed file
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>>;
+
+    /// BLAKE3 of a listed file, read through [`open`](Backend::open) unless the backend knows it
+    fn hash(&self, item: &Item) -> Result<String> { Ok(blake3(self.open(item)?)?) }
+
+    /// call `write` on the entry of every file, in order, stopping at the first error unless `on_error` takes the
+    /// files which can't be read. Files are listed first, then hashed by chunks in parallel mode. Backends reading
+    /// the whole tree at once override it, and stop at the first error as their stream can't be resumed.
+    fn generate(&self, parallel: bool, with_size: bool, with_mtime: bool, on_error: Option<OnError>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+        let items = self.list()?;
+        log::info!("{} files to hash", items.len());
+        let hash = |item: &Item| -> Result<Option<Entry>> {
+            interrupt::check()?;
+            let hash = match self.hash(item).map_err(|e| e.reading(&item.path)) {
+                Ok(hash) => hash,
+                Err(e) => match &on_error {
+                    Some(on_error) if e.path().is_some() => {
+                        on_error(e);
+                        return Ok(None);
+                    }
+                    _ => return Err(e),
+                },
+            };
+            Ok(Some(Entry {
+                hash,
+                size: with_size.then_some(item.size),
+                mtime: with_mtime.then_some(item.mtime),
+                xattrs: None,
+                target: None,
+                digests: Vec::new(),
+                path: item.path.clone(),
+            }))
+        };
+        if !parallel {
+            return items.iter().try_for_each(|item| hash(item)?.map_or(Ok(()), &mut *write));
+        }
+        for chunk in items.chunks(CHUNK) {
+            let entries = threads::io(|| chunk.par_iter().map(hash).collect::<Vec<_>>());
+            entries.into_iter().try_for_each(|entry| entry?.map_or(Ok(()), &mut *write))?;
+        }
+        Ok(())
+    }
+}
+
+/// BLAKE3 of everything read from `reader`
+pub fn blake3(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().to_string())
+}
+
+/// options of the backends, most of them only applying to local directories, see [`open`]
+#[derive(Default, Clone)]
+pub struct Options {
+    /// hash cache of local files
+    pub cache: Option<Arc<Cache>>,
+    /// take the hash of S3 objects from their metadata
+    pub trust_metadata: bool,
+    /// read this revision of the git repository at the location instead of its files
+    pub git: Option<String>,
+    /// read the location as a tar archive, `-` being the standard input
+    pub tar: bool,
+    /// what to do with the symbolic links of local directories
+    pub symlinks: Symlinks,
+    /// what to do with the FIFOs, sockets and devices of local directories
+    pub special: Special,
+    /// files and directories left out of local directories
+    pub excludes: Excludes,
+    /// other algorithms digesting the files of local directories, next to BLAKE3
+    pub digests: Vec<Algorithm>,
+}
+
+/// `location` is a local directory or file, read by path : neither an URL, nor a git repository or a tar archive
+pub fn is_local(location: &str, options: &Options) -> bool { options.git.is_none() && !options.tar && !location.contains("://") }
+
+/// backend of `location` : an `s3://`, `sftp://`, `ssh://`, `dav://`, `davs://` or `oci://` URL, a local path,
+/// a git repository with [`Options::git`] or a tar archive with [`Options::tar`]
+pub fn open(location: &str, options: &Options) -> Result<Box<dyn Backend>> {
+    if !is_local(location, options) {
+        let unsupported = local_only(options);
+        if !unsupported.is_empty() {
+            return Err(BlakediffError::Format(format!("{} : {} only apply to local directories", location, unsupported.join(", "))));
+        }
+        if options.cache.is_some() {
+            log::warn!("{} : the hash cache only applies to local directories, it is not used", location);
+        }
+    }
+    if let Some(rev) = &options.git {
+        return Ok(Box::new(crate::git::Tree::new(Path::new(location), rev)?));
+    }
+    if options.tar {
+        #[cfg(feature = "tar")]
+        return Ok(Box::new(crate::tarball::Archive { location: location.to_owned() }));
+        #[cfg(not(feature = "tar"))]
+        return Err(unsupported(location, "tar"));
+    }
+    match location.split_once("://").map(|(scheme, _)| scheme) {
+        #[cfg(feature = "s3")]
+        Some("s3") => Ok(Box::new(crate::s3::Source::new(location, options.trust_metadata)?)),
+        #[cfg(not(feature = "s3"))]
+        Some("s3") => Err(unsupported(location, "s3")),
+        #[cfg(feature = "sftp")]
+        Some("sftp" | "ssh") => Ok(Box::new(crate::sftp::Remote::parse(location).ok_or_else(|| invalid_url(location, "sftp://[user@]host[:port]/path"))?)),
+        #[cfg(not(feature = "sftp"))]
+        Some("sftp" | "ssh") => Err(unsupported(location, "sftp")),
+        #[cfg(feature = "webdav")]
+        Some("dav" | "davs") => Ok(Box::new(crate::webdav::Share::open(location)?)),
+        #[cfg(not(feature = "webdav"))]
+        Some("dav" | "davs") => Err(unsupported(location, "webdav")),
+        #[cfg(feature = "oci")]
+        Some("oci") => Ok(Box::new(crate::oci::Image::open(location)?)),
+        #[cfg(not(feature = "oci"))]
+        Some("oci") => Err(unsupported(location, "oci")),
+        _ => Ok(Box::new(Local {
+            root: PathBuf::from(location),
+            cache: options.cache.clone(),
+            symlinks: options.symlinks,
+            special: options.special,
+            excludes: options.excludes.clone(),
+            digests: options.digests.clone(),
+        })),
+    }
+}
+
+/// options of `options` changing the report of a local directory which the other backends would silently ignore
+fn local_only(options: &Options) -> Vec<&'static str> {
+    [
+        (!options.digests.is_empty(), "other digests than BLAKE3"),
+        (!options.excludes.is_empty(), "excludes"),
+        (options.symlinks != Symlinks::default(), "--symlinks"),
+        (options.special != Special::default(), "--special"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect()
+}
+
+#[allow(unused)]
+fn invalid_url(location: &str, expected: &str) -> BlakediffError { BlakediffError::Format(format!("'{}' is not an {} URL", location, expected)) }
+
+#[allow(unused)]
+fn unsupported(location: &str, feature: &str) -> BlakediffError { BlakediffError::Format(format!("{} : blakediff was built without the {} feature", location, feature)) }
+
+/// A local directory tree, or a single file.
+pub struct Local {
+    pub root: PathBuf,
+    pub cache: Option<Arc<Cache>>,
+    pub symlinks: Symlinks,
+    pub special: Special,
+    pub excludes: Excludes,
+    pub digests: Vec<Algorithm>,
+}
+
+impl Backend for Local {
+    fn list(&self) -> Result<Vec<Item>> {
+        Walker::new(&self.root)
+            .symlinks(self.symlinks)
+            .special(self.special)
+            .exclude(self.excludes.clone())
+            .iter()
+            .map(|file| {
+                let file = file?;
+                let path = file.path.to_string_lossy().into_owned();
+                Ok(Item {
+                    location: path.clone(),
+                    path,
+                    size: file.metadata.len(),
+                    mtime: report::mtime(&file.metadata),
+                })
+            })
+            .collect()
+    }
+
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>> { Ok(Box::new(Input::open(Path::new(&item.location))?)) }
+
+    fn hash(&self, item: &Item) -> Result<String> {
+        let path = Path::new(&item.location);
+        Ok(input::retry(path, || Input::open(path)?.hash())?)
+    }
+
+    /// walked, hashed and written by separate threads, see [`Pipeline`]
+    fn generate(&self, parallel: bool, with_size: bool, with_mtime: bool, on_error: Option<OnError>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+        let workers = if parallel { threads::io_threads() } else { 1 };
+        Pipeline::new(Walker::new(&self.root).parallel(parallel).symlinks(self.symlinks).special(self.special).exclude(self.excludes.clone()))
+            .workers(workers)
+            .metadata(with_size, with_mtime)
+            .cache(self.cache.clone())
+            .digests(self.digests.clone())
+            .skip_errors(on_error)
+            .run(write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_options_are_refused_elsewhere() {
+        let mut options = Options { symlinks: Symlinks::Skip, ..Options::default() };
+        options.excludes.skip_vcs();
+        let error = open("s3://bucket/prefix", &options).err().unwrap();
+        assert!(matches!(&error, BlakediffError::Format(message) if message.ends_with("excludes, --symlinks only apply to local directories")), "{}", error);
+        assert!(open(".", &options).is_ok());
+    }
+}
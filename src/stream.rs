@@ -0,0 +1,35 @@
+//! async variant of [`generate`](crate::generate), for services driving scans from a tokio runtime
+
+use std::path::Path;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::report::Entry;
+use crate::walker::Walker;
+use crate::{hash_file, Result};
+
+/// number of hashed entries waiting for the consumer before the scan pauses
+const BUFFER: usize = 64;
+
+/// hash every file under `dir` into a report entry, with the requested metadata, in walk order.
+/// The walk and the hashing run on a blocking thread of the current tokio runtime, which waits
+/// while the stream isn't polled. The stream ends after the first error, and the scan stops when
+/// the stream is dropped.
+///
+/// Panics when called outside of a tokio runtime.
+pub fn generate_stream(dir: impl AsRef<Path>, with_size: bool, with_mtime: bool) -> impl Stream<Item = Result<Entry>> {
+    let (tx, rx) = mpsc::channel(BUFFER);
+    let walker = Walker::new(dir);
+    tokio::task::spawn_blocking(move || {
+        for file in walker.iter() {
+            let entry = file.and_then(|file| Ok(hash_file(&file, with_size, with_mtime)?));
+            let failed = entry.is_err();
+            if tx.blocking_send(entry).is_err() || failed {
+                break;
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
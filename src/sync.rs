@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use itertools::Itertools;
+
+use blakediff::input::Input;
+use blakediff::output::{quoted, Size};
+use blakediff::walker::Walker;
+use blakediff::Result;
+
+enum Operation {
+    /// copy the file from the source
+    Copy,
+    /// rename a destination file with the same content, which is not in the source anymore
+    Move(PathBuf),
+}
+
+/// make `dst` contain every file of `src`, copying only contents missing at the destination.
+/// Files of `dst` are never deleted, only renamed when the source holds them under another path.
+pub fn sync(src: String, dst: String, dry_run: bool, parallel: bool) -> Result<()> {
+    let (src, dst) = (Path::new(&src), Path::new(&dst));
+    let src_files = hash_tree(src, parallel)?;
+    let dst_files = hash_tree(dst, parallel)?;
+
+    // destination files whose path is not in the source, available to be renamed
+    let mut movable: HashMap<&String, Vec<&PathBuf>> = dst_files.iter().filter(|(path, _)| !src_files.contains_key(*path)).map(|(path, (hash, _))| (hash, path)).into_group_map();
+
+    let mut plan = Vec::new();
+    let mut unchanged = 0usize;
+    for (path, (hash, size)) in src_files.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        match dst_files.get(path) {
+            Some((dst_hash, _)) if dst_hash == hash => unchanged += 1,
+            _ => match movable.get_mut(hash).and_then(|paths| paths.pop()) {
+                Some(from) => plan.push((path, *size, Operation::Move(from.clone()))),
+                None => plan.push((path, *size, Operation::Copy)),
+            },
+        }
+    }
+
+    let total = plan.len();
+    let (mut copied, mut copied_bytes, mut moved) = (0usize, 0u64, 0usize);
+    for (i, (path, size, operation)) in plan.into_iter().enumerate() {
+        let target = dst.join(path);
+        match operation {
+            Operation::Copy => {
+                println!("[{}/{}] copy : {}", i + 1, total, quoted(&path.to_string_lossy()));
+                if !dry_run {
+                    copy(&src.join(path), &target)?;
+                }
+                copied += 1;
+                copied_bytes += size;
+            }
+            Operation::Move(from) => {
+                println!("[{}/{}] move : {} -> {}", i + 1, total, quoted(&from.to_string_lossy()), quoted(&path.to_string_lossy()));
+                if !dry_run {
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(dst.join(from), &target)?;
+                }
+                moved += 1;
+            }
+        }
+    }
+    let prefix = if dry_run { "sync (dry run)" } else { "sync" };
+    summary!("{} : {} copied ({}), {} moved, {} unchanged", prefix, copied, Size(copied_bytes), moved, unchanged);
+    Ok(())
+}
+
+/// hash and size of every file under `root`, by path relative to `root`
+fn hash_tree(root: &Path, parallel: bool) -> Result<HashMap<PathBuf, (String, u64)>> {
+    let files = Mutex::new(HashMap::new());
+    if root.exists() {
+        Walker::new(root).parallel(parallel).for_each(|file| {
+            let mut input = Input::open(&file.path)?;
+            let (hash, size) = (input.hash()?, input.len()?);
+            let relative = file.path.strip_prefix(root).unwrap_or(&file.path).to_owned();
+            files.lock().unwrap().insert(relative, (hash, size));
+            Ok(())
+        })?;
+    }
+    Ok(files.into_inner().unwrap())
+}
+
+/// copy `from` into `to` through a temporary sibling, so that `to` is never left half written
+fn copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(".blakediff.tmp");
+    fs::copy(from, &tmp)?;
+    fs::rename(&tmp, to)
+}
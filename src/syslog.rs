@@ -0,0 +1,75 @@
+use std::io;
+
+use clap::ValueEnum;
+use log::LevelFilter;
+
+/// where the logs go
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// the standard error
+    #[default]
+    Stderr,
+    /// the local syslog daemon or the systemd journal, through /dev/log
+    Syslog,
+}
+
+/// send the logs up to `level` to the local syslog daemon
+#[cfg(unix)]
+pub fn init(level: LevelFilter) -> io::Result<()> {
+    let logger = Syslog::connect(level)?;
+    log::set_boxed_logger(Box::new(logger)).map_err(io::Error::other)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn init(_level: LevelFilter) -> io::Result<()> { Err(io::Error::new(io::ErrorKind::Unsupported, "syslog is only supported on unix")) }
+
+/// sockets of the local syslog daemon : linux and the BSDs, macos
+#[cfg(unix)]
+const SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+
+/// facility of the messages, `user`
+#[cfg(unix)]
+const FACILITY: u8 = 1;
+
+/// logger sending each record as a datagram to the syslog socket, in the format of RFC 3164 without the timestamp
+/// and hostname, which the daemon adds
+#[cfg(unix)]
+struct Syslog {
+    socket: std::os::unix::net::UnixDatagram,
+    level: LevelFilter,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    fn connect(level: LevelFilter) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        match SOCKETS.iter().any(|path| socket.connect(path).is_ok()) {
+            true => Ok(Self { socket, level }),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, "no syslog socket, neither /dev/log nor /var/run/syslog")),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl log::Log for Syslog {
+    fn enabled(&self, metadata: &log::Metadata) -> bool { metadata.level() <= self.level }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let severity = match record.level() {
+            log::Level::Error => 3,
+            log::Level::Warn => 4,
+            log::Level::Info => 6,
+            log::Level::Debug | log::Level::Trace => 7,
+        };
+        let message = format!("<{}>blakediff[{}]: {}", FACILITY * 8 + severity, std::process::id(), record.args());
+        // a lost message can't be reported anywhere
+        let _ = self.socket.send(message.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
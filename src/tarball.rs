@@ -0,0 +1,141 @@
+//! Tar archives as sources of `generate` : `generate --tar archive.tar`, or `-` for a stream on the standard
+//! input (`ssh host tar cf - /data | blakediff generate --tar -`). Gzip archives are decompressed on the fly,
+//! and members are reported with their path in the archive.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::error::OnError;
+use crate::report::Entry;
+use crate::source::{Backend, Item};
+use crate::{interrupt, BlakediffError, Result};
+
+/// A tar archive, `-` being the standard input.
+pub struct Archive {
+    pub location: String,
+}
+
+impl Archive {
+    fn reader(&self) -> Result<Box<dyn Read + Send>> {
+        let reader: Box<dyn Read + Send> = match self.location.as_str() {
+            "-" => Box::new(io::stdin()),
+            path => Box::new(File::open(path)?),
+        };
+        Ok(decompress(reader)?)
+    }
+}
+
+impl Backend for Archive {
+    /// regular files and hard links, the standard input being consumed
+    fn list(&self) -> Result<Vec<Item>> {
+        let mut archive = tar::Archive::new(self.reader()?);
+        let mut items = Vec::new();
+        let mut files = HashMap::new();
+        for member in archive.entries()? {
+            let member = member?;
+            let path = member.path()?.to_string_lossy().into_owned();
+            let mut item = Item {
+                path: normalize(&path),
+                location: path,
+                size: member.header().size()?,
+                mtime: Duration::from_secs(member.header().mtime()?),
+            };
+            let entry_type = member.header().entry_type();
+            if entry_type.is_hard_link() {
+                let Some(target) = member.link_name()? else { continue };
+                let Some(file) = files.get(&normalize(&target.to_string_lossy())).map(|&file: &usize| &items[file]) else { continue };
+                item = Item { path: item.path, ..Item::clone(file) };
+            } else if !entry_type.is_file() {
+                continue;
+            }
+            files.insert(item.path.clone(), items.len());
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    /// the archive is read again up to the member
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>> {
+        let mut archive = tar::Archive::new(self.reader()?);
+        for member in archive.entries()? {
+            let mut member = member?;
+            if member.header().entry_type().is_file() && member.path()?.to_string_lossy() == item.location {
+                let mut content = Vec::new();
+                member.read_to_end(&mut content)?;
+                return Ok(Box::new(io::Cursor::new(content)));
+            }
+        }
+        Err(BlakediffError::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} : {} not found", self.location, item.path))))
+    }
+
+    /// members hashed while the archive is read, in its order
+    fn generate(&self, _parallel: bool, with_size: bool, with_mtime: bool, _on_error: Option<OnError>, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+        hash_members(self.reader()?, |path| path.to_owned(), with_size, with_mtime, write)
+    }
+}
+
+/// archive read through a gzip decoder when it starts with the gzip magic number
+pub fn decompress<'a>(mut reader: impl Read + Send + 'a) -> io::Result<Box<dyn Read + Send + 'a>> {
+    let mut magic = [0; 4];
+    let mut read = 0;
+    while read < magic.len() {
+        match reader.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    let reader = io::Cursor::new(magic[..read].to_vec()).chain(reader);
+    match magic {
+        [0x1f, 0x8b, ..] => Ok(Box::new(MultiGzDecoder::new(reader))),
+        [0x28, 0xb5, 0x2f, 0xfd] => Err(io::Error::new(io::ErrorKind::InvalidData, "zstd compressed archives are not supported")),
+        _ => Ok(Box::new(reader)),
+    }
+}
+
+/// hash the regular files and hard links of a tar archive, in its order, `path` giving their path in the report
+/// from their path in the archive
+pub fn hash_members(reader: impl Read, mut path: impl FnMut(&str) -> String, with_size: bool, with_mtime: bool, write: &mut dyn FnMut(Entry) -> Result<()>) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    // hash and size of the files met so far, for the hard links to them
+    let mut files = HashMap::new();
+    for member in archive.entries()? {
+        interrupt::check()?;
+        let mut member = member?;
+        let name = normalize(&member.path()?.to_string_lossy());
+        let mtime = Duration::from_secs(member.header().mtime()?);
+        let entry_type = member.header().entry_type();
+        let (hash, size) = if entry_type.is_hard_link() {
+            let Some(target) = member.link_name()? else { continue };
+            match files.get(&normalize(&target.to_string_lossy())) {
+                Some(file) => Clone::clone(file),
+                None => {
+                    log::warn!("{} : link to {} which is not in the archive", name, target.display());
+                    continue;
+                }
+            }
+        } else if entry_type.is_file() {
+            let size = member.header().size()?;
+            (crate::source::blake3(&mut member)?, size)
+        } else {
+            continue;
+        };
+        write(Entry {
+            hash: hash.clone(),
+            size: with_size.then_some(size),
+            mtime: with_mtime.then_some(mtime),
+            xattrs: None,
+            target: None,
+            digests: Vec::new(),
+            path: path(&name),
+        })?;
+        files.insert(name, (hash, size));
+    }
+    Ok(())
+}
+
+/// path of a member without its leading `./`
+fn normalize(path: &str) -> String { path.trim_start_matches("./").to_owned() }
@@ -0,0 +1,24 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// temporary directory removed when dropped, even on error
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("blakediff-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            log::warn!("cannot remove {} : {}", self.0.display(), e);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Optional thread pools separating I/O-bound from CPU-bound work : directory walks and file reads run
+//! in the I/O pool, the multi-threaded hashing of large mapped files in the hash pool. Unless
+//! configured, work runs in the current rayon pool. The hashing workers of `generate`, and the rayon
+//! workers of `analyze` and `dedupe` scans, hash in the hash pool too.
+
+use std::sync::OnceLock;
+use std::thread;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::error::{BlakediffError, Result};
+
+static IO: OnceLock<ThreadPool> = OnceLock::new();
+static HASH: OnceLock<ThreadPool> = OnceLock::new();
+
+/// build the pools with the given number of threads, only once and before any work
+pub fn configure(io_threads: Option<usize>, hash_threads: Option<usize>) -> Result<()> {
+    let build = |pool: &OnceLock<ThreadPool>, threads: usize, name: &'static str| {
+        let built = ThreadPoolBuilder::new().num_threads(threads).thread_name(move |i| format!("blakediff-{}-{}", name, i)).build().map_err(|e| BlakediffError::Other(e.to_string()))?;
+        pool.set(built).map_err(|_| BlakediffError::Other(format!("{} pool already configured", name)))
+    };
+    if let Some(threads) = io_threads {
+        build(&IO, threads, "io")?;
+    }
+    if let Some(threads) = hash_threads {
+        build(&HASH, threads, "hash")?;
+    }
+    Ok(())
+}
+
+/// run `op` in the I/O pool
+pub fn io<R: Send>(op: impl FnOnce() -> R + Send) -> R { install(&IO, op) }
+
+/// run `op` in the hash pool. A rayon worker waiting on another pool steals more work of its own meanwhile,
+/// which would nest hashes up to a stack overflow : the workers of the I/O pool, or of the global one, wait on
+/// a thread of their own instead, which only blocks.
+pub fn hash<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    match HASH.get() {
+        Some(pool) if pool.current_thread_index().is_none() && rayon::current_thread_index().is_some() => {
+            thread::scope(|scope| scope.spawn(|| pool.install(op)).join()).unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        }
+        Some(pool) if pool.current_thread_index().is_none() => pool.install(op),
+        _ => op(),
+    }
+}
+
+/// number of threads reading files
+pub fn io_threads() -> usize { IO.get().map_or_else(rayon::current_num_threads, ThreadPool::current_num_threads) }
+
+fn install<R: Send>(pool: &OnceLock<ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool.get() {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn io_workers_hash_in_the_hash_pool() {
+        configure(Some(4), Some(3)).unwrap();
+        let pools = io(|| {
+            (0..64)
+                .into_par_iter()
+                .map(|_| hash(|| (thread::current().name().map(str::to_owned), rayon::current_num_threads(), hash(rayon::current_num_threads))))
+                .collect::<Vec<_>>()
+        });
+        for (name, threads, nested) in pools {
+            assert!(name.is_some_and(|name| name.starts_with("blakediff-hash-")));
+            assert_eq!((threads, nested), (3, 3));
+        }
+        assert_eq!(hash(rayon::current_num_threads), 3);
+    }
+}
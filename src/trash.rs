@@ -0,0 +1,127 @@
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// move `path` to the trash of the desktop instead of deleting it : the freedesktop.org trash of the home directory,
+/// or the one at the top of the mount point of the file when it is on another filesystem
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    use std::io::Write;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let path = absolute(path)?;
+    let device = fs::symlink_metadata(&path)?.dev();
+    let home = match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(data) => PathBuf::from(data).join("Trash"),
+        None => home()?.join(".local/share/Trash"),
+    };
+    let home_device = home.ancestors().find_map(|dir| fs::metadata(dir).ok()).map(|metadata| metadata.dev());
+    // the path recorded in the trash is relative to the top directory of the volume for its own trash
+    let (trash, recorded) = if home_device == Some(device) {
+        (home, path.clone())
+    } else {
+        let top = path.ancestors().skip(1).take_while(|dir| fs::metadata(dir).is_ok_and(|metadata| metadata.dev() == device)).last().unwrap_or(Path::new("/")).to_owned();
+        let uid = unsafe { libc::getuid() };
+        let shared = top.join(".Trash");
+        // the shared trash is only trusted when it is a sticky directory, not a symbolic link
+        let trash = match fs::symlink_metadata(&shared) {
+            Ok(metadata) if metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0 => shared.join(uid.to_string()),
+            _ => top.join(format!(".Trash-{}", uid)),
+        };
+        (trash, path.strip_prefix(&top).unwrap_or(&path).to_owned())
+    };
+    if !trash.exists() {
+        fs::create_dir_all(&trash)?;
+        fs::set_permissions(&trash, fs::Permissions::from_mode(0o700))?;
+    }
+    fs::create_dir_all(trash.join("files"))?;
+    fs::create_dir_all(trash.join("info"))?;
+
+    // the info file is created first, reserving the name in the trash
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let (mut info, info_path, trashed) = (1..)
+        .map(|n| if n == 1 { name.clone() } else { format!("{}.{}", name, n) })
+        .find_map(|name| {
+            let info_path = trash.join("info").join(format!("{}.trashinfo", name));
+            match fs::File::options().write(true).create_new(true).open(&info_path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => None,
+                info => Some(info.map(|info| (info, info_path, trash.join("files").join(&name)))),
+            }
+        })
+        .expect("names are endless")?;
+    write!(info, "[Trash Info]\nPath={}\nDeletionDate={}\n", encode(&recorded.to_string_lossy()), now())
+        .and_then(|_| fs::rename(&path, &trashed))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&info_path);
+        })?;
+    Ok(trashed)
+}
+
+/// move `path` to the trash of the user, renamed when the trash already holds a file with its name
+#[cfg(target_os = "macos")]
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    let trash = home()?.join(".Trash");
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let trashed = (1..).map(|n| if n == 1 { trash.join(&name) } else { trash.join(format!("{} {}", name, n)) }).find(|trashed| fs::symlink_metadata(trashed).is_err()).expect("names are endless");
+    fs::rename(path, &trashed)?;
+    Ok(trashed)
+}
+
+/// absolute path of `path` with its parent directories resolved, but not its last component : a symbolic link
+/// stays the link itself instead of the file it points to
+pub fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} : no file name", path.display())))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Ok(std::fs::canonicalize(parent)?.join(name))
+}
+
+#[cfg(not(unix))]
+pub fn trash(_path: &Path) -> io::Result<PathBuf> { Err(io::Error::new(io::ErrorKind::Unsupported, "the trash is only supported on linux, the BSDs and macos")) }
+
+#[cfg(unix)]
+fn home() -> io::Result<PathBuf> { std::env::var_os("HOME").filter(|home| !home.is_empty()).map(PathBuf::from).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set, no trash")) }
+
+/// local time of the deletion, as `YYYY-MM-DDThh:mm:ss`
+#[cfg(all(unix, not(target_os = "macos")))]
+fn now() -> String {
+    let time = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+/// percent-encoding of a path, as in URLs
+#[cfg(all(unix, not(target_os = "macos")))]
+fn encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+
+    #[test]
+    fn links_are_trashed_not_their_target() {
+        let tmp = TempDir::new("trash").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        std::env::set_var("XDG_DATA_HOME", dir.join("share"));
+        fs::write(dir.join("data/target.txt"), b"kept").unwrap();
+        std::os::unix::fs::symlink("target.txt", dir.join("data/link.txt")).unwrap();
+
+        assert_eq!(absolute(&dir.join("data/../data/link.txt")).unwrap(), fs::canonicalize(dir).unwrap().join("data/link.txt"));
+        let trashed = trash(&dir.join("data/link.txt")).unwrap();
+        assert!(fs::symlink_metadata(&trashed).unwrap().file_type().is_symlink());
+        assert!(fs::symlink_metadata(dir.join("data/link.txt")).is_err());
+        assert_eq!(fs::read(dir.join("data/target.txt")).unwrap(), b"kept");
+        let info = fs::read_to_string(dir.join("share/Trash/info/link.txt.trashinfo")).unwrap();
+        assert!(info.contains("/data/link.txt\n"), "{}", info);
+    }
+}
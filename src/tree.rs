@@ -0,0 +1,115 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use blakediff::input::Input;
+use blakediff::{walker, BlakediffError, Result};
+
+struct Node {
+    name: String,
+    hash: String,
+    /// target of symbolic links, None for the other files
+    target: Option<String>,
+    /// None for files
+    children: Option<Vec<Node>>,
+}
+
+/// print the tree of `dir` with the hash of each file and a rollup hash for each directory,
+/// computed from the names and hashes of its entries: two directories with the same rollup hash have the same content.
+pub fn tree(dir: String, depth: Option<usize>) -> Result<()> {
+    let path = Path::new(&dir);
+    let root = hash_node(path, &fs::metadata(path)?, dir.clone())?.ok_or_else(|| BlakediffError::Format(format!("{} : not a file nor a directory", dir)))?;
+    print_node(&root, 0, depth);
+    Ok(())
+}
+
+/// node of the file at `path`, None for the special files left out like `generate` does. Symbolic links under the
+/// root are not followed, which would loop on a link to an ancestor : the path they point to is hashed instead, as
+/// with `generate --symlinks hash-target`.
+fn hash_node(path: &Path, metadata: &fs::Metadata, name: String) -> io::Result<Option<Node>> {
+    if metadata.file_type().is_symlink() {
+        let entry = blakediff::hash_link(path, metadata, false, false)?;
+        return Ok(Some(Node {
+            name,
+            hash: entry.hash,
+            target: entry.target,
+            children: None,
+        }));
+    }
+    // FIFOs, sockets and devices may block forever when read
+    if let Some(kind) = walker::special(metadata) {
+        log::info!("{} : {}, skipped", path.display(), kind);
+        return Ok(None);
+    }
+    if !metadata.is_dir() {
+        return Ok(Some(Node {
+            name,
+            hash: Input::open_with_metadata(path, metadata)?.hash()?,
+            target: None,
+            children: None,
+        }));
+    }
+    let mut children = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+        children.extend(hash_node(&path, &fs::symlink_metadata(&path)?, entry.file_name().to_string_lossy().into_owned())?);
+    }
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut hasher = blake3::Hasher::new();
+    for child in &children {
+        hasher.update(match (&child.children, &child.target) {
+            (Some(_), _) => b"d ",
+            (None, Some(_)) => b"l ",
+            (None, None) => b"f ",
+        });
+        hasher.update(child.hash.as_bytes());
+        hasher.update(b" ");
+        hasher.update(child.name.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(Some(Node {
+        name,
+        hash: hasher.finalize().to_string(),
+        target: None,
+        children: Some(children),
+    }))
+}
+
+fn print_node(node: &Node, level: usize, depth: Option<usize>) {
+    let suffix = match (&node.children, &node.target) {
+        (Some(_), _) => String::from("/"),
+        (None, Some(target)) => format!(" -> {}", target),
+        (None, None) => String::new(),
+    };
+    println!("{}{} {}{}", "  ".repeat(level), node.hash, node.name, suffix);
+    if depth.is_none_or(|depth| level < depth) {
+        node.children.iter().flatten().for_each(|child| print_node(child, level + 1, depth));
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+
+    #[test]
+    fn links_are_not_followed_nor_special_files_read() {
+        let tmp = TempDir::new("tree").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("a/file"), b"content").unwrap();
+        std::os::unix::fs::symlink("..", dir.join("a/up")).unwrap();
+        let fifo = std::ffi::CString::new(dir.join("a/fifo").into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let root = hash_node(dir, &fs::metadata(dir).unwrap(), String::from("root")).unwrap().unwrap();
+        let a = &root.children.as_ref().unwrap()[0];
+        let children = a.children.as_ref().unwrap();
+        assert_eq!(children.iter().map(|child| child.name.as_str()).collect::<Vec<_>>(), ["file", "up"]);
+        assert_eq!(children[0].hash, blake3::hash(b"content").to_string());
+        assert_eq!(children[1].target.as_deref(), Some(".."));
+        assert!(children[1].children.is_none());
+    }
+}
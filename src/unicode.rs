@@ -0,0 +1,29 @@
+//! Unicode normalization of the paths written in reports : macOS writes file names decomposed (NFD) where linux
+//! keeps them as created, most often composed (NFC), so the same synced tree gets different paths on each side.
+
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// composed, as most linux and windows tools write names
+    Nfc,
+    /// decomposed, as macOS filesystems return names
+    Nfd,
+}
+
+impl Normalization {
+    /// `path` in this form, borrowed when it already is
+    #[cfg(feature = "normalize")]
+    pub fn normalize(self, path: &str) -> Cow<'_, str> {
+        match self {
+            Self::Nfc => icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(path),
+            Self::Nfd => icu_normalizer::DecomposingNormalizerBorrowed::new_nfd().normalize(path),
+        }
+    }
+
+    /// `path` as it is, blakediff being built without the normalize feature
+    #[cfg(not(feature = "normalize"))]
+    pub fn normalize(self, path: &str) -> Cow<'_, str> { Cow::Borrowed(path) }
+}
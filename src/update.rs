@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use itertools::Itertools;
+
+use blakediff::report::{self, Entry, Field};
+use blakediff::walker::{FileEntry, Symlinks, Walker};
+use blakediff::{xattrs, Result};
+
+enum Status {
+    Unchanged,
+    Updated,
+    Added,
+}
+
+/// outcome of a refresh, with the paths of rehashed files
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub unchanged: usize,
+    pub updated: Vec<String>,
+    pub added: Vec<String>,
+    /// entries of files which no longer exist, left out with `--prune-missing`
+    pub removed: Vec<String>,
+    /// bytes read to hash the updated and added files
+    pub bytes_hashed: u64,
+    /// files of the refreshed report
+    pub files: usize,
+    /// groups of files sharing their content in the refreshed report, and the extra copies in them
+    pub duplicate_groups: usize,
+    pub duplicate_files: usize,
+}
+
+/// refresh `report_file` with the files of `dir`, rehashing only those whose size or mtime changed, and dropping the
+/// entries of the files which no longer exist with `prune_missing`
+pub fn update(report_file: String, dir: String, parallel: bool, prune_missing: bool) -> Result<()> {
+    let summary = refresh(Path::new(&report_file), Path::new(&dir), parallel, prune_missing)?;
+    match prune_missing {
+        true => summary!("update : {} unchanged, {} updated, {} added, {} removed", summary.unchanged, summary.updated.len(), summary.added.len(), summary.removed.len()),
+        false => summary!("update : {} unchanged, {} updated, {} added", summary.unchanged, summary.updated.len(), summary.added.len()),
+    }
+    Ok(())
+}
+
+/// rewrite `report_path` with the files of `dir`, rehashing only those whose size or mtime changed.
+/// A missing report is created. With `prune_missing`, the entries of files which no longer exist are left out.
+/// The columns of the report are kept, with its size and mtime added : the rehashed files get their other digests,
+/// extended attributes and link targets again.
+pub fn refresh(report_path: &Path, dir: &Path, parallel: bool, prune_missing: bool) -> Result<Summary> {
+    let mut entries: HashMap<String, Entry> = HashMap::new();
+    let mut fields = vec![Field::Hash, Field::Path];
+    if report_path.exists() {
+        let mut reader = report::Reader::open(report_path)?;
+        for entry in reader.by_ref() {
+            let entry = entry?;
+            entries.insert(entry.path.clone(), entry);
+        }
+        fields = reader.fields().to_vec();
+    }
+    let digests = fields.iter().filter_map(|field| if let Field::Digest(algorithm) = field { Some(*algorithm) } else { None }).collect::<Vec<_>>();
+    let (with_xattrs, with_targets) = (fields.contains(&Field::Xattrs), fields.contains(&Field::Target));
+    let metadata = [Some(Field::Size), Some(Field::Mtime), with_xattrs.then_some(Field::Xattrs), Some(Field::Path), with_targets.then_some(Field::Target)];
+    let fields = std::iter::once(Field::Hash).chain(digests.iter().map(|&algorithm| Field::Digest(algorithm))).chain(metadata.into_iter().flatten()).collect::<Vec<_>>();
+    let hash = |file: &FileEntry| -> Result<Entry> {
+        let mut entry = blakediff::hash_file_digests(file, true, true, &digests)?;
+        if with_xattrs {
+            entry.xattrs = Some(xattrs::digest(&file.path)?);
+        }
+        Ok(entry)
+    };
+
+    // the report may live in the scanned directory, it must not end up listed in itself
+    let report_canonical = fs::canonicalize(report_path).ok();
+    let is_report = |path: &Path| path.file_name() == report_path.file_name() && fs::canonicalize(path).ok() == report_canonical;
+
+    let refreshed = Mutex::new(Vec::new());
+    // links recorded with their target are rehashed as links, not followed
+    let symlinks = if with_targets { Symlinks::HashTarget } else { Symlinks::Follow };
+    Walker::new(dir).parallel(parallel).symlinks(symlinks).for_each(|file| {
+        if is_report(&file.path) {
+            return Ok(());
+        }
+        let (size, mtime) = (file.metadata.len(), report::mtime(&file.metadata));
+        let refresh = match entries.get(file.path.to_string_lossy().as_ref()) {
+            Some(e) if e.size == Some(size) && e.mtime == Some(mtime) => (Status::Unchanged, e.clone()),
+            Some(_) => (Status::Updated, hash(&file)?),
+            None => (Status::Added, hash(&file)?),
+        };
+        refreshed.lock().unwrap().push(refresh);
+        Ok(())
+    })?;
+
+    let mut summary = Summary::default();
+    let mut seen = HashSet::new();
+    for (status, entry) in refreshed.into_inner().unwrap() {
+        match status {
+            Status::Unchanged => summary.unchanged += 1,
+            Status::Updated => summary.updated.push(entry.path.clone()),
+            Status::Added => summary.added.push(entry.path.clone()),
+        }
+        if !matches!(status, Status::Unchanged) {
+            summary.bytes_hashed += entry.size.unwrap_or(0);
+        }
+        seen.insert(entry.path.clone());
+        entries.insert(entry.path.clone(), entry);
+    }
+    if prune_missing {
+        // files left out of the walk, by an exclusion or a skipped link, are kept as long as they exist
+        summary.removed = entries.keys().filter(|path| !seen.contains(*path) && fs::symlink_metadata(path).is_err_and(|e| e.kind() == io::ErrorKind::NotFound)).cloned().sorted().collect();
+        summary.removed.iter().for_each(|path| _ = entries.remove(path));
+    }
+    summary.files = entries.len();
+    for copies in entries.values().counts_by(|e| &e.hash).into_values().filter(|&copies| copies > 1) {
+        summary.duplicate_groups += 1;
+        summary.duplicate_files += copies - 1;
+    }
+    report::save(report_path, &fields, entries.values().sorted_by(|a, b| a.path.cmp(&b.path)))?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+    use blakediff::input::Algorithm;
+
+    #[test]
+    fn the_columns_of_the_report_are_kept() {
+        let tmp = TempDir::new("update").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("files")).unwrap();
+        fs::write(dir.join("files/a"), "a").unwrap();
+        fs::write(dir.join("files/b"), "b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a", dir.join("files/link")).unwrap();
+        let report_file = dir.join("report.txt");
+        report::save(&report_file, &[Field::Hash, Field::Digest(Algorithm::Sha256), Field::Path, Field::Target], []).unwrap();
+        let sha256 = |content: &str| format!("{:x}", <sha2::Sha256 as sha2::Digest>::digest(content));
+        let load = || {
+            let mut reader = report::Reader::open(&report_file).unwrap();
+            let entries = reader.by_ref().map(Result::unwrap).map(|e| (e.path.rsplit('/').next().unwrap().to_owned(), e)).collect::<HashMap<_, _>>();
+            (reader.fields().to_vec(), entries)
+        };
+
+        let summary = refresh(&report_file, &dir.join("files"), false, false).unwrap();
+        assert_eq!(summary.added.len(), if cfg!(unix) { 3 } else { 2 });
+        let (fields, entries) = load();
+        assert_eq!(fields, [Field::Hash, Field::Digest(Algorithm::Sha256), Field::Size, Field::Mtime, Field::Path, Field::Target]);
+        assert_eq!(entries["a"].digest(Algorithm::Sha256), Some(sha256("a").as_str()));
+        assert_eq!(entries["b"].digest(Algorithm::Sha256), Some(sha256("b").as_str()));
+        #[cfg(unix)]
+        assert_eq!(entries["link"].target.as_deref(), Some("a"));
+
+        // the rehashed file gets its other digest again, the unchanged ones keep theirs
+        fs::write(dir.join("files/b"), "edited").unwrap();
+        let summary = refresh(&report_file, &dir.join("files"), true, false).unwrap();
+        assert_eq!(summary.updated.len(), 1);
+        let (fields, entries) = load();
+        assert_eq!(fields, [Field::Hash, Field::Digest(Algorithm::Sha256), Field::Size, Field::Mtime, Field::Path, Field::Target]);
+        assert_eq!(entries["a"].digest(Algorithm::Sha256), Some(sha256("a").as_str()));
+        assert_eq!(entries["b"].digest(Algorithm::Sha256), Some(sha256("edited").as_str()));
+        #[cfg(unix)]
+        assert_eq!(entries["link"].target.as_deref(), Some("a"));
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use blakediff::input::{self, Input};
+use blakediff::output::quoted;
+use blakediff::report::{self, Entry};
+use blakediff::{pipeline, threads, walker};
+use blakediff::{BlakediffError, Result};
+
+use crate::pager;
+use crate::progress::Progress;
+use crate::VerifyOptions;
+
+/// result of the check of a file
+enum Outcome {
+    Ok,
+    /// its size or modification time differ from the recorded ones, with `--quick`
+    Changed,
+    /// another content, or an error while reading it
+    Failed(Option<io::Error>),
+    Missing,
+}
+
+/// rehash every file listed in the report and check it against its recorded hash, on as many threads as the I/O pool with
+/// `parallel`, printing the files found `OK` unless `quiet_ok`. With `quick`, files are checked against their recorded size and modification time instead, and only
+/// rehashed with `rehash` when these didn't change. The report is read as its files are checked, never whole, and
+/// they are printed in its order, see [`pipeline::ordered`].
+pub fn verify(options: VerifyOptions) -> Result<crate::Outcome> {
+    let open = || report::Reader::open(Path::new(&options.report_file));
+    // drawn when the results go to a file or a pipe, it would be mixed with them on a terminal. Its totals come
+    // from a first reading of the report.
+    let mut progress = match !pager::active() && !io::stdout().is_terminal() {
+        true => {
+            let (files, bytes) = open()?.try_fold((0usize, 0u64), |(files, bytes), entry| entry.map(|entry| (files + 1, bytes + entry.size.unwrap_or(0))))?;
+            Some(Progress::new(files, bytes))
+        }
+        false => None,
+    };
+    let workers = match options.parallel && !walker::hdd() {
+        true => threads::io_threads(),
+        false => 1,
+    };
+    let (mut ok, mut failed, mut missing, mut changed) = (0usize, 0usize, 0usize, 0usize);
+    pipeline::ordered(open()?, workers, |entry| check(&entry, &resolve(options.root.as_deref(), &entry.path), &options).map(|outcome| Some((entry, outcome))), |(entry, outcome)| {
+        match outcome {
+            Outcome::Ok => {
+                ok += 1;
+                if !options.quiet_ok {
+                    println!("OK : {}", quoted(&entry.path));
+                }
+            }
+            Outcome::Changed => {
+                changed += 1;
+                println!("CHANGED : {}", quoted(&entry.path));
+            }
+            Outcome::Failed(None) => {
+                failed += 1;
+                println!("FAILED : {}", quoted(&entry.path));
+            }
+            Outcome::Failed(Some(e)) => {
+                failed += 1;
+                println!("FAILED : {} ({})", quoted(&entry.path), e);
+            }
+            Outcome::Missing => {
+                missing += 1;
+                println!("MISSING : {}", quoted(&entry.path));
+            }
+        }
+        if let Some(progress) = &mut progress {
+            progress.add(entry.size.unwrap_or(0));
+        }
+        Ok(())
+    })?;
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    match options.quick {
+        true => summary!("verify : {} OK, {} CHANGED, {} FAILED, {} MISSING", ok, changed, failed, missing),
+        false => summary!("verify : {} OK, {} FAILED, {} MISSING", ok, failed, missing),
+    }
+    Ok(crate::Outcome::found(failed + missing + changed > 0))
+}
+
+/// check the file of `entry` at `path`
+fn check(entry: &Entry, path: &Path, options: &VerifyOptions) -> Result<Outcome> {
+    if options.quick && entry.target.is_none() && !entry.is_special() {
+        if entry.size.is_none() && entry.mtime.is_none() {
+            return Err(BlakediffError::Format(String::from("--quick needs a report of `generate --with-size` or `--with-mtime`")));
+        }
+        // a file which can't be read is reported below, like without --quick
+        match fs::metadata(path) {
+            Ok(metadata) if entry.size.is_some_and(|size| size != metadata.len()) || entry.mtime.is_some_and(|mtime| mtime != report::mtime(&metadata)) => return Ok(Outcome::Changed),
+            Ok(_) if !options.rehash => return Ok(Outcome::Ok),
+            _ => {}
+        }
+    }
+    let hash = match entry.target {
+        // recorded as a special file, only its type is checked
+        _ if entry.is_special() => fs::metadata(path).map(|metadata| walker::special(&metadata).unwrap_or("file").to_owned()),
+        // recorded as a link, its target is checked
+        Some(_) => fs::symlink_metadata(path).and_then(|metadata| blakediff::hash_link(path, &metadata, false, false)).map(|link| link.hash),
+        None => input::retry(path, || Input::open(path)?.hash()),
+    };
+    Ok(match hash {
+        Ok(hash) if entry.matches(&hash) => Outcome::Ok,
+        Ok(_) => Outcome::Failed(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Outcome::Missing,
+        Err(e) => Outcome::Failed(Some(e)),
+    })
+}
+
+/// path of a report entry on disk, relative paths being taken from `root` when given
+pub fn resolve(root: Option<&str>, path: &str) -> PathBuf {
+    match root {
+        Some(root) => Path::new(root).join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blakediff::tempdir::TempDir;
+    use blakediff::report::Field;
+
+    #[test]
+    fn reports_are_checked() {
+        let tmp = TempDir::new("verify").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("files")).unwrap();
+        let entries = (0..515)
+            .map(|i| {
+                let content = format!("file {}", i % 7);
+                fs::write(dir.join("files").join(i.to_string()), &content).unwrap();
+                Entry {
+                    hash: blake3::hash(content.as_bytes()).to_string(),
+                    size: Some(content.len() as u64),
+                    mtime: None,
+                    xattrs: None,
+                    path: format!("files/{}", i),
+                    target: None,
+                    digests: Vec::new(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let report_file = dir.join("report.txt");
+        report::save(&report_file, &[Field::Hash, Field::Size, Field::Path], &entries).unwrap();
+        let options = VerifyOptions {
+            report_file: report_file.to_string_lossy().into_owned(),
+            root: Some(dir.to_string_lossy().into_owned()),
+            quick: false,
+            rehash: false,
+            parallel: false,
+            quiet_ok: true,
+        };
+        for parallel in [false, true] {
+            for quick in [false, true] {
+                assert_eq!(verify(VerifyOptions { parallel, quick, ..options.clone() }).unwrap(), crate::Outcome::Clean);
+            }
+        }
+
+        let check = |entry: &Entry| check(entry, &resolve(options.root.as_deref(), &entry.path), &options).unwrap();
+        fs::write(dir.join("files/0"), "edited").unwrap();
+        fs::remove_file(dir.join("files/1")).unwrap();
+        assert!(matches!(check(&entries[0]), Outcome::Failed(None)));
+        assert!(matches!(check(&entries[1]), Outcome::Missing));
+        assert!(matches!(check(&entries[2]), Outcome::Ok));
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::Path;
+use std::process::Command;
+
+use blakediff::{BlakediffError, Result};
+
+/// A shadow copy of the volume of a directory, deleted when dropped. It is created through the `Win32_ShadowCopy`
+/// WMI class, which needs administrator rights.
+pub struct Shadow {
+    id: String,
+    /// the directory, as given
+    dir: String,
+    /// the same directory in the shadow copy
+    root: String,
+}
+
+impl Shadow {
+    /// snapshot the volume holding `dir`
+    pub fn create(dir: &str) -> Result<Self> {
+        if !cfg!(windows) {
+            return Err(BlakediffError::Format(String::from("--vss is only supported on windows")));
+        }
+        let absolute = std::fs::canonicalize(Path::new(dir))?.to_string_lossy().into_owned();
+        let absolute = absolute.strip_prefix(r"\\?\").unwrap_or(&absolute);
+        // `C:` and `\Users\me`
+        let Some((volume, relative)) = absolute.split_once(':').filter(|(volume, _)| volume.len() == 1) else {
+            return Err(BlakediffError::Format(format!("{} : shadow copies are only made of local volumes with a drive letter", dir)));
+        };
+        let script = format!(
+            "$ErrorActionPreference = 'Stop'; \
+             $created = (Get-WmiObject -List Win32_ShadowCopy).Create('{}:\\', 'ClientAccessible'); \
+             if ($created.ReturnValue -ne 0) {{ Write-Error \"Win32_ShadowCopy.Create returned $($created.ReturnValue)\" }}; \
+             $shadow = Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq $created.ShadowID }}; \
+             Write-Output $shadow.ID $shadow.DeviceObject",
+            volume
+        );
+        let output = powershell(&script).map_err(|e| BlakediffError::Other(format!("shadow copy of {}: failed, administrator rights are needed : {}", volume, e)))?;
+        let mut lines = output.lines().map(str::trim).filter(|line| !line.is_empty());
+        let (Some(id), Some(device)) = (lines.next(), lines.next()) else {
+            return Err(BlakediffError::Other(format!("shadow copy of {}: unexpected answer '{}'", volume, output.trim())));
+        };
+        log::info!("shadow copy {} of {}: at {}", id, volume, device);
+        Ok(Self {
+            id: id.to_owned(),
+            dir: dir.trim_end_matches(['\\', '/']).to_owned(),
+            root: format!("{}{}", device, relative.trim_end_matches('\\')),
+        })
+    }
+
+    /// path of the directory in the shadow copy
+    pub fn root(&self) -> &str { &self.root }
+
+    /// path of a file of the shadow copy under the original directory
+    pub fn original(&self, path: &str) -> String {
+        match path.strip_prefix(&self.root) {
+            Some(rest) => format!("{}{}", self.dir, rest),
+            None => path.to_owned(),
+        }
+    }
+}
+
+impl Drop for Shadow {
+    fn drop(&mut self) {
+        let script = format!("Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq '{}' }} | ForEach-Object {{ $_.Delete() }}", self.id);
+        match powershell(&script) {
+            Ok(_) => log::info!("shadow copy {} deleted", self.id),
+            Err(e) => log::warn!("cannot delete the shadow copy {}, `vssadmin delete shadows /Shadow={}` removes it : {}", self.id, self.id, e),
+        }
+    }
+}
+
+/// standard output of a PowerShell script, its standard error on failure
+fn powershell(script: &str) -> std::result::Result<String, String> {
+    let output = Command::new("powershell.exe").args(["-NoProfile", "-NonInteractive", "-Command", script]).output().map_err(|e| e.to_string())?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_owned()),
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+use crate::error::{BlakediffError, OnError, Result};
+use crate::exclude::Excludes;
+
+/// A file found by a [`Walker`].
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    /// metadata of the file, symbolic links being followed unless the walker doesn't
+    pub metadata: fs::Metadata,
+    /// the path itself is a symbolic link
+    pub symlink: bool,
+    /// 1 for the files directly in the root, 0 when the root is the file itself
+    pub depth: usize,
+}
+
+type Filter = Box<dyn Fn(&FileEntry) -> bool + Send + Sync>;
+
+static HDD: AtomicBool = AtomicBool::new(false);
+
+/// spinning disk mode for the whole process : directories are walked sequentially, their entries in inode
+/// order, which follows the physical layout on most filesystems, and files are read by a single thread
+pub fn set_hdd(enabled: bool) { HDD.store(enabled, Ordering::Relaxed) }
+
+pub fn hdd() -> bool { HDD.load(Ordering::Relaxed) }
+
+/// what to do with the symbolic links met while walking a tree
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    /// hash the files they point to and walk the directories
+    #[default]
+    Follow,
+    /// leave them out
+    Skip,
+    /// record the links themselves, hashing the path they point to, so that a retargeted link is noticed
+    HashTarget,
+}
+
+/// what to do with the special files met while walking a tree : FIFOs, sockets and devices, which can't be
+/// read like files and may block forever when opened
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Special {
+    /// leave them out
+    #[default]
+    Skip,
+    /// record them with their type instead of a hash, see [`SPECIAL`](crate::report::SPECIAL)
+    Record,
+    /// stop the walk on the first one
+    Fail,
+}
+
+/// type of a special file, one of [`SPECIAL`](crate::report::SPECIAL), or `None` for the directories, the regular files and the
+/// symbolic links
+#[cfg(unix)]
+pub fn special(metadata: &fs::Metadata) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = metadata.file_type();
+    match () {
+        _ if file_type.is_fifo() => Some("fifo"),
+        _ if file_type.is_socket() => Some("socket"),
+        _ if file_type.is_char_device() => Some("char-device"),
+        _ if file_type.is_block_device() => Some("block-device"),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn special(_metadata: &fs::Metadata) -> Option<&'static str> { None }
+
+/// Walk the files of a directory tree through a callback, an iterator or a channel. Symbolic links are followed,
+/// see [`Walker::symlinks`], and special files skipped, see [`Walker::special`].
+pub struct Walker {
+    root: PathBuf,
+    parallel: bool,
+    follow: bool,
+    special: Special,
+    max_depth: Option<usize>,
+    filters: Vec<Filter>,
+    /// canonical root, once a followed link needs it
+    real_root: OnceLock<PathBuf>,
+    /// canonical targets of the directory links followed so far
+    linked: Mutex<HashSet<PathBuf>>,
+    on_error: Option<OnError>,
+}
+
+impl Walker {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+            parallel: false,
+            follow: true,
+            special: Special::Skip,
+            max_depth: None,
+            filters: Vec::new(),
+            real_root: OnceLock::new(),
+            linked: Mutex::new(HashSet::new()),
+            on_error: None,
+        }
+    }
+
+    /// walk directories with several threads, only used by `for_each` and `channel`, and never in [`hdd`] mode
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// follow the symbolic links under the root, or walk them as files with the metadata of the links themselves
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// walk the symbolic links as `symlinks` tells, see [`Symlinks`]
+    pub fn symlinks(self, symlinks: Symlinks) -> Self {
+        match symlinks {
+            Symlinks::Follow => self,
+            // not even followed to read their metadata, which fails on a broken link
+            Symlinks::Skip => self.follow(false).filter(|entry| !entry.symlink),
+            Symlinks::HashTarget => self.follow(false),
+        }
+    }
+
+    /// walk the special files as `special` tells, see [`Special`]
+    pub fn special(mut self, special: Special) -> Self {
+        self.special = special;
+        self
+    }
+
+    /// leave out the files and directories `excludes` matches, by their path relative to the root
+    pub fn exclude(self, excludes: Excludes) -> Self {
+        if excludes.is_empty() {
+            return self;
+        }
+        let root = self.root.clone();
+        self.filter(move |entry| !excludes.excludes(entry.path.strip_prefix(&root).unwrap_or(&entry.path), entry.metadata.is_dir()))
+    }
+
+    /// don't go deeper than `max_depth` levels below the root
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// hand the files and directories under the root which can't be read to `on_error` and walk the others,
+    /// instead of stopping at the first one. Only used by `for_each` and `channel`.
+    pub fn skip_errors(mut self, on_error: Option<OnError>) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// only walk the entries for which `filter` is true. It is also called on directories,
+    /// so that a whole subtree can be skipped.
+    pub fn filter(mut self, filter: impl Fn(&FileEntry) -> bool + Send + Sync + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    fn accept(&self, entry: &FileEntry) -> Result<bool> {
+        let walked = self.filters.iter().all(|filter| filter(entry)) && self.max_depth.is_none_or(|max| entry.depth <= max);
+        if walked && entry.depth > 0 && entry.symlink && entry.metadata.is_dir() && !self.first_link(entry) {
+            return Ok(false);
+        }
+        match special(&entry.metadata).filter(|_| walked) {
+            None => Ok(walked),
+            Some(_) if self.special == Special::Record => Ok(true),
+            Some(_) if self.special == Special::Skip => Ok(false),
+            Some(kind) => Err(walk_error(&entry.path, io::Error::new(io::ErrorKind::Unsupported, format!("{}, not a regular file", kind)))),
+        }
+    }
+
+    /// a followed link to a directory is only walked when it leads out of the tree, to a directory no other link led
+    /// to, which breaks the loops and doesn't count the same files twice. On windows, junctions and symbolic link
+    /// reparse points are links too.
+    fn first_link(&self, entry: &FileEntry) -> bool {
+        // an error is left to the walk of the link
+        let Ok(target) = fs::canonicalize(&entry.path) else { return true };
+        let root = self.real_root.get_or_init(|| fs::canonicalize(&self.root).unwrap_or_else(|_| self.root.clone()));
+        let first = !target.starts_with(root) && !root.starts_with(&target) && self.linked.lock().unwrap().insert(target.clone());
+        if !first {
+            log::warn!("{} : link to {}, already walked, skipped", entry.path.display(), target.display());
+        }
+        first
+    }
+
+    fn entry(path: PathBuf, depth: usize) -> Result<FileEntry> {
+        let symlink = fs::symlink_metadata(&path).map_err(|source| walk_error(&path, source))?.file_type().is_symlink();
+        let metadata = fs::metadata(&path).map_err(|source| walk_error(&path, source))?;
+        Ok(FileEntry { path, metadata, symlink, depth })
+    }
+
+    /// one stat per entry : the type comes with the directory listing, and only symbolic links are followed by path
+    fn dir_entry(entry: fs::DirEntry, depth: usize, follow: bool) -> Result<FileEntry> {
+        let path = entry.path();
+        let symlink = entry.file_type().map_err(|source| walk_error(&path, source))?.is_symlink();
+        let metadata = if symlink && follow { fs::metadata(&path) } else { entry.metadata() }.map_err(|source| walk_error(&path, source))?;
+        Ok(FileEntry { path, metadata, symlink, depth })
+    }
+
+    /// call `cb` on every file, stopping at the first error
+    pub fn for_each<F>(&self, cb: F) -> Result<()>
+    where
+        F: Fn(FileEntry) -> Result<()> + Sync,
+    {
+        let root = Self::entry(self.root.clone(), 0)?;
+        if root.metadata.is_dir() {
+            self.visit(&root, &cb)
+        } else if self.accept(&root)? {
+            cb(root)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn visit<F>(&self, dir: &FileEntry, cb: &F) -> Result<()>
+    where
+        F: Fn(FileEntry) -> Result<()> + Sync,
+    {
+        let parcours = |entry: io::Result<fs::DirEntry>| -> Result<()> {
+            self.skipped((|| {
+                let entry = Self::dir_entry(entry.map_err(|source| walk_error(&dir.path, source))?, dir.depth + 1, self.follow)?;
+                if !self.accept(&entry)? {
+                    Ok(())
+                } else if entry.metadata.is_dir() {
+                    self.visit(&entry, cb)
+                } else {
+                    cb(entry)
+                }
+            })())
+        };
+        // the root itself is never skipped
+        let skipped = |source| if dir.depth > 0 { self.skipped(Err(walk_error(&dir.path, source))) } else { Err(walk_error(&dir.path, source)) };
+        let it = match fs::read_dir(&dir.path) {
+            Ok(it) => it,
+            Err(source) => return skipped(source),
+        };
+        if hdd() {
+            let mut entries = match it.collect::<io::Result<Vec<_>>>() {
+                Ok(entries) => entries,
+                Err(source) => return skipped(source),
+            };
+            entries.sort_by_key(inode);
+            entries.into_iter().map(Ok).try_for_each(parcours)
+        } else if self.parallel {
+            crate::threads::io(|| it.par_bridge().try_for_each(parcours))
+        } else {
+            it.into_iter().try_for_each(parcours)
+        }
+    }
+
+    /// `result`, unless it failed on a file or directory which is handed to [`skip_errors`](Walker::skip_errors)
+    fn skipped(&self, result: Result<()>) -> Result<()> {
+        match (result, &self.on_error) {
+            (Err(e), Some(on_error)) if e.path().is_some() => {
+                on_error(e);
+                Ok(())
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// iterate over the files, sequentially
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            walker: self,
+            root: Some(self.root.clone()),
+            stack: Vec::new(),
+        }
+    }
+
+    /// walk in a background thread, sending the files through a channel. The walk stops
+    /// after the first error, sent as the last item, or when the receiver is dropped.
+    pub fn channel(self) -> mpsc::Receiver<Result<FileEntry>> {
+        let (tx, rx) = mpsc::sync_channel(1024);
+        thread::spawn(move || {
+            let disconnected = || BlakediffError::Io(io::Error::new(io::ErrorKind::BrokenPipe, "receiver dropped"));
+            if let Err(e) = self.for_each(|entry| tx.send(Ok(entry)).map_err(|_| disconnected())) {
+                let _ = tx.send(Err(e));
+            }
+        });
+        rx
+    }
+}
+
+/// Sequential iterator over the files of a [`Walker`].
+pub struct Iter<'a> {
+    walker: &'a Walker,
+    root: Option<PathBuf>,
+    /// directories being read, with their depth
+    stack: Vec<(fs::ReadDir, usize)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<FileEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take() {
+            let root = match Walker::entry(root, 0) {
+                Ok(root) => root,
+                Err(e) => return Some(Err(e)),
+            };
+            if !root.metadata.is_dir() {
+                return self.walker.accept(&root).map(|accepted| accepted.then_some(root)).transpose();
+            }
+            match fs::read_dir(&root.path) {
+                Ok(it) => self.stack.push((it, 1)),
+                Err(e) => return Some(Err(walk_error(&root.path, e))),
+            }
+        }
+        loop {
+            let (it, depth) = self.stack.last_mut()?;
+            let depth = *depth;
+            let Some(entry) = it.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let entry = match entry.map_err(BlakediffError::from).and_then(|entry| Walker::dir_entry(entry, depth, self.walker.follow)) {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.walker.accept(&entry) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+            if !entry.metadata.is_dir() {
+                return Some(Ok(entry));
+            }
+            match fs::read_dir(&entry.path) {
+                Ok(it) => self.stack.push((it, depth + 1)),
+                Err(e) => return Some(Err(walk_error(&entry.path, e))),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(entry: &fs::DirEntry) -> u64 {
+    use std::os::unix::fs::DirEntryExt;
+    entry.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_entry: &fs::DirEntry) -> u64 { 0 }
+
+fn walk_error(path: &Path, source: io::Error) -> BlakediffError { BlakediffError::Walk { path: path.to_owned(), source } }
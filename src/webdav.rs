@@ -0,0 +1,245 @@
+//! WebDAV shares (Nextcloud, ownCloud, Apache mod_dav...) : `davs://host/path` is walked collection by collection
+//! with PROPFIND requests and its files hashed through GET requests, `dav://` being plain HTTP. Credentials
+//! come from the user of the URL or `BLAKEDIFF_WEBDAV_USER`, and `BLAKEDIFF_WEBDAV_PASSWORD`. They are only sent in
+//! clear over `dav://` when `BLAKEDIFF_WEBDAV_INSECURE` is set.
+
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::time::UNIX_EPOCH;
+
+use crate::source::{Backend, Item};
+use crate::{interrupt, BlakediffError, Result};
+
+/// properties of the members of a collection asked to the server
+const PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/><d:getlastmodified/></d:prop></d:propfind>"#;
+
+/// A WebDAV share.
+pub struct Share {
+    /// `dav` or `davs`
+    scheme: &'static str,
+    /// `host[:port]`
+    host: String,
+    /// absolute path of the root collection, percent-encoded
+    root: String,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Share {
+    /// share of a `dav://[user@]host[:port]/path` or `davs://` URL, refusing to send credentials over plain HTTP
+    /// unless `BLAKEDIFF_WEBDAV_INSECURE` is `1`, `true` or `yes`
+    pub fn open(url: &str) -> Result<Self> {
+        let share = Self::parse(url).ok_or_else(|| BlakediffError::Format(format!("'{}' is not a dav(s)://[user@]host[:port]/path URL", url)))?;
+        let insecure = std::env::var("BLAKEDIFF_WEBDAV_INSECURE").is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"));
+        if share.scheme == "dav" && share.authorization.is_some() && !insecure {
+            return Err(BlakediffError::Format(format!("{} : credentials would be sent in clear, use davs:// or set BLAKEDIFF_WEBDAV_INSECURE=1", url)));
+        }
+        Ok(share)
+    }
+
+    /// share of a `dav://[user@]host[:port]/path` or `davs://` URL
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, rest) = match url.split_once("://")? {
+            ("dav", rest) => ("dav", rest),
+            ("davs", rest) => ("davs", rest),
+            _ => return None,
+        };
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |slash| rest.split_at(slash));
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_owned()), host),
+            None => (None, authority),
+        };
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let authorization = user.or_else(|| var("BLAKEDIFF_WEBDAV_USER")).map(|user| {
+            let credentials = format!("{}:{}", user, var("BLAKEDIFF_WEBDAV_PASSWORD").unwrap_or_default());
+            format!("Basic {}", base64(credentials.as_bytes()))
+        });
+        (!host.is_empty()).then(|| Share {
+            scheme,
+            host: host.to_owned(),
+            root: encode(path.trim_end_matches('/')),
+            authorization,
+            agent: ureq::agent(),
+        })
+    }
+
+    fn location(&self, href: &str) -> String { format!("{}://{}{}", if self.scheme == "davs" { "https" } else { "http" }, self.host, href) }
+
+    /// URL of the file `href` in the report
+    pub fn url(&self, href: &str) -> String { format!("{}://{}{}", self.scheme, self.host, decode(href)) }
+
+    fn call(&self, method: &str, href: &str, depth: Option<&str>) -> Result<ureq::Response> {
+        let mut request = self.agent.request(method, &self.location(href));
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        let response = match depth {
+            Some(depth) => request.set("Depth", depth).set("Content-Type", "application/xml; charset=utf-8").send_string(PROPFIND),
+            None => request.call(),
+        };
+        response.map_err(|e| match e {
+            ureq::Error::Status(404, _) => BlakediffError::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} : not found", self.url(href)))),
+            e => BlakediffError::Other(e.to_string()),
+        })
+    }
+
+    /// every file under the root collection, depth first, sorted by name in each collection. Each collection is
+    /// listed once, and the members a server answers outside the root are left out, so that a looping share ends.
+    fn walk(&self) -> Result<Vec<Item>> {
+        let root = format!("{}/", decode(&self.root));
+        let mut files = Vec::new();
+        let mut collections = vec![format!("{}/", self.root)];
+        let mut visited = HashSet::new();
+        while let Some(collection) = collections.pop() {
+            if !visited.insert(decode(&collection).trim_end_matches('/').to_owned()) {
+                log::warn!("{} : listed twice, skipped", self.url(&collection));
+                continue;
+            }
+            interrupt::check()?;
+            let body = self.call("PROPFIND", &collection, Some("1"))?.into_string()?;
+            let (mut members, mut children) = (Vec::new(), Vec::new());
+            for (href, member) in self.members(&body)? {
+                // the collection itself is listed among its members
+                if decode(&href).trim_end_matches('/') == decode(&collection).trim_end_matches('/') {
+                    continue;
+                }
+                if !decode(&href).starts_with(&root) {
+                    log::warn!("{} : outside of {}, skipped", self.url(&href), self.url(&self.root));
+                    continue;
+                }
+                match member {
+                    Some(file) => members.push(file),
+                    None => children.push(href),
+                }
+            }
+            members.sort_by(|a, b| a.location.cmp(&b.location));
+            files.extend(members);
+            children.sort();
+            collections.extend(children.into_iter().rev());
+        }
+        Ok(files)
+    }
+
+    /// href of the members of a PROPFIND answer, with their properties for files and None for collections
+    fn members(&self, body: &str) -> Result<Vec<(String, Option<Item>)>> {
+        let document = roxmltree::Document::parse(body).map_err(|e| BlakediffError::Other(format!("{} : {}", self.url(&self.root), e)))?;
+        Ok(document
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name(("DAV:", "response")))
+            .map(|response| {
+                // some servers answer full URLs
+                let href = text(response, "href");
+                let href = match href.split_once("://") {
+                    Some((_, rest)) => rest.find('/').map_or(String::from("/"), |slash| rest[slash..].to_owned()),
+                    None => href,
+                };
+                let collection = find(response, "resourcetype").is_some_and(|t| t.children().any(|n| n.has_tag_name(("DAV:", "collection"))));
+                let file = (!collection).then(|| Item {
+                    path: self.url(&href),
+                    location: href.clone(),
+                    size: text(response, "getcontentlength").parse().unwrap_or(0),
+                    mtime: httpdate::parse_http_date(&text(response, "getlastmodified")).ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default(),
+                });
+                (href, file)
+            })
+            .collect())
+    }
+}
+
+impl Backend for Share {
+    fn list(&self) -> Result<Vec<Item>> { self.walk() }
+
+    fn open(&self, item: &Item) -> Result<Box<dyn Read + Send + '_>> { Ok(self.call("GET", &item.location, None)?.into_reader()) }
+}
+
+/// first element `name` of the DAV: namespace under `node`
+fn find<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> { node.descendants().find(|n| n.has_tag_name(("DAV:", name))) }
+
+fn text(node: roxmltree::Node, name: &str) -> String { find(node, name).and_then(|n| n.text()).unwrap_or_default().trim().to_owned() }
+
+/// percent-encoding of a path, keeping the characters allowed in its segments
+fn encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn decode(href: &str) -> String {
+    let mut bytes = Vec::with_capacity(href.len());
+    let mut rest = href.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        match (b, tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    bytes
+        .chunks(3)
+        .flat_map(|chunk| {
+            let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+            (0..4).map(move |i| if i <= chunk.len() { ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodings() {
+        assert_eq!(base64(b"me:secret"), "bWU6c2VjcmV0");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(encode("/files/my music/é"), "/files/my%20music/%C3%A9");
+        assert_eq!(decode("/files/my%20music/%C3%A9"), "/files/my music/é");
+    }
+
+    #[test]
+    fn credentials_need_tls_or_opt_in() {
+        assert!(Share::open("dav://host/files").is_ok());
+        assert!(Share::open("davs://me@host/files").is_ok());
+        assert!(matches!(Share::open("dav://me@host/files"), Err(BlakediffError::Format(_))));
+        assert!(matches!(Share::open("ftp://host/files"), Err(BlakediffError::Format(_))));
+    }
+
+    #[test]
+    fn looping_shares_are_walked_once() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        // every collection lists the root and a sub collection again, and one collection points out of the share
+        let answer = |url: &str| {
+            let member = |href: &str, collection: bool| {
+                let kind = if collection { "<d:collection/>" } else { "" };
+                format!("<d:response><d:href>{}</d:href><d:propstat><d:prop><d:resourcetype>{}</d:resourcetype><d:getcontentlength>3</d:getcontentlength></d:prop></d:propstat></d:response>", href, kind)
+            };
+            let members = [member(url, true), member("/share/", true), member("/share/loop/", true), member("/elsewhere/", true), member(&format!("{}file", url), false)];
+            format!("<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\">{}</d:multistatus>", members.concat())
+        };
+        let listener = std::thread::spawn(move || {
+            for request in server.incoming_requests().take(2) {
+                let body = answer(request.url());
+                request.respond(tiny_http::Response::from_string(body).with_status_code(207)).unwrap();
+            }
+        });
+        let share = Share::open(&format!("dav://127.0.0.1:{}/share", port)).unwrap();
+        let files: Vec<String> = share.walk().unwrap().into_iter().map(|item| item.location).collect();
+        listener.join().unwrap();
+        assert_eq!(files, ["/share/file", "/share/loop/file"]);
+    }
+}
@@ -0,0 +1,92 @@
+//! Extended attributes of files, and the ACLs stored among them on linux (`system.posix_acl_access` and
+//! `system.posix_acl_default`), summed up in a single BLAKE3 digest : two files with the same content but other
+//! labels, capabilities or permissions get different digests.
+
+use std::io;
+use std::path::Path;
+
+/// BLAKE3 of the extended attributes of `path`, sorted by name, a symbolic link being not followed. Files without
+/// attributes, or on filesystems without them, all get the digest of the empty list.
+pub fn digest(path: &Path) -> io::Result<String> {
+    let mut attributes = attributes(path)?;
+    attributes.sort();
+    let mut hasher = blake3::Hasher::new();
+    for (name, value) in &attributes {
+        // lengths first, so that names and values can't be shifted into each other
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// names and values of the extended attributes of a file
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn attributes(path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let names = match read(|buffer| unsafe { sys::list(&path, buffer) }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut attributes = Vec::new();
+    for name in names.split(|&c| c == 0).filter(|name| !name.is_empty()) {
+        let name = CString::new(name)?;
+        match read(|buffer| unsafe { sys::get(&path, &name, buffer) }) {
+            Ok(value) => attributes.push((name.into_bytes(), value)),
+            // removed since it was listed
+            Err(e) if e.raw_os_error() == Some(sys::ENOATTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(attributes)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn attributes(_path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> { Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are only read on linux and macos")) }
+
+/// whole result of a call asked its size first, with an empty buffer, then called again if it grew meanwhile
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read(call: impl Fn(&mut [u8]) -> isize) -> io::Result<Vec<u8>> {
+    loop {
+        let size = call(&mut []);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = vec![0; size as usize];
+        match call(&mut buffer) {
+            read if read >= 0 => {
+                buffer.truncate(read as usize);
+                return Ok(buffer);
+            }
+            _ if io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) => continue,
+            _ => return Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::CStr;
+
+    pub const ENOATTR: i32 = libc::ENODATA;
+
+    pub unsafe fn list(path: &CStr, buffer: &mut [u8]) -> isize { libc::llistxattr(path.as_ptr(), buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) }
+
+    pub unsafe fn get(path: &CStr, name: &CStr, buffer: &mut [u8]) -> isize { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::CStr;
+
+    pub const ENOATTR: i32 = libc::ENOATTR;
+
+    pub unsafe fn list(path: &CStr, buffer: &mut [u8]) -> isize { libc::listxattr(path.as_ptr(), buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), libc::XATTR_NOFOLLOW) }
+
+    pub unsafe fn get(path: &CStr, name: &CStr, buffer: &mut [u8]) -> isize { libc::getxattr(path.as_ptr(), name.as_ptr(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0, libc::XATTR_NOFOLLOW) }
+}